@@ -1,51 +1,237 @@
 use crate::application_errors::ApplicationError;
-use crate::client::{ClientInfo, TorrentClient};
+use crate::bandwidth::{Bandwidth, TorrentBandwidth};
+use crate::client::{
+    ClientInfo, Seeder, SeedingLimits, TorrentClient, TorrentHandle, TorrentStats,
+};
 use crate::constants::TIME_BETWEEN_ACCEPTS;
-use crate::download_manager::get_existing_pieces;
-use crate::server::Server;
+use crate::lsd::{LocalDiscovery, LocalDiscoverySender};
+use crate::metainfo::Metainfo;
+use crate::peer::{Choker, ConnectionLimit};
+use crate::server::{ServedTorrent, Server, ServerSender};
+use crate::storage::{
+    open_storage, open_unchecked_storage, recheck, resume_path, RecheckProgress, RecheckReport,
+    ResumeData, ResumeWriter,
+};
 use crate::tracker::TrackerService;
 use crate::ui::{init_ui, UIMessage};
 use gtk::{self, glib};
 use log::*;
+use std::thread::JoinHandle;
 
+/// What the torrents of a session share: the server accepting connections for every one of them,
+/// the local service discovery announcing them, the client wide rate limits and the cap on open connections
+#[derive(Clone)]
+pub struct TorrentContext {
+    pub server: ServerSender,
+    pub local_discovery: LocalDiscoverySender,
+    pub bandwidth: Bandwidth,
+    pub connections: ConnectionLimit,
+}
+
+/// A torrent started with [`start_torrent`] or by a session
+pub struct RunningTorrent {
+    pub handle: TorrentHandle,
+    pub metainfo: Metainfo,
+    pub stats: TorrentStats,
+    thread: JoinHandle<Result<(), ApplicationError>>,
+    // the server and local service discovery started for this torrent alone, None if they are shared with the session
+    server: Option<(Server, LocalDiscovery)>,
+}
+
+impl RunningTorrent {
+    /// Whether the torrent stopped and finished running, so `join` won't block
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Waits until the torrent is stopped and its server shut down
+    pub fn join(self) -> Result<(), ApplicationError> {
+        let result = self.thread.join();
+        if let Some((server, local_discovery)) = self.server {
+            if let Err(err) = server.stop() {
+                error!("Server stopped with error: {}", err);
+            }
+            local_discovery.stop();
+        }
+        result?
+    }
+}
+
+/// Downloads the torrent and then seeds it until it is stopped or a seeding limit is reached
 pub fn run_with_torrent(
     torrent_path: &str,
     config_path: &str,
     ui_message_sender: Option<glib::Sender<UIMessage>>,
 ) -> Result<(), ApplicationError> {
-    let mut client_info = ClientInfo::new(torrent_path, config_path)?;
-    let ui_message_sender = init_ui(ui_message_sender, &mut client_info);
-
-    let pieces_dir = format!(
-        "{}/{}/pieces",
-        client_info.config.download_path, client_info.metainfo.info.name
-    );
-
-    let mut tracker_service = TrackerService::new(client_info.clone());
+    start_torrent(torrent_path, config_path, ui_message_sender)?.join()
+}
 
-    let _ = Server::run(
+/// Opens the torrent and starts downloading it in a new thread, with a server and a local service discovery of its own.
+/// Once downloaded it keeps seeding, until it is stopped through the returned handle
+/// or one of the seeding limits of the config is reached
+pub fn start_torrent(
+    torrent_path: &str,
+    config_path: &str,
+    ui_message_sender: Option<glib::Sender<UIMessage>>,
+) -> Result<RunningTorrent, ApplicationError> {
+    let client_info = ClientInfo::new(torrent_path, config_path)?;
+    let server = Server::listen(
         client_info.peer_id.to_vec(),
-        client_info.metainfo.clone(),
         client_info.config.listen_port,
         TIME_BETWEEN_ACCEPTS,
-        &pieces_dir,
-        tracker_service.clone(),
+        ConnectionLimit::unlimited(),
+        client_info.config.encryption,
+        client_info.config.transport,
     );
-    let initial_pieces: Vec<u32> =
-        get_existing_pieces(client_info.metainfo.get_piece_count(), pieces_dir.as_str());
-    println!("{}/pieces", client_info.config.download_path);
+    let local_discovery = LocalDiscovery::start(client_info.config.listen_port);
+    let context = TorrentContext {
+        server: server.sender(),
+        local_discovery: local_discovery.sender(),
+        bandwidth: Bandwidth::global(&client_info.config),
+        connections: ConnectionLimit::unlimited(),
+    };
+    let mut torrent = start_torrent_in(client_info, &context, ui_message_sender)?;
+    torrent.server = Some((server, local_discovery));
+    Ok(torrent)
+}
+
+/// Starts the torrent in a new thread like [`start_torrent`], sharing the server, the local service discovery
+/// and the limits of `context`. Once it stops, it is removed from both
+pub fn start_torrent_in(
+    mut client_info: ClientInfo,
+    context: &TorrentContext,
+    ui_message_sender: Option<glib::Sender<UIMessage>>,
+) -> Result<RunningTorrent, ApplicationError> {
+    let ui_message_sender = init_ui(ui_message_sender, &mut client_info);
+
+    let resume_path = resume_path(&client_info.config, &client_info.metainfo);
+    let resume = match ResumeData::load(&resume_path) {
+        Ok(resume) => Some(resume),
+        Err(err) => {
+            info!("No resume data to use: {}", err);
+            None
+        }
+    };
+    let storage = open_storage(&client_info.config, &client_info.metainfo, resume.as_ref())?;
+
+    let choker = Choker::default();
+    let bandwidth = TorrentBandwidth::new(context.bandwidth.clone(), &client_info.config);
+    let mut tracker_service = TrackerService::new(client_info.clone())
+        .with_storage(storage.clone())
+        .with_bandwidth(bandwidth.clone());
+
+    context.server.add_torrent(ServedTorrent {
+        metainfo: client_info.metainfo.clone(),
+        storage: storage.clone(),
+        tracker_service: tracker_service.clone(),
+        choker: choker.clone(),
+        bandwidth: bandwidth.clone(),
+    });
+    let initial_pieces: Vec<u32> = storage.completed_pieces();
     println!("i've got pieces: {:?}", initial_pieces);
 
     for _ in initial_pieces.clone() {
         ui_message_sender.send_downloaded_piece(client_info.peer_id.to_vec());
     }
 
-    let client: TorrentClient =
-        TorrentClient::new(&client_info, ui_message_sender, initial_pieces)?;
-    client.run(client_info, &mut tracker_service)?;
+    let resume_writer = ResumeWriter::new(
+        &resume_path,
+        storage.clone(),
+        bandwidth.clone(),
+        resume.as_ref(),
+    );
+    let seeding_ui_message_sender = ui_message_sender.clone();
+    let stats = TorrentStats::new(
+        storage.clone(),
+        bandwidth.clone(),
+        ui_message_sender.clone(),
+    );
+    let uploaded_before = resume.as_ref().map(|data| data.uploaded).unwrap_or(0);
+    let client: TorrentClient = TorrentClient::new(
+        &client_info,
+        ui_message_sender,
+        initial_pieces,
+        storage,
+        resume_writer.clone(),
+        choker,
+        bandwidth.clone(),
+    )?
+    .with_connection_limit(context.connections.clone())
+    .with_peer_limit(client_info.config.max_connections_per_torrent)
+    .with_encryption(client_info.config.encryption)
+    .with_transport(client_info.config.transport);
+    let handle = client.handle();
+    context.local_discovery.add_torrent(
+        &client_info.metainfo,
+        client.peer_connection_manager_sender(),
+    );
+    let seeder = Seeder::new(
+        handle.clone(),
+        SeedingLimits::from_config(&client_info.config),
+        bandwidth,
+        uploaded_before,
+        client_info.metainfo.info.length,
+        seeding_ui_message_sender,
+    )
+    .with_resume_writer(resume_writer);
+    let metainfo = client_info.metainfo.clone();
+    let server = context.server.clone();
+    let local_discovery = context.local_discovery.clone();
+    let seeding_handle = handle.clone();
+    let thread = std::thread::spawn(move || {
+        let info_hash = client_info.metainfo.info_hash.clone();
+        // it seeds as soon as the wanted pieces are downloaded, even if skipped pieces keep the client running
+        let seeding = std::thread::spawn(move || seeder.run());
+        let downloaded = client.run(client_info, &mut tracker_service);
+        if downloaded.is_err() {
+            seeding_handle.stop();
+        }
+        if seeding.join().is_err() {
+            error!("The seeding thread panicked");
+        }
+        // announces the stop to the tracker
+        server.remove_torrent(&info_hash);
+        local_discovery.remove_torrent(&info_hash);
+        downloaded?;
+        info!("Exited bittorrent client succesfully!");
+        Ok(())
+    });
+    Ok(RunningTorrent {
+        handle,
+        metainfo,
+        stats,
+        thread,
+        server: None,
+    })
+}
 
-    //server.stop()?;
+/// Hashes every piece of the torrent's data on disk, no matter what the resume data says.
+/// Pieces that don't match are left out of the storage and the resume file, so they are downloaded again
+pub fn recheck_torrent(
+    torrent_path: &str,
+    config_path: &str,
+    on_progress: impl FnMut(RecheckProgress),
+) -> Result<RecheckReport, ApplicationError> {
+    let client_info = ClientInfo::new(torrent_path, config_path)?;
+    let storage = open_unchecked_storage(&client_info.config, &client_info.metainfo)?;
+    let resume_path = resume_path(&client_info.config, &client_info.metainfo);
+    let previous = ResumeData::load(&resume_path).ok();
+    // the pieces the resume data had as stored are the ones reported as bad if they don't match
+    if let Some(previous) = &previous {
+        storage.set_completed(&previous.pieces)?;
+    }
+    let report = recheck(
+        storage.as_ref(),
+        &client_info.metainfo.info.pieces,
+        on_progress,
+    )?;
 
-    info!("Exited bittorrent client succesfully!");
-    Ok(())
+    ResumeWriter::new(
+        &resume_path,
+        storage,
+        TorrentBandwidth::unlimited(),
+        previous.as_ref(),
+    )
+    .save()?;
+    Ok(report)
 }
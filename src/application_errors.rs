@@ -5,6 +5,7 @@ use crate::logger::LoggerError;
 use crate::metainfo::MetainfoParserError;
 use crate::peer::PeerConnectionError;
 use crate::server::ServerError;
+use crate::storage::StorageError;
 use crate::tracker::TrackerError;
 use std::fmt;
 use std::fmt::Display;
@@ -22,6 +23,7 @@ pub enum ApplicationError {
     PeerConnectionError(PeerConnectionError),
     ServerError(ServerError),
     DownloadError(DownloadManagerError),
+    StorageError(StorageError),
 }
 
 impl From<ServerError> for ApplicationError {
@@ -72,6 +74,12 @@ impl From<DownloadManagerError> for ApplicationError {
     }
 }
 
+impl From<StorageError> for ApplicationError {
+    fn from(error: StorageError) -> Self {
+        ApplicationError::StorageError(error)
+    }
+}
+
 impl From<Box<dyn std::any::Any + std::marker::Send>> for ApplicationError {
     fn from(error: Box<dyn std::any::Any + std::marker::Send>) -> Self {
         ApplicationError::JoinError(format!("{:?}", error))
@@ -88,6 +96,7 @@ impl Display for ApplicationError {
             ApplicationError::JoinError(cause) => write!(f, "Join Error - {}", cause),
             ApplicationError::ServerError(error) => write!(f, "Server Error - {}", error),
             ApplicationError::DownloadError(err) => write!(f, "Download Error - {}", err),
+            ApplicationError::StorageError(err) => write!(f, "Storage Error - {}", err),
             ApplicationError::HttpsServiceError(error) => {
                 return write!(f, "HttpsService Error - {}", error);
             }
@@ -0,0 +1,8 @@
+/// Seconds of traffic a rate is measured over
+pub const RATE_WINDOW_SECONDS: u64 = 5;
+
+/// Seconds worth of the limit that can be spent at once after being idle
+pub const BURST_SECONDS: f64 = 1.0;
+
+/// Limits in the config file are written in KiB per second
+pub const BYTES_PER_KIB: u64 = 1024;
@@ -0,0 +1,198 @@
+use super::constants::*;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const UNLIMITED: u64 = 0;
+
+/// Maximum amount of bytes per second, shared by every limiter created with it,
+/// so changing it at runtime takes effect on all of them
+#[derive(Debug, Clone, Default)]
+pub struct RateLimit(Arc<AtomicU64>);
+
+impl RateLimit {
+    pub fn new(bytes_per_second: Option<u64>) -> Self {
+        let limit = Self::default();
+        limit.set(bytes_per_second);
+        limit
+    }
+
+    /// Sets the limit, `None` or zero mean unlimited
+    pub fn set(&self, bytes_per_second: Option<u64>) {
+        self.0
+            .store(bytes_per_second.unwrap_or(UNLIMITED), Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> Option<u64> {
+        match self.0.load(Ordering::Relaxed) {
+            UNLIMITED => None,
+            limit => Some(limit),
+        }
+    }
+}
+
+// Token bucket: it fills at the limit's rate up to BURST_SECONDS worth of bytes,
+// and sending takes bytes out of it. It can go below zero, so a message bigger than
+// the bucket still goes through, and the deficit is the time the caller has to wait
+#[derive(Debug)]
+struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: 0.0,
+            last_refill: Instant::now(),
+        }
+    }
+
+    fn reserve(&mut self, bytes: usize) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+
+        let rate = match self.limit.get() {
+            Some(rate) => rate as f64,
+            None => {
+                self.tokens = 0.0;
+                return Duration::ZERO;
+            }
+        };
+        self.tokens = (self.tokens + elapsed * rate).min(rate * BURST_SECONDS);
+        self.tokens -= bytes as f64;
+        if self.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-self.tokens / rate)
+    }
+}
+
+// Measures the transferred bytes over the last RATE_WINDOW_SECONDS, and since it was created
+#[derive(Debug, Default)]
+struct RateMeter {
+    samples: VecDeque<(Instant, usize)>,
+    window_bytes: usize,
+    total_bytes: u64,
+}
+
+impl RateMeter {
+    fn record(&mut self, bytes: usize) {
+        self.samples.push_back((Instant::now(), bytes));
+        self.window_bytes += bytes;
+        self.total_bytes += bytes as u64;
+        self.drop_old_samples();
+    }
+
+    fn rate(&mut self) -> f64 {
+        self.drop_old_samples();
+        self.window_bytes as f64 / RATE_WINDOW_SECONDS as f64
+    }
+
+    fn drop_old_samples(&mut self) {
+        let window = Duration::from_secs(RATE_WINDOW_SECONDS);
+        while let Some((time, bytes)) = self.samples.front() {
+            if time.elapsed() <= window {
+                break;
+            }
+            self.window_bytes -= bytes;
+            self.samples.pop_front();
+        }
+    }
+}
+
+/// Limits and measures the traffic going in one direction.
+/// Clones share the same bucket, so every connection holding one is limited together
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    limit: RateLimit,
+    state: Arc<Mutex<(TokenBucket, RateMeter)>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            state: Arc::new(Mutex::new((
+                TokenBucket::new(limit.clone()),
+                RateMeter::default(),
+            ))),
+            limit,
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(RateLimit::default())
+    }
+
+    /// Accounts for bytes about to be transferred.
+    /// Returns how long to wait before transferring them to stay under the limit
+    pub fn reserve(&self, bytes: usize) -> Duration {
+        match self.state.lock() {
+            Ok(mut state) => {
+                state.1.record(bytes);
+                state.0.reserve(bytes)
+            }
+            Err(_) => Duration::ZERO,
+        }
+    }
+
+    /// Bytes per second transferred lately
+    pub fn rate(&self) -> f64 {
+        self.state
+            .lock()
+            .map(|mut state| state.1.rate())
+            .unwrap_or(0.0)
+    }
+
+    /// Bytes transferred since the limiter was created
+    pub fn total(&self) -> u64 {
+        self.state
+            .lock()
+            .map(|state| state.1.total_bytes)
+            .unwrap_or(0)
+    }
+
+    pub fn limit(&self) -> &RateLimit {
+        &self.limit
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unlimited_limiter_never_waits() {
+        let limiter = RateLimiter::unlimited();
+        assert_eq!(limiter.reserve(10_000_000), Duration::ZERO);
+    }
+
+    #[test]
+    fn going_over_the_limit_waits_for_the_deficit() {
+        let limiter = RateLimiter::new(RateLimit::new(Some(1000)));
+        let wait = limiter.reserve(2000);
+        assert!(wait > Duration::from_millis(1900) && wait <= Duration::from_secs(2));
+    }
+
+    #[test]
+    fn changing_the_limit_affects_every_limiter_sharing_it() {
+        let limit = RateLimit::new(Some(1000));
+        let limiter = RateLimiter::new(limit.clone());
+        limit.set(None);
+        assert_eq!(limiter.reserve(5000), Duration::ZERO);
+        assert_eq!(limiter.limit().get(), None);
+    }
+
+    #[test]
+    fn rate_is_measured_over_the_window() {
+        let limiter = RateLimiter::unlimited();
+        limiter.reserve(5000);
+        limiter.reserve(5000);
+        assert_eq!(limiter.rate(), 10000.0 / RATE_WINDOW_SECONDS as f64);
+        assert_eq!(limiter.total(), 10000);
+    }
+}
@@ -0,0 +1,7 @@
+mod constants;
+mod limiter;
+mod types;
+
+pub use constants::*;
+pub use limiter::{RateLimit, RateLimiter};
+pub use types::{limit_from_kib, Bandwidth, PeerBandwidth, TorrentBandwidth};
@@ -0,0 +1,161 @@
+use super::constants::*;
+use super::limiter::{RateLimit, RateLimiter};
+use crate::config::Config;
+use std::time::Duration;
+
+/// Upload and download limiters of one level: the whole client, a torrent or a peer
+#[derive(Debug, Clone)]
+pub struct Bandwidth {
+    pub upload: RateLimiter,
+    pub download: RateLimiter,
+}
+
+impl Bandwidth {
+    /// Limits are in bytes per second, `None` means unlimited
+    pub fn new(max_upload_rate: Option<u64>, max_download_rate: Option<u64>) -> Self {
+        Self::with_limits(
+            RateLimit::new(max_upload_rate),
+            RateLimit::new(max_download_rate),
+        )
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(None, None)
+    }
+
+    /// Limits of the whole client, taken from the config
+    pub fn global(config: &Config) -> Self {
+        Self::new(config.max_upload_rate, config.max_download_rate)
+    }
+
+    fn with_limits(upload: RateLimit, download: RateLimit) -> Self {
+        Self {
+            upload: RateLimiter::new(upload),
+            download: RateLimiter::new(download),
+        }
+    }
+
+    /// Changes the limits at runtime, `None` means unlimited
+    pub fn set_limits(&self, max_upload_rate: Option<u64>, max_download_rate: Option<u64>) {
+        self.upload.limit().set(max_upload_rate);
+        self.download.limit().set(max_download_rate);
+    }
+}
+
+/// Bandwidth of a torrent: the client wide limiters, the ones of the torrent,
+/// and the limits every peer of the torrent gets on its own
+#[derive(Debug, Clone)]
+pub struct TorrentBandwidth {
+    pub global: Bandwidth,
+    pub torrent: Bandwidth,
+    peer_upload_limit: RateLimit,
+    peer_download_limit: RateLimit,
+}
+
+impl TorrentBandwidth {
+    pub fn new(global: Bandwidth, config: &Config) -> Self {
+        Self {
+            global,
+            torrent: Bandwidth::new(
+                config.torrent_max_upload_rate,
+                config.torrent_max_download_rate,
+            ),
+            peer_upload_limit: RateLimit::new(config.peer_max_upload_rate),
+            peer_download_limit: RateLimit::new(config.peer_max_download_rate),
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self {
+            global: Bandwidth::unlimited(),
+            torrent: Bandwidth::unlimited(),
+            peer_upload_limit: RateLimit::default(),
+            peer_download_limit: RateLimit::default(),
+        }
+    }
+
+    /// Changes the limits of each peer of the torrent, including the ones already connected
+    pub fn set_peer_limits(&self, max_upload_rate: Option<u64>, max_download_rate: Option<u64>) {
+        self.peer_upload_limit.set(max_upload_rate);
+        self.peer_download_limit.set(max_download_rate);
+    }
+
+    /// Bytes of the torrent uploaded in this session
+    pub fn uploaded(&self) -> u64 {
+        self.torrent.upload.total()
+    }
+
+    /// Bytes of the torrent downloaded in this session
+    pub fn downloaded(&self) -> u64 {
+        self.torrent.download.total()
+    }
+
+    /// Limiters for a new connection of the torrent
+    pub fn for_peer(&self) -> PeerBandwidth {
+        PeerBandwidth {
+            torrent: self.clone(),
+            peer: Bandwidth::with_limits(
+                self.peer_upload_limit.clone(),
+                self.peer_download_limit.clone(),
+            ),
+        }
+    }
+}
+
+/// Limiters a connection goes through when sending and receiving blocks.
+/// Transfers wait for the most restrictive of the client, torrent and peer limits
+#[derive(Debug, Clone)]
+pub struct PeerBandwidth {
+    torrent: TorrentBandwidth,
+    peer: Bandwidth,
+}
+
+impl PeerBandwidth {
+    pub fn unlimited() -> Self {
+        TorrentBandwidth::unlimited().for_peer()
+    }
+
+    /// Waits until `bytes` can be sent to the peer
+    pub fn throttle_upload(&self, bytes: usize) {
+        let wait = [
+            self.torrent.global.upload.reserve(bytes),
+            self.torrent.torrent.upload.reserve(bytes),
+            self.peer.upload.reserve(bytes),
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or(Duration::ZERO);
+        std::thread::sleep(wait);
+    }
+
+    /// Waits until `bytes` received from the peer can be taken
+    pub fn throttle_download(&self, bytes: usize) {
+        let wait = [
+            self.torrent.global.download.reserve(bytes),
+            self.torrent.torrent.download.reserve(bytes),
+            self.peer.download.reserve(bytes),
+        ]
+        .into_iter()
+        .max()
+        .unwrap_or(Duration::ZERO);
+        std::thread::sleep(wait);
+    }
+
+    /// Bytes per second sent to this peer lately
+    pub fn upload_rate(&self) -> f64 {
+        self.peer.upload.rate()
+    }
+
+    /// Bytes per second received from this peer lately
+    pub fn download_rate(&self) -> f64 {
+        self.peer.download.rate()
+    }
+}
+
+/// Parses a limit written in KiB per second, where zero means unlimited
+pub fn limit_from_kib(kib_per_second: u64) -> Option<u64> {
+    match kib_per_second {
+        0 => None,
+        kib => Some(kib * BYTES_PER_KIB),
+    }
+}
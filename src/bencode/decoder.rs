@@ -1,5 +1,5 @@
 use super::errors::BencodeDecoderError;
-use super::types::BencodeDecodedValue;
+use super::types::{BencodeDecodedValue, BencodeSpan};
 use std::collections::HashMap;
 const INTEGER_START_TOKEN: char = 'i';
 const LIST_START_TOKEN: char = 'l';
@@ -28,34 +28,89 @@ use crate::boxed_result::BoxedResult;
 /// assert_eq!(decoded, BencodeDecodedValue::Integer(454));
 /// ```
 pub fn decode(bytes: &[u8]) -> Result<BencodeDecodedValue, BencodeDecoderError> {
+    Ok(decode_with_spans(bytes)?.0)
+}
+
+/// Decodes a bencoded byte slice like [`decode`] does, also returning where each value was in it.
+/// Useful to read parts of the original bytes, which may not be encoded the way [`encode`](super::encode)
+/// would encode them back
+///
+/// ## Example
+///
+/// ```
+/// use bittorrent_rustico::bencode::{decode_with_spans, BencodeDecodedValue};
+///
+/// let (decoded, span) = decode_with_spans(b"li4ei-2ee").unwrap();
+/// assert_eq!(
+///     decoded,
+///     BencodeDecodedValue::List(vec![
+///         BencodeDecodedValue::Integer(4),
+///         BencodeDecodedValue::Integer(-2)
+///     ])
+/// );
+/// assert_eq!(span.range, 0..9);
+/// assert_eq!(span.item(1).unwrap().range, 4..8);
+/// ```
+pub fn decode_with_spans(
+    bytes: &[u8],
+) -> Result<(BencodeDecodedValue, BencodeSpan), BencodeDecoderError> {
+    let total_length = bytes.len();
     let mut bytes = bytes.iter().enumerate();
-    let bencoded_value = decode_and_consume_iterator(&mut bytes)?;
-    Ok(bencoded_value)
+    let decoded = decode_and_consume_iterator(&mut bytes, total_length)?;
+    Ok(decoded)
+}
+
+type Bytes<'a> = std::iter::Enumerate<std::slice::Iter<'a, u8>>;
+
+// the entries of a dictionary along with where each value was
+type DecodedDictionary = (
+    HashMap<Vec<u8>, BencodeDecodedValue>,
+    HashMap<Vec<u8>, BencodeSpan>,
+);
+
+// position of the next byte the iterator will return
+fn position(bytes: &Bytes<'_>, total_length: usize) -> usize {
+    total_length - bytes.len()
 }
 
 fn decode_and_consume_iterator(
-    bytes: &mut std::iter::Enumerate<std::slice::Iter<'_, u8>>,
-) -> BoxedResult<BencodeDecodedValue> {
+    bytes: &mut Bytes<'_>,
+    total_length: usize,
+) -> BoxedResult<(BencodeDecodedValue, BencodeSpan)> {
+    let start = position(bytes, total_length);
     let next_byte = bytes.next();
+    let mut span = BencodeSpan::default();
     if let Some((idx, byte)) = next_byte {
-        match *byte as char {
-            INTEGER_START_TOKEN => read_integer(bytes).map(BencodeDecodedValue::Integer),
-            LIST_START_TOKEN => read_list(bytes).map(BencodeDecodedValue::List),
-            '0'..='9' => read_string(bytes, *byte).map(BencodeDecodedValue::String),
-            DICTIONARY_START_TOKEN => read_dictionary(bytes).map(BencodeDecodedValue::Dictionary),
-            END_TOKEN => Ok(BencodeDecodedValue::End),
-            _ => Err(BencodeDecoderError(format!(
-                "Unknown token {} at position {}",
-                *byte as char, idx
-            ))
-            .into()),
-        }
+        let value = match *byte as char {
+            INTEGER_START_TOKEN => BencodeDecodedValue::Integer(read_integer(bytes)?),
+            LIST_START_TOKEN => {
+                let (list, items) = read_list(bytes, total_length)?;
+                span.items = items;
+                BencodeDecodedValue::List(list)
+            }
+            '0'..='9' => BencodeDecodedValue::String(read_string(bytes, *byte)?),
+            DICTIONARY_START_TOKEN => {
+                let (dictionary, entries) = read_dictionary(bytes, total_length)?;
+                span.entries = entries;
+                BencodeDecodedValue::Dictionary(dictionary)
+            }
+            END_TOKEN => BencodeDecodedValue::End,
+            _ => {
+                return Err(BencodeDecoderError(format!(
+                    "Unknown token {} at position {}",
+                    *byte as char, idx
+                ))
+                .into())
+            }
+        };
+        span.range = start..position(bytes, total_length);
+        Ok((value, span))
     } else {
         Err(BencodeDecoderError("Unexpected end of stream".to_string()).into())
     }
 }
 
-fn read_integer(bytes: &mut std::iter::Enumerate<std::slice::Iter<'_, u8>>) -> BoxedResult<i64> {
+fn read_integer(bytes: &mut Bytes<'_>) -> BoxedResult<i64> {
     let mut integer = 0i64;
     let mut sign = 1i64;
     let mut first_digit = true;
@@ -94,10 +149,7 @@ fn read_integer(bytes: &mut std::iter::Enumerate<std::slice::Iter<'_, u8>>) -> B
     Ok(sign * integer)
 }
 
-fn read_string(
-    bytes: &mut std::iter::Enumerate<std::slice::Iter<'_, u8>>,
-    byte: u8,
-) -> BoxedResult<Vec<u8>> {
+fn read_string(bytes: &mut Bytes<'_>, byte: u8) -> BoxedResult<Vec<u8>> {
     let mut length = byte as usize - ('0' as usize);
 
     loop {
@@ -139,29 +191,35 @@ fn read_string(
 }
 
 fn read_list(
-    bytes: &mut std::iter::Enumerate<std::slice::Iter<'_, u8>>,
-) -> BoxedResult<Vec<BencodeDecodedValue>> {
+    bytes: &mut Bytes<'_>,
+    total_length: usize,
+) -> BoxedResult<(Vec<BencodeDecodedValue>, Vec<BencodeSpan>)> {
     let mut list: Vec<BencodeDecodedValue> = Vec::new();
+    let mut spans = Vec::new();
     loop {
-        let next_item = decode_and_consume_iterator(bytes)?;
+        let (next_item, span) = decode_and_consume_iterator(bytes, total_length)?;
         match next_item {
             BencodeDecodedValue::End => break,
-            _ => list.push(next_item),
+            _ => {
+                list.push(next_item);
+                spans.push(span);
+            }
         }
     }
-    Ok(list)
+    Ok((list, spans))
 }
 
-fn read_dictionary(
-    bytes: &mut std::iter::Enumerate<std::slice::Iter<'_, u8>>,
-) -> BoxedResult<HashMap<Vec<u8>, BencodeDecodedValue>> {
+fn read_dictionary(bytes: &mut Bytes<'_>, total_length: usize) -> BoxedResult<DecodedDictionary> {
     let mut dictionary: HashMap<Vec<u8>, BencodeDecodedValue> = HashMap::new();
+    let mut spans = HashMap::new();
     loop {
-        let next_item = decode_and_consume_iterator(bytes)?;
+        let (next_item, _) = decode_and_consume_iterator(bytes, total_length)?;
         match next_item {
             BencodeDecodedValue::End => break,
             BencodeDecodedValue::String(key) => {
-                dictionary.insert(key, decode_and_consume_iterator(bytes)?);
+                let (value, span) = decode_and_consume_iterator(bytes, total_length)?;
+                dictionary.insert(key.clone(), value);
+                spans.insert(key, span);
             }
             invalid_key => {
                 return Err(BencodeDecoderError(format!(
@@ -172,13 +230,25 @@ fn read_dictionary(
             }
         }
     }
-    Ok(dictionary)
+    Ok((dictionary, spans))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn spans_cover_nested_values_as_written() {
+        let bytes = b"d1:bli-0e3:abce1:ai1ee";
+        let (_, span) = decode_with_spans(bytes).unwrap();
+        assert_eq!(span.range, 0..bytes.len());
+        let list = span.get(b"b").unwrap();
+        assert_eq!(&bytes[list.range.clone()], b"li-0e3:abce");
+        assert_eq!(&bytes[list.item(1).unwrap().range.clone()], b"3:abc");
+        assert_eq!(&bytes[span.get(b"a").unwrap().range.clone()], b"i1e");
+        assert_eq!(span.get(b"c"), None);
+    }
+
     #[test]
     fn decodes_positive_number() {
         assert_eq!(decode(b"i123e").unwrap(), BencodeDecodedValue::Integer(123));
@@ -3,7 +3,7 @@ mod encoder;
 mod errors;
 mod types;
 
-pub use decoder::decode;
+pub use decoder::{decode, decode_with_spans};
 pub use encoder::encode;
 pub use errors::BencodeDecoderError;
-pub use types::BencodeDecodedValue;
+pub use types::{BencodeDecodedValue, BencodeSpan};
@@ -1,5 +1,6 @@
 use super::errors::*;
 use std::collections::HashMap;
+use std::ops::Range;
 #[derive(Debug, Clone, PartialEq, Eq)]
 /// The type that is returned by the decoder
 /// and is used to represent the decoded bencode value
@@ -61,3 +62,36 @@ impl BencodeDecodedValue {
         }
     }
 }
+
+/// Where a decoded value was in the bencoded bytes and, for lists and dictionaries,
+/// where each of the values inside it was
+///
+/// ## Example
+/// ```
+/// use bittorrent_rustico::bencode::decode_with_spans;
+/// let bytes = b"d4:infod1:xi1ee1:yi2ee";
+/// let (_, span) = decode_with_spans(bytes).unwrap();
+///
+/// assert_eq!(&bytes[span.get(b"info").unwrap().range.clone()], b"d1:xi1ee");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BencodeSpan {
+    /// the bytes of the value, from its first token to its last one included
+    pub range: Range<usize>,
+    /// spans of the items of a list, in order
+    pub items: Vec<BencodeSpan>,
+    /// spans of the values of a dictionary, by their key
+    pub entries: HashMap<Vec<u8>, BencodeSpan>,
+}
+
+impl BencodeSpan {
+    /// Span of the value with the given key, if this is a dictionary holding it
+    pub fn get(&self, key: &[u8]) -> Option<&BencodeSpan> {
+        self.entries.get(key)
+    }
+
+    /// Span of the item at the given index, if this is a list that long
+    pub fn item(&self, index: usize) -> Option<&BencodeSpan> {
+        self.items.get(index)
+    }
+}
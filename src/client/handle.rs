@@ -0,0 +1,281 @@
+use crate::bandwidth::TorrentBandwidth;
+use crate::metainfo::Metainfo;
+use crate::peer::Choker;
+use crate::piece_manager::{piece_priorities, DownloadMode, FilePriority, PieceManagerSender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// Whether a torrent is transferring data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TorrentState {
+    Running,
+    /// Every wanted piece was downloaded, the torrent only uploads until a skipped file is wanted
+    Seeding,
+    /// The peer connections stay open, but no piece is asked for nor uploaded
+    Paused,
+    /// The peer connections were closed and the torrent won't run again
+    Stopped,
+}
+
+/// Controls how a running torrent downloads, it can be cloned and used from any thread.
+/// Settings changed before the client runs apply from the start
+#[derive(Clone)]
+pub struct TorrentHandle {
+    piece_manager: PieceManagerSender,
+    metainfo: Metainfo,
+    choker: Choker,
+    bandwidth: TorrentBandwidth,
+    state: Arc<(Mutex<HandleState>, Condvar)>,
+}
+
+// the state a paused torrent goes back to depends on whether it had finished downloading
+struct HandleState {
+    current: TorrentState,
+    downloaded: bool,
+}
+
+impl TorrentHandle {
+    pub fn new(
+        piece_manager: PieceManagerSender,
+        metainfo: Metainfo,
+        choker: Choker,
+        bandwidth: TorrentBandwidth,
+    ) -> Self {
+        Self {
+            piece_manager,
+            metainfo,
+            choker,
+            bandwidth,
+            state: Arc::new((
+                Mutex::new(HandleState {
+                    current: TorrentState::Running,
+                    downloaded: false,
+                }),
+                Condvar::new(),
+            )),
+        }
+    }
+
+    pub fn set_download_mode(&self, mode: DownloadMode) {
+        self.piece_manager.set_download_mode(mode);
+    }
+
+    /// Moves the streaming window to the piece being played
+    pub fn set_playback_position(&self, piece_index: u32) {
+        self.piece_manager.set_playback_position(piece_index);
+    }
+
+    /// Sets the priority of each file of the torrent, in the order of `Info.files`.
+    /// Files left out keep a normal priority. Once the wanted files are downloaded the torrent seeds,
+    /// and goes back to downloading if a skipped file is wanted
+    pub fn set_file_priorities(&self, priorities: &[FilePriority]) {
+        self.piece_manager
+            .set_piece_priorities(piece_priorities(&self.metainfo, priorities));
+    }
+
+    /// Changes the limits of the torrent in bytes per second, `None` means unlimited.
+    /// The client wide limits still apply
+    pub fn set_rate_limits(&self, max_upload_rate: Option<u64>, max_download_rate: Option<u64>) {
+        self.bandwidth
+            .torrent
+            .set_limits(max_upload_rate, max_download_rate);
+    }
+
+    pub fn state(&self) -> TorrentState {
+        let (state, _) = &*self.state;
+        state
+            .lock()
+            .map(|state| state.current)
+            .unwrap_or(TorrentState::Stopped)
+    }
+
+    /// Called once every wanted piece is downloaded, a running torrent goes on seeding
+    pub fn set_seeding(&self) {
+        let (state, _) = &*self.state;
+        if let Ok(mut state) = state.lock() {
+            state.downloaded = true;
+            if state.current == TorrentState::Running {
+                state.current = TorrentState::Seeding;
+            }
+        }
+    }
+
+    /// Called when there are pieces to download again, a seeding torrent goes back to running
+    pub fn set_downloading(&self) {
+        let (state, _) = &*self.state;
+        if let Ok(mut state) = state.lock() {
+            state.downloaded = false;
+            if state.current == TorrentState::Seeding {
+                state.current = TorrentState::Running;
+            }
+        }
+    }
+
+    /// Stops asking for pieces and uploading until `resume` is called.
+    /// Pieces already asked for still arrive and are saved
+    pub fn pause(&self) {
+        if self.change_state(|state| match state.current {
+            TorrentState::Running | TorrentState::Seeding => Some(TorrentState::Paused),
+            _ => None,
+        }) {
+            self.choker.pause();
+            self.piece_manager.pause();
+        }
+    }
+
+    pub fn resume(&self) {
+        if self.change_state(|state| match state.current {
+            TorrentState::Paused if state.downloaded => Some(TorrentState::Seeding),
+            TorrentState::Paused => Some(TorrentState::Running),
+            _ => None,
+        }) {
+            self.choker.resume();
+            self.piece_manager.resume();
+        }
+    }
+
+    /// Closes the peer connections, saves the resume data and lets the client finish running.
+    /// Does nothing if it was already stopped
+    pub fn stop(&self) {
+        let (state, stopped) = &*self.state;
+        if let Ok(mut state) = state.lock() {
+            if state.current == TorrentState::Stopped {
+                return;
+            }
+            state.current = TorrentState::Stopped;
+            stopped.notify_all();
+        }
+        self.choker.pause();
+        self.piece_manager.stop();
+    }
+
+    /// Blocks until `stop` is called from some other thread
+    pub fn wait_until_stopped(&self) {
+        let (state, stopped) = &*self.state;
+        if let Ok(state) = state.lock() {
+            let _stopped_state =
+                stopped.wait_while(state, |state| state.current != TorrentState::Stopped);
+        }
+    }
+
+    /// Blocks until `stop` is called or the timeout passes, returns whether it was stopped
+    pub fn wait_until_stopped_for(&self, timeout: Duration) -> bool {
+        let (state, stopped) = &*self.state;
+        match state.lock() {
+            Ok(state) => stopped
+                .wait_timeout_while(state, timeout, |state| {
+                    state.current != TorrentState::Stopped
+                })
+                .map(|(state, _)| state.current == TorrentState::Stopped)
+                .unwrap_or(true),
+            Err(_) => true,
+        }
+    }
+
+    // moves to the state `next` returns for the current one, if any. Returns whether it changed
+    fn change_state(&self, next: impl FnOnce(&HandleState) -> Option<TorrentState>) -> bool {
+        let (state, _) = &*self.state;
+        let Ok(mut state) = state.lock() else {
+            return false;
+        };
+        match next(&state) {
+            Some(next) => {
+                state.current = next;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metainfo::Info;
+    use crate::piece_manager::PieceManagerMessage;
+    use std::sync::mpsc;
+
+    fn handle() -> (TorrentHandle, mpsc::Receiver<PieceManagerMessage>) {
+        let (sender, receiver) = mpsc::channel();
+        let metainfo = Metainfo {
+            announce: "".to_string(),
+            info_hash: vec![],
+            info: Info {
+                piece_length: 1,
+                pieces: vec![],
+                name: "handle".to_string(),
+                length: 0,
+                files: None,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let handle = TorrentHandle::new(
+            PieceManagerSender { sender },
+            metainfo,
+            Choker::new(1),
+            TorrentBandwidth::unlimited(),
+        );
+        (handle, receiver)
+    }
+
+    #[test]
+    fn pausing_stops_uploads_until_resumed() {
+        let (handle, receiver) = handle();
+
+        handle.pause();
+        handle.pause();
+        assert_eq!(handle.state(), TorrentState::Paused);
+        assert!(handle.choker.is_paused());
+
+        handle.resume();
+        assert_eq!(handle.state(), TorrentState::Running);
+        assert!(!handle.choker.is_paused());
+
+        let messages: Vec<_> = receiver.try_iter().collect();
+        assert!(matches!(
+            messages[..],
+            [PieceManagerMessage::Pause, PieceManagerMessage::Resume]
+        ));
+    }
+
+    #[test]
+    fn a_downloaded_torrent_goes_back_to_seeding_when_resumed() {
+        let (handle, _receiver) = handle();
+
+        handle.set_seeding();
+        assert_eq!(handle.state(), TorrentState::Seeding);
+        handle.pause();
+        handle.resume();
+
+        assert_eq!(handle.state(), TorrentState::Seeding);
+        assert!(!handle.wait_until_stopped_for(Duration::from_millis(1)));
+    }
+
+    #[test]
+    fn a_seeding_torrent_runs_again_when_there_are_pieces_to_download() {
+        let (handle, _receiver) = handle();
+        handle.set_seeding();
+        handle.set_downloading();
+        assert_eq!(handle.state(), TorrentState::Running);
+
+        // once paused it goes back to downloading, not seeding
+        handle.pause();
+        handle.resume();
+        assert_eq!(handle.state(), TorrentState::Running);
+    }
+
+    #[test]
+    fn stopping_wakes_up_whoever_waits_for_it() {
+        let (handle, receiver) = handle();
+        let waiting = handle.clone();
+        let waiter = std::thread::spawn(move || waiting.wait_until_stopped());
+
+        handle.stop();
+        handle.resume();
+        waiter.join().unwrap();
+
+        assert_eq!(handle.state(), TorrentState::Stopped);
+        assert_eq!(receiver.try_iter().count(), 1);
+    }
+}
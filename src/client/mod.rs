@@ -1,9 +1,15 @@
 mod constants;
+mod handle;
 mod info;
+mod seeding;
+mod stats;
 mod torrent_client;
 mod utils;
 
 pub use constants::*;
+pub use handle::{TorrentHandle, TorrentState};
 pub use info::ClientInfo;
+pub use seeding::{Seeder, SeedingLimit, SeedingLimits};
+pub use stats::TorrentStats;
 pub use torrent_client::*;
 pub use utils::*;
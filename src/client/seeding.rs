@@ -0,0 +1,218 @@
+use super::handle::{TorrentHandle, TorrentState};
+use crate::bandwidth::TorrentBandwidth;
+use crate::config::Config;
+use crate::logger::CustomLogger;
+use crate::storage::{ResumeWriter, RESUME_SAVE_INTERVAL};
+use crate::ui::UIMessageSender;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+const LOGGER: CustomLogger = CustomLogger::init("Seeding");
+
+// how often the limits are checked while seeding
+const SEEDING_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The limit that made a completed torrent stop seeding
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SeedingLimit {
+    /// uploaded as many times the size of the torrent, adding up every session
+    Ratio(f64),
+    /// seeded for this long
+    Time(Duration),
+    /// nothing was uploaded for this long
+    IdleTime(Duration),
+}
+
+impl fmt::Display for SeedingLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SeedingLimit::Ratio(ratio) => write!(f, "share ratio {:.2} reached", ratio),
+            SeedingLimit::Time(time) => write!(f, "seeded for {} minutes", time.as_secs() / 60),
+            SeedingLimit::IdleTime(time) => {
+                write!(f, "idle for {} minutes", time.as_secs() / 60)
+            }
+        }
+    }
+}
+
+/// When a completed torrent stops seeding, it seeds until stopped if every limit is None
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SeedingLimits {
+    pub ratio: Option<f64>,
+    pub time: Option<Duration>,
+    pub idle_time: Option<Duration>,
+}
+
+impl SeedingLimits {
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            ratio: config.seed_ratio,
+            time: config.seed_time,
+            idle_time: config.seed_idle_time,
+        }
+    }
+
+    /// The first limit reached by a torrent with the given share ratio, seeding and idle time
+    pub fn reached(
+        &self,
+        ratio: f64,
+        seeding_for: Duration,
+        idle_for: Duration,
+    ) -> Option<SeedingLimit> {
+        if let Some(limit) = self.ratio.filter(|limit| ratio >= *limit) {
+            return Some(SeedingLimit::Ratio(limit));
+        }
+        if let Some(limit) = self.time.filter(|limit| seeding_for >= *limit) {
+            return Some(SeedingLimit::Time(limit));
+        }
+        self.idle_time
+            .filter(|limit| idle_for >= *limit)
+            .map(SeedingLimit::IdleTime)
+    }
+}
+
+/// Keeps a completed torrent seeding until it is stopped or one of its limits is reached.
+/// It runs along with the download, time only counts while seeding: not while paused,
+/// nor while downloading, before the wanted files are complete or once a skipped file is wanted
+pub struct Seeder {
+    handle: TorrentHandle,
+    limits: SeedingLimits,
+    bandwidth: TorrentBandwidth,
+    // bytes uploaded in previous sessions, they count towards the ratio
+    uploaded_before: u64,
+    torrent_length: u64,
+    ui_message_sender: UIMessageSender,
+    // the piece saver is gone once downloaded, the bytes uploaded while seeding are saved from here
+    resume: Option<ResumeWriter>,
+}
+
+impl Seeder {
+    pub fn new(
+        handle: TorrentHandle,
+        limits: SeedingLimits,
+        bandwidth: TorrentBandwidth,
+        uploaded_before: u64,
+        torrent_length: u64,
+        ui_message_sender: UIMessageSender,
+    ) -> Self {
+        Self {
+            handle,
+            limits,
+            bandwidth,
+            uploaded_before,
+            torrent_length,
+            ui_message_sender,
+            resume: None,
+        }
+    }
+
+    /// Writes the resume file every `RESUME_SAVE_INTERVAL` while seeding, and once seeding ends
+    pub fn with_resume_writer(mut self, resume: ResumeWriter) -> Self {
+        self.resume = Some(resume);
+        self
+    }
+
+    /// Blocks until the torrent is stopped, stopping it if a limit is reached while seeding.
+    /// Returns the limit reached, if any
+    pub fn run(self) -> Option<SeedingLimit> {
+        let limit = self.seed();
+        self.save_resume_data();
+        limit
+    }
+
+    fn seed(&self) -> Option<SeedingLimit> {
+        let mut seeding = false;
+        let mut seeding_for = Duration::ZERO;
+        let mut idle_for = Duration::ZERO;
+        let mut last_uploaded = self.bandwidth.uploaded();
+        let mut last_check = Instant::now();
+        let mut last_resume_save = Instant::now();
+        while !self.handle.wait_until_stopped_for(SEEDING_CHECK_INTERVAL) {
+            let elapsed = last_check.elapsed();
+            last_check = Instant::now();
+            if last_resume_save.elapsed() >= RESUME_SAVE_INTERVAL {
+                self.save_resume_data();
+                last_resume_save = Instant::now();
+            }
+            let state = self.handle.state();
+            if state != TorrentState::Paused && seeding != (state == TorrentState::Seeding) {
+                seeding = state == TorrentState::Seeding;
+                if seeding {
+                    self.ui_message_sender.send_torrent_state("Seeding");
+                    LOGGER.info_str("Seeding until a limit is reached or the torrent is stopped");
+                }
+            }
+            if state != TorrentState::Seeding {
+                continue;
+            }
+
+            let uploaded = self.bandwidth.uploaded();
+            seeding_for += elapsed;
+            idle_for = if uploaded > last_uploaded {
+                Duration::ZERO
+            } else {
+                idle_for + elapsed
+            };
+            last_uploaded = uploaded;
+
+            if let Some(limit) = self.limits.reached(self.ratio(), seeding_for, idle_for) {
+                LOGGER.info(format!("Stopped seeding: {}", limit));
+                self.ui_message_sender
+                    .send_torrent_state(&format!("Finished, {}", limit));
+                self.handle.stop();
+                return Some(limit);
+            }
+        }
+        self.ui_message_sender.send_torrent_state("Stopped");
+        None
+    }
+
+    fn save_resume_data(&self) {
+        if let Some(Err(err)) = self.resume.as_ref().map(ResumeWriter::save) {
+            LOGGER.error(format!("Couldn't save resume data: {}", err));
+        }
+    }
+
+    fn ratio(&self) -> f64 {
+        if self.torrent_length == 0 {
+            return 0.0;
+        }
+        (self.uploaded_before + self.bandwidth.uploaded()) as f64 / self.torrent_length as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn without_limits_seeding_never_ends() {
+        let limits = SeedingLimits::default();
+        assert_eq!(limits.reached(100.0, MINUTE * 1000, MINUTE * 1000), None);
+    }
+
+    #[test]
+    fn the_first_limit_reached_is_returned() {
+        let limits = SeedingLimits {
+            ratio: Some(2.0),
+            time: Some(MINUTE * 60),
+            idle_time: Some(MINUTE * 10),
+        };
+
+        assert_eq!(limits.reached(1.5, MINUTE * 30, MINUTE * 5), None);
+        assert_eq!(
+            limits.reached(2.0, MINUTE * 30, MINUTE * 5),
+            Some(SeedingLimit::Ratio(2.0))
+        );
+        assert_eq!(
+            limits.reached(1.5, MINUTE * 60, MINUTE * 5),
+            Some(SeedingLimit::Time(MINUTE * 60))
+        );
+        assert_eq!(
+            limits.reached(1.5, MINUTE * 30, MINUTE * 10),
+            Some(SeedingLimit::IdleTime(MINUTE * 10))
+        );
+    }
+}
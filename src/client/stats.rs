@@ -0,0 +1,67 @@
+use crate::bandwidth::TorrentBandwidth;
+use crate::storage::SharedStorage;
+use crate::ui::{PeerStatistics, UIMessageSender};
+
+/// Reads how far a running torrent got and how fast it transfers,
+/// it can be cloned and used from any thread
+#[derive(Clone)]
+pub struct TorrentStats {
+    storage: SharedStorage,
+    bandwidth: TorrentBandwidth,
+    ui_message_sender: UIMessageSender,
+}
+
+impl TorrentStats {
+    pub fn new(
+        storage: SharedStorage,
+        bandwidth: TorrentBandwidth,
+        ui_message_sender: UIMessageSender,
+    ) -> Self {
+        Self {
+            storage,
+            bandwidth,
+            ui_message_sender,
+        }
+    }
+
+    pub fn completed_pieces(&self) -> u32 {
+        self.storage.completed_pieces().len() as u32
+    }
+
+    pub fn piece_count(&self) -> u32 {
+        self.storage.piece_count()
+    }
+
+    /// Fraction of the pieces stored, from 0 to 1
+    pub fn progress(&self) -> f64 {
+        match self.piece_count() {
+            0 => 1.0,
+            piece_count => self.completed_pieces() as f64 / piece_count as f64,
+        }
+    }
+
+    /// Bytes per second the torrent is downloading at, adding up every peer
+    pub fn download_rate(&self) -> f64 {
+        self.bandwidth.torrent.download.rate()
+    }
+
+    /// Bytes per second the torrent is uploading at, adding up every peer
+    pub fn upload_rate(&self) -> f64 {
+        self.bandwidth.torrent.upload.rate()
+    }
+
+    /// Bytes downloaded since the torrent started running
+    pub fn downloaded(&self) -> u64 {
+        self.bandwidth.downloaded()
+    }
+
+    /// Bytes uploaded since the torrent started running
+    pub fn uploaded(&self) -> u64 {
+        self.bandwidth.uploaded()
+    }
+
+    /// The peers the client connected to, with their latest statistics
+    pub fn peers(&self) -> Vec<PeerStatistics> {
+        self.ui_message_sender.peer_statistics()
+    }
+}
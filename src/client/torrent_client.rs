@@ -1,13 +1,18 @@
-use super::ClientInfo;
+use super::{ClientInfo, TorrentHandle};
 use crate::application_errors::ApplicationError;
-use crate::download_manager;
+use crate::bandwidth::TorrentBandwidth;
+use crate::encryption::EncryptionPolicy;
+use crate::peer::{Choker, ConnectionLimit, PeerSource};
 use crate::peer_connection_manager::*;
 use crate::piece_manager::*;
 use crate::piece_saver::*;
+use crate::storage::{ResumeWriter, SharedStorage};
 use crate::tracker::Event;
 use crate::tracker::ITrackerService;
 use crate::ui::UIMessageSender;
+use crate::utp::Transport;
 use log::*;
+use std::sync::mpsc;
 use std::thread::JoinHandle;
 
 pub struct ClientHandles {
@@ -29,6 +34,8 @@ struct ClientWorkers {
 pub struct TorrentClient {
     senders: ClientSenders,
     workers: ClientWorkers,
+    storage: SharedStorage,
+    handle: TorrentHandle,
 }
 
 impl TorrentClient {
@@ -36,6 +43,10 @@ impl TorrentClient {
         client_info: &ClientInfo,
         ui_message_sender: UIMessageSender,
         initial_pieces: Vec<u32>,
+        storage: SharedStorage,
+        resume: ResumeWriter,
+        choker: Choker,
+        bandwidth: TorrentBandwidth,
     ) -> Result<Self, ApplicationError> {
         let (piece_manager_sender, piece_manager_worker) =
             Self::init_piece_manager(client_info, ui_message_sender.clone(), initial_pieces);
@@ -43,15 +54,22 @@ impl TorrentClient {
         let (piece_saver_sender, piece_saver_worker) = Self::init_piece_saver(
             piece_manager_sender.clone(),
             client_info,
+            storage.clone(),
+            resume,
             ui_message_sender.clone(),
         );
 
         let (peer_connection_manager_sender, peer_connection_manager_worker) =
             Self::init_peer_connection_manager(
-                piece_manager_sender,
+                piece_manager_sender.clone(),
                 piece_saver_sender,
                 client_info,
-                ui_message_sender,
+                ConnectionContext::new(
+                    storage.clone(),
+                    choker.clone(),
+                    bandwidth.clone(),
+                    ui_message_sender,
+                ),
             );
 
         Ok(TorrentClient {
@@ -63,14 +81,63 @@ impl TorrentClient {
                 piece_saver: piece_saver_worker,
                 peer_connection_manager: peer_connection_manager_worker,
             },
+            storage,
+            handle: TorrentHandle::new(
+                piece_manager_sender,
+                client_info.metainfo.clone(),
+                choker,
+                bandwidth,
+            ),
         })
     }
 
+    /// Counts the connections the client opens against `connections`, no more are opened once it is reached.
+    /// Unlimited otherwise
+    pub fn with_connection_limit(mut self, connections: ConnectionLimit) -> Self {
+        self.workers.peer_connection_manager.connections = connections;
+        self
+    }
+
+    /// Keeps at most `max_peers` connections of the torrent open, on top of the session's cap. Unlimited if None
+    pub fn with_peer_limit(mut self, max_peers: Option<usize>) -> Self {
+        self.workers.peer_connection_manager.max_peers = max_peers;
+        self
+    }
+
+    /// Encrypts the connections the client opens as the policy says. Plaintext otherwise
+    pub fn with_encryption(mut self, encryption: EncryptionPolicy) -> Self {
+        self.workers.peer_connection_manager.context.encryption = encryption;
+        self
+    }
+
+    /// Tries uTP before TCP for the connections the client opens if the transport says so. TCP otherwise
+    pub fn with_transport(mut self, transport: Transport) -> Self {
+        self.workers.peer_connection_manager.context.transport = transport;
+        self
+    }
+
+    /// Hands peers found outside the trackers to the torrent, while it downloads
+    pub fn peer_connection_manager_sender(&self) -> PeerConnectionManagerSender {
+        self.senders.peer_connection_manager.clone()
+    }
+
+    /// Handle to pause, resume or stop the torrent, and to change how it downloads while it runs
+    pub fn handle(&self) -> TorrentHandle {
+        self.handle.clone()
+    }
+
+    /// Downloads the wanted pieces, announcing the torrent as completed and moving it to seeding
+    /// once they are all downloaded. Returns once the torrent is stopped, or completely downloaded
     pub fn run(
         mut self,
         client_info: ClientInfo,
         tracker_service: &mut (impl ITrackerService + Send + 'static),
     ) -> Result<(), ApplicationError> {
+        let was_complete = is_complete(&self.storage);
+        let storage = self.storage.clone();
+        let handle = self.handle.clone();
+        let (progress_sender, progress) = mpsc::channel();
+        self.workers.piece_manager.progress = Some(progress_sender);
         let piece_saver_handle = std::thread::spawn(move || {
             self.workers.piece_saver.listen().unwrap();
         });
@@ -89,10 +156,14 @@ impl TorrentClient {
         let peer_connection_manager_sender_clone = self.senders.peer_connection_manager.clone();
         let mut tracker_service_clone = tracker_service.clone();
         let peer_connection_manager_handle = std::thread::spawn(move || {
-            self.workers.peer_connection_manager.start_peer_connections(
+            self.workers.peer_connection_manager.connect_to(
+                PeerSource::Tracker,
                 tracker_response.peers,
                 peer_connection_manager_sender_clone.clone(),
             );
+            self.workers
+                .peer_connection_manager
+                .connect_to_web_seeds(peer_connection_manager_sender_clone.clone());
             self.workers
                 .peer_connection_manager
                 .listen(
@@ -109,36 +180,28 @@ impl TorrentClient {
             peer_connection_manager: peer_connection_manager_handle,
         };
 
-        Self::wait_to_end(handles)?;
-
-        info!("About to join pieces into target file");
-
-        let download_path = format!(
-            "{}/{}",
-            client_info.config.download_path, client_info.metainfo.info.name
-        );
-
-        let target_name = format!(
-            "{}/target/{}",
-            download_path, client_info.metainfo.info.name
-        );
-
-        if !client_info.config.persist_pieces {
-            // delete file at target_name
-            let _ = std::fs::remove_file(target_name.clone());
+        // until the piece manager stops
+        let mut completed = was_complete;
+        for progress in progress {
+            match progress {
+                DownloadProgress::WantedPiecesDownloaded => {
+                    if !completed {
+                        let _ = tracker_service.announce(Some(Event::Completed));
+                        completed = true;
+                    }
+                    handle.set_seeding();
+                }
+                DownloadProgress::Downloading => handle.set_downloading(),
+            }
         }
 
-        if !std::path::Path::new(&target_name).exists() {
-            download_manager::make_target_file(
-                client_info.metainfo.get_piece_count(),
-                &client_info.metainfo.info.name,
-                &download_path,
-                client_info.config.persist_pieces,
-            )?;
-
-            let _ = tracker_service.announce(Some(Event::Completed));
-        }
+        Self::wait_to_end(handles)?;
 
+        info!(
+            "About to finish the target files of {}",
+            client_info.metainfo.info.name
+        );
+        storage.finish()?;
         Ok(())
     }
 
@@ -164,22 +227,23 @@ impl TorrentClient {
             client_info.metainfo.info.pieces.len() as u32,
             ui_message_sender,
             initial_pieces,
+            client_info.config.download_mode,
         )
     }
 
     fn init_piece_saver(
         piece_manager_sender: PieceManagerSender,
         client_info: &ClientInfo,
+        storage: SharedStorage,
+        resume: ResumeWriter,
         ui_message_sender: UIMessageSender,
     ) -> (PieceSaverSender, PieceSaverWorker) {
-        let donwload_path = format!(
-            "{}/{}",
-            client_info.config.download_path, client_info.metainfo.info.name
-        );
         new_piece_saver(
             piece_manager_sender,
             client_info.metainfo.info.pieces.clone(),
-            donwload_path,
+            client_info.metainfo.piece_hashes_v2(),
+            storage,
+            resume,
             ui_message_sender,
         )
     }
@@ -188,14 +252,19 @@ impl TorrentClient {
         piece_manager_sender: PieceManagerSender,
         piece_saver_sender: PieceSaverSender,
         client_info: &ClientInfo,
-        ui_message_sender: UIMessageSender,
+        context: ConnectionContext,
     ) -> (PeerConnectionManagerSender, PeerConnectionManagerWorker) {
         new_peer_connection_manager(
             piece_manager_sender,
             piece_saver_sender,
             &client_info.metainfo,
             &client_info.peer_id,
-            ui_message_sender,
+            context,
+            ConnectionLimit::unlimited(),
         )
     }
 }
+
+fn is_complete(storage: &SharedStorage) -> bool {
+    storage.completed_pieces().len() as u32 == storage.piece_count()
+}
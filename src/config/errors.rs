@@ -10,6 +10,20 @@ pub enum ConfigError {
     /// there is a key missing in the config file
     MissingKey(String),
     CreateDirectoryError,
+    /// the rate limit of the given key is not a valid amount of KiB per second
+    InvalidRate(String),
+    /// the storage kind is neither `file` nor `pieces`
+    InvalidStorage(String),
+    /// the download mode is neither `rarest`, `sequential` nor `streaming`
+    InvalidDownloadMode(String),
+    /// the seeding limit of the given key is not a positive ratio or amount of minutes
+    InvalidSeedLimit(String),
+    /// the session limit of the given key is not a positive amount
+    InvalidLimit(String),
+    /// the encryption policy is neither `disabled`, `prefer` nor `require`
+    InvalidEncryption(String),
+    /// the transport is neither `tcp` nor `utp`
+    InvalidTransport(String),
 }
 
 impl From<std::num::ParseIntError> for ConfigError {
@@ -30,6 +44,19 @@ impl std::fmt::Display for ConfigError {
             ConfigError::CreateDirectoryError => {
                 write!(f, "Could not create download directory")
             }
+            ConfigError::InvalidRate(key) => write!(f, "Invalid rate limit for key: {}", key),
+            ConfigError::InvalidStorage(kind) => write!(f, "Invalid storage kind: {}", kind),
+            ConfigError::InvalidDownloadMode(mode) => write!(f, "Invalid download mode: {}", mode),
+            ConfigError::InvalidSeedLimit(key) => {
+                write!(f, "Invalid seeding limit for key: {}", key)
+            }
+            ConfigError::InvalidLimit(key) => write!(f, "Invalid limit for key: {}", key),
+            ConfigError::InvalidEncryption(policy) => {
+                write!(f, "Invalid encryption policy: {}", policy)
+            }
+            ConfigError::InvalidTransport(transport) => {
+                write!(f, "Invalid transport: {}", transport)
+            }
         }
     }
 }
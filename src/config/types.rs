@@ -1,16 +1,40 @@
 use super::errors::ConfigError;
+use crate::bandwidth::limit_from_kib;
 use crate::download_manager;
+use crate::encryption::EncryptionPolicy;
+use crate::storage::StorageKind;
+use crate::utp::Transport;
 use std::collections::HashMap;
 use std::env;
 use std::fs;
 use std::path;
 use std::str;
+use std::time::Duration;
 const LISTEN_PORT: &str = "listen_port";
 const LOG_PATH: &str = "log_path";
 const DOWNLOAD_PATH: &str = "download_path";
 const SEPARATOR: &str = "=";
 const PERSIST_PIECES: &str = "persist_pieces";
+const MAX_UPLOAD_RATE: &str = "max_upload_rate";
+const MAX_DOWNLOAD_RATE: &str = "max_download_rate";
+const TORRENT_MAX_UPLOAD_RATE: &str = "torrent_max_upload_rate";
+const TORRENT_MAX_DOWNLOAD_RATE: &str = "torrent_max_download_rate";
+const PEER_MAX_UPLOAD_RATE: &str = "peer_max_upload_rate";
+const PEER_MAX_DOWNLOAD_RATE: &str = "peer_max_download_rate";
+const STORAGE: &str = "storage";
+const DOWNLOAD_MODE: &str = "download_mode";
+const SEED_RATIO: &str = "seed_ratio";
+const SEED_TIME: &str = "seed_time";
+const SEED_IDLE_TIME: &str = "seed_idle_time";
+const MAX_CONNECTIONS: &str = "max_connections";
+const MAX_CONNECTIONS_PER_TORRENT: &str = "max_connections_per_torrent";
+const MAX_ACTIVE_DOWNLOADS: &str = "max_active_downloads";
+const RPC_PORT: &str = "rpc_port";
+const WATCH_DIR: &str = "watch_dir";
+const ENCRYPTION: &str = "encryption";
+const TRANSPORT: &str = "transport";
 use crate::logger::CustomLogger;
+use crate::piece_manager::DownloadMode;
 
 const LOGGER: CustomLogger = CustomLogger::init("Config");
 
@@ -23,8 +47,44 @@ pub struct Config {
     pub log_path: String,
     /// file path where the downloaded file will be located at
     pub download_path: String,
-    /// whether to persist pieces in the disk or delete them after download
+    /// whether to persist pieces in the disk or delete them after download, only used by the pieces storage
     pub persist_pieces: bool,
+    /// how the downloaded pieces are kept in disk, a preallocated target file unless told otherwise
+    pub storage: StorageKind,
+    /// bytes per second the client uploads at most, adding up every torrent. None if unlimited
+    pub max_upload_rate: Option<u64>,
+    /// bytes per second the client downloads at most, adding up every torrent. None if unlimited
+    pub max_download_rate: Option<u64>,
+    /// bytes per second each torrent uploads at most. None if unlimited
+    pub torrent_max_upload_rate: Option<u64>,
+    /// bytes per second each torrent downloads at most. None if unlimited
+    pub torrent_max_download_rate: Option<u64>,
+    /// bytes per second uploaded to each peer at most. None if unlimited
+    pub peer_max_upload_rate: Option<u64>,
+    /// bytes per second downloaded from each peer at most. None if unlimited
+    pub peer_max_download_rate: Option<u64>,
+    /// order the pieces are downloaded in, rarest first unless told otherwise
+    pub download_mode: DownloadMode,
+    /// uploaded to downloaded ratio after which a completed torrent stops seeding. None if unlimited
+    pub seed_ratio: Option<f64>,
+    /// time a completed torrent seeds for at most. None if unlimited
+    pub seed_time: Option<Duration>,
+    /// time a completed torrent keeps seeding without uploading anything. None if unlimited
+    pub seed_idle_time: Option<Duration>,
+    /// peer connections open at most, adding up every torrent. None if unlimited
+    pub max_connections: Option<usize>,
+    /// peer connections each torrent opens at most. None if unlimited
+    pub max_connections_per_torrent: Option<usize>,
+    /// torrents downloading at the same time at most, the rest wait in a queue. None if unlimited
+    pub max_active_downloads: Option<usize>,
+    /// local TCP port where the JSON-RPC control API listens. None if disabled
+    pub rpc_port: Option<u16>,
+    /// directory polled for new torrent files to add to the session. None if disabled
+    pub watch_dir: Option<String>,
+    /// whether peer connections are encrypted: `disabled`, `prefer` or `require`
+    pub encryption: EncryptionPolicy,
+    /// how peer connections are opened: `tcp`, or `utp` falling back to TCP
+    pub transport: Transport,
 }
 
 impl Config {
@@ -93,9 +153,139 @@ fn create_config(config_dict: &HashMap<String, String>) -> Result<Config, Config
         log_path,
         download_path,
         persist_pieces: persist_pieces == "true",
+        storage: parse_storage(config_dict)?,
+        max_upload_rate: parse_rate(config_dict, MAX_UPLOAD_RATE)?,
+        max_download_rate: parse_rate(config_dict, MAX_DOWNLOAD_RATE)?,
+        torrent_max_upload_rate: parse_rate(config_dict, TORRENT_MAX_UPLOAD_RATE)?,
+        torrent_max_download_rate: parse_rate(config_dict, TORRENT_MAX_DOWNLOAD_RATE)?,
+        peer_max_upload_rate: parse_rate(config_dict, PEER_MAX_UPLOAD_RATE)?,
+        peer_max_download_rate: parse_rate(config_dict, PEER_MAX_DOWNLOAD_RATE)?,
+        download_mode: parse_download_mode(config_dict)?,
+        seed_ratio: parse_seed_ratio(config_dict)?,
+        seed_time: parse_minutes(config_dict, SEED_TIME)?,
+        seed_idle_time: parse_minutes(config_dict, SEED_IDLE_TIME)?,
+        max_connections: parse_count(config_dict, MAX_CONNECTIONS)?,
+        max_connections_per_torrent: parse_count(config_dict, MAX_CONNECTIONS_PER_TORRENT)?,
+        max_active_downloads: parse_count(config_dict, MAX_ACTIVE_DOWNLOADS)?,
+        rpc_port: config_dict
+            .get(RPC_PORT)
+            .map(|port| port.trim().parse())
+            .transpose()?,
+        watch_dir: config_dict
+            .get(WATCH_DIR)
+            .map(|dir| dir.trim().to_string())
+            .filter(|dir| !dir.is_empty()),
+        encryption: parse_encryption(config_dict)?,
+        transport: parse_transport(config_dict)?,
     })
 }
 
+// the storage is optional, the preallocated target file is used if it is missing
+fn parse_storage(config_dict: &HashMap<String, String>) -> Result<StorageKind, ConfigError> {
+    match config_dict.get(STORAGE) {
+        Some(kind) => {
+            StorageKind::from_name(kind).ok_or_else(|| ConfigError::InvalidStorage(kind.clone()))
+        }
+        None => Ok(StorageKind::default()),
+    }
+}
+
+// the download mode is optional, rarest-first is used if it is missing
+fn parse_download_mode(config_dict: &HashMap<String, String>) -> Result<DownloadMode, ConfigError> {
+    match config_dict.get(DOWNLOAD_MODE) {
+        Some(mode) => DownloadMode::from_name(mode)
+            .ok_or_else(|| ConfigError::InvalidDownloadMode(mode.clone())),
+        None => Ok(DownloadMode::default()),
+    }
+}
+
+// the encryption is optional, connections are plaintext if it is missing
+fn parse_encryption(
+    config_dict: &HashMap<String, String>,
+) -> Result<EncryptionPolicy, ConfigError> {
+    match config_dict.get(ENCRYPTION) {
+        Some(policy) => EncryptionPolicy::from_name(policy)
+            .ok_or_else(|| ConfigError::InvalidEncryption(policy.clone())),
+        None => Ok(EncryptionPolicy::default()),
+    }
+}
+
+// the transport is optional, connections are opened through TCP if it is missing
+fn parse_transport(config_dict: &HashMap<String, String>) -> Result<Transport, ConfigError> {
+    match config_dict.get(TRANSPORT) {
+        Some(transport) => Transport::from_name(transport)
+            .ok_or_else(|| ConfigError::InvalidTransport(transport.clone())),
+        None => Ok(Transport::default()),
+    }
+}
+
+// the seed ratio is optional, a missing key or zero mean seeding regardless of the ratio
+fn parse_seed_ratio(config_dict: &HashMap<String, String>) -> Result<Option<f64>, ConfigError> {
+    match config_dict.get(SEED_RATIO) {
+        Some(value) => {
+            let ratio = value
+                .trim()
+                .parse::<f64>()
+                .ok()
+                .filter(|ratio| ratio.is_finite() && *ratio >= 0.0)
+                .ok_or_else(|| ConfigError::InvalidSeedLimit(SEED_RATIO.to_string()))?;
+            Ok(Some(ratio).filter(|ratio| *ratio > 0.0))
+        }
+        None => Ok(None),
+    }
+}
+
+// seeding times are optional and written in minutes, a missing key or zero mean unlimited
+fn parse_minutes(
+    config_dict: &HashMap<String, String>,
+    key: &str,
+) -> Result<Option<Duration>, ConfigError> {
+    match config_dict.get(key) {
+        Some(value) => {
+            let minutes = value
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| ConfigError::InvalidSeedLimit(key.to_string()))?;
+            Ok(Some(Duration::from_secs(minutes * 60)).filter(|time| !time.is_zero()))
+        }
+        None => Ok(None),
+    }
+}
+
+// session limits are optional, a missing key or zero mean unlimited
+fn parse_count(
+    config_dict: &HashMap<String, String>,
+    key: &str,
+) -> Result<Option<usize>, ConfigError> {
+    match config_dict.get(key) {
+        Some(value) => {
+            let count = value
+                .trim()
+                .parse::<usize>()
+                .map_err(|_| ConfigError::InvalidLimit(key.to_string()))?;
+            Ok(Some(count).filter(|count| *count > 0))
+        }
+        None => Ok(None),
+    }
+}
+
+// rate limits are optional and written in KiB per second, a missing key or zero mean unlimited
+fn parse_rate(
+    config_dict: &HashMap<String, String>,
+    key: &str,
+) -> Result<Option<u64>, ConfigError> {
+    match config_dict.get(key) {
+        Some(value) => {
+            let kib_per_second = value
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| ConfigError::InvalidRate(key.to_string()))?;
+            Ok(limit_from_kib(kib_per_second))
+        }
+        None => Ok(None),
+    }
+}
+
 //validates that path point to valid directories
 fn validate_path(path: &str) -> Result<(), ConfigError> {
     if !path::Path::new(path).exists() {
@@ -127,6 +317,82 @@ mod test {
         assert_eq!(config.log_path, "src/config/test_files/");
         assert_eq!(config.download_path, "src/config/test_files/");
         assert_eq!(config.persist_pieces, true);
+        assert_eq!(config.max_upload_rate, None);
+        assert_eq!(config.storage, StorageKind::File);
+        assert_eq!(config.download_mode, DownloadMode::RarestFirst);
+    }
+
+    #[test]
+    fn parses_legacy_pieces_storage() {
+        let config = Config::from_path("src/config/test_files/pieces_storage_config.txt").unwrap();
+        assert_eq!(config.storage, StorageKind::Pieces);
+    }
+
+    #[test]
+    fn parses_streaming_download_mode() {
+        let config = Config::from_path("src/config/test_files/streaming_config.txt").unwrap();
+        assert_eq!(
+            config.download_mode,
+            DownloadMode::Streaming(Default::default())
+        );
+    }
+
+    #[test]
+    fn parses_seeding_limits() {
+        let config = Config::from_path("src/config/test_files/seeding_config.txt").unwrap();
+        assert_eq!(config.seed_ratio, Some(1.5));
+        assert_eq!(config.seed_time, Some(Duration::from_secs(90 * 60)));
+        assert_eq!(config.seed_idle_time, None);
+    }
+
+    #[test]
+    fn parses_session_limits() {
+        let config = Config::from_path("src/config/test_files/session_config.txt").unwrap();
+        assert_eq!(config.max_connections, Some(200));
+        assert_eq!(config.max_connections_per_torrent, Some(50));
+        assert_eq!(config.max_active_downloads, None);
+        assert_eq!(config.rpc_port, Some(9091));
+    }
+
+    #[test]
+    fn parses_watch_dir() {
+        let config = Config::from_path("src/config/test_files/watch_config.txt").unwrap();
+        assert_eq!(config.watch_dir, Some("torrents/watch".to_string()));
+        let config = Config::from_path("src/config/test_files/correct_config.txt").unwrap();
+        assert_eq!(config.watch_dir, None);
+    }
+
+    #[test]
+    fn parses_encryption_policy() {
+        let config = Config::from_path("src/config/test_files/encryption_config.txt").unwrap();
+        assert_eq!(config.encryption, EncryptionPolicy::Require);
+        let config = Config::from_path("src/config/test_files/correct_config.txt").unwrap();
+        assert_eq!(config.encryption, EncryptionPolicy::Disabled);
+    }
+
+    #[test]
+    fn parses_transport() {
+        let config = Config::from_path("src/config/test_files/utp_config.txt").unwrap();
+        assert_eq!(config.transport, Transport::Utp);
+        let config = Config::from_path("src/config/test_files/correct_config.txt").unwrap();
+        assert_eq!(config.transport, Transport::Tcp);
+    }
+
+    #[test]
+    fn parses_rate_limits_in_kib_per_second() {
+        let config = Config::from_path("src/config/test_files/rate_limits_config.txt").unwrap();
+        assert_eq!(config.max_upload_rate, Some(100 * 1024));
+        assert_eq!(config.max_download_rate, None);
+        assert_eq!(config.peer_max_download_rate, Some(20 * 1024));
+    }
+
+    #[test]
+    fn throws_on_invalid_rate_limit() {
+        let config = Config::from_path("src/config/test_files/invalid_rate_config.txt");
+        assert_eq!(
+            config.unwrap_err(),
+            ConfigError::InvalidRate(MAX_UPLOAD_RATE.to_string())
+        );
     }
 
     #[test]
@@ -0,0 +1,27 @@
+/// Prime of the Diffie-Hellman key exchange, 768 bits written in hexadecimal
+pub const DH_PRIME: &str = "FFFFFFFFFFFFFFFFC90FDAA22168C234C4C6628B80DC1CD129024E088A67CC74020BBEA63B139B22514A08798E3404DDEF9519B3CD3A431B302B0A6DF25F14374FE1356D6D51C245E485B576625E7EC6F44C42E9A63A36210000000000090563";
+pub const DH_GENERATOR: u32 = 2;
+/// Bytes of the public keys and of the shared secret
+pub const KEY_LENGTH: usize = 96;
+/// Bytes of the private keys, 160 bits
+pub const PRIVATE_KEY_LENGTH: usize = 20;
+
+/// Verification constant, 8 zeros, sent encrypted so the other side can find where the stream starts
+pub const VC: [u8; 8] = [0; 8];
+/// Bits of crypto_provide and crypto_select
+pub const CRYPTO_PLAINTEXT: u32 = 0x01;
+pub const CRYPTO_RC4: u32 = 0x02;
+
+/// Random bytes sent after the keys and the negotiation at most
+pub const MAX_PAD_LENGTH: usize = 512;
+/// Bytes of the RC4 keystream thrown away before using it
+pub const RC4_DISCARD: usize = 1024;
+
+pub const REQ1: &[u8] = b"req1";
+pub const REQ2: &[u8] = b"req2";
+pub const REQ3: &[u8] = b"req3";
+pub const KEY_A: &[u8] = b"keyA";
+pub const KEY_B: &[u8] = b"keyB";
+
+/// How a plaintext connection starts, the length of the protocol name and the name itself
+pub const PLAINTEXT_HANDSHAKE_START: &[u8] = b"\x13BitTorrent protocol";
@@ -0,0 +1,60 @@
+use super::constants::*;
+use super::errors::EncryptionError;
+use num_bigint::BigUint;
+use rand::Rng;
+
+/// Diffie-Hellman keys of one side of a negotiation
+pub struct KeyPair {
+    private: BigUint,
+    /// the public key, as sent to the other side
+    pub public: Vec<u8>,
+}
+
+fn prime() -> BigUint {
+    BigUint::parse_bytes(DH_PRIME.as_bytes(), 16).expect("the prime is valid hexadecimal")
+}
+
+// big endian, padded with zeros to the length of the keys
+fn to_key_bytes(number: &BigUint) -> Vec<u8> {
+    let bytes = number.to_bytes_be();
+    let mut key = vec![0; KEY_LENGTH.saturating_sub(bytes.len())];
+    key.extend(bytes);
+    key
+}
+
+impl KeyPair {
+    pub fn generate() -> Self {
+        let private = BigUint::from_bytes_be(&rand::thread_rng().gen::<[u8; PRIVATE_KEY_LENGTH]>());
+        let public = BigUint::from(DH_GENERATOR).modpow(&private, &prime());
+        Self {
+            public: to_key_bytes(&public),
+            private,
+        }
+    }
+
+    /// The secret shared with the side whose public key is `other_public`
+    pub fn shared_secret(&self, other_public: &[u8]) -> Result<Vec<u8>, EncryptionError> {
+        let prime = prime();
+        let other = BigUint::from_bytes_be(other_public);
+        if other_public.len() != KEY_LENGTH || other <= BigUint::from(1u32) || other >= prime {
+            return Err(EncryptionError::InvalidKey);
+        }
+        Ok(to_key_bytes(&other.modpow(&self.private, &prime)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn both_sides_get_the_same_secret() {
+        let a = KeyPair::generate();
+        let b = KeyPair::generate();
+        assert_eq!(a.public.len(), KEY_LENGTH);
+        let secret = a.shared_secret(&b.public).unwrap();
+        assert_eq!(secret.len(), KEY_LENGTH);
+        assert_eq!(secret, b.shared_secret(&a.public).unwrap());
+        assert!(a.shared_secret(&[0; KEY_LENGTH]).is_err());
+    }
+}
@@ -0,0 +1,48 @@
+use std::fmt;
+
+#[derive(Debug)]
+/// Errors negotiating an encrypted connection
+pub enum EncryptionError {
+    IoError(std::io::Error),
+    /// the other side's key isn't a valid public key
+    InvalidKey,
+    /// the synchronization hash or the verification constant wasn't found where they can be
+    SynchronizationFailed,
+    /// the other side asked for a torrent that isn't served
+    UnknownTorrent,
+    /// the sides don't have a method in common, holds the ones offered or selected
+    NoCommonMethod(u32),
+    /// a plaintext connection arrived while encryption is required
+    PlaintextRefused,
+}
+
+impl From<std::io::Error> for EncryptionError {
+    fn from(error: std::io::Error) -> Self {
+        EncryptionError::IoError(error)
+    }
+}
+
+impl fmt::Display for EncryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EncryptionError::IoError(error) => write!(f, "IO error while negotiating: {}", error),
+            EncryptionError::InvalidKey => write!(f, "Invalid public key"),
+            EncryptionError::SynchronizationFailed => {
+                write!(f, "Couldn't find the start of the encrypted stream")
+            }
+            EncryptionError::UnknownTorrent => {
+                write!(f, "Negotiation for a torrent that isn't served")
+            }
+            EncryptionError::NoCommonMethod(provided) => {
+                write!(
+                    f,
+                    "No encryption method in common, offered: {:#x}",
+                    provided
+                )
+            }
+            EncryptionError::PlaintextRefused => {
+                write!(f, "Encryption is required, refused a plaintext connection")
+            }
+        }
+    }
+}
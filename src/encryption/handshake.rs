@@ -0,0 +1,419 @@
+use super::constants::*;
+use super::dh::KeyPair;
+use super::errors::EncryptionError;
+use super::rc4::Rc4;
+use super::types::EncryptionPolicy;
+use rand::Rng;
+use sha1::{Digest, Sha1};
+use std::io::{Read, Write};
+
+const SELECT_LENGTH: usize = 4;
+const LENGTH_PREFIX: usize = 2;
+const HASH_LENGTH: usize = 20;
+
+/// Ciphers of an encrypted connection, one for each direction
+pub struct CipherPair {
+    pub encrypt: Rc4,
+    pub decrypt: Rc4,
+}
+
+impl CipherPair {
+    /// The side that opened the connection encrypts with keyA and the other one with keyB.
+    /// `skey` is the info_hash of the torrent the connection is for
+    fn new(secret: &[u8], skey: &[u8], initiator: bool) -> Self {
+        let key_a = Rc4::discarding(&hash(&[KEY_A, secret, skey]), RC4_DISCARD);
+        let key_b = Rc4::discarding(&hash(&[KEY_B, secret, skey]), RC4_DISCARD);
+        if initiator {
+            Self {
+                encrypt: key_a,
+                decrypt: key_b,
+            }
+        } else {
+            Self {
+                encrypt: key_b,
+                decrypt: key_a,
+            }
+        }
+    }
+}
+
+fn hash(parts: &[&[u8]]) -> Vec<u8> {
+    let mut hasher = Sha1::new();
+    parts.iter().for_each(|part| hasher.update(part));
+    hasher.finalize().to_vec()
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b).map(|(a, b)| a ^ b).collect()
+}
+
+fn random_pad() -> Vec<u8> {
+    let mut rng = rand::thread_rng();
+    (0..rng.gen_range(0..=MAX_PAD_LENGTH))
+        .map(|_| rng.gen())
+        .collect()
+}
+
+fn length_prefixed(bytes: &[u8]) -> Vec<u8> {
+    [&(bytes.len() as u16).to_be_bytes(), bytes].concat()
+}
+
+fn be_u16(bytes: &[u8]) -> usize {
+    u16::from_be_bytes([bytes[0], bytes[1]]) as usize
+}
+
+fn be_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+/// Negotiates the encryption of a connection we opened for the torrent of `info_hash`.
+/// Returns the ciphers the rest of the connection goes through, or None if the other
+/// peer chose to go on in plaintext
+pub fn negotiate_outgoing<S: Read + Write + ?Sized>(
+    stream: &mut S,
+    info_hash: &[u8],
+    policy: EncryptionPolicy,
+) -> Result<Option<CipherPair>, EncryptionError> {
+    let keys = KeyPair::generate();
+    stream.write_all(&[keys.public.clone(), random_pad()].concat())?;
+    let mut other_public = vec![0; KEY_LENGTH];
+    stream.read_exact(&mut other_public)?;
+    let secret = keys.shared_secret(&other_public)?;
+    let mut ciphers = CipherPair::new(&secret, info_hash, true);
+
+    // no padding nor initial payload, the handshake is sent once the negotiation is done
+    let mut negotiation = [
+        &VC[..],
+        &policy.crypto_provide().to_be_bytes(),
+        &length_prefixed(&[]),
+        &length_prefixed(&[]),
+    ]
+    .concat();
+    ciphers.encrypt.apply(&mut negotiation);
+    stream.write_all(
+        &[
+            hash(&[REQ1, &secret]),
+            xor(&hash(&[REQ2, info_hash]), &hash(&[REQ3, &secret])),
+            negotiation,
+        ]
+        .concat(),
+    )?;
+
+    // the answer starts with the encrypted VC, after the other peer's padding
+    let mut encrypted_vc = VC;
+    ciphers.decrypt.clone().apply(&mut encrypted_vc);
+    let mut received = Vec::new();
+    while !received.ends_with(&encrypted_vc) {
+        if received.len() >= MAX_PAD_LENGTH + VC.len() {
+            return Err(EncryptionError::SynchronizationFailed);
+        }
+        let mut byte = [0u8];
+        stream.read_exact(&mut byte)?;
+        received.push(byte[0]);
+    }
+    ciphers.decrypt.apply(&mut VC.clone());
+
+    let mut answer = [0u8; SELECT_LENGTH + LENGTH_PREFIX];
+    stream.read_exact(&mut answer)?;
+    ciphers.decrypt.apply(&mut answer);
+    let mut pad = vec![0; be_u16(&answer[SELECT_LENGTH..])];
+    stream.read_exact(&mut pad)?;
+    ciphers.decrypt.apply(&mut pad);
+
+    match be_u32(&answer) {
+        CRYPTO_RC4 => Ok(Some(ciphers)),
+        CRYPTO_PLAINTEXT if policy.allows_plaintext() => Ok(None),
+        selected => Err(EncryptionError::NoCommonMethod(selected)),
+    }
+}
+
+/// A connection once its negotiation is done
+pub struct Negotiated {
+    /// The torrent the other peer asked for, None for plaintext connections,
+    /// whose handshake tells it
+    pub info_hash: Option<Vec<u8>>,
+    /// None if the connection goes on in plaintext
+    pub ciphers: Option<CipherPair>,
+    /// What the other peer sent after the negotiation, already decrypted
+    pub payload: Vec<u8>,
+}
+
+// what the negotiation waits for
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    PublicKey,
+    Synchronizing,
+    Identifying,
+    Provide,
+    // the padding and the length of the initial payload
+    Padding(usize),
+    InitialPayload(usize),
+}
+
+/// The negotiation of a connection someone else opened.
+/// It is fed the bytes as they arrive, so it can be driven by the reactor.
+/// Connections starting with a plaintext handshake are told apart, and accepted if the policy allows it
+pub struct InboundNegotiation {
+    policy: EncryptionPolicy,
+    keys: KeyPair,
+    step: Step,
+    received: Vec<u8>,
+    secret: Vec<u8>,
+    info_hash: Vec<u8>,
+    ciphers: Option<CipherPair>,
+    provided: u32,
+}
+
+impl InboundNegotiation {
+    pub fn new(policy: EncryptionPolicy) -> Self {
+        Self {
+            policy,
+            keys: KeyPair::generate(),
+            step: Step::PublicKey,
+            received: Vec::new(),
+            secret: Vec::new(),
+            info_hash: Vec::new(),
+            ciphers: None,
+            provided: 0,
+        }
+    }
+
+    /// Handles the bytes received, the peer may ask for any of `info_hashes`.
+    /// Returns what has to be sent back, and the connection once the negotiation is done
+    pub fn receive(
+        &mut self,
+        data: &[u8],
+        info_hashes: &[Vec<u8>],
+    ) -> Result<(Vec<u8>, Option<Negotiated>), EncryptionError> {
+        self.received.extend_from_slice(data);
+        let mut reply = Vec::new();
+        loop {
+            match self.step {
+                Step::PublicKey => {
+                    if self.received.len() < PLAINTEXT_HANDSHAKE_START.len() {
+                        break;
+                    }
+                    if self.received.starts_with(PLAINTEXT_HANDSHAKE_START) {
+                        if !self.policy.allows_plaintext() {
+                            return Err(EncryptionError::PlaintextRefused);
+                        }
+                        let negotiated = Negotiated {
+                            info_hash: None,
+                            ciphers: None,
+                            payload: std::mem::take(&mut self.received),
+                        };
+                        return Ok((reply, Some(negotiated)));
+                    }
+                    if self.received.len() < KEY_LENGTH {
+                        break;
+                    }
+                    let other_public: Vec<u8> = self.received.drain(..KEY_LENGTH).collect();
+                    self.secret = self.keys.shared_secret(&other_public)?;
+                    reply.extend(&self.keys.public);
+                    reply.extend(random_pad());
+                    self.step = Step::Synchronizing;
+                }
+                Step::Synchronizing => {
+                    let req1 = hash(&[REQ1, &self.secret]);
+                    match self.received.windows(HASH_LENGTH).position(|w| w == req1) {
+                        Some(position) => {
+                            self.received.drain(..position + HASH_LENGTH);
+                            self.step = Step::Identifying;
+                        }
+                        None if self.received.len() >= MAX_PAD_LENGTH + HASH_LENGTH => {
+                            return Err(EncryptionError::SynchronizationFailed)
+                        }
+                        None => break,
+                    }
+                }
+                Step::Identifying => {
+                    if self.received.len() < HASH_LENGTH {
+                        break;
+                    }
+                    let obfuscated: Vec<u8> = self.received.drain(..HASH_LENGTH).collect();
+                    let req3 = hash(&[REQ3, &self.secret]);
+                    let info_hash = info_hashes
+                        .iter()
+                        .find(|info_hash| xor(&hash(&[REQ2, info_hash]), &req3) == obfuscated)
+                        .ok_or(EncryptionError::UnknownTorrent)?;
+                    self.ciphers = Some(CipherPair::new(&self.secret, info_hash, false));
+                    self.info_hash = info_hash.clone();
+                    self.step = Step::Provide;
+                }
+                Step::Provide => {
+                    let Some(fields) =
+                        self.take_decrypted(VC.len() + SELECT_LENGTH + LENGTH_PREFIX)
+                    else {
+                        break;
+                    };
+                    if fields[..VC.len()] != VC {
+                        return Err(EncryptionError::SynchronizationFailed);
+                    }
+                    self.provided = be_u32(&fields[VC.len()..]);
+                    let pad_length = be_u16(&fields[VC.len() + SELECT_LENGTH..]);
+                    self.step = Step::Padding(pad_length + LENGTH_PREFIX);
+                }
+                Step::Padding(length) => {
+                    let Some(pad) = self.take_decrypted(length) else {
+                        break;
+                    };
+                    self.step = Step::InitialPayload(be_u16(&pad[length - LENGTH_PREFIX..]));
+                }
+                Step::InitialPayload(length) => {
+                    let Some(payload) = self.take_decrypted(length) else {
+                        break;
+                    };
+                    return self
+                        .finish(reply, payload)
+                        .map(|(reply, negotiated)| (reply, Some(negotiated)));
+                }
+            }
+        }
+        Ok((reply, None))
+    }
+
+    // the next `length` bytes received, decrypted, if they all arrived
+    fn take_decrypted(&mut self, length: usize) -> Option<Vec<u8>> {
+        if self.received.len() < length {
+            return None;
+        }
+        let mut bytes: Vec<u8> = self.received.drain(..length).collect();
+        if let Some(ciphers) = self.ciphers.as_mut() {
+            ciphers.decrypt.apply(&mut bytes);
+        }
+        Some(bytes)
+    }
+
+    // chooses the method and answers with it, what comes after the initial payload uses it
+    fn finish(
+        &mut self,
+        mut reply: Vec<u8>,
+        mut payload: Vec<u8>,
+    ) -> Result<(Vec<u8>, Negotiated), EncryptionError> {
+        let selected = self.policy.crypto_select(self.provided)?;
+        let mut ciphers = self
+            .ciphers
+            .take()
+            .ok_or(EncryptionError::SynchronizationFailed)?;
+        let mut answer = [
+            &VC[..],
+            &selected.to_be_bytes(),
+            &length_prefixed(&random_pad()),
+        ]
+        .concat();
+        ciphers.encrypt.apply(&mut answer);
+        reply.extend(answer);
+
+        let mut rest = std::mem::take(&mut self.received);
+        if selected == CRYPTO_RC4 {
+            ciphers.decrypt.apply(&mut rest);
+        }
+        payload.extend(rest);
+        let negotiated = Negotiated {
+            info_hash: Some(std::mem::take(&mut self.info_hash)),
+            ciphers: (selected == CRYPTO_RC4).then_some(ciphers),
+            payload,
+        };
+        Ok((reply, negotiated))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    // the torrent the other peer asked for, None in plaintext, and the payload read after the negotiation
+    type Accepted = Result<(Option<Vec<u8>>, Vec<u8>), EncryptionError>;
+
+    // accepts a connection and negotiates it, reading until `expected` bytes arrived after it
+    fn accept_one(
+        listener: TcpListener,
+        policy: EncryptionPolicy,
+        info_hashes: Vec<Vec<u8>>,
+        expected: usize,
+    ) -> thread::JoinHandle<Accepted> {
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept()?;
+            let mut negotiation = InboundNegotiation::new(policy);
+            let mut buffer = [0u8; 1024];
+            let mut negotiated = loop {
+                let read = stream.read(&mut buffer)?;
+                let (reply, negotiated) = negotiation.receive(&buffer[..read], &info_hashes)?;
+                stream.write_all(&reply)?;
+                if let Some(negotiated) = negotiated {
+                    break negotiated;
+                }
+            };
+            while negotiated.payload.len() < expected {
+                let read = stream.read(&mut buffer)?;
+                let mut data = buffer[..read].to_vec();
+                if let Some(ciphers) = negotiated.ciphers.as_mut() {
+                    ciphers.decrypt.apply(&mut data);
+                }
+                negotiated.payload.extend(data);
+            }
+            Ok((negotiated.info_hash, negotiated.payload))
+        })
+    }
+
+    #[test]
+    fn both_sides_agree_on_the_torrent_and_the_keys() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let info_hash = vec![7u8; 20];
+        let server = accept_one(
+            listener,
+            EncryptionPolicy::Require,
+            vec![vec![1u8; 20], info_hash.clone()],
+            5,
+        );
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        let mut ciphers = negotiate_outgoing(&mut stream, &info_hash, EncryptionPolicy::Prefer)
+            .unwrap()
+            .expect("RC4 is selected when both sides support it");
+        let mut message = b"hello".to_vec();
+        ciphers.encrypt.apply(&mut message);
+        stream.write_all(&message).unwrap();
+
+        let (asked_for, payload) = server.join().unwrap().unwrap();
+        assert_eq!(asked_for, Some(info_hash));
+        assert_eq!(payload, b"hello".to_vec());
+    }
+
+    #[test]
+    fn plaintext_handshakes_are_only_accepted_if_allowed() {
+        let mut handshake = PLAINTEXT_HANDSHAKE_START.to_vec();
+        handshake.extend([0u8; 48]);
+
+        let mut negotiation = InboundNegotiation::new(EncryptionPolicy::Prefer);
+        let (reply, negotiated) = negotiation.receive(&handshake[..10], &[]).unwrap();
+        assert!(reply.is_empty() && negotiated.is_none());
+        let (_, negotiated) = negotiation.receive(&handshake[10..], &[]).unwrap();
+        let negotiated = negotiated.unwrap();
+        assert!(negotiated.ciphers.is_none());
+        assert_eq!(negotiated.payload, handshake);
+
+        let mut negotiation = InboundNegotiation::new(EncryptionPolicy::Require);
+        assert!(matches!(
+            negotiation.receive(&handshake, &[]),
+            Err(EncryptionError::PlaintextRefused)
+        ));
+    }
+
+    #[test]
+    fn unknown_torrents_are_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = accept_one(listener, EncryptionPolicy::Prefer, vec![vec![1u8; 20]], 0);
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        let _ = negotiate_outgoing(&mut stream, &[2u8; 20], EncryptionPolicy::Prefer);
+        assert!(matches!(
+            server.join().unwrap(),
+            Err(EncryptionError::UnknownTorrent)
+        ));
+    }
+}
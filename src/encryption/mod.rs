@@ -0,0 +1,13 @@
+mod constants;
+mod dh;
+mod errors;
+mod handshake;
+mod rc4;
+mod types;
+
+pub use constants::*;
+pub use dh::KeyPair;
+pub use errors::EncryptionError;
+pub use handshake::{negotiate_outgoing, CipherPair, InboundNegotiation, Negotiated};
+pub use rc4::Rc4;
+pub use types::EncryptionPolicy;
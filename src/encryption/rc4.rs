@@ -0,0 +1,67 @@
+/// RC4 stream cipher, encrypting and decrypting are the same operation
+#[derive(Clone)]
+pub struct Rc4 {
+    state: [u8; 256],
+    i: u8,
+    j: u8,
+}
+
+impl Rc4 {
+    pub fn new(key: &[u8]) -> Self {
+        let mut state = [0u8; 256];
+        for (i, byte) in state.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let mut j: u8 = 0;
+        for i in 0..256 {
+            j = j.wrapping_add(state[i]).wrapping_add(key[i % key.len()]);
+            state.swap(i, j as usize);
+        }
+        Self { state, i: 0, j: 0 }
+    }
+
+    /// Cipher whose first `discarded` bytes of keystream were thrown away
+    pub fn discarding(key: &[u8], discarded: usize) -> Self {
+        let mut rc4 = Self::new(key);
+        rc4.apply(&mut vec![0; discarded]);
+        rc4
+    }
+
+    /// XORs the data with the next bytes of the keystream
+    pub fn apply(&mut self, data: &mut [u8]) {
+        for byte in data {
+            self.i = self.i.wrapping_add(1);
+            self.j = self.j.wrapping_add(self.state[self.i as usize]);
+            self.state.swap(self.i as usize, self.j as usize);
+            let k = self.state
+                [self.state[self.i as usize].wrapping_add(self.state[self.j as usize]) as usize];
+            *byte ^= k;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_the_known_keystream() {
+        let mut data = b"Plaintext".to_vec();
+        Rc4::new(b"Key").apply(&mut data);
+        assert_eq!(
+            data,
+            vec![0xBB, 0xF3, 0x16, 0xE8, 0xD9, 0x40, 0xAF, 0x0A, 0xD3]
+        );
+
+        let mut data = b"Attack at dawn".to_vec();
+        let mut rc4 = Rc4::new(b"Secret");
+        rc4.apply(&mut data[..5]);
+        rc4.apply(&mut data[5..]);
+        assert_eq!(
+            data,
+            vec![
+                0x45, 0xA0, 0x1F, 0x64, 0x5F, 0xC3, 0x5B, 0x38, 0x35, 0x52, 0x54, 0x4B, 0x9B, 0xF5
+            ]
+        );
+    }
+}
@@ -0,0 +1,49 @@
+use super::constants::*;
+use super::errors::EncryptionError;
+
+/// Whether peer connections are encrypted with Message Stream Encryption
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EncryptionPolicy {
+    /// only plaintext connections
+    #[default]
+    Disabled,
+    /// encrypted connections when the other peer supports them, plaintext otherwise
+    Prefer,
+    /// only encrypted connections
+    Require,
+}
+
+impl EncryptionPolicy {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim() {
+            "disabled" => Some(EncryptionPolicy::Disabled),
+            "prefer" => Some(EncryptionPolicy::Prefer),
+            "require" => Some(EncryptionPolicy::Require),
+            _ => None,
+        }
+    }
+
+    /// Methods offered when opening a connection
+    pub fn crypto_provide(&self) -> u32 {
+        match self {
+            EncryptionPolicy::Require => CRYPTO_RC4,
+            _ => CRYPTO_RC4 | CRYPTO_PLAINTEXT,
+        }
+    }
+
+    /// Method chosen among the ones the other side offered, RC4 if possible
+    pub fn crypto_select(&self, provided: u32) -> Result<u32, EncryptionError> {
+        if provided & CRYPTO_RC4 != 0 {
+            Ok(CRYPTO_RC4)
+        } else if provided & CRYPTO_PLAINTEXT != 0 && *self != EncryptionPolicy::Require {
+            Ok(CRYPTO_PLAINTEXT)
+        } else {
+            Err(EncryptionError::NoCommonMethod(provided))
+        }
+    }
+
+    /// Whether the other side may connect with a plain BitTorrent handshake
+    pub fn allows_plaintext(&self) -> bool {
+        *self != EncryptionPolicy::Require
+    }
+}
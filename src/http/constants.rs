@@ -3,3 +3,8 @@ pub const URN_SEPARATOR: &str = "://";
 pub const HOST_SEPARATOR: char = '/';
 pub const REQUEST_TIMEOUT: u64 = 100;
 pub const MAX_RETRIES: u8 = 3;
+pub const HTTP_PORT: u16 = 80;
+pub const HTTPS_PORT: u16 = 443;
+pub const PARTIAL_CONTENT_STATUS: u16 = 206;
+pub const READ_BUFFER_SIZE: usize = 16 * 1024;
+pub const MAX_HEAD_LENGTH: usize = 64 * 1024;
@@ -4,8 +4,10 @@ use super::types::IHttpService;
 use crate::boxed_result::BoxedResult;
 use log::*;
 use native_tls::{TlsConnector, TlsStream};
+use std::collections::HashMap;
 use std::io::{Read, Write};
 use std::net::TcpStream;
+use std::ops::Range;
 use std::time::Duration;
 
 pub enum CustomTcpStream {
@@ -21,6 +23,13 @@ impl CustomTcpStream {
         }
     }
 
+    pub fn read(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
+        match self {
+            CustomTcpStream::Https(stream) => stream.read(buf),
+            CustomTcpStream::Http(stream) => stream.read(buf),
+        }
+    }
+
     pub fn write_all(&mut self, buf: &[u8]) -> Result<(), std::io::Error> {
         match self {
             CustomTcpStream::Https(stream) => stream.write_all(buf),
@@ -29,10 +38,32 @@ impl CustomTcpStream {
     }
 }
 
+/// Status and headers of a response, header names in lowercase
+#[derive(Debug, PartialEq)]
+pub struct ResponseHead {
+    pub status: u16,
+    pub headers: HashMap<String, String>,
+}
+
+impl ResponseHead {
+    pub fn content_length(&self) -> Option<u64> {
+        self.headers.get("content-length")?.trim().parse().ok()
+    }
+
+    pub fn keeps_alive(&self) -> bool {
+        self.headers
+            .get("connection")
+            .is_none_or(|value| !value.eq_ignore_ascii_case("close"))
+    }
+}
+
 pub struct HttpsService {
     stream: CustomTcpStream,
+    url: String,
     host: String,
     max_retries: u8,
+    // the server closed the connection after the last response, so it has to be opened again
+    closed: bool,
 }
 
 impl HttpsService {
@@ -42,29 +73,37 @@ impl HttpsService {
 
         let host = HttpsService::url_to_host(url)?;
         trace!("host: {}", host);
-        let stream = TcpStream::connect(&host)?;
+        Ok(HttpsService {
+            stream: Self::connect(url, &host)?,
+            url: url.to_string(),
+            host,
+            max_retries: MAX_RETRIES,
+            closed: false,
+        })
+    }
+
+    fn connect(url: &str, host: &str) -> Result<CustomTcpStream, HttpsServiceError> {
+        let stream = TcpStream::connect(host)?;
         stream.set_write_timeout(Some(Duration::new(REQUEST_TIMEOUT, 0)))?;
         stream.set_read_timeout(Some(Duration::new(REQUEST_TIMEOUT, 0)))?;
 
         if url.starts_with("https://") {
             let connector = TlsConnector::new()?;
-            let stream = connector.connect(&Self::remove_port_from_host(&host), stream)?;
-            let stream = CustomTcpStream::Https(stream);
-            Ok(HttpsService {
-                stream,
-                host,
-                max_retries: MAX_RETRIES,
-            })
+            let stream = connector.connect(&Self::remove_port_from_host(host), stream)?;
+            Ok(CustomTcpStream::Https(stream))
         } else {
-            let stream = CustomTcpStream::Http(stream);
-            Ok(HttpsService {
-                stream,
-                host,
-                max_retries: MAX_RETRIES,
-            })
+            Ok(CustomTcpStream::Http(stream))
         }
     }
 
+    /// Opens the connection again if the server closed it
+    fn reconnect(&mut self) -> Result<(), HttpsServiceError> {
+        debug!("Opening again the connection to {}", self.host);
+        self.stream = Self::connect(&self.url, &self.host)?;
+        self.closed = false;
+        Ok(())
+    }
+
     pub fn remove_port_from_host(host: &str) -> String {
         let mut host_without_port = host.to_string();
         if let Some(index) = host_without_port.find(':') {
@@ -89,8 +128,12 @@ impl HttpsService {
             .ok_or_else(|| HttpsServiceError(format!("Missing URN in URL: {}", url)))?;
 
         if !host.contains(':') {
-            let host = host.to_string() + ":443";
-            return Ok(host);
+            let port = if url.starts_with("http://") {
+                HTTP_PORT
+            } else {
+                HTTPS_PORT
+            };
+            return Ok(format!("{}:{}", host, port));
         }
         Ok(host.into())
     }
@@ -108,6 +151,67 @@ impl HttpsService {
             ))))
         }
     }
+
+    // reads until the end of the headers, returning them along with the part of the body read
+    fn read_response_head(&mut self) -> BoxedResult<(ResponseHead, Vec<u8>)> {
+        let mut response = vec![];
+        let mut buf = [0u8; READ_BUFFER_SIZE];
+        loop {
+            if let Some(i) = response.windows(4).position(|arr| arr == SEPARATOR) {
+                let head = parse_response_head(&response[..i])?;
+                return Ok((head, response[i + 4..].to_vec()));
+            }
+            if response.len() > MAX_HEAD_LENGTH {
+                return Err(Box::new(HttpsServiceError(
+                    "Response headers are too long".to_string(),
+                )));
+            }
+            let read = self.stream.read(&mut buf)?;
+            if read == 0 {
+                return Err(Box::new(HttpsServiceError(
+                    "Connection closed before the end of the headers".to_string(),
+                )));
+            }
+            response.extend_from_slice(&buf[..read]);
+        }
+    }
+
+    fn try_range_request(&mut self, request: &str, length: u64) -> BoxedResult<Vec<u8>> {
+        if self.closed {
+            self.reconnect()?;
+        }
+        self.stream.write_all(request.as_bytes())?;
+        let (head, mut body) = self.read_response_head()?;
+        self.closed = !head.keeps_alive();
+        if head.status != PARTIAL_CONTENT_STATUS {
+            self.closed = true;
+            return Err(Box::new(HttpsServiceError(format!(
+                "Expected partial content, got status {}",
+                head.status
+            ))));
+        }
+        if head.content_length() != Some(length) {
+            self.closed = true;
+            return Err(Box::new(HttpsServiceError(format!(
+                "Expected {} bytes, the response has {:?}",
+                length,
+                head.content_length()
+            ))));
+        }
+        let mut buf = [0u8; READ_BUFFER_SIZE];
+        while (body.len() as u64) < length {
+            let read = self.stream.read(&mut buf)?;
+            if read == 0 {
+                self.closed = true;
+                return Err(Box::new(HttpsServiceError(
+                    "Connection closed before the end of the body".to_string(),
+                )));
+            }
+            body.extend_from_slice(&buf[..read]);
+        }
+        body.truncate(length as usize);
+        Ok(body)
+    }
 }
 
 impl IHttpService for HttpsService {
@@ -133,6 +237,53 @@ impl IHttpService for HttpsService {
             trace!("try number {} of tracker request", retries);
         }
     }
+
+    fn get_range(&mut self, path: &str, range: Range<u64>) -> Result<Vec<u8>, HttpsServiceError> {
+        if range.is_empty() {
+            return Ok(vec![]);
+        }
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\nRange: bytes={}-{}\r\nConnection: keep-alive\r\n\r\n",
+            path,
+            self.host,
+            range.start,
+            range.end - 1
+        );
+        let mut retries = 0;
+        loop {
+            match self.try_range_request(&request, range.end - range.start) {
+                Ok(body) => return Ok(body),
+                Err(e) => {
+                    // the server may have dropped the idle connection
+                    self.closed = true;
+                    if retries >= self.max_retries {
+                        return Err(HttpsServiceError(format!(
+                            "Could not get bytes {:?} of {} from {}. {}",
+                            range, path, self.host, e
+                        )));
+                    }
+                    retries += 1;
+                }
+            }
+            trace!("try number {} of range request", retries);
+        }
+    }
+}
+
+/// Parses the status line and headers of a response, without the blank line ending them
+pub fn parse_response_head(bytes: &[u8]) -> Result<ResponseHead, HttpsServiceError> {
+    let head = String::from_utf8_lossy(bytes);
+    let mut lines = head.split("\r\n");
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| HttpsServiceError(format!("Invalid status line in: {}", head)))?;
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+    Ok(ResponseHead { status, headers })
 }
 
 #[cfg(test)]
@@ -145,4 +296,54 @@ impl IHttpService for MockHttpsService {
     fn get(&mut self, _path: &str, _query_params: &str) -> Result<Vec<u8>, HttpsServiceError> {
         Ok(self.read_bytes.clone())
     }
+
+    fn get_range(&mut self, _path: &str, range: Range<u64>) -> Result<Vec<u8>, HttpsServiceError> {
+        self.read_bytes
+            .get(range.start as usize..range.end as usize)
+            .map(|bytes| bytes.to_vec())
+            .ok_or_else(|| HttpsServiceError(format!("Range {:?} out of the body", range)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_the_head_of_a_partial_response() {
+        let head = parse_response_head(
+            b"HTTP/1.1 206 Partial Content\r\nContent-Length: 16384\r\nConnection: close\r\nContent-Range: bytes 0-16383/40000",
+        )
+        .unwrap();
+        assert_eq!(head.status, PARTIAL_CONTENT_STATUS);
+        assert_eq!(head.content_length(), Some(16384));
+        assert!(!head.keeps_alive());
+        assert_eq!(
+            head.headers["content-range"],
+            "bytes 0-16383/40000".to_string()
+        );
+    }
+
+    #[test]
+    fn connections_are_kept_alive_unless_the_server_closes_them() {
+        let head = parse_response_head(b"HTTP/1.1 200 OK\r\nContent-Length: 3").unwrap();
+        assert!(head.keeps_alive());
+        assert!(parse_response_head(b"garbage").is_err());
+    }
+
+    #[test]
+    fn urls_without_port_use_the_default_of_their_scheme() {
+        assert_eq!(
+            HttpsService::url_to_host("http://cdimage.debian.org/debian-cd/").unwrap(),
+            "cdimage.debian.org:80"
+        );
+        assert_eq!(
+            HttpsService::url_to_host("https://torrent.ubuntu.com/announce").unwrap(),
+            "torrent.ubuntu.com:443"
+        );
+        assert_eq!(
+            HttpsService::url_to_host("http://localhost:8080/announce").unwrap(),
+            "localhost:8080"
+        );
+    }
 }
@@ -1,5 +1,10 @@
 use super::errors::HttpsServiceError;
+use std::ops::Range;
 
 pub trait IHttpService {
     fn get(&mut self, path: &str, query_params: &str) -> Result<Vec<u8>, HttpsServiceError>;
+
+    /// The bytes in `range` of the resource at `path`, asked with a Range request
+    /// over a connection kept alive between requests
+    fn get_range(&mut self, path: &str, range: Range<u64>) -> Result<Vec<u8>, HttpsServiceError>;
 }
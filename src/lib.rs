@@ -1,20 +1,31 @@
 pub mod application;
 pub mod application_errors;
+pub mod bandwidth;
 pub mod bencode;
 pub mod client;
 pub mod config;
 pub mod constants;
 pub mod download_manager;
+pub mod encryption;
 pub mod http;
 pub mod logger;
+pub mod lsd;
+pub mod magnet;
 pub mod metainfo;
 pub mod peer;
 pub mod peer_connection_manager;
 pub mod piece_manager;
 pub mod piece_saver;
+pub mod reactor;
+pub mod rpc;
 pub mod server;
+pub mod session;
+pub mod storage;
+pub mod torrent_creator;
 pub mod tracker;
 pub mod ui;
+pub mod utils;
+pub mod utp;
 
 pub mod boxed_result {
     use std::error;
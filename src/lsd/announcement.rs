@@ -0,0 +1,100 @@
+use super::constants::*;
+use crate::utils::{from_hex, to_hex};
+
+const SEARCH_LINE: &str = "BT-SEARCH * HTTP/1.1";
+
+/// A `BT-SEARCH` message, telling the local network which torrents a peer has and where it listens
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Announcement {
+    /// The port the peer accepts connections on
+    pub port: u16,
+    pub info_hashes: Vec<Vec<u8>>,
+    /// Random value the sender puts in every announcement, so it can ignore its own
+    pub cookie: String,
+}
+
+impl Announcement {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut message = format!(
+            "{}\r\nHost: {}:{}\r\nPort: {}\r\n",
+            SEARCH_LINE, LSD_MULTICAST_GROUP, LSD_PORT, self.port
+        );
+        for info_hash in &self.info_hashes {
+            message.push_str(&format!("Infohash: {}\r\n", to_hex(info_hash)));
+        }
+        message.push_str(&format!("cookie: {}\r\n\r\n\r\n", self.cookie));
+        message.into_bytes()
+    }
+
+    /// Reads an announcement, None if the datagram isn't one.
+    /// Header names are case insensitive, and info_hashes that aren't 20 bytes long are left out
+    pub fn parse(datagram: &[u8]) -> Option<Self> {
+        let message = std::str::from_utf8(datagram).ok()?;
+        let mut lines = message.split("\r\n");
+        if lines.next()? != SEARCH_LINE {
+            return None;
+        }
+        let mut port = None;
+        let mut info_hashes = vec![];
+        let mut cookie = String::new();
+        for line in lines.take_while(|line| !line.is_empty()) {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let value = value.trim();
+            match name.trim().to_ascii_lowercase().as_str() {
+                "port" => port = value.parse().ok(),
+                "infohash" => {
+                    if let Some(info_hash) = from_hex(value).filter(|hash| hash.len() == 20) {
+                        info_hashes.push(info_hash);
+                    }
+                }
+                "cookie" => cookie = value.to_string(),
+                _ => {}
+            }
+        }
+        Some(Self {
+            port: port?,
+            info_hashes,
+            cookie,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn announcements_go_both_ways() {
+        let announcement = Announcement {
+            port: 6881,
+            info_hashes: vec![vec![0xab; 20], vec![1; 20]],
+            cookie: "f00d".to_string(),
+        };
+
+        let bytes = announcement.to_bytes();
+
+        assert!(bytes.starts_with(b"BT-SEARCH * HTTP/1.1\r\nHost: 239.192.152.143:6771\r\n"));
+        assert_eq!(Announcement::parse(&bytes), Some(announcement));
+    }
+
+    #[test]
+    fn announcements_from_other_clients_are_read() {
+        let datagram = format!(
+            "BT-SEARCH * HTTP/1.1\r\nHOST: 239.192.152.143:6771\r\nPORT: 51413\r\nINFOHASH: {}\r\nInfohash: nothex\r\n\r\n\r\n",
+            "ab".repeat(20)
+        );
+
+        let announcement = Announcement::parse(datagram.as_bytes()).unwrap();
+
+        assert_eq!(announcement.port, 51413);
+        assert_eq!(announcement.info_hashes, vec![vec![0xab; 20]]);
+        assert_eq!(announcement.cookie, "");
+        assert_eq!(
+            Announcement::parse(b"M-SEARCH * HTTP/1.1\r\nPort: 1\r\n\r\n"),
+            None
+        );
+        assert_eq!(Announcement::parse(b"BT-SEARCH * HTTP/1.1\r\n\r\n"), None);
+    }
+}
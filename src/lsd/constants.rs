@@ -0,0 +1,15 @@
+use std::net::Ipv4Addr;
+use std::time::Duration;
+
+/// Multicast group Local Service Discovery (BEP 14) announcements are sent to
+pub const LSD_MULTICAST_GROUP: Ipv4Addr = Ipv4Addr::new(239, 192, 152, 143);
+pub const LSD_PORT: u16 = 6771;
+
+/// Every torrent is announced again this often
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// How long the service waits for announcements before checking its messages
+pub const RECEIVE_TIMEOUT: Duration = Duration::from_secs(1);
+/// Announcements are small, larger datagrams aren't read whole
+pub const MAX_ANNOUNCEMENT_SIZE: usize = 1400;
+/// Torrents announced in a single datagram at most, so it isn't fragmented
+pub const MAX_INFO_HASHES_PER_ANNOUNCEMENT: usize = 10;
@@ -0,0 +1,7 @@
+mod announcement;
+mod constants;
+mod service;
+
+pub use announcement::Announcement;
+pub use constants::*;
+pub use service::{LocalDiscovery, LocalDiscoverySender};
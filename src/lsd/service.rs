@@ -0,0 +1,293 @@
+use super::announcement::Announcement;
+use super::constants::*;
+use crate::metainfo::Metainfo;
+use crate::peer::{peer_message_service_provider, Peer, PeerSource, PeerSourcePolicy};
+use crate::peer_connection_manager::PeerConnectionManagerSender;
+use crate::utils::to_hex;
+use log::*;
+use socket2::{Domain, Protocol, Socket, Type};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::JoinHandle;
+use std::time::Instant;
+
+enum LocalDiscoveryMessage {
+    AddTorrent(Vec<u8>, PeerConnectionManagerSender),
+    RemoveTorrent(Vec<u8>),
+    Stop,
+}
+
+/// Adds and removes the torrents announced on the local network, it can be cloned and used from any thread
+#[derive(Clone)]
+pub struct LocalDiscoverySender {
+    sender: Sender<LocalDiscoveryMessage>,
+}
+
+impl LocalDiscoverySender {
+    /// Announces the torrent and hands the peers announcing it to `peers`.
+    /// Private torrents are left out, their peers only come from their trackers
+    pub fn add_torrent(&self, metainfo: &Metainfo, peers: PeerConnectionManagerSender) {
+        if !PeerSourcePolicy::for_torrent(metainfo).allows(PeerSource::LocalDiscovery) {
+            return;
+        }
+        let _ = self.sender.send(LocalDiscoveryMessage::AddTorrent(
+            metainfo.info_hash.clone(),
+            peers,
+        ));
+    }
+
+    pub fn remove_torrent(&self, info_hash: &[u8]) {
+        let _ = self
+            .sender
+            .send(LocalDiscoveryMessage::RemoveTorrent(info_hash.to_vec()));
+    }
+}
+
+/// Local Service Discovery (BEP 14).
+/// Finds the peers of the same local network without a tracker: every torrent added is announced
+/// to a multicast group along with the port we listen on, and the peers announcing the same
+/// torrents are handed to its peer connection manager
+pub struct LocalDiscovery {
+    sender: LocalDiscoverySender,
+    handle: JoinHandle<()>,
+}
+
+impl LocalDiscovery {
+    /// Starts announcing `port` as the one we accept connections on, without torrents.
+    /// If the multicast group can't be joined the error is logged and nothing is announced
+    pub fn start(port: u16) -> LocalDiscovery {
+        let (sender, receiver) = mpsc::channel();
+        let handle = std::thread::spawn(move || match join_multicast_group() {
+            Ok(socket) => run(socket, port, receiver),
+            Err(err) => error!("Local service discovery is disabled: {}", err),
+        });
+        LocalDiscovery {
+            sender: LocalDiscoverySender { sender },
+            handle,
+        }
+    }
+
+    pub fn sender(&self) -> LocalDiscoverySender {
+        self.sender.clone()
+    }
+
+    pub fn stop(self) {
+        let _ = self.sender.sender.send(LocalDiscoveryMessage::Stop);
+        let _ = self.handle.join();
+    }
+}
+
+// every client of the machine listens on the same port, so it is bound reusing the address
+fn join_multicast_group() -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    #[cfg(unix)]
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, LSD_PORT)).into())?;
+    let socket = UdpSocket::from(socket);
+    socket.join_multicast_v4(&LSD_MULTICAST_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_loop_v4(true)?;
+    socket.set_read_timeout(Some(RECEIVE_TIMEOUT))?;
+    Ok(socket)
+}
+
+fn run(socket: UdpSocket, port: u16, receiver: Receiver<LocalDiscoveryMessage>) {
+    let mut torrents = AnnouncedTorrents::new(port, to_hex(&rand::random::<[u8; 8]>()));
+    let group = SocketAddr::from((LSD_MULTICAST_GROUP, LSD_PORT));
+    let mut datagram = [0u8; MAX_ANNOUNCEMENT_SIZE];
+    loop {
+        loop {
+            match receiver.try_recv() {
+                Ok(LocalDiscoveryMessage::AddTorrent(info_hash, peers)) => {
+                    torrents.add(info_hash, peers)
+                }
+                Ok(LocalDiscoveryMessage::RemoveTorrent(info_hash)) => torrents.remove(&info_hash),
+                Ok(LocalDiscoveryMessage::Stop) | Err(TryRecvError::Disconnected) => return,
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+        if let Some(announcement) = torrents.due_announcement(Instant::now()) {
+            if let Err(err) = socket.send_to(&announcement.to_bytes(), group) {
+                debug!(
+                    "Couldn't send local service discovery announcement: {}",
+                    err
+                );
+            }
+        }
+        // times out every RECEIVE_TIMEOUT, so messages are handled in time
+        if let Ok((length, from)) = socket.recv_from(&mut datagram) {
+            torrents.receive(&datagram[..length], from);
+        }
+    }
+}
+
+struct AnnouncedTorrent {
+    peers: PeerConnectionManagerSender,
+    last_announce: Option<Instant>,
+}
+
+// the torrents announced, and what to do with the announcements of others
+struct AnnouncedTorrents {
+    port: u16,
+    cookie: String,
+    torrents: HashMap<Vec<u8>, AnnouncedTorrent>,
+}
+
+impl AnnouncedTorrents {
+    fn new(port: u16, cookie: String) -> Self {
+        Self {
+            port,
+            cookie,
+            torrents: HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, info_hash: Vec<u8>, peers: PeerConnectionManagerSender) {
+        self.torrents.insert(
+            info_hash,
+            AnnouncedTorrent {
+                peers,
+                last_announce: None,
+            },
+        );
+    }
+
+    fn remove(&mut self, info_hash: &[u8]) {
+        self.torrents.remove(info_hash);
+    }
+
+    // the torrents added since the last announcement, or announced longer than ANNOUNCE_INTERVAL ago,
+    // at most MAX_INFO_HASHES_PER_ANNOUNCEMENT so the datagram isn't fragmented
+    fn due_announcement(&mut self, now: Instant) -> Option<Announcement> {
+        let info_hashes: Vec<Vec<u8>> = self
+            .torrents
+            .iter_mut()
+            .filter(|(_, torrent)| {
+                torrent
+                    .last_announce
+                    .is_none_or(|last_announce| now - last_announce >= ANNOUNCE_INTERVAL)
+            })
+            .take(MAX_INFO_HASHES_PER_ANNOUNCEMENT)
+            .map(|(info_hash, torrent)| {
+                torrent.last_announce = Some(now);
+                info_hash.clone()
+            })
+            .collect();
+        (!info_hashes.is_empty()).then(|| Announcement {
+            port: self.port,
+            info_hashes,
+            cookie: self.cookie.clone(),
+        })
+    }
+
+    // hands the peer that sent the announcement to the torrents it announced that we have, ignoring our own
+    fn receive(&self, datagram: &[u8], from: SocketAddr) {
+        let Some(announcement) = Announcement::parse(datagram) else {
+            return;
+        };
+        // the peers of a torrent are connected to through IPv4
+        let IpAddr::V4(ip) = from.ip() else {
+            return;
+        };
+        if announcement.cookie == self.cookie {
+            return;
+        }
+        for info_hash in &announcement.info_hashes {
+            if let Some(torrent) = self.torrents.get(info_hash) {
+                trace!(
+                    "Found peer {}:{} in the local network",
+                    ip,
+                    announcement.port
+                );
+                torrent.peers.add_peers(
+                    PeerSource::LocalDiscovery,
+                    vec![discovered_peer(ip, announcement.port)],
+                );
+            }
+        }
+    }
+}
+
+// peers announce themselves again every so often, their address identifies them until the handshake
+fn discovered_peer(ip: Ipv4Addr, port: u16) -> Peer {
+    Peer {
+        ip: ip.to_string(),
+        port,
+        peer_id: format!("{}:{}", ip, port).into_bytes(),
+        peer_message_service_provider,
+        web_seed: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer_connection_manager::PeerConnectionManagerMessage;
+    use std::time::Duration;
+
+    fn manager() -> (
+        PeerConnectionManagerSender,
+        Receiver<PeerConnectionManagerMessage>,
+    ) {
+        let (sender, receiver) = mpsc::channel();
+        (PeerConnectionManagerSender { sender }, receiver)
+    }
+
+    #[test]
+    fn torrents_are_announced_when_added_and_every_interval() {
+        let (peers, _receiver) = manager();
+        let mut torrents = AnnouncedTorrents::new(6881, "me".to_string());
+        let now = Instant::now();
+        assert_eq!(torrents.due_announcement(now), None);
+
+        torrents.add(vec![1; 20], peers);
+
+        let announcement = torrents.due_announcement(now).unwrap();
+        assert_eq!(announcement.port, 6881);
+        assert_eq!(announcement.info_hashes, vec![vec![1; 20]]);
+        assert_eq!(
+            torrents.due_announcement(now + Duration::from_secs(60)),
+            None
+        );
+        assert!(torrents.due_announcement(now + ANNOUNCE_INTERVAL).is_some());
+    }
+
+    #[test]
+    fn announcing_peers_are_added_to_the_torrent() {
+        let (peers, receiver) = manager();
+        let mut torrents = AnnouncedTorrents::new(6881, "me".to_string());
+        torrents.add(vec![1; 20], peers);
+        let from = SocketAddr::from(([192, 168, 0, 7], LSD_PORT));
+        let announcement = |cookie: &str, info_hash: Vec<u8>| Announcement {
+            port: 6882,
+            info_hashes: vec![info_hash],
+            cookie: cookie.to_string(),
+        };
+
+        torrents.receive(&announcement("me", vec![1; 20]).to_bytes(), from);
+        torrents.receive(&announcement("other", vec![2; 20]).to_bytes(), from);
+        torrents.receive(&announcement("other", vec![1; 20]).to_bytes(), from);
+
+        let added: Vec<PeerConnectionManagerMessage> = receiver.try_iter().collect();
+        assert_eq!(added.len(), 1);
+        let PeerConnectionManagerMessage::AddPeers(source, peers) = &added[0] else {
+            panic!("expected the peer to be added");
+        };
+        assert_eq!(*source, PeerSource::LocalDiscovery);
+        assert_eq!(peers[0].ip, "192.168.0.7");
+        assert_eq!(peers[0].port, 6882);
+    }
+
+    #[test]
+    fn private_torrents_are_not_announced() {
+        let (sender, receiver) = mpsc::channel();
+        let (peers, _receiver) = manager();
+        let mut metainfo = Metainfo::default();
+        metainfo.info.private = true;
+
+        LocalDiscoverySender { sender }.add_torrent(&metainfo, peers);
+
+        assert!(receiver.try_recv().is_err());
+    }
+}
@@ -0,0 +1,27 @@
+use std::time::Duration;
+
+/// Bit of the 6th reserved byte of the handshake telling the extension protocol (BEP 10) is supported
+pub const EXTENSION_PROTOCOL_BIT: u8 = 0x10;
+/// Id of the messages of the extension protocol
+pub const EXTENDED_MESSAGE_ID: u8 = 20;
+/// Extended message id of the extension handshake
+pub const EXTENDED_HANDSHAKE_ID: u8 = 0;
+/// The id we ask peers to send us metadata messages (BEP 9) with
+pub const UT_METADATA_ID: u8 = 1;
+pub const UT_METADATA: &[u8] = b"ut_metadata";
+
+/// Types of the metadata messages
+pub const METADATA_REQUEST: i64 = 0;
+pub const METADATA_DATA: i64 = 1;
+pub const METADATA_REJECT: i64 = 2;
+
+/// The metadata is sent in pieces this long, but for the last one
+pub const METADATA_PIECE_LENGTH: usize = 16 * 1024;
+/// Peers announcing longer metadata are left, no real torrent's info dictionary is this long
+pub const MAX_METADATA_SIZE: usize = 16 * 1024 * 1024;
+/// Longer messages aren't read, a bitfield this long would tell about millions of pieces
+pub const MAX_MESSAGE_LENGTH: usize = 1024 * 1024;
+/// Time a peer has to send each message while its metadata is fetched
+pub const METADATA_TIMEOUT: Duration = Duration::from_secs(10);
+/// Bytes trackers are told are left while the size of the torrent is unknown
+pub const UNKNOWN_LENGTH: u64 = 16 * 1024;
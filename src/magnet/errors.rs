@@ -0,0 +1,55 @@
+use crate::metainfo::MetainfoParserError;
+use std::fmt;
+
+#[derive(Debug)]
+/// Errors reading a magnet link or fetching the metadata of its torrent
+pub enum MagnetError {
+    /// the link isn't a magnet link this client can use, holds why
+    InvalidLink(String),
+    /// the link has no tracker nor peer to ask for the metadata, and none of its trackers answered with one
+    NoPeers,
+    /// the peer can't send the metadata, or didn't follow the extension protocol, holds why
+    PeerError(String),
+    /// the metadata sent doesn't hash to the info_hash of the link
+    InvalidMetadata,
+    /// none of the peers sent the metadata, holds why the last one didn't
+    MetadataUnavailable(String),
+    IoError(std::io::Error),
+    MetainfoError(MetainfoParserError),
+}
+
+impl From<std::io::Error> for MagnetError {
+    fn from(error: std::io::Error) -> Self {
+        MagnetError::IoError(error)
+    }
+}
+
+impl From<MetainfoParserError> for MagnetError {
+    fn from(error: MetainfoParserError) -> Self {
+        MagnetError::MetainfoError(error)
+    }
+}
+
+impl fmt::Display for MagnetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MagnetError::InvalidLink(reason) => write!(f, "Invalid magnet link: {}", reason),
+            MagnetError::NoPeers => write!(f, "No peer to fetch the torrent's metadata from"),
+            MagnetError::PeerError(reason) => {
+                write!(f, "The peer couldn't send the metadata: {}", reason)
+            }
+            MagnetError::InvalidMetadata => {
+                write!(f, "The metadata doesn't match the info_hash of the link")
+            }
+            MagnetError::MetadataUnavailable(reason) => {
+                write!(
+                    f,
+                    "No peer sent the torrent's metadata, the last one: {}",
+                    reason
+                )
+            }
+            MagnetError::IoError(error) => write!(f, "IO error: {}", error),
+            MagnetError::MetainfoError(error) => write!(f, "Metainfo Error - {}", error),
+        }
+    }
+}
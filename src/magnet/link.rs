@@ -0,0 +1,209 @@
+use super::errors::MagnetError;
+use crate::metainfo::{parse_info, Metainfo};
+use crate::utils::{from_hex, to_hex};
+use log::*;
+use std::net::SocketAddr;
+
+const MAGNET_PREFIX: &str = "magnet:?";
+const BTIH_PREFIX: &str = "urn:btih:";
+const BTMH_PREFIX: &str = "urn:btmh:";
+const HEX_INFO_HASH_LENGTH: usize = 40;
+const BASE32_INFO_HASH_LENGTH: usize = 32;
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+/// A magnet link (BEP 9): what is needed to fetch the metadata of a torrent from its peers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MagnetLink {
+    /// 20 byte SHA-1 hash of the info dictionary (xt)
+    pub info_hash: Vec<u8>,
+    /// name of the torrent to show until its metadata is fetched (dn)
+    pub name: Option<String>,
+    /// URLs of the trackers of the torrent (tr)
+    pub trackers: Vec<String>,
+    /// peers known to have the torrent (x.pe)
+    pub peers: Vec<SocketAddr>,
+    /// URLs of web servers holding the same files (ws)
+    pub web_seeds: Vec<String>,
+}
+
+impl MagnetLink {
+    /// Reads a link like `magnet:?xt=urn:btih:<info_hash>&dn=<name>&tr=<tracker>`, the info_hash
+    /// written in hexadecimal or base32. v2 only links (`urn:btmh:`) aren't supported, and the link
+    /// needs a tracker, since the peers of the torrent are found through them
+    pub fn parse(link: &str) -> Result<MagnetLink, MagnetError> {
+        let query = link
+            .strip_prefix(MAGNET_PREFIX)
+            .ok_or_else(|| invalid("it doesn't start with magnet:?"))?;
+        let mut info_hash = None;
+        let mut v2_only = false;
+        let mut name = None;
+        let mut trackers = vec![];
+        let mut peers = vec![];
+        let mut web_seeds = vec![];
+        for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+            let value = percent_decode(value)
+                .ok_or_else(|| invalid(&format!("the {} parameter isn't url-encoded", key)))?;
+            match base_key(key) {
+                "xt" => match value.strip_prefix(BTIH_PREFIX) {
+                    Some(hash) => info_hash = Some(decode_info_hash(hash)?),
+                    None => v2_only |= value.starts_with(BTMH_PREFIX),
+                },
+                "dn" => name = Some(value),
+                "tr" => trackers.push(value),
+                "x.pe" => match value.parse() {
+                    Ok(peer) => peers.push(peer),
+                    Err(_) => debug!("Ignoring peer {} of the magnet link", value),
+                },
+                "ws" => web_seeds.push(value),
+                _ => {}
+            }
+        }
+        let info_hash = match info_hash {
+            Some(info_hash) => info_hash,
+            None if v2_only => {
+                return Err(invalid(
+                    "v2 only torrents can't be downloaded from a magnet link",
+                ))
+            }
+            None => return Err(invalid("it has no urn:btih: info_hash")),
+        };
+        if trackers.is_empty() {
+            return Err(invalid(
+                "it has no tracker to find the peers of the torrent",
+            ));
+        }
+        Ok(MagnetLink {
+            info_hash,
+            name,
+            trackers,
+            peers,
+            web_seeds,
+        })
+    }
+
+    /// The name of the link, or its info_hash if it has none
+    pub fn display_name(&self) -> String {
+        self.name.clone().unwrap_or_else(|| to_hex(&self.info_hash))
+    }
+
+    /// Builds the metainfo of the torrent from its info dictionary, as fetched from its peers,
+    /// with the trackers and web seeds of the link
+    pub fn metainfo(&self, info: &[u8]) -> Result<Metainfo, MagnetError> {
+        let mut metainfo = parse_info(info, &self.trackers)?;
+        metainfo.url_list.extend(self.web_seeds.iter().cloned());
+        Ok(metainfo)
+    }
+}
+
+// parameters can be numbered when there are many of them, like xt.1 and xt.2
+fn base_key(key: &str) -> &str {
+    match key.rsplit_once('.') {
+        Some((base, number))
+            if !number.is_empty() && number.bytes().all(|byte| byte.is_ascii_digit()) =>
+        {
+            base
+        }
+        _ => key,
+    }
+}
+
+fn decode_info_hash(hash: &str) -> Result<Vec<u8>, MagnetError> {
+    let decoded = match hash.len() {
+        HEX_INFO_HASH_LENGTH => from_hex(hash),
+        BASE32_INFO_HASH_LENGTH => from_base32(hash),
+        _ => None,
+    };
+    decoded.ok_or_else(|| invalid(&format!("{} isn't a hexadecimal or base32 info_hash", hash)))
+}
+
+// reads base32 without padding, the way 20 byte info_hashes are written in 32 characters
+fn from_base32(base32: &str) -> Option<Vec<u8>> {
+    let mut bytes = vec![];
+    let mut buffer: u16 = 0;
+    let mut bits = 0;
+    for character in base32.bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|letter| *letter == character.to_ascii_uppercase())?;
+        buffer = (buffer << 5) | value as u16;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+// decodes the %XX escapes and the + standing for spaces, None if the result isn't UTF-8
+fn percent_decode(value: &str) -> Option<String> {
+    let mut bytes = vec![];
+    let mut encoded = value.bytes();
+    while let Some(byte) = encoded.next() {
+        match byte {
+            b'%' => {
+                let hex = [encoded.next()?, encoded.next()?];
+                bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b'+' => bytes.push(b' '),
+            byte => bytes.push(byte),
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn invalid(reason: &str) -> MagnetError {
+    MagnetError::InvalidLink(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO_HASH: &str = "c9e15763f722f23e98a29decdfae341b98d53056";
+
+    #[test]
+    fn reads_the_info_hash_name_trackers_and_peers() {
+        let link = MagnetLink::parse(&format!(
+            "magnet:?xt=urn:btih:{}&dn=Some+file%20name&tr=http%3A%2F%2Ftracker.example%2Fannounce&tr.1=http://other.example&x.pe=10.0.0.1:6881&x.pe=nope",
+            INFO_HASH
+        ))
+        .unwrap();
+
+        assert_eq!(to_hex(&link.info_hash), INFO_HASH);
+        assert_eq!(link.display_name(), "Some file name");
+        assert_eq!(
+            link.trackers,
+            vec!["http://tracker.example/announce", "http://other.example"]
+        );
+        assert_eq!(link.peers, vec!["10.0.0.1:6881".parse().unwrap()]);
+    }
+
+    #[test]
+    fn reads_base32_info_hashes() {
+        let link = MagnetLink::parse(
+            "magnet:?xt=urn:btih:ZHQVOY7XELZD5GFCTXWN7LRUDOMNKMCW&tr=http://tracker.example",
+        )
+        .unwrap();
+        assert_eq!(to_hex(&link.info_hash), INFO_HASH);
+        assert_eq!(link.display_name(), INFO_HASH);
+    }
+
+    #[test]
+    fn links_without_a_v1_info_hash_or_a_tracker_are_invalid() {
+        let invalid_links = [
+            format!("http://example.com/?xt=urn:btih:{}", INFO_HASH),
+            "magnet:?xt=urn:btih:1234&tr=http://tracker.example".to_string(),
+            "magnet:?xt=urn:btmh:1220abcd&tr=http://tracker.example".to_string(),
+            format!("magnet:?xt=urn:btih:{}", INFO_HASH),
+        ];
+        for link in invalid_links {
+            assert!(
+                matches!(MagnetLink::parse(&link), Err(MagnetError::InvalidLink(_))),
+                "{}",
+                link
+            );
+        }
+    }
+}
@@ -0,0 +1,378 @@
+use super::constants::*;
+use super::errors::MagnetError;
+use super::link::MagnetLink;
+use crate::bencode::{decode_with_spans, encode, BencodeDecodedValue};
+use crate::client::ClientInfo;
+use crate::config::Config;
+use crate::metainfo::{Info, Metainfo};
+use crate::peer::{create_handshake_message, sha1_of};
+use crate::reactor::{Reactor, ReactorStream, CONNECT_TIMEOUT};
+use crate::tracker::{ITrackerService, TrackerService};
+use log::*;
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::time::Duration;
+
+const HANDSHAKE_LENGTH: usize = 68;
+// the reserved byte of the handshake holding the extension protocol bit
+const EXTENSION_BYTE: usize = 25;
+const INFO_HASH: Range<usize> = 28..48;
+const MESSAGE_TYPE_KEY: &[u8] = b"msg_type";
+const PIECE_KEY: &[u8] = b"piece";
+const METADATA_SIZE_KEY: &[u8] = b"metadata_size";
+const EXTENSIONS_KEY: &[u8] = b"m";
+
+/// Finds the peers of the link's torrent, the ones in the link first and then the ones its trackers
+/// answer with, and builds its metainfo from the metadata of the first one that sends it.
+/// Peers are asked one at a time, while `keep_trying` returns true
+pub fn download_metainfo(
+    link: &MagnetLink,
+    peer_id: [u8; 20],
+    config: &Config,
+    keep_trying: impl Fn() -> bool,
+) -> Result<Metainfo, MagnetError> {
+    let peers = find_peers(link, peer_id, config);
+    if peers.is_empty() {
+        return Err(MagnetError::NoPeers);
+    }
+    let reactor = Reactor::global().map_err(|err| MagnetError::PeerError(err.to_string()))?;
+    let mut last_error = MagnetError::NoPeers;
+    for address in peers {
+        if !keep_trying() {
+            break;
+        }
+        debug!(
+            "Asking {} for the metadata of {}",
+            address,
+            link.display_name()
+        );
+        let fetched =
+            ReactorStream::connect(&reactor, address, Duration::from_secs(CONNECT_TIMEOUT))
+                .map_err(MagnetError::from)
+                .and_then(|mut stream| {
+                    stream.set_read_timeout(Some(METADATA_TIMEOUT));
+                    fetch_metadata(&mut stream, &link.info_hash, &peer_id)
+                });
+        match fetched {
+            Ok(info) => return link.metainfo(&info),
+            Err(err) => {
+                debug!("{} didn't send the metadata: {}", address, err);
+                last_error = err;
+            }
+        }
+    }
+    Err(MagnetError::MetadataUnavailable(last_error.to_string()))
+}
+
+/// Fetches the info dictionary of the torrent from the peer at the other end of the stream, through
+/// the extension protocol (BEP 10) and its metadata messages (BEP 9). It is checked against `info_hash`
+pub fn fetch_metadata(
+    stream: &mut (impl Read + Write),
+    info_hash: &[u8],
+    peer_id: &[u8],
+) -> Result<Vec<u8>, MagnetError> {
+    let mut handshake = create_handshake_message(info_hash, peer_id);
+    handshake[EXTENSION_BYTE] |= EXTENSION_PROTOCOL_BIT;
+    stream.write_all(&handshake)?;
+    let mut answer = [0u8; HANDSHAKE_LENGTH];
+    stream.read_exact(&mut answer)?;
+    if answer[EXTENSION_BYTE] & EXTENSION_PROTOCOL_BIT == 0 {
+        return Err(peer_error("it doesn't support the extension protocol"));
+    }
+    if answer[INFO_HASH] != *info_hash {
+        return Err(peer_error("it answered for another torrent"));
+    }
+
+    let extensions = dictionary(vec![(UT_METADATA, integer(UT_METADATA_ID as i64))]);
+    send_extended(
+        stream,
+        EXTENDED_HANDSHAKE_ID,
+        &dictionary(vec![(EXTENSIONS_KEY, extensions)]),
+    )?;
+    let (metadata_id, metadata_size) = loop {
+        let (id, payload) = read_extended(stream)?;
+        if id == EXTENDED_HANDSHAKE_ID {
+            break read_extended_handshake(&payload)?;
+        }
+    };
+
+    let piece_count = metadata_size.div_ceil(METADATA_PIECE_LENGTH);
+    for piece in 0..piece_count {
+        send_extended(
+            stream,
+            metadata_id,
+            &dictionary(vec![
+                (MESSAGE_TYPE_KEY, integer(METADATA_REQUEST)),
+                (PIECE_KEY, integer(piece as i64)),
+            ]),
+        )?;
+    }
+    let mut metadata = vec![0; metadata_size];
+    let mut missing = piece_count;
+    let mut received = vec![false; piece_count];
+    while missing > 0 {
+        let (id, payload) = read_extended(stream)?;
+        if id != UT_METADATA_ID {
+            continue;
+        }
+        let (message, span) =
+            decode_with_spans(&payload).map_err(|err| peer_error(&err.to_string()))?;
+        let message = message
+            .get_as_dictionary()
+            .map_err(|err| peer_error(&err.to_string()))?;
+        let piece = integer_of(message, PIECE_KEY)?;
+        match integer_of(message, MESSAGE_TYPE_KEY)? {
+            METADATA_DATA => {}
+            METADATA_REJECT => {
+                return Err(peer_error(&format!("it rejected piece {}", piece)));
+            }
+            _ => continue,
+        }
+        let piece = usize::try_from(piece)
+            .ok()
+            .filter(|piece| *piece < piece_count)
+            .ok_or_else(|| peer_error(&format!("it sent unknown piece {}", piece)))?;
+        let start = piece * METADATA_PIECE_LENGTH;
+        let end = metadata_size.min(start + METADATA_PIECE_LENGTH);
+        let data = &payload[span.range.end..];
+        if data.len() != end - start {
+            return Err(peer_error(&format!("piece {} has the wrong length", piece)));
+        }
+        metadata[start..end].copy_from_slice(data);
+        if !received[piece] {
+            received[piece] = true;
+            missing -= 1;
+        }
+    }
+    if sha1_of(&metadata) != info_hash {
+        return Err(MagnetError::InvalidMetadata);
+    }
+    Ok(metadata)
+}
+
+// the peers of the link first, then the ones its trackers answer with, without repeating any
+fn find_peers(link: &MagnetLink, peer_id: [u8; 20], config: &Config) -> Vec<SocketAddr> {
+    let mut peers = link.peers.clone();
+    for tracker in &link.trackers {
+        // the size of the torrent is unknown until its metadata is fetched
+        let metainfo = Metainfo {
+            announce: tracker.clone(),
+            info_hash: link.info_hash.clone(),
+            info: Info {
+                name: link.display_name(),
+                length: UNKNOWN_LENGTH,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut tracker_service = TrackerService::new(ClientInfo {
+            peer_id,
+            config: config.clone(),
+            metainfo,
+        });
+        match tracker_service.announce(None) {
+            Ok(response) => {
+                let found = response
+                    .peers
+                    .iter()
+                    .filter_map(|peer| format!("{}:{}", peer.ip, peer.port).parse().ok());
+                for peer in found {
+                    if !peers.contains(&peer) {
+                        peers.push(peer);
+                    }
+                }
+            }
+            Err(err) => debug!("Tracker {} didn't answer: {:?}", tracker, err),
+        }
+    }
+    peers
+}
+
+// the id the peer wants metadata messages sent with, and the length of the metadata
+fn read_extended_handshake(payload: &[u8]) -> Result<(u8, usize), MagnetError> {
+    let (handshake, _) = decode_with_spans(payload).map_err(|err| peer_error(&err.to_string()))?;
+    let handshake = handshake
+        .get_as_dictionary()
+        .map_err(|err| peer_error(&err.to_string()))?;
+    let metadata_id = handshake
+        .get(EXTENSIONS_KEY)
+        .and_then(|extensions| extensions.get_as_dictionary().ok())
+        .map(|extensions| integer_of(extensions, UT_METADATA))
+        .transpose()?
+        .and_then(|id| u8::try_from(id).ok())
+        .filter(|id| *id != EXTENDED_HANDSHAKE_ID)
+        .ok_or_else(|| peer_error("it doesn't support metadata messages"))?;
+    let metadata_size = usize::try_from(integer_of(handshake, METADATA_SIZE_KEY)?)
+        .ok()
+        .filter(|size| (1..=MAX_METADATA_SIZE).contains(size))
+        .ok_or_else(|| peer_error("it announced an invalid metadata size"))?;
+    Ok((metadata_id, metadata_size))
+}
+
+fn send_extended(
+    stream: &mut impl Write,
+    id: u8,
+    message: &BencodeDecodedValue,
+) -> Result<(), MagnetError> {
+    let payload = encode(message);
+    let mut bytes = ((payload.len() + 2) as u32).to_be_bytes().to_vec();
+    bytes.extend([EXTENDED_MESSAGE_ID, id]);
+    bytes.extend(payload);
+    stream.write_all(&bytes)?;
+    Ok(())
+}
+
+// reads messages until an extended one arrives, returning its extended id and its payload
+fn read_extended(stream: &mut impl Read) -> Result<(u8, Vec<u8>), MagnetError> {
+    loop {
+        let mut length = [0u8; 4];
+        stream.read_exact(&mut length)?;
+        let length = u32::from_be_bytes(length) as usize;
+        if length > MAX_MESSAGE_LENGTH {
+            return Err(peer_error("it sent a message too long"));
+        }
+        let mut message = vec![0; length];
+        stream.read_exact(&mut message)?;
+        match message[..] {
+            [EXTENDED_MESSAGE_ID, id, ..] => return Ok((id, message.split_off(2))),
+            [EXTENDED_MESSAGE_ID] => return Err(peer_error("it sent an empty extended message")),
+            _ => {}
+        }
+    }
+}
+
+fn integer_of(
+    dictionary: &HashMap<Vec<u8>, BencodeDecodedValue>,
+    key: &[u8],
+) -> Result<i64, MagnetError> {
+    dictionary
+        .get(key)
+        .and_then(|value| value.get_as_integer().ok())
+        .copied()
+        .ok_or_else(|| {
+            peer_error(&format!(
+                "it sent a message without {}",
+                String::from_utf8_lossy(key)
+            ))
+        })
+}
+
+fn dictionary(entries: Vec<(&[u8], BencodeDecodedValue)>) -> BencodeDecodedValue {
+    BencodeDecodedValue::Dictionary(
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.to_vec(), value))
+            .collect(),
+    )
+}
+
+fn integer(value: i64) -> BencodeDecodedValue {
+    BencodeDecodedValue::Integer(value)
+}
+
+fn peer_error(reason: &str) -> MagnetError {
+    MagnetError::PeerError(reason.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::decode;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread::JoinHandle;
+
+    const PEER_METADATA_ID: u8 = 3;
+
+    // info dictionary long enough to be sent in three pieces
+    fn info_dictionary() -> Vec<u8> {
+        let pieces = vec![7u8; 20 * 2000];
+        encode(&dictionary(vec![
+            (b"length", integer(2000)),
+            (b"name", BencodeDecodedValue::String(b"magnet.txt".to_vec())),
+            (b"piece length", integer(1)),
+            (b"pieces", BencodeDecodedValue::String(pieces)),
+        ]))
+    }
+
+    // a peer sending `metadata` as the metadata of whatever torrent it's asked for
+    fn peer_sending(metadata: Vec<u8>) -> (TcpStream, JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let peer = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut handshake = [0u8; HANDSHAKE_LENGTH];
+            stream.read_exact(&mut handshake).unwrap();
+            assert_ne!(handshake[EXTENSION_BYTE] & EXTENSION_PROTOCOL_BIT, 0);
+            stream.write_all(&handshake).unwrap();
+            // a regular message first, which is skipped
+            stream.write_all(&[0, 0, 0, 1, 2]).unwrap();
+
+            let extensions = dictionary(vec![(UT_METADATA, integer(PEER_METADATA_ID as i64))]);
+            let answer = dictionary(vec![
+                (EXTENSIONS_KEY, extensions),
+                (METADATA_SIZE_KEY, integer(metadata.len() as i64)),
+            ]);
+            send_extended(&mut stream, EXTENDED_HANDSHAKE_ID, &answer).unwrap();
+            loop {
+                let Ok((id, payload)) = read_extended(&mut stream) else {
+                    return;
+                };
+                if id != PEER_METADATA_ID {
+                    continue;
+                }
+                let request = decode(&payload).unwrap();
+                let piece = integer_of(request.get_as_dictionary().unwrap(), PIECE_KEY).unwrap();
+                let start = piece as usize * METADATA_PIECE_LENGTH;
+                let end = metadata.len().min(start + METADATA_PIECE_LENGTH);
+                let mut message = encode(&dictionary(vec![
+                    (MESSAGE_TYPE_KEY, integer(METADATA_DATA)),
+                    (PIECE_KEY, integer(piece)),
+                ]));
+                message.extend_from_slice(&metadata[start..end]);
+                let mut bytes = ((message.len() + 2) as u32).to_be_bytes().to_vec();
+                bytes.extend([EXTENDED_MESSAGE_ID, UT_METADATA_ID]);
+                bytes.extend(message);
+                if stream.write_all(&bytes).is_err() {
+                    return;
+                }
+            }
+        });
+        (client, peer)
+    }
+
+    #[test]
+    fn fetches_the_metadata_in_pieces() {
+        let info = info_dictionary();
+        assert!(info.len() > 2 * METADATA_PIECE_LENGTH);
+        let info_hash = sha1_of(&info);
+        let (mut stream, peer) = peer_sending(info.clone());
+
+        let metadata = fetch_metadata(&mut stream, &info_hash, &[1; 20]).unwrap();
+        drop(stream);
+        peer.join().unwrap();
+
+        assert_eq!(metadata, info);
+        let link = MagnetLink::parse(&format!(
+            "magnet:?xt=urn:btih:{}&tr=http://tracker.example",
+            crate::utils::to_hex(&info_hash)
+        ))
+        .unwrap();
+        let metainfo = link.metainfo(&metadata).unwrap();
+        assert_eq!(metainfo.info_hash, info_hash);
+        assert_eq!(metainfo.info.name, "magnet.txt");
+        assert_eq!(metainfo.announce, "http://tracker.example");
+    }
+
+    #[test]
+    fn metadata_of_another_torrent_is_refused() {
+        let (mut stream, peer) = peer_sending(info_dictionary());
+
+        let fetched = fetch_metadata(&mut stream, &[9; 20], &[1; 20]);
+        drop(stream);
+        peer.join().unwrap();
+
+        assert!(matches!(fetched, Err(MagnetError::InvalidMetadata)));
+    }
+}
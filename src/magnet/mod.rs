@@ -0,0 +1,9 @@
+mod constants;
+mod errors;
+mod link;
+mod metadata;
+
+pub use constants::*;
+pub use errors::MagnetError;
+pub use link::MagnetLink;
+pub use metadata::{download_metainfo, fetch_metadata};
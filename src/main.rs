@@ -1,13 +1,23 @@
-use bittorrent_rustico::application::run_with_torrent;
+use bittorrent_rustico::application::recheck_torrent;
+use bittorrent_rustico::rpc::RpcServer;
+use bittorrent_rustico::session::{Session, QUEUE_CHECK_INTERVAL};
+use bittorrent_rustico::torrent_creator::{write_torrent, TorrentOptions};
 use bittorrent_rustico::ui::{run_ui, UIMessage};
+use bittorrent_rustico::utils::to_hex;
 use gtk::{self, glib};
 use log::*;
 use std::env;
-use std::sync::mpsc;
-use std::thread::{self, JoinHandle};
+use std::io::{self, Write};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
 fn main() {
     pretty_env_logger::init();
-    if env::var("UI").is_ok() {
+    if env::args().nth(1).as_deref() == Some("recheck") {
+        run_recheck();
+    } else if env::args().nth(1).as_deref() == Some("create") {
+        run_create();
+    } else if env::var("UI").is_ok() {
         run_client_with_ui();
     } else {
         run_client_with_no_ui();
@@ -23,32 +33,140 @@ fn run_client_with_ui() {
     let client_handle = thread::spawn(move || {
         let ui_tx = client_receiver.recv().unwrap(); // receive the ui sender from the client
         run_client(Some(ui_tx)); // run the client with the ui sender
+                                 // every torrent was stopped, so the ui has nothing left to show
+        std::process::exit(0);
     });
     run_ui(client_sender);
     client_handle.join().unwrap();
 }
 
+// recheck <config file> <torrent1> <torrent2> ...
+fn run_recheck() {
+    let mut args = env::args().skip(2);
+    let config_file = args.next().unwrap_or_default();
+    for torrent_file in args {
+        println!("Rechecking {}", torrent_file);
+        let result = recheck_torrent(&torrent_file, &config_file, |progress| {
+            print!(
+                "\r{}/{} pieces checked, {} valid",
+                progress.checked, progress.total, progress.valid
+            );
+            let _ = io::stdout().flush();
+        });
+        println!();
+        match result {
+            Ok(report) if report.bad_pieces.is_empty() => println!("No bad pieces found"),
+            Ok(report) => println!(
+                "{} bad pieces will be downloaded again: {:?}",
+                report.bad_pieces.len(),
+                report.bad_pieces
+            ),
+            Err(err) => {
+                error!("Error rechecking torrent file: {}", torrent_file);
+                error!("{}", err);
+            }
+        }
+    }
+}
+
+// create <file or directory> <output torrent> <announce url> [options]
+// options: --tracker <url> (a backup tier), --comment <text>, --private, --web-seed <url>, --piece-length <KiB>
+fn run_create() {
+    let mut args = env::args().skip(2);
+    let (Some(source), Some(torrent_file), Some(announce)) =
+        (args.next(), args.next(), args.next())
+    else {
+        println!("Usage: create <file or directory> <output torrent> <announce url> [--tracker <url>] [--comment <text>] [--private] [--web-seed <url>] [--piece-length <KiB>]");
+        return;
+    };
+    let mut options = TorrentOptions::new(&announce);
+    while let Some(option) = args.next() {
+        if option == "--private" {
+            options = options.with_private(true);
+            continue;
+        }
+        let Some(value) = args.next() else {
+            error!("Missing value of option: {}", option);
+            return;
+        };
+        options = match option.as_str() {
+            "--tracker" => options.with_tier(vec![value]),
+            "--comment" => options.with_comment(&value),
+            "--web-seed" => options.with_web_seed(&value),
+            "--piece-length" => match value.parse::<u32>() {
+                Ok(kib) => options.with_piece_length(kib.saturating_mul(1024)),
+                Err(_) => {
+                    error!("Invalid piece length: {}", value);
+                    return;
+                }
+            },
+            _ => {
+                error!("Invalid option: {}", option);
+                return;
+            }
+        };
+    }
+    match write_torrent(&source, &torrent_file, &options) {
+        Ok(metainfo) => println!(
+            "Created {} with {} pieces, info_hash {}",
+            torrent_file,
+            metainfo.get_piece_count(),
+            to_hex(&metainfo.info_hash)
+        ),
+        Err(err) => {
+            error!("Error creating torrent from: {}", source);
+            error!("{}", err);
+        }
+    }
+}
+
 fn run_client(ui_message_sender: Option<glib::Sender<UIMessage>>) {
     let mut args = env::args().skip(1);
     let config_file = args.next().unwrap_or_else(|| "".to_string());
-    // iterate through all args and call run_with_torrent for each torrent file
-    let mut torrent_handles: Vec<JoinHandle<()>> = vec![];
+    let session = match Session::new(&config_file, ui_message_sender) {
+        Ok(session) => Arc::new(session),
+        Err(err) => {
+            error!("Error starting the session: {}", err);
+            return;
+        }
+    };
     for torrent_file in args {
         info!("Running with torrent file: {}", torrent_file);
-        let ui_msg_sender_clone = ui_message_sender.clone();
-        let torrent_file = torrent_file.to_string();
-        let cfg = config_file.clone();
-        torrent_handles.push(thread::spawn(move || {
-            if let Err(err) = run_with_torrent(&torrent_file, &cfg, ui_msg_sender_clone) {
-                error!("Error running with torrent file: {}", torrent_file);
-                error!("{}", err);
-            }
-        }));
+        if let Err(err) = session.add_torrent(&torrent_file) {
+            error!("Error running with torrent file: {}", torrent_file);
+            error!("{}", err);
+        }
     }
 
-    for torrent_handle in torrent_handles {
-        torrent_handle.join().unwrap();
+    let rpc_server = session.config().rpc_port.and_then(|port| {
+        RpcServer::listen(port, session.clone())
+            .map_err(|err| error!("Couldn't start the control API on port {}: {}", port, err))
+            .ok()
+    });
+
+    // the torrents keep seeding until the process is told to stop or every one of them finished,
+    // with the control API or a watch folder torrents can still be added, so it runs until told to stop
+    let keep_running = rpc_server.is_some() || session.config().watch_dir.is_some();
+    let (stop_sender, stop_receiver) = mpsc::channel();
+    if let Err(err) = ctrlc::set_handler(move || {
+        let _ = stop_sender.send(());
+    }) {
+        error!("Couldn't listen for stop signals: {}", err);
+    }
+    while keep_running || !session.is_empty() {
+        match stop_receiver.recv_timeout(QUEUE_CHECK_INTERVAL) {
+            Err(RecvTimeoutError::Timeout) => continue,
+            _ => break,
+        }
     }
 
+    if let Some(rpc_server) = rpc_server {
+        rpc_server.stop();
+    }
+    info!("Stopping every torrent");
+    match Arc::try_unwrap(session) {
+        Ok(session) => session.stop(),
+        Err(_) => error!("The session is still in use, torrents weren't stopped"),
+    }
     info!("Finished running");
 }
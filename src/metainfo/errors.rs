@@ -12,6 +12,8 @@ pub enum MetainfoParserError {
     UTF8Error,
     //A certain value in Info or Metainfo was invalid
     ValidationError,
+    ///The name or a path component of a file would place it outside the download directory
+    UnsafePath(String),
 }
 
 impl From<BencodeDecoderError> for MetainfoParserError {
@@ -51,6 +53,9 @@ impl Display for MetainfoParserError {
             MetainfoParserError::ValidationError => {
                 writeln!(f, "Validation error: A Metainfo or Info value was invalid")
             }
+            MetainfoParserError::UnsafePath(component) => {
+                writeln!(f, "Unsafe file name or path component: '{}'", component)
+            }
         }
     }
 }
@@ -0,0 +1,164 @@
+use sha2::{Digest, Sha256};
+
+/// Size of the blocks hashed as the leaves of the merkle trees of v2 torrents
+pub const MERKLE_BLOCK_SIZE: usize = 16 * 1024;
+pub const SHA256_LENGTH: usize = 32;
+
+/// Hash a piece of a v2 torrent has in its file's merkle tree.
+/// The piece holds `length` bytes of the file, the rest of a shorter last piece isn't hashed
+#[derive(Debug, Clone, PartialEq)]
+pub struct PieceHashV2 {
+    pub hash: Vec<u8>,
+    pub length: usize,
+    /// leaves of the subtree the piece is the root of
+    pub leaves: usize,
+}
+
+impl PieceHashV2 {
+    /// Whether the first `length` bytes of the piece have its hash
+    pub fn verify(&self, piece: &[u8]) -> bool {
+        piece.len() >= self.length
+            && root_of_blocks(&piece[..self.length], self.leaves) == self.hash
+    }
+}
+
+pub fn sha256_of(data: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finalize().to_vec()
+}
+
+// root of a tree with `width` leaves, the ones missing are `pad`
+fn root_of(hashes: &[Vec<u8>], width: usize, pad: &[u8]) -> Vec<u8> {
+    let mut layer = hashes.to_vec();
+    let mut pad = pad.to_vec();
+    let mut width = width.max(1);
+    layer.resize(width, pad.clone());
+    while width > 1 {
+        layer = layer
+            .chunks(2)
+            .map(|pair| sha256_of(&pair.concat()))
+            .collect();
+        pad = sha256_of(&[pad.clone(), pad].concat());
+        width /= 2;
+    }
+    layer.pop().unwrap_or(pad)
+}
+
+// root of the blocks of the data, in a tree of `leaves` leaves padded with zeros
+fn root_of_blocks(data: &[u8], leaves: usize) -> Vec<u8> {
+    let blocks: Vec<Vec<u8>> = data.chunks(MERKLE_BLOCK_SIZE).map(sha256_of).collect();
+    root_of(&blocks, leaves, &[0; SHA256_LENGTH])
+}
+
+// root of a tree of `leaves` leaves that are all zeros
+fn zeros_root(leaves: usize) -> Vec<u8> {
+    root_of(&[], leaves, &[0; SHA256_LENGTH])
+}
+
+fn blocks_per_piece(piece_length: u64) -> usize {
+    (piece_length as usize / MERKLE_BLOCK_SIZE).max(1)
+}
+
+/// Hash of the piece at `piece` of a file of `file_length` bytes.
+/// Files no longer than a piece have a single one, the root of the tree of their blocks
+pub fn piece_hash_v2(
+    hash: Vec<u8>,
+    piece: u64,
+    piece_length: u64,
+    file_length: u64,
+) -> PieceHashV2 {
+    let start = piece * piece_length;
+    let length = piece_length.min(file_length.saturating_sub(start)) as usize;
+    let leaves = if file_length > piece_length {
+        blocks_per_piece(piece_length)
+    } else {
+        length.div_ceil(MERKLE_BLOCK_SIZE).next_power_of_two()
+    };
+    PieceHashV2 {
+        hash,
+        length,
+        leaves,
+    }
+}
+
+/// Pieces root of a file from the hashes of its pieces (its piece layer),
+/// padded with the root of pieces full of zeros
+pub fn pieces_root(layer: &[Vec<u8>], piece_length: u64) -> Vec<u8> {
+    root_of(
+        layer,
+        layer.len().next_power_of_two(),
+        &zeros_root(blocks_per_piece(piece_length)),
+    )
+}
+
+/// Piece layer of the file's data, one hash per piece.
+/// Files no longer than a piece don't have one, their root is enough
+pub fn piece_layer(data: &[u8], piece_length: u64) -> Vec<Vec<u8>> {
+    data.chunks(piece_length as usize)
+        .map(|piece| root_of_blocks(piece, blocks_per_piece(piece_length)))
+        .collect()
+}
+
+/// Pieces root of the file's data, None for empty files
+pub fn file_root(data: &[u8], piece_length: u64) -> Option<Vec<u8>> {
+    if data.is_empty() {
+        None
+    } else if data.len() as u64 <= piece_length {
+        Some(root_of_blocks(
+            data,
+            data.len().div_ceil(MERKLE_BLOCK_SIZE).next_power_of_two(),
+        ))
+    } else {
+        Some(pieces_root(&piece_layer(data, piece_length), piece_length))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIECE_LENGTH: u64 = 4 * MERKLE_BLOCK_SIZE as u64;
+
+    #[test]
+    fn small_files_are_the_root_of_their_blocks() {
+        let block = vec![7u8; MERKLE_BLOCK_SIZE];
+        assert_eq!(
+            file_root(&block[..10], PIECE_LENGTH),
+            Some(sha256_of(&block[..10]))
+        );
+
+        let data = [block.clone(), block[..5].to_vec()].concat();
+        let expected = sha256_of(&[sha256_of(&block), sha256_of(&block[..5])].concat());
+        assert_eq!(file_root(&data, PIECE_LENGTH), Some(expected.clone()));
+        assert!(piece_hash_v2(expected, 0, PIECE_LENGTH, data.len() as u64).verify(&data));
+        assert_eq!(file_root(&[], PIECE_LENGTH), None);
+    }
+
+    #[test]
+    fn pieces_of_big_files_are_checked_against_their_layer() {
+        let data: Vec<u8> = (0..PIECE_LENGTH * 2 + 100).map(|i| i as u8).collect();
+        let layer = piece_layer(&data, PIECE_LENGTH);
+        assert_eq!(layer.len(), 3);
+        // the fourth piece of the tree is padding, all of its leaves are zeros
+        let padding = root_of_blocks(&[], 4);
+        let expected = sha256_of(
+            &[
+                sha256_of(&[layer[0].clone(), layer[1].clone()].concat()),
+                sha256_of(&[layer[2].clone(), padding].concat()),
+            ]
+            .concat(),
+        );
+        assert_eq!(file_root(&data, PIECE_LENGTH), Some(expected));
+
+        let file_length = data.len() as u64;
+        let last = piece_hash_v2(layer[2].clone(), 2, PIECE_LENGTH, file_length);
+        assert_eq!(last.length, 100);
+        // a hybrid torrent pads the last piece with zeros, they aren't part of the file
+        let mut piece = data[2 * PIECE_LENGTH as usize..].to_vec();
+        piece.resize(PIECE_LENGTH as usize, 0);
+        assert!(last.verify(&piece));
+        piece[3] ^= 1;
+        assert!(!last.verify(&piece));
+    }
+}
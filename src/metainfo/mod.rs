@@ -1,8 +1,10 @@
 mod errors;
+mod merkle;
 mod parser;
 mod types;
 
 pub use errors::MetainfoParserError;
-pub use parser::parse;
+pub use merkle::*;
+pub use parser::{parse, parse_info};
 pub use types::Info;
-pub use types::{File, Metainfo};
+pub use types::{File, MetaVersion, Metainfo, TreeFile};
@@ -6,6 +6,7 @@ use crate::logger::CustomLogger;
 use log::*;
 use sha1::{Digest, Sha1};
 use std::collections::HashMap;
+use std::path::{Component, Path};
 use std::str::from_utf8;
 const LOGGER: CustomLogger = CustomLogger::init("Config");
 ///Receives a byte array and Bencode-Decodes it to build a [Metainfo].
@@ -21,96 +22,327 @@ const LOGGER: CustomLogger = CustomLogger::init("Config");
 /// ```
 pub fn parse(bytes: &[u8]) -> Result<Metainfo, MetainfoParserError> {
     trace!("Decoding bencode bytes");
-    let decoded = decode(bytes)
+    let (decoded, span) = decode_with_spans(bytes)
         .map_err(|e| MetainfoParserError::BencodeError(format!("Error decoding bytes: {}", e)))?;
     trace!("Building metainfo");
-    build_metainfo(decoded.get_as_dictionary()?)
+    let info_span = span
+        .get(INFO_KEY)
+        .ok_or_else(|| MetainfoParserError::MetainfoKeyNotFound("info".to_string()))?;
+    let info_bytes = &bytes[info_span.range.clone()];
+    build_metainfo(
+        decoded.get_as_dictionary()?,
+        get_hash(info_bytes),
+        sha256_of(info_bytes),
+    )
 }
 
-//Builds Metainfo Struct from a hashmap containing the relevant Bencode-Decoded Values
+/// Builds the [Metainfo] of a torrent from its bencoded info dictionary alone, like the one fetched
+/// from the peers of a magnet link. The first tracker is its announce URL, and each one a tier of its announce-list
+pub fn parse_info(info_bytes: &[u8], trackers: &[String]) -> Result<Metainfo, MetainfoParserError> {
+    let string = |bytes: &[u8]| encode(&BencodeDecodedValue::String(bytes.to_vec()));
+    let announce = trackers.first().map(String::as_str).unwrap_or_default();
+    let announce_list = BencodeDecodedValue::List(
+        trackers
+            .iter()
+            .map(|tracker| {
+                BencodeDecodedValue::List(vec![BencodeDecodedValue::String(
+                    tracker.as_bytes().to_vec(),
+                )])
+            })
+            .collect(),
+    );
+    // written by hand so the info dictionary is kept as it was, and its hash with it
+    let mut torrent = vec![b'd'];
+    torrent.extend(string(ANNOUNCE_KEY));
+    torrent.extend(string(announce.as_bytes()));
+    torrent.extend(string(ANNOUNCE_LIST_KEY));
+    torrent.extend(encode(&announce_list));
+    torrent.extend(string(INFO_KEY));
+    torrent.extend_from_slice(info_bytes);
+    torrent.push(b'e');
+    parse(&torrent)
+}
+
+const INFO_KEY: &[u8] = b"info";
+const PIECE_LENGTH_KEY: &[u8] = b"piece length";
+const PIECES_KEY: &[u8] = b"pieces";
+const NAME_KEY: &[u8] = b"name";
+const LENGTH_KEY: &[u8] = b"length";
+const FILES_KEY: &[u8] = b"files";
+const PATH_KEY: &[u8] = b"path";
+const MD5SUM_KEY: &[u8] = b"md5sum";
+const PRIVATE_KEY: &[u8] = b"private";
+const ANNOUNCE_KEY: &[u8] = b"announce";
+const ANNOUNCE_LIST_KEY: &[u8] = b"announce-list";
+const COMMENT_KEY: &[u8] = b"comment";
+const CREATED_BY_KEY: &[u8] = b"created by";
+const CREATION_DATE_KEY: &[u8] = b"creation date";
+const ENCODING_KEY: &[u8] = b"encoding";
+const URL_LIST_KEY: &[u8] = b"url-list";
+const HTTP_SEEDS_KEY: &[u8] = b"httpseeds";
+const META_VERSION_KEY: &[u8] = b"meta version";
+const FILE_TREE_KEY: &[u8] = b"file tree";
+const PIECES_ROOT_KEY: &[u8] = b"pieces root";
+const PIECE_LAYERS_KEY: &[u8] = b"piece layers";
+const META_VERSION_2: i64 = 2;
+
+//Builds Metainfo Struct from a hashmap containing the relevant Bencode-Decoded Values.
+//v2 torrents are known by their SHA-256 hash, truncated, unless they are hybrid
 fn build_metainfo(
     hashmap: &HashMap<Vec<u8>, BencodeDecodedValue>,
+    info_hash: Vec<u8>,
+    info_hash_v2: Vec<u8>,
 ) -> Result<Metainfo, MetainfoParserError> {
-    let info_key = b"info";
-    let piece_length_key = b"piece length";
-    let pieces_key = b"pieces";
-    let name_key = b"name";
-    let length_key = b"length";
-    let announce_key = b"announce";
-    let files_key = b"files";
-    let path_key = b"path";
-
-    let info_hashmap_decoded = get_from_bencoded_values_hashmap(hashmap, info_key)?;
-    let info_hashmap = info_hashmap_decoded.get_as_dictionary()?;
-
-    let total_length = match get_from_bencoded_values_hashmap(info_hashmap, length_key) {
-        Ok(length) => *length.get_as_integer()? as u64,
-        Err(_) => {
-            let files_hashmap_decoded = get_from_bencoded_values_hashmap(info_hashmap, files_key)?;
-            let files = files_hashmap_decoded.get_as_list()?;
-            let mut total_length = 0;
-            for file in files {
-                let file_hashmap = file.get_as_dictionary()?;
-                let length = get_from_bencoded_values_hashmap(file_hashmap, length_key)?;
-                total_length += length.get_as_integer()?;
-            }
-            total_length as u64
-        }
+    let info_hashmap_decoded = get_from_bencoded_values_hashmap(hashmap, INFO_KEY)?;
+    let info = build_info(info_hashmap_decoded.get_as_dictionary()?)?;
+    let info_hash_v2 = (info.meta_version == Some(META_VERSION_2)).then_some(info_hash_v2);
+    let info_hash = match &info_hash_v2 {
+        Some(hash) if info.pieces.is_empty() => hash[..SHA1_LENGTH].to_vec(),
+        _ => info_hash,
     };
 
-    let pieces_as_vec_u8 = get_from_bencoded_values_hashmap(info_hashmap, pieces_key)?
-        .get_as_string()?
-        .to_vec();
-
-    let files: Option<Vec<File>> = match get_from_bencoded_values_hashmap(info_hashmap, files_key) {
-        Ok(files_bencoded) => {
-            let file_list = files_bencoded.get_as_list()?;
-            let mut files = Vec::new();
-            for file in file_list {
-                let file_hashmap = file.get_as_dictionary()?;
-                let path = bencode_list_to_string_path(&get_from_bencoded_values_hashmap(
-                    file_hashmap,
-                    path_key,
-                )?)?;
-                let length = *get_from_bencoded_values_hashmap(file_hashmap, length_key)?
-                    .get_as_integer()? as u64;
-                files.push(File { path, length });
-            }
-            Some(files)
-        }
-        Err(_) => None,
+    let announce_list = match hashmap.get(ANNOUNCE_LIST_KEY) {
+        Some(tiers) => tiers
+            .get_as_list()?
+            .iter()
+            .map(strings_of)
+            .filter(|tier| !tier.is_empty())
+            .collect(),
+        None => vec![],
     };
 
-    let info = Info {
-        piece_length: *get_from_bencoded_values_hashmap(info_hashmap, piece_length_key)?
-            .get_as_integer()? as u32,
-        pieces: get_vec_of_hashes(&pieces_as_vec_u8),
-        name: bencode_decoded_bytes_to_string(info_hashmap, name_key)?,
-        length: total_length,
-        files,
+    let piece_layers = match hashmap.get(PIECE_LAYERS_KEY) {
+        Some(layers) => layers
+            .get_as_dictionary()?
+            .iter()
+            .map(|(root, layer)| {
+                let hashes = layer
+                    .get_as_string()?
+                    .chunks(SHA256_LENGTH)
+                    .map(|hash| hash.to_vec())
+                    .collect();
+                Ok((root.clone(), hashes))
+            })
+            .collect::<Result<HashMap<Vec<u8>, Vec<Vec<u8>>>, BencodeDecoderError>>()?,
+        None => HashMap::new(),
     };
 
     let metainfo = Metainfo {
         info,
-        info_hash: get_hash(hashmap, info_key),
-        announce: bencode_decoded_bytes_to_string(hashmap, announce_key)?,
+        info_hash,
+        info_hash_v2,
+        piece_layers,
+        announce: bencode_decoded_bytes_to_string(hashmap, ANNOUNCE_KEY)?,
+        announce_list,
+        comment: optional_string(hashmap, COMMENT_KEY),
+        created_by: optional_string(hashmap, CREATED_BY_KEY),
+        creation_date: hashmap
+            .get(CREATION_DATE_KEY)
+            .and_then(|date| date.get_as_integer().ok().copied()),
+        encoding: optional_string(hashmap, ENCODING_KEY),
+        url_list: hashmap
+            .get(URL_LIST_KEY)
+            .map(strings_of)
+            .unwrap_or_default(),
+        http_seeds: hashmap
+            .get(HTTP_SEEDS_KEY)
+            .map(strings_of)
+            .unwrap_or_default(),
     };
     validate(&metainfo)?;
     Ok(metainfo)
 }
 
-// function that converts a Bencoded decoded List and turns it into a Bencode Decoded String
-fn bencode_list_to_string_path(list: &BencodeDecodedValue) -> Result<String, BencodeDecoderError> {
-    let mut path = String::new();
-    let mut list_iter = list.get_as_list()?.iter();
-    while let Some(value) = list_iter.next() {
-        let value_string = value.get_as_string()?;
-        path.push_str(&String::from_utf8_lossy(value_string));
-        if list_iter.next().is_some() {
-            path.push('/');
+//Builds Info Struct from the info dictionary, keeping the keys it doesn't read in `extra`.
+//v2 only torrents have their files in the file tree, without pieces
+fn build_info(
+    info_hashmap: &HashMap<Vec<u8>, BencodeDecodedValue>,
+) -> Result<Info, MetainfoParserError> {
+    let meta_version = info_hashmap
+        .get(META_VERSION_KEY)
+        .map(|version| version.get_as_integer().copied())
+        .transpose()?;
+    let file_tree = match info_hashmap.get(FILE_TREE_KEY) {
+        Some(tree) => {
+            let mut files = vec![];
+            build_file_tree(tree.get_as_dictionary()?, &mut vec![], &mut files)?;
+            files.sort_by(|(a, _), (b, _)| a.cmp(b));
+            files.into_iter().map(|(_, file)| file).collect()
+        }
+        None => vec![],
+    };
+    let v2_only = meta_version == Some(META_VERSION_2) && !info_hashmap.contains_key(PIECES_KEY);
+
+    let files: Option<Vec<File>> = match info_hashmap.get(FILES_KEY) {
+        Some(files_bencoded) => Some(
+            files_bencoded
+                .get_as_list()?
+                .iter()
+                .map(build_file)
+                .collect::<Result<Vec<File>, MetainfoParserError>>()?,
+        ),
+        None => None,
+    };
+    let length = match &files {
+        Some(files) => files.iter().map(|file| file.length).sum(),
+        None if v2_only => file_tree.iter().map(|file: &TreeFile| file.length).sum(),
+        None => {
+            *get_from_bencoded_values_hashmap(info_hashmap, LENGTH_KEY)?.get_as_integer()? as u64
+        }
+    };
+
+    let pieces_as_vec_u8 = if v2_only {
+        vec![]
+    } else {
+        get_from_bencoded_values_hashmap(info_hashmap, PIECES_KEY)?
+            .get_as_string()?
+            .to_vec()
+    };
+    // a private flag other than 1 doesn't make the torrent private, but it is part of the info_hash
+    let private = info_hashmap.get(PRIVATE_KEY) == Some(&BencodeDecodedValue::Integer(1));
+    let mut known_keys = vec![
+        NAME_KEY,
+        PIECE_LENGTH_KEY,
+        PIECES_KEY,
+        LENGTH_KEY,
+        FILES_KEY,
+        META_VERSION_KEY,
+        FILE_TREE_KEY,
+    ];
+    if private {
+        known_keys.push(PRIVATE_KEY);
+    }
+    let md5sum = optional_string(info_hashmap, MD5SUM_KEY);
+    if md5sum.is_some() {
+        known_keys.push(MD5SUM_KEY);
+    }
+
+    Ok(Info {
+        piece_length: *get_from_bencoded_values_hashmap(info_hashmap, PIECE_LENGTH_KEY)?
+            .get_as_integer()? as u32,
+        pieces: get_vec_of_hashes(&pieces_as_vec_u8),
+        name: safe_path_component(bencode_decoded_bytes_to_string(info_hashmap, NAME_KEY)?)?,
+        length,
+        files,
+        private,
+        md5sum,
+        meta_version,
+        file_tree,
+        extra: other_keys(info_hashmap, &known_keys),
+    })
+}
+
+// Flattens the file tree, each file is under an empty key in the dictionary of its directory.
+// The files are returned with the names of their path, to be sorted by them
+fn build_file_tree(
+    tree: &HashMap<Vec<u8>, BencodeDecodedValue>,
+    path: &mut Vec<Vec<u8>>,
+    files: &mut Vec<(Vec<Vec<u8>>, TreeFile)>,
+) -> Result<(), MetainfoParserError> {
+    for (name, entry) in tree {
+        let entry = entry.get_as_dictionary()?;
+        if name.is_empty() {
+            let pieces_root = entry
+                .get(PIECES_ROOT_KEY)
+                .map(|root| root.get_as_string().cloned())
+                .transpose()?;
+            let file = TreeFile {
+                path: path
+                    .iter()
+                    .map(|name| safe_path_component(String::from_utf8_lossy(name).to_string()))
+                    .collect::<Result<Vec<String>, MetainfoParserError>>()?
+                    .join("/"),
+                length: *get_from_bencoded_values_hashmap(entry, LENGTH_KEY)?.get_as_integer()?
+                    as u64,
+                pieces_root,
+                extra: other_keys(entry, &[LENGTH_KEY, PIECES_ROOT_KEY]),
+            };
+            files.push((path.clone(), file));
+        } else {
+            path.push(name.clone());
+            build_file_tree(entry, path, files)?;
+            path.pop();
         }
     }
-    Ok(path)
+    Ok(())
+}
+
+fn build_file(file: &BencodeDecodedValue) -> Result<File, MetainfoParserError> {
+    let file_hashmap = file.get_as_dictionary()?;
+    let path =
+        bencode_list_to_string_path(&get_from_bencoded_values_hashmap(file_hashmap, PATH_KEY)?)?;
+    let length =
+        *get_from_bencoded_values_hashmap(file_hashmap, LENGTH_KEY)?.get_as_integer()? as u64;
+    let md5sum = optional_string(file_hashmap, MD5SUM_KEY);
+    let mut known_keys = vec![PATH_KEY, LENGTH_KEY];
+    if md5sum.is_some() {
+        known_keys.push(MD5SUM_KEY);
+    }
+    Ok(File {
+        path,
+        length,
+        md5sum,
+        extra: other_keys(file_hashmap, &known_keys),
+    })
+}
+
+// the entries of the dictionary but the ones with the given keys
+fn other_keys(
+    hashmap: &HashMap<Vec<u8>, BencodeDecodedValue>,
+    known_keys: &[&[u8]],
+) -> HashMap<Vec<u8>, BencodeDecodedValue> {
+    hashmap
+        .iter()
+        .filter(|(key, _)| !known_keys.contains(&key.as_slice()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect()
+}
+
+// optional keys are read as UTF-8 leniently and left out if they aren't strings
+fn optional_string(hashmap: &HashMap<Vec<u8>, BencodeDecodedValue>, key: &[u8]) -> Option<String> {
+    hashmap
+        .get(key)
+        .and_then(|value| value.get_as_string().ok())
+        .map(|value| String::from_utf8_lossy(value).to_string())
+}
+
+// a single string or a list of them, like url-list can be written as
+fn strings_of(value: &BencodeDecodedValue) -> Vec<String> {
+    match value {
+        BencodeDecodedValue::String(string) => vec![String::from_utf8_lossy(string).to_string()],
+        BencodeDecodedValue::List(list) => list
+            .iter()
+            .filter_map(|item| item.get_as_string().ok())
+            .map(|string| String::from_utf8_lossy(string).to_string())
+            .filter(|string| !string.is_empty())
+            .collect(),
+        _ => vec![],
+    }
+}
+
+// function that converts a Bencoded decoded List and turns it into a Bencode Decoded String
+fn bencode_list_to_string_path(list: &BencodeDecodedValue) -> Result<String, MetainfoParserError> {
+    let parts = list
+        .get_as_list()?
+        .iter()
+        .map(|value| {
+            safe_path_component(String::from_utf8_lossy(value.get_as_string()?).to_string())
+        })
+        .collect::<Result<Vec<String>, MetainfoParserError>>()?;
+    Ok(parts.join("/"))
+}
+
+// The files are written under the download directory joining the name and the path components as they come,
+// so each one has to be a plain file or directory name: not empty, `.` or `..`, absolute, nor holding a separator
+fn safe_path_component(component: String) -> Result<String, MetainfoParserError> {
+    let mut components = Path::new(&component).components();
+    let plain = matches!(components.next(), Some(Component::Normal(name)) if name == component.as_str())
+        && components.next().is_none()
+        && !component.contains(['/', '\\', '\0']);
+    match plain {
+        true => Ok(component),
+        false => Err(MetainfoParserError::UnsafePath(component)),
+    }
 }
 
 // Converts the vector of pieces into a vector of each piece hash
@@ -126,11 +358,10 @@ fn get_vec_of_hashes(pieces: &[u8]) -> Vec<Vec<u8>> {
     pieces_as_vec_of_hashes
 }
 
-//Retrieves the 20-byte SHA-1 hash from the received hashmap value corresponding to the key
-fn get_hash(hashmap: &HashMap<Vec<u8>, BencodeDecodedValue>, key: &[u8]) -> Vec<u8> {
+//Computes the 20-byte SHA-1 hash of the info dictionary, as it was written in the torrent
+fn get_hash(info_bytes: &[u8]) -> Vec<u8> {
     let mut hasher = Sha1::new();
-    let info = hashmap.get(key).unwrap();
-    hasher.update(encode(info));
+    hasher.update(info_bytes);
     let result = hasher.finalize();
     result[..].to_vec()
 }
@@ -177,21 +408,68 @@ fn validate_pieces(
     Ok(())
 }
 
+// Checks the piece layer of every file longer than a piece against the file's pieces root
+fn validate_file_tree(metainfo: &Metainfo) -> Result<(), MetainfoParserError> {
+    let piece_length = metainfo.info.piece_length as u64;
+    if !piece_length.is_power_of_two()
+        || piece_length < MERKLE_BLOCK_SIZE as u64
+        || metainfo.info.file_tree.is_empty()
+    {
+        return Err(MetainfoParserError::ValidationError);
+    }
+    for file in &metainfo.info.file_tree {
+        let Some(root) = &file.pieces_root else {
+            if file.length == 0 {
+                continue;
+            }
+            return Err(MetainfoParserError::ValidationError);
+        };
+        if root.len() != SHA256_LENGTH {
+            return Err(MetainfoParserError::ValidationError);
+        }
+        if file.length <= piece_length {
+            continue;
+        }
+        let layer = metainfo
+            .piece_layers
+            .get(root)
+            .ok_or(MetainfoParserError::ValidationError)?;
+        if layer.len() as u64 != file.length.div_ceil(piece_length)
+            || layer.iter().any(|hash| hash.len() != SHA256_LENGTH)
+            || pieces_root(layer, piece_length) != *root
+        {
+            return Err(MetainfoParserError::ValidationError);
+        }
+    }
+    Ok(())
+}
+
 //Performs basic validation of certain values in Info and Metainfo
 fn validate(metainfo: &Metainfo) -> Result<(), MetainfoParserError> {
     let info: &Info = &metainfo.info;
     if metainfo.announce.is_empty()
         || info.piece_length == 0
-        || info.pieces.is_empty()
         || info.length == 0
+        || info
+            .meta_version
+            .is_some_and(|version| version != META_VERSION_2)
     {
         return Err(MetainfoParserError::ValidationError);
     }
-    validate_pieces(
-        &info.pieces,
-        info.length as usize,
-        info.piece_length as usize,
-    )?;
+    let version = metainfo.version();
+    if version != MetaVersion::V2 {
+        if info.pieces.is_empty() {
+            return Err(MetainfoParserError::ValidationError);
+        }
+        validate_pieces(
+            &info.pieces,
+            info.length as usize,
+            info.piece_length as usize,
+        )?;
+    }
+    if version != MetaVersion::V1 {
+        validate_file_tree(metainfo)?;
+    }
     LOGGER.info_str("Torrent parsed successfully");
 
     Ok(())
@@ -229,17 +507,40 @@ mod tests {
             name: "sample.txt".to_string(),
             length: 20,
             files: None,
+            ..Default::default()
         };
 
         let expected_metainfo: Metainfo = Metainfo {
             info: expected_info,
             info_hash: decode_hex("d0d14c926e6e99761a2fdcff27b403d96376eff6").unwrap(),
             announce: "udp://tracker.openbittorrent.com:80".to_string(),
+            ..Default::default()
         };
 
         assert_eq!(metainfo, expected_metainfo);
     }
 
+    #[test]
+    fn an_info_dictionary_alone_keeps_its_hash() {
+        let test_bytes: Vec<u8> = std::fs::read("example_torrents/sample.torrent").unwrap();
+        let (_, span) = decode_with_spans(&test_bytes).unwrap();
+        let info_bytes = &test_bytes[span.get(INFO_KEY).unwrap().range.clone()];
+        let trackers = vec![
+            "http://tracker.example/announce".to_string(),
+            "http://other.example/announce".to_string(),
+        ];
+
+        let metainfo = parse_info(info_bytes, &trackers).unwrap();
+
+        assert_eq!(metainfo.info_hash, parse(&test_bytes).unwrap().info_hash);
+        assert_eq!(metainfo.info.name, "sample.txt");
+        assert_eq!(metainfo.announce, trackers[0]);
+        assert_eq!(
+            metainfo.trackers(),
+            vec![vec![trackers[0].clone()], vec![trackers[1].clone()]]
+        );
+    }
+
     #[test]
     fn works_on_ubuntu_torrent() {
         let test_bytes: Vec<u8> = std::fs::read("example_torrents/ubuntu.torrent").unwrap();
@@ -247,6 +548,265 @@ mod tests {
         assert!(matches!(metainfo_result, Ok(_)));
     }
 
+    #[test]
+    fn keeps_the_optional_keys_and_the_unknown_ones() {
+        let torrent = b"d8:announce9:http://a/13:announce-listll9:http://a/el9:http://b/ee7:comment2:hi10:created by4:test13:creation datei1700000000e8:encoding5:UTF-88:url-list9:http://w/4:infod5:filesld6:lengthi3e6:md5sum32:0123456789abcdef0123456789abcdef4:pathl3:dir1:aeed6:lengthi2e4:pathl1:be4:sha120:aaaaaaaaaaaaaaaaaaaaee4:name4:test12:piece lengthi16384e6:pieces20:bbbbbbbbbbbbbbbbbbbb7:privatei1e6:sourcei7eee";
+        let metainfo = parse(torrent).unwrap();
+
+        assert_eq!(
+            metainfo.trackers(),
+            vec![vec!["http://a/".to_string()], vec!["http://b/".to_string()]]
+        );
+        assert_eq!(metainfo.comment.as_deref(), Some("hi"));
+        assert_eq!(metainfo.created_by.as_deref(), Some("test"));
+        assert_eq!(metainfo.creation_date, Some(1700000000));
+        assert_eq!(metainfo.encoding.as_deref(), Some("UTF-8"));
+        assert_eq!(metainfo.web_seeds(), vec!["http://w/".to_string()]);
+        assert!(metainfo.info.private);
+        let files = metainfo.info.files.clone().unwrap();
+        assert_eq!(files[0].path, "dir/a");
+        assert_eq!(
+            files[0].md5sum.as_deref(),
+            Some("0123456789abcdef0123456789abcdef")
+        );
+        assert!(files[1].extra.contains_key(b"sha1".as_slice()));
+        assert_eq!(
+            metainfo.info.extra.get(b"source".as_slice()),
+            Some(&BencodeDecodedValue::Integer(7))
+        );
+
+        let mut hasher = Sha1::new();
+        hasher.update(encode(&metainfo.info.to_bencode()));
+        assert_eq!(hasher.finalize().to_vec(), metainfo.info_hash);
+    }
+
+    #[test]
+    fn info_hash_is_computed_from_the_bytes_as_written() {
+        // keys out of order, the info dictionary isn't encoded the way the encoder would
+        let torrent = b"d8:announce9:http://a/4:infod6:pieces20:bbbbbbbbbbbbbbbbbbbb4:name4:test12:piece lengthi16384e6:lengthi5eee";
+        let metainfo = parse(torrent).unwrap();
+
+        let info_start = 28;
+        let mut hasher = Sha1::new();
+        hasher.update(&torrent[info_start..torrent.len() - 1]);
+        assert_eq!(metainfo.info_hash, hasher.finalize().to_vec());
+        let mut hasher = Sha1::new();
+        hasher.update(encode(&metainfo.info.to_bencode()));
+        assert_ne!(metainfo.info_hash, hasher.finalize().to_vec());
+    }
+
+    #[test]
+    fn info_dictionaries_of_example_torrents_round_trip() {
+        for torrent in ["sample", "ubuntu", "multifile-puppy", "debian"] {
+            let path = format!("example_torrents/{}.torrent", torrent);
+            let metainfo = Metainfo::from_torrent(&path).unwrap();
+            let mut hasher = Sha1::new();
+            hasher.update(encode(&metainfo.info.to_bencode()));
+            assert_eq!(hasher.finalize().to_vec(), metainfo.info_hash, "{}", path);
+        }
+    }
+
+    fn sha1_of(data: &[u8]) -> Vec<u8> {
+        let mut hasher = Sha1::new();
+        hasher.update(data);
+        hasher.finalize().to_vec()
+    }
+
+    fn dictionary(entries: Vec<(&[u8], BencodeDecodedValue)>) -> BencodeDecodedValue {
+        BencodeDecodedValue::Dictionary(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.to_vec(), value))
+                .collect(),
+        )
+    }
+
+    fn tree_file(data: &[u8]) -> BencodeDecodedValue {
+        dictionary(vec![(
+            b"",
+            dictionary(vec![
+                (LENGTH_KEY, BencodeDecodedValue::Integer(data.len() as i64)),
+                (
+                    PIECES_ROOT_KEY,
+                    BencodeDecodedValue::String(file_root(data, 16384).unwrap()),
+                ),
+            ]),
+        )])
+    }
+
+    fn v1_file(path: &[&str], length: usize, padding: bool) -> BencodeDecodedValue {
+        let mut entries = vec![
+            (LENGTH_KEY, BencodeDecodedValue::Integer(length as i64)),
+            (
+                PATH_KEY,
+                BencodeDecodedValue::List(
+                    path.iter()
+                        .map(|name| BencodeDecodedValue::String(name.as_bytes().to_vec()))
+                        .collect(),
+                ),
+            ),
+        ];
+        if padding {
+            entries.push((b"attr", BencodeDecodedValue::String(b"p".to_vec())));
+        }
+        dictionary(entries)
+    }
+
+    // a hybrid torrent with a file longer than a piece, padded so the next one starts at a piece
+    fn hybrid_torrent(v1: bool, layer: Vec<Vec<u8>>) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        let a: Vec<u8> = (0..16394).map(|i| i as u8).collect();
+        let b = b"hello".to_vec();
+        let mut info = vec![
+            (NAME_KEY, BencodeDecodedValue::String(b"hybrid".to_vec())),
+            (PIECE_LENGTH_KEY, BencodeDecodedValue::Integer(16384)),
+            (META_VERSION_KEY, BencodeDecodedValue::Integer(2)),
+            (
+                FILE_TREE_KEY,
+                dictionary(vec![(b"a", tree_file(&a)), (b"b", tree_file(&b))]),
+            ),
+        ];
+        if v1 {
+            let mut last_of_a = a[16384..].to_vec();
+            last_of_a.resize(16384, 0);
+            let pieces = [sha1_of(&a[..16384]), sha1_of(&last_of_a), sha1_of(&b)].concat();
+            info.push((PIECES_KEY, BencodeDecodedValue::String(pieces)));
+            info.push((
+                FILES_KEY,
+                BencodeDecodedValue::List(vec![
+                    v1_file(&["a"], a.len(), false),
+                    v1_file(&[".pad", "16374"], 16374, true),
+                    v1_file(&["b"], b.len(), false),
+                ]),
+            ));
+        }
+        let torrent = dictionary(vec![
+            (
+                ANNOUNCE_KEY,
+                BencodeDecodedValue::String(b"http://a/".to_vec()),
+            ),
+            (INFO_KEY, dictionary(info)),
+            (
+                PIECE_LAYERS_KEY,
+                dictionary(vec![(
+                    &file_root(&a, 16384).unwrap()[..],
+                    BencodeDecodedValue::String(layer.concat()),
+                )]),
+            ),
+        ]);
+        (encode(&torrent), a, b)
+    }
+
+    #[test]
+    fn hybrid_torrents_have_both_hashes_of_each_piece() {
+        let layer = piece_layer(&(0..16394).map(|i| i as u8).collect::<Vec<u8>>(), 16384);
+        let (torrent, a, b) = hybrid_torrent(true, layer);
+        let metainfo = parse(&torrent).unwrap();
+
+        assert_eq!(metainfo.version(), MetaVersion::Hybrid);
+        let info_bytes = encode(&metainfo.info.to_bencode());
+        assert_eq!(metainfo.info_hash, sha1_of(&info_bytes));
+        assert_eq!(metainfo.info_hash_v2, Some(sha256_of(&info_bytes)));
+        assert_eq!(
+            metainfo.swarm_info_hashes(),
+            vec![sha1_of(&info_bytes), sha256_of(&info_bytes)[..20].to_vec()]
+        );
+        let paths: Vec<&str> = metainfo
+            .info
+            .file_tree
+            .iter()
+            .map(|file| file.path.as_str())
+            .collect();
+        assert_eq!(paths, vec!["a", "b"]);
+
+        let hashes = metainfo.piece_hashes_v2();
+        let mut last_of_a = a[16384..].to_vec();
+        last_of_a.resize(16384, 0);
+        assert!(hashes[0].as_ref().unwrap().verify(&a[..16384]));
+        assert!(hashes[1].as_ref().unwrap().verify(&last_of_a));
+        assert!(hashes[2].as_ref().unwrap().verify(&b));
+        assert!(!hashes[2].as_ref().unwrap().verify(b"jello"));
+    }
+
+    #[test]
+    fn v2_torrents_are_known_by_their_truncated_hash() {
+        let layer = piece_layer(&(0..16394).map(|i| i as u8).collect::<Vec<u8>>(), 16384);
+        let (torrent, _, _) = hybrid_torrent(false, layer);
+        let metainfo = parse(&torrent).unwrap();
+
+        assert_eq!(metainfo.version(), MetaVersion::V2);
+        assert!(metainfo.info.pieces.is_empty());
+        assert_eq!(metainfo.info.length, 16394 + 5);
+        let info_bytes = encode(&metainfo.info.to_bencode());
+        assert_eq!(metainfo.info_hash, sha256_of(&info_bytes)[..20].to_vec());
+        assert_eq!(
+            metainfo.swarm_info_hashes(),
+            vec![metainfo.info_hash.clone()]
+        );
+        assert!(metainfo.piece_hashes_v2().is_empty());
+    }
+
+    #[test]
+    fn piece_layers_must_match_the_pieces_root() {
+        let (torrent, _, _) = hybrid_torrent(true, vec![vec![0; 32], vec![1; 32]]);
+        assert!(matches!(
+            parse(&torrent),
+            Err(MetainfoParserError::ValidationError)
+        ));
+    }
+
+    fn multi_file_torrent(name: &str, path: &[&str]) -> Vec<u8> {
+        let info = vec![
+            (
+                NAME_KEY,
+                BencodeDecodedValue::String(name.as_bytes().to_vec()),
+            ),
+            (PIECE_LENGTH_KEY, BencodeDecodedValue::Integer(16384)),
+            (PIECES_KEY, BencodeDecodedValue::String(vec![0; 20])),
+            (
+                FILES_KEY,
+                BencodeDecodedValue::List(vec![v1_file(path, 5, false)]),
+            ),
+        ];
+        encode(&dictionary(vec![
+            (
+                ANNOUNCE_KEY,
+                BencodeDecodedValue::String(b"http://a/".to_vec()),
+            ),
+            (INFO_KEY, dictionary(info)),
+        ]))
+    }
+
+    #[test]
+    fn files_can_only_be_placed_inside_the_download_directory() {
+        assert!(parse(&multi_file_torrent("test", &["dir", "a"])).is_ok());
+        for path in [
+            vec!["..", "evil"],
+            vec!["/etc", "passwd"],
+            vec!["dir/../../evil"],
+            vec!["dir\\..\\evil"],
+            vec!["dir", ""],
+            vec![".", "a"],
+        ] {
+            assert!(matches!(
+                parse(&multi_file_torrent("test", &path)),
+                Err(MetainfoParserError::UnsafePath(_))
+            ));
+        }
+        for name in ["..", "/tmp", "", "a/b"] {
+            assert!(matches!(
+                parse(&multi_file_torrent(name, &["a"])),
+                Err(MetainfoParserError::UnsafePath(_))
+            ));
+        }
+
+        let mut tree = HashMap::new();
+        tree.insert(b"..".to_vec(), tree_file(b"hello"));
+        assert!(matches!(
+            build_file_tree(&tree, &mut vec![], &mut vec![]),
+            Err(MetainfoParserError::UnsafePath(_))
+        ));
+    }
+
     #[test]
     fn empty_byte_array() {
         let empty_bytes: Vec<u8> = Vec::new();
@@ -295,12 +855,14 @@ mod tests {
             name: "sample.txt".to_string(),
             length: 20,
             files: None,
+            ..Default::default()
         };
 
         let invalid_metainfo: Metainfo = Metainfo {
             info: invalid_info,
             info_hash: decode_hex("d0d14c926e6e99761a2fdcff27b403d96376eff6").unwrap(),
             announce: "udp://tracker.openbittorrent.com:80".to_string(),
+            ..Default::default()
         };
 
         assert!(matches!(
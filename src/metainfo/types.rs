@@ -1,22 +1,44 @@
 use super::errors::MetainfoParserError;
+use super::merkle::{piece_hash_v2, PieceHashV2};
 use super::parser::parse;
+use crate::bencode::BencodeDecodedValue;
 use crate::logger::CustomLogger;
 use log::*;
+use std::collections::HashMap;
 use std::fs;
 use std::vec::Vec;
 const LOGGER: CustomLogger = CustomLogger::init("Config");
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 ///Bencode-Decoded metainfo file.
 pub struct Metainfo {
     ///contains information about the file to download
     pub info: Info,
-    ///20 byte SHA-1 hash obtained from hashing 'info' dictionary
+    ///20 byte SHA-1 hash obtained from hashing 'info' dictionary.
+    ///v2 only torrents use the SHA-256 hash truncated to 20 bytes instead
     pub info_hash: Vec<u8>,
+    ///32 byte SHA-256 hash of the 'info' dictionary, only in v2 and hybrid torrents
+    pub info_hash_v2: Option<Vec<u8>>,
+    ///hashes of the pieces of each file longer than a piece, by the pieces root of the file
+    pub piece_layers: HashMap<Vec<u8>, Vec<Vec<u8>>>,
     ///the announce URL used for connecting to the tracker
     pub announce: String,
+    ///tiers of backup trackers, empty if the torrent only has the announce URL
+    pub announce_list: Vec<Vec<String>>,
+    ///free-form text written by the author of the torrent
+    pub comment: Option<String>,
+    ///name and version of the program that created the torrent
+    pub created_by: Option<String>,
+    ///creation time of the torrent, in seconds since the unix epoch
+    pub creation_date: Option<i64>,
+    ///the string encoding used for the names in the info dictionary
+    pub encoding: Option<String>,
+    ///URLs of web servers holding the same files (url-list)
+    pub url_list: Vec<String>,
+    ///URLs of HTTP seeds serving pieces by their index (httpseeds)
+    pub http_seeds: Vec<String>,
 }
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 ///Bencode-Decoded Info Dictionary of a metainfo file.
 pub struct Info {
     ///the length in bytes of each single piece
@@ -29,12 +51,46 @@ pub struct Info {
     pub length: u64,
     /// files structure in case it is a multi-file torrent
     pub files: Option<Vec<File>>,
+    ///whether peers may only be found through the trackers of the torrent
+    pub private: bool,
+    ///MD5 of the file in hexadecimal, only in some single file torrents
+    pub md5sum: Option<String>,
+    ///2 in v2 and hybrid torrents, missing in v1 ones
+    pub meta_version: Option<i64>,
+    ///files of v2 and hybrid torrents, in the order of their file tree
+    pub file_tree: Vec<TreeFile>,
+    ///keys of the info dictionary this client doesn't read, kept as they were
+    pub extra: HashMap<Vec<u8>, BencodeDecodedValue>,
 }
 
-#[derive(Debug, Clone)]
+/// Versions of the protocol a torrent can be downloaded with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetaVersion {
+    V1,
+    V2,
+    /// both, with the same files, joining the swarm of each version
+    Hybrid,
+}
+
+/// File of the file tree of a v2 torrent
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TreeFile {
+    pub path: String,
+    pub length: u64,
+    ///root of the merkle tree of the file's blocks, empty files don't have one
+    pub pieces_root: Option<Vec<u8>>,
+    ///keys of the file dictionary this client doesn't read, kept as they were
+    pub extra: HashMap<Vec<u8>, BencodeDecodedValue>,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct File {
     pub path: String,
     pub length: u64,
+    ///MD5 of the file in hexadecimal, only in some torrents
+    pub md5sum: Option<String>,
+    ///keys of the file dictionary this client doesn't read, kept as they were
+    pub extra: HashMap<Vec<u8>, BencodeDecodedValue>,
 }
 
 impl File {
@@ -65,6 +121,204 @@ impl Metainfo {
     pub fn get_piece_count(&self) -> u32 {
         self.info.pieces.len() as u32
     }
+
+    /// Tiers of trackers to announce to, in order.
+    /// The announce-list replaces the announce URL when the torrent has one
+    pub fn trackers(&self) -> Vec<Vec<String>> {
+        if self.announce_list.is_empty() {
+            vec![vec![self.announce.clone()]]
+        } else {
+            self.announce_list.clone()
+        }
+    }
+
+    pub fn version(&self) -> MetaVersion {
+        match (self.info.meta_version, self.info.pieces.is_empty()) {
+            (Some(2), true) => MetaVersion::V2,
+            (Some(2), false) => MetaVersion::Hybrid,
+            _ => MetaVersion::V1,
+        }
+    }
+
+    /// The v2 info_hash truncated to 20 bytes, used by v2 peers and trackers
+    pub fn truncated_info_hash_v2(&self) -> Option<Vec<u8>> {
+        self.info_hash_v2.as_ref().map(|hash| hash[..20].to_vec())
+    }
+
+    /// Info hashes of the swarms the torrent joins, the one it's known by first.
+    /// Hybrid torrents join the swarm of each version
+    pub fn swarm_info_hashes(&self) -> Vec<Vec<u8>> {
+        let mut hashes = vec![self.info_hash.clone()];
+        if let Some(hash) = self.truncated_info_hash_v2() {
+            if hash != self.info_hash {
+                hashes.push(hash);
+            }
+        }
+        hashes
+    }
+
+    /// Hash each piece has in the merkle tree of its file, besides its SHA-1, in hybrid torrents.
+    /// Their files start at a piece, so each piece is part of a single one.
+    /// None for the pieces of padding files, and for every piece of the other torrents
+    pub fn piece_hashes_v2(&self) -> Vec<Option<PieceHashV2>> {
+        let mut hashes = vec![None; self.info.pieces.len()];
+        if self.version() != MetaVersion::Hybrid {
+            return hashes;
+        }
+        let piece_length = self.info.piece_length as u64;
+        let files = match &self.info.files {
+            Some(files) => files
+                .iter()
+                .map(|file| (file.path.clone(), file.length))
+                .collect(),
+            None => vec![(self.info.name.clone(), self.info.length)],
+        };
+        let mut offset = 0;
+        for (path, length) in files {
+            let tree_file = self.info.file_tree.iter().find(|file| file.path == path);
+            if let Some(root) = tree_file.and_then(|file| file.pieces_root.as_ref()) {
+                let first_piece = (offset / piece_length) as usize;
+                for piece in 0..length.div_ceil(piece_length) {
+                    let hash = if length <= piece_length {
+                        Some(root.clone())
+                    } else {
+                        self.piece_layers
+                            .get(root)
+                            .and_then(|layer| layer.get(piece as usize).cloned())
+                    };
+                    if let (Some(hash), Some(slot)) =
+                        (hash, hashes.get_mut(first_piece + piece as usize))
+                    {
+                        *slot = Some(piece_hash_v2(hash, piece, piece_length, length));
+                    }
+                }
+            }
+            offset += length;
+        }
+        hashes
+    }
+
+    /// Every URL of a web server or HTTP seed holding the torrent's data
+    pub fn web_seeds(&self) -> Vec<String> {
+        self.url_list
+            .iter()
+            .chain(&self.http_seeds)
+            .cloned()
+            .collect()
+    }
+}
+
+impl Info {
+    /// Builds the info dictionary back, with the keys this client doesn't read as they were,
+    /// so encoding it gives the bytes the info_hash was computed from
+    pub fn to_bencode(&self) -> BencodeDecodedValue {
+        let mut info = self.extra.clone();
+        info.insert(b"name".to_vec(), string(&self.name));
+        info.insert(
+            b"piece length".to_vec(),
+            BencodeDecodedValue::Integer(self.piece_length as i64),
+        );
+        // v2 only torrents don't have the keys of v1 ones
+        if self.meta_version != Some(2) || !self.pieces.is_empty() {
+            info.insert(
+                b"pieces".to_vec(),
+                BencodeDecodedValue::String(self.pieces.concat()),
+            );
+            match &self.files {
+                Some(files) => {
+                    let files = files.iter().map(File::to_bencode).collect();
+                    info.insert(b"files".to_vec(), BencodeDecodedValue::List(files));
+                }
+                None => {
+                    info.insert(
+                        b"length".to_vec(),
+                        BencodeDecodedValue::Integer(self.length as i64),
+                    );
+                }
+            }
+        }
+        if let Some(meta_version) = self.meta_version {
+            info.insert(
+                b"meta version".to_vec(),
+                BencodeDecodedValue::Integer(meta_version),
+            );
+        }
+        if !self.file_tree.is_empty() {
+            let mut tree = HashMap::new();
+            for file in &self.file_tree {
+                let path: Vec<&str> = file.path.split('/').collect();
+                insert_in_tree(&mut tree, &path, file.to_bencode());
+            }
+            info.insert(b"file tree".to_vec(), BencodeDecodedValue::Dictionary(tree));
+        }
+        if self.private {
+            info.insert(b"private".to_vec(), BencodeDecodedValue::Integer(1));
+        }
+        if let Some(md5sum) = &self.md5sum {
+            info.insert(b"md5sum".to_vec(), string(md5sum));
+        }
+        BencodeDecodedValue::Dictionary(info)
+    }
+}
+
+impl File {
+    fn to_bencode(&self) -> BencodeDecodedValue {
+        let mut file = self.extra.clone();
+        file.insert(
+            b"length".to_vec(),
+            BencodeDecodedValue::Integer(self.length as i64),
+        );
+        file.insert(
+            b"path".to_vec(),
+            BencodeDecodedValue::List(self.path.split('/').map(string).collect()),
+        );
+        if let Some(md5sum) = &self.md5sum {
+            file.insert(b"md5sum".to_vec(), string(md5sum));
+        }
+        BencodeDecodedValue::Dictionary(file)
+    }
+}
+
+impl TreeFile {
+    fn to_bencode(&self) -> BencodeDecodedValue {
+        let mut file = self.extra.clone();
+        file.insert(
+            b"length".to_vec(),
+            BencodeDecodedValue::Integer(self.length as i64),
+        );
+        if let Some(pieces_root) = &self.pieces_root {
+            file.insert(
+                b"pieces root".to_vec(),
+                BencodeDecodedValue::String(pieces_root.clone()),
+            );
+        }
+        BencodeDecodedValue::Dictionary(file)
+    }
+}
+
+// the file tree has a dictionary per directory, the file itself is under an empty key
+fn insert_in_tree(
+    tree: &mut HashMap<Vec<u8>, BencodeDecodedValue>,
+    path: &[&str],
+    file: BencodeDecodedValue,
+) {
+    match path.split_first() {
+        None => {
+            tree.insert(vec![], file);
+        }
+        Some((name, rest)) => {
+            let entry = tree
+                .entry(name.as_bytes().to_vec())
+                .or_insert_with(|| BencodeDecodedValue::Dictionary(HashMap::new()));
+            if let BencodeDecodedValue::Dictionary(subtree) = entry {
+                insert_in_tree(subtree, rest, file);
+            }
+        }
+    }
+}
+
+fn string(value: &str) -> BencodeDecodedValue {
+    BencodeDecodedValue::String(value.as_bytes().to_vec())
 }
 
 impl PartialEq for Info {
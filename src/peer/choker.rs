@@ -0,0 +1,178 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Amount of peers that can be unchoked at the same time, across inbound and outbound connections
+pub const MAX_UNCHOKED_PEERS: usize = 25;
+/// Pieces remembered as recently served, suggested to new peers with the fast extension
+pub const RECENTLY_SERVED_PIECES: usize = 4;
+
+/// Decides which remote peers we upload to.
+/// It is shared between every connection of a torrent, so the outgoing connections
+/// opened by the client and the incoming ones accepted by the server compete for the same slots.
+/// While the torrent is paused no slot is given and no block is served.
+/// Peers that snub us only get the optimistic slot, one of the `max_unchoked`, so they can't take
+/// the slots of the peers that send us blocks but still get a chance to start sending again.
+/// It also remembers the pieces served last, which are likely still in the disk cache.
+#[derive(Debug, Clone)]
+pub struct Choker {
+    unchoked: Arc<Mutex<usize>>,
+    max_unchoked: usize,
+    paused: Arc<AtomicBool>,
+    optimistic_taken: Arc<AtomicBool>,
+    // the most recent first
+    recently_served: Arc<Mutex<VecDeque<u32>>>,
+}
+
+impl Default for Choker {
+    fn default() -> Self {
+        Self::new(MAX_UNCHOKED_PEERS)
+    }
+}
+
+impl Choker {
+    pub fn new(max_unchoked: usize) -> Self {
+        Self {
+            unchoked: Arc::new(Mutex::new(0)),
+            max_unchoked,
+            paused: Arc::new(AtomicBool::new(false)),
+            optimistic_taken: Arc::new(AtomicBool::new(false)),
+            recently_served: Arc::new(Mutex::new(VecDeque::new())),
+        }
+    }
+
+    /// Takes an upload slot if there is one free.
+    /// Returns true if the caller is now allowed to unchoke its peer
+    pub fn try_unchoke(&self) -> bool {
+        if self.is_paused() {
+            return false;
+        }
+        match self.unchoked.lock() {
+            Ok(mut unchoked) if *unchoked < self.max_unchoked => {
+                *unchoked += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Gives back an upload slot taken with `try_unchoke`
+    pub fn release(&self) {
+        if let Ok(mut unchoked) = self.unchoked.lock() {
+            *unchoked = unchoked.saturating_sub(1);
+        }
+    }
+
+    /// Takes the optimistic slot for a peer that snubs us, if it is free and there is room for it.
+    /// Returns true if the caller is now allowed to unchoke its peer
+    pub fn try_unchoke_optimistically(&self) -> bool {
+        if self.optimistic_taken.swap(true, Ordering::SeqCst) {
+            return false;
+        }
+        if self.try_unchoke() {
+            return true;
+        }
+        self.optimistic_taken.store(false, Ordering::SeqCst);
+        false
+    }
+
+    /// Gives back the slot taken with `try_unchoke_optimistically`
+    pub fn release_optimistic(&self) {
+        self.release();
+        self.optimistic_taken.store(false, Ordering::SeqCst);
+    }
+
+    pub fn unchoked_count(&self) -> usize {
+        self.unchoked.lock().map(|unchoked| *unchoked).unwrap_or(0)
+    }
+
+    /// Stops uploading to every peer of the torrent until `resume` is called
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::Relaxed);
+    }
+
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Remembers that a block of the piece was just read to be sent
+    pub fn served(&self, piece_index: u32) {
+        if let Ok(mut recent) = self.recently_served.lock() {
+            recent.retain(|index| *index != piece_index);
+            recent.push_front(piece_index);
+            recent.truncate(RECENTLY_SERVED_PIECES);
+        }
+    }
+
+    /// The pieces served last, the most recent first
+    pub fn recently_served(&self) -> Vec<u32> {
+        self.recently_served
+            .lock()
+            .map(|recent| recent.iter().copied().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gives_no_more_slots_than_allowed() {
+        let choker = Choker::new(2);
+        assert!(choker.try_unchoke());
+        assert!(choker.clone().try_unchoke());
+        assert!(!choker.try_unchoke());
+        assert_eq!(choker.unchoked_count(), 2);
+    }
+
+    #[test]
+    fn gives_no_slots_while_paused() {
+        let choker = Choker::new(2);
+        choker.clone().pause();
+        assert!(!choker.try_unchoke());
+        choker.resume();
+        assert!(choker.try_unchoke());
+    }
+
+    #[test]
+    fn remembers_the_last_pieces_served() {
+        let choker = Choker::new(1);
+        for index in [1, 2, 1, 3, 4, 5] {
+            choker.clone().served(index);
+        }
+        assert_eq!(choker.recently_served(), vec![5, 4, 3, 1]);
+    }
+
+    #[test]
+    fn peers_snubbing_us_share_a_single_slot() {
+        let choker = Choker::new(3);
+        assert!(choker.try_unchoke_optimistically());
+        assert!(!choker.try_unchoke_optimistically());
+        assert!(choker.try_unchoke());
+        assert!(choker.try_unchoke());
+        assert_eq!(choker.unchoked_count(), 3);
+
+        choker.release_optimistic();
+        choker.release();
+        assert!(choker.try_unchoke_optimistically());
+        // the last slot is there, but not for a second peer snubbing us
+        assert!(!choker.try_unchoke_optimistically());
+        assert!(choker.try_unchoke());
+    }
+
+    #[test]
+    fn released_slot_can_be_taken_again() {
+        let choker = Choker::new(1);
+        assert!(choker.try_unchoke());
+        choker.release();
+        assert!(choker.try_unchoke());
+        choker.release();
+        choker.release();
+        assert_eq!(choker.unchoked_count(), 0);
+    }
+}
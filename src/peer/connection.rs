@@ -1,30 +1,41 @@
+use super::constants::{BLOCK_REQUEST_TIMEOUT, SNUB_TIMEOUT};
 use super::errors::IPeerMessageServiceError;
 use super::errors::PeerConnectionError;
 use super::service::*;
 use super::types::*;
+use super::upload::{UploadEvent, UploadState};
 use super::utils::*;
 use super::Peer;
+use crate::bandwidth::PeerBandwidth;
 use crate::constants::*;
 use crate::metainfo::Metainfo;
 use crate::ui::UIMessageSender;
 use log::*;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::net::Ipv4Addr;
+use std::time::{Duration, Instant};
 
+/// Connection with another peer, opened by the client.
+/// It downloads pieces from the peer and, through its [`UploadState`], serves the blocks
+/// the peer requests from us in between, just like the connections accepted by the server.
 pub struct PeerConnection {
-    pub _am_choking: bool,
     pub _am_interested: bool,
     pub peer_choking: bool,
-    pub _peer_interested: bool,
+    pub upload: UploadState,
     pub message_service: Box<dyn IClientPeerMessageService + Send>,
     pub metainfo: Metainfo,
     pub client_peer_id: Vec<u8>,
     pub bitfield: Bitfield,
     pub peer_id: Vec<u8>,
     pub peer: Peer,
-    pub last_download_rate_update: std::time::Instant,
-    pub last_downloaded_pieces: Arc<AtomicUsize>,
+    pub bandwidth: PeerBandwidth,
     pub ui_message_sender: UIMessageSender,
+    /// pieces the peer lets us request while it chokes us (fast extension)
+    pub allowed_fast: HashSet<u32>,
+    /// whether the peer stopped sending the blocks we asked for while unchoking us
+    pub snubbed: bool,
+    // since when we wait for blocks without receiving any, None once one arrives
+    waiting_since: Option<Instant>,
 }
 
 impl PeerConnection {
@@ -32,23 +43,27 @@ impl PeerConnection {
         peer: Peer,
         client_peer_id: &[u8],
         metainfo: &Metainfo,
-        message_service: Box<dyn IClientPeerMessageService + Send>,
+        mut message_service: Box<dyn IClientPeerMessageService + Send>,
         ui_message_sender: UIMessageSender,
+        upload: UploadState,
+        bandwidth: PeerBandwidth,
     ) -> Self {
+        message_service.set_bandwidth(bandwidth.clone());
         Self {
-            _am_choking: true,
             _am_interested: true,
             peer_choking: true,
-            _peer_interested: false,
+            upload,
             client_peer_id: client_peer_id.to_vec(),
             metainfo: metainfo.clone(),
             message_service,
             bitfield: Bitfield::new(),
             peer_id: peer.peer_id.clone(),
-            last_downloaded_pieces: Arc::new(AtomicUsize::new(0)),
-            last_download_rate_update: std::time::Instant::now(),
+            bandwidth,
             ui_message_sender,
             peer,
+            allowed_fast: HashSet::new(),
+            snubbed: false,
+            waiting_since: None,
         }
     }
     pub fn get_peer_id(&self) -> Vec<u8> {
@@ -62,26 +77,79 @@ impl PeerConnection {
         self.bitfield.clone()
     }
 
+    pub fn connection_state(&self) -> PeerConnectionState {
+        PeerConnectionState {
+            client: PeerState {
+                chocked: self.peer_choking,
+                interested: self._am_interested,
+            },
+            peer: PeerState {
+                chocked: self.upload.am_choking,
+                interested: self.upload.peer_interested,
+            },
+            snubbed: self.snubbed,
+        }
+    }
+
     fn wait_for_message(&mut self) -> Result<PeerMessage, IPeerMessageServiceError> {
         let message = self.message_service.wait_for_message()?;
+        self.handle_message(message)
+    }
+
+    // Like wait_for_message, but gives up at the deadline, returning None
+    fn wait_for_message_until(
+        &mut self,
+        deadline: Instant,
+    ) -> Result<Option<PeerMessage>, IPeerMessageServiceError> {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match self.message_service.wait_for_message_within(timeout)? {
+            Some(message) => self.handle_message(message).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn handle_message(
+        &mut self,
+        message: PeerMessage,
+    ) -> Result<PeerMessage, IPeerMessageServiceError> {
         match message.id {
             PeerMessageId::Unchoke => {
+                // the time spent choked doesn't count as snubbing
+                if self.peer_choking {
+                    self.waiting_since = self.waiting_since.map(|_| Instant::now());
+                }
                 self.peer_choking = false;
             }
             PeerMessageId::Choke => {
                 self.peer_choking = true;
             }
-            PeerMessageId::Interested => {
-                self._peer_interested = true;
-            }
-            PeerMessageId::NotInterested => {
-                self._peer_interested = false;
-            }
             PeerMessageId::Bitfield => {
                 self.bitfield.set_bitfield(&message.payload);
             }
-            PeerMessageId::Have => {}
-            PeerMessageId::Piece => {}
+            PeerMessageId::HaveAll | PeerMessageId::HaveNone => {
+                let has = message.id == PeerMessageId::HaveAll;
+                let pieces = vec![has; self.metainfo.info.pieces.len()];
+                self.bitfield
+                    .set_bitfield(&bitmap_from_pieces_vector(&pieces));
+            }
+            PeerMessageId::AllowedFast => {
+                if let Some(index) = piece_index_of(&message) {
+                    self.allowed_fast.insert(index);
+                }
+            }
+            PeerMessageId::Interested
+            | PeerMessageId::NotInterested
+            | PeerMessageId::Request
+            | PeerMessageId::Cancel => {
+                let event = self
+                    .upload
+                    .handle_message(&message, &mut *self.message_service)?;
+                self.after_upload_event(event);
+            }
+            PeerMessageId::Have
+            | PeerMessageId::Piece
+            | PeerMessageId::SuggestPiece
+            | PeerMessageId::RejectRequest => {}
             _ => {
                 return Err(IPeerMessageServiceError::UnhandledMessage);
             }
@@ -89,33 +157,105 @@ impl PeerConnection {
         Ok(message)
     }
 
+    fn after_upload_event(&self, event: UploadEvent) {
+        match event {
+            UploadEvent::BlockSent(piece_index, block_number) => {
+                trace!(
+                    "Sent block {} of piece {} to peer {:?}",
+                    block_number,
+                    piece_index,
+                    self.peer_id
+                );
+                self.ui_message_sender
+                    .send_upload_rate(self.bandwidth.upload_rate() as f32, &self.peer_id);
+            }
+            UploadEvent::Unchoked | UploadEvent::Choked => {
+                self.ui_message_sender
+                    .update_peer_state(self.peer_id.clone(), self.connection_state());
+            }
+            _ => {}
+        }
+    }
+
+    // A snubbing peer only gets the optimistic upload slot until it sends us a block again
+    fn set_snubbed(&mut self, snubbed: bool) -> Result<(), IPeerMessageServiceError> {
+        if self.snubbed == snubbed {
+            return Ok(());
+        }
+        self.snubbed = snubbed;
+        debug!("Peer {:?} snubbed: {}", self.peer_id, snubbed);
+        self.upload
+            .set_snubbed(snubbed, &mut *self.message_service)?;
+        self.ui_message_sender
+            .update_peer_state(self.peer_id.clone(), self.connection_state());
+        Ok(())
+    }
+
+    fn block_received(&mut self) -> Result<(), IPeerMessageServiceError> {
+        self.waiting_since = None;
+        self.set_snubbed(false)
+    }
+
+    // Takes back the request that wasn't answered in time. The peer is snubbing us
+    // if it has been unchoking us for SNUB_TIMEOUT without sending the blocks asked for
+    fn request_timed_out(&mut self, request: &PeerMessage) -> Result<(), IPeerMessageServiceError> {
+        self.message_service
+            .send_message(&PeerMessage::cancel(&request.payload))?;
+        let waited = self
+            .waiting_since
+            .map_or(Duration::ZERO, |since| since.elapsed());
+        if !self.peer_choking && waited >= Duration::from_secs(SNUB_TIMEOUT) {
+            self.set_snubbed(true)?;
+        }
+        Ok(())
+    }
+
+    /// Reads and answers the messages the peer already sent, without blocking if there are none.
+    /// Used while the connection has no piece to download, so the peer can keep downloading from us
+    pub fn serve_pending_messages(&mut self) -> Result<(), IPeerMessageServiceError> {
+        while self.message_service.has_pending_message() {
+            self.wait_for_message()?;
+        }
+        Ok(())
+    }
+
+    /// Tells the peer we have a new piece it can ask for
+    pub fn send_have(&mut self, piece_index: u32) -> Result<(), IPeerMessageServiceError> {
+        self.message_service
+            .send_message(&PeerMessage::have(piece_index))
+    }
+
     fn wait_until_ready(&mut self) -> Result<(), IPeerMessageServiceError> {
         loop {
             self.wait_for_message()?;
-            self.ui_message_sender.update_peer_state(
-                self.peer_id.clone(),
-                PeerConnectionState {
-                    client: (PeerState {
-                        chocked: self.peer_choking,
-                        interested: self._am_interested,
-                    }),
-                    peer: (PeerState {
-                        chocked: self._am_choking,
-                        interested: self._peer_interested,
-                    }),
-                },
-            );
-
-            if !self.peer_choking && self.bitfield.non_empty() {
+            self.ui_message_sender
+                .update_peer_state(self.peer_id.clone(), self.connection_state());
+
+            let can_request = !self.peer_choking || !self.allowed_fast.is_empty();
+            if can_request && self.bitfield.non_empty() {
                 break;
             }
         }
         Ok(())
     }
 
+    // With the fast extension the connection may be ready while choked, pieces outside the allowed fast set
+    // have to wait for the peer to unchoke us
+    fn wait_until_requestable(&mut self, piece_index: u32) -> Result<(), IPeerMessageServiceError> {
+        while self.message_service.supports_fast_extension()
+            && self.peer_choking
+            && !self.allowed_fast.contains(&piece_index)
+        {
+            self.wait_for_message()?;
+        }
+        Ok(())
+    }
+
     // Requests a block of data of some piece (index refers to the index of the piece).
     // Data starts from the offset within the piece, and its size is the length requested.
     // Once a block is recieved, it is checked if it is valid, and if it is, it is returned.
+    // A peer with the fast extension may reject the request instead,
+    // and a request that isn't answered in BLOCK_REQUEST_TIMEOUT is cancelled.
     fn request_block(
         &mut self,
         index: u32,
@@ -128,27 +268,46 @@ impl PeerConnection {
         // calculate duration between sending the message and moving on to next instruction
         let msg = PeerMessage::request(index, begin, lenght);
         self.message_service.send_message(&msg)?;
+        self.waiting_since.get_or_insert_with(Instant::now);
+        let deadline = Instant::now() + Duration::from_secs(BLOCK_REQUEST_TIMEOUT);
 
         loop {
-            let message = self.wait_for_message().map_err(|_| {
+            let waiting_error = |_| {
                 PeerConnectionError::PieceRequestingError("Failed while waiting for message".into())
-            })?;
+            };
+            let Some(message) = self
+                .wait_for_message_until(deadline)
+                .map_err(waiting_error)?
+            else {
+                self.request_timed_out(&msg)?;
+                break Err(PeerConnectionError::RequestTimedOut(index));
+            };
 
+            if message.id == PeerMessageId::RejectRequest
+                && valid_block(&message.payload, index, begin)
+            {
+                break Err(PeerConnectionError::RequestRejected(index));
+            }
             if message.id == PeerMessageId::Piece {
                 if valid_block(&message.payload, index, begin) {
+                    self.block_received()?;
                     let block = message.payload[8..].to_vec();
                     break Ok(block);
-                } else {
-                    break Err(PeerConnectionError::PieceRequestingError(
-                        "Invalid block received".to_string(),
-                    ));
                 }
+                // the answer to a request that timed out, it was already asked to someone else
+                debug!(
+                    "Ignoring block nobody waits for from peer {:?}, {} bytes wasted",
+                    self.peer_id,
+                    message.payload.len().saturating_sub(8)
+                );
             }
         }
     }
 
     // Requests a specific piece from the peer.
     // It does it sequentially, by requesting blocks of data, until the whole piece is recieved.
+    // Each block is written to the storage as it arrives, and the ones already written,
+    // by a previous attempt or a previous run, aren't requested again.
     // Returns the piece unchecked
     pub fn request_piece(
         &mut self,
@@ -159,25 +318,27 @@ impl PeerConnection {
         let mut counter = 0;
         let mut piece: Vec<u8> = vec![];
         debug!("requesting piece: {}", piece_index);
+        self.wait_until_requestable(piece_index)?;
+        let storage = self.upload.storage().clone();
         while counter < self.metainfo.info.piece_length {
-            let ui_sender_clone = ui_message_sender.clone();
-            let block: Vec<u8> =
-                self.request_block(piece_index, counter, block_size, ui_sender_clone)?;
+            let block = match storage.read_written_block(piece_index, counter, block_size) {
+                Some(block) => block,
+                None => {
+                    let ui_sender_clone = ui_message_sender.clone();
+                    let block: Vec<u8> =
+                        self.request_block(piece_index, counter, block_size, ui_sender_clone)?;
+                    if let Err(err) = storage.write_block(piece_index, counter, &block) {
+                        debug!("Couldn't keep block of piece {}: {}", piece_index, err);
+                    }
+                    block
+                }
+            };
             piece.extend(block);
             counter += block_size;
         }
 
-        self.last_downloaded_pieces.fetch_add(1, Ordering::Relaxed);
-
-        if self.last_downloaded_pieces.load(Ordering::Relaxed) == 1 {
-            let time = self.last_download_rate_update.elapsed().as_secs_f32();
-            self.ui_message_sender.send_download_rate(
-                2f32 * self.metainfo.info.piece_length as f32 / time,
-                &self.get_peer_id(),
-            );
-            self.last_download_rate_update = std::time::Instant::now();
-            self.last_downloaded_pieces.store(0, Ordering::Relaxed);
-        }
+        self.ui_message_sender
+            .send_download_rate(self.bandwidth.download_rate() as f32, &self.peer_id);
         debug!(
             "recieved piece (not validated yet), piece index: {}",
             piece_index
@@ -193,8 +354,33 @@ impl PeerConnection {
                 IPeerMessageServiceError::PeerHandshakeError("Handshake error".to_string())
             })?;
 
-        self.message_service
-            .send_message(&PeerMessage::unchoke())
+        if self.message_service.supports_fast_extension() {
+            let ip = self.peer.ip.parse::<Ipv4Addr>().ok();
+            self.upload
+                .enable_fast_extension(ip, &self.metainfo.info_hash);
+        }
+
+        // with the fast extension the peer is always told what we have, HaveNone included
+        let have_message = self.upload.have_message();
+        if self.upload.fast_extension || have_message.payload.iter().any(|byte| *byte != 0) {
+            self.message_service
+                .send_message(&have_message)
+                .map_err(|_| {
+                    IPeerMessageServiceError::SendingMessageError(
+                        "Error trying to send bitfield message".to_string(),
+                    )
+                })?;
+        }
+        self.upload
+            .send_fast_hints(&mut *self.message_service)
+            .map_err(|_| {
+                IPeerMessageServiceError::SendingMessageError(
+                    "Error trying to send allowed fast messages".to_string(),
+                )
+            })?;
+
+        self.upload
+            .offer_unchoke(&mut *self.message_service)
             .map_err(|_| {
                 IPeerMessageServiceError::SendingMessageError(
                     "Error trying to send unchoke message".to_string(),
@@ -214,12 +400,22 @@ impl PeerConnection {
     }
 }
 
+// the piece index is the first field of Have, Suggest Piece and Allowed Fast messages
+fn piece_index_of(message: &PeerMessage) -> Option<u32> {
+    let index: [u8; 4] = message.payload.get(0..4)?.try_into().ok()?;
+    Some(u32::from_be_bytes(index))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::metainfo::Info;
     use crate::metainfo::Metainfo;
+    use crate::peer::Choker;
+    use crate::storage::PieceFileStorage;
     use sha1::{Digest, Sha1};
+    use std::collections::VecDeque;
+    use std::sync::Arc;
 
     fn get_pieces_hash_from_bytes(file: &Vec<u8>) -> Vec<Vec<u8>> {
         let mut pieces = Vec::new();
@@ -246,8 +442,10 @@ mod tests {
                 length: file.len() as u64,
                 name: "".to_string(),
                 files: None,
+                ..Default::default()
             },
             info_hash: vec![],
+            ..Default::default()
         };
 
         let peer_mock = Peer {
@@ -255,6 +453,7 @@ mod tests {
             port: 0,
             peer_id: vec![],
             peer_message_service_provider: mock_peer_message_service_provider,
+            web_seed: None,
         };
         let peer_message_stream_mock = PeerMessageServiceMock {
             counter: 0,
@@ -267,6 +466,11 @@ mod tests {
             &metainfo_mock,
             Box::new(peer_message_stream_mock),
             UIMessageSender::no_ui(),
+            UploadState::new(
+                Arc::new(PieceFileStorage::new("", 2, "", true)),
+                Choker::default(),
+            ),
+            PeerBandwidth::unlimited(),
         );
 
         // measure time spent requesting a piece
@@ -276,48 +480,109 @@ mod tests {
         assert_eq!(file[0..8], piece);
     }
 
-    #[test]
-    fn gets_invalid_block() {
-        let file = vec![0, 0, 0, 0, 1, 1, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0];
+    // a peer that sends the scripted messages, once they run out every wait times out right away
+    struct ScriptedPeerMock {
+        messages: VecDeque<PeerMessage>,
+        sent: Vec<PeerMessageId>,
+    }
 
-        let mut pieces: Vec<Vec<u8>> = Vec::new();
-        pieces.push(sha1_of(&file[0..8].to_vec()));
-        pieces.push(sha1_of(&file[8..16].to_vec()));
+    impl IPeerMessageService for ScriptedPeerMock {
+        fn wait_for_message(&mut self) -> Result<PeerMessage, IPeerMessageServiceError> {
+            Err(IPeerMessageServiceError::UnhandledMessage)
+        }
 
+        fn wait_for_message_within(
+            &mut self,
+            _timeout: Duration,
+        ) -> Result<Option<PeerMessage>, IPeerMessageServiceError> {
+            Ok(self.messages.pop_front())
+        }
+
+        fn send_message(&mut self, message: &PeerMessage) -> Result<(), IPeerMessageServiceError> {
+            self.sent.push(message.id);
+            Ok(())
+        }
+    }
+
+    impl IClientPeerMessageService for ScriptedPeerMock {
+        fn handshake(
+            &mut self,
+            _info_hash: &[u8],
+            _peer_id: &[u8],
+        ) -> Result<(), IPeerMessageServiceError> {
+            Ok(())
+        }
+    }
+
+    fn scripted_connection(messages: Vec<PeerMessage>) -> PeerConnection {
         let metainfo_mock = Metainfo {
-            announce: "".to_string(),
             info: Info {
                 piece_length: 8,
-                pieces: pieces,
+                pieces: vec![vec![0; 20]; 2],
                 length: 16,
-                name: "".to_string(),
-                files: None,
+                ..Default::default()
             },
-            info_hash: vec![],
+            ..Default::default()
         };
-
         let peer_mock = Peer {
             ip: "".to_string(),
             port: 0,
             peer_id: vec![],
             peer_message_service_provider: mock_peer_message_service_provider,
-        };
-        let peer_message_stream_mock = PeerMessageServiceMock {
-            counter: 0,
-            file: file.clone(),
-            block_size: 2 as u32,
+            web_seed: None,
         };
         let mut peer_connection = PeerConnection::new(
             peer_mock,
             &vec![1, 2, 3, 4],
             &metainfo_mock,
-            Box::new(peer_message_stream_mock),
+            Box::new(ScriptedPeerMock {
+                messages: messages.into(),
+                sent: vec![],
+            }),
             UIMessageSender::no_ui(),
+            UploadState::new(
+                Arc::new(PieceFileStorage::new("", 2, "", true)),
+                Choker::default(),
+            ),
+            PeerBandwidth::unlimited(),
         );
+        peer_connection.peer_choking = false;
+        peer_connection
+    }
+
+    #[test]
+    fn blocks_that_werent_requested_are_ignored() {
+        let file = vec![0, 0, 1, 1, 2, 2, 3, 3];
+        let mut messages = vec![PeerMessage::piece(1, 0, vec![9, 9])];
+        for (block, data) in file.chunks(2).enumerate() {
+            messages.push(PeerMessage::piece(0, block * 2, data.to_vec()));
+        }
+        let mut peer_connection = scripted_connection(messages);
+
+        let piece = peer_connection.request_piece(0, 2, UIMessageSender::no_ui());
+
+        assert_eq!(piece.unwrap(), file);
+    }
+
+    #[test]
+    fn unanswered_requests_time_out_and_snubbing_is_detected() {
+        let mut peer_connection = scripted_connection(vec![]);
+
+        let first = peer_connection.request_piece(0, 2, UIMessageSender::no_ui());
+        assert!(matches!(
+            first,
+            Err(PeerConnectionError::RequestTimedOut(0))
+        ));
+        assert!(!peer_connection.snubbed);
 
+        // unchoked and waiting for blocks for longer than SNUB_TIMEOUT
+        peer_connection.waiting_since = Some(Instant::now() - Duration::from_secs(SNUB_TIMEOUT));
+        let second = peer_connection.request_piece(0, 2, UIMessageSender::no_ui());
         assert!(matches!(
-            peer_connection.request_piece(1, BLOCK_SIZE, UIMessageSender::no_ui()),
-            Err(PeerConnectionError::PieceRequestingError(_))
+            second,
+            Err(PeerConnectionError::RequestTimedOut(0))
         ));
+        assert!(peer_connection.snubbed);
+        assert!(peer_connection.connection_state().snubbed);
     }
 }
@@ -0,0 +1,82 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Caps the amount of peer connections open at the same time.
+/// Clones share the same count, so a session hands one to the server and to every torrent
+/// and the inbound and outbound connections of all of them add up to the same cap
+#[derive(Debug, Clone)]
+pub struct ConnectionLimit {
+    open: Arc<AtomicUsize>,
+    max_open: Option<usize>,
+}
+
+/// A connection counted by a [`ConnectionLimit`], it is given back when dropped
+#[derive(Debug)]
+pub struct ConnectionSlot {
+    open: Arc<AtomicUsize>,
+}
+
+impl ConnectionLimit {
+    /// `None` means unlimited
+    pub fn new(max_open: Option<usize>) -> Self {
+        Self {
+            open: Arc::new(AtomicUsize::new(0)),
+            max_open,
+        }
+    }
+
+    pub fn unlimited() -> Self {
+        Self::new(None)
+    }
+
+    /// Takes a slot for a new connection, None if the cap was reached
+    pub fn try_acquire(&self) -> Option<ConnectionSlot> {
+        self.open
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |open| {
+                match self.max_open {
+                    Some(max_open) if open >= max_open => None,
+                    _ => Some(open + 1),
+                }
+            })
+            .ok()
+            .map(|_| ConnectionSlot {
+                open: self.open.clone(),
+            })
+    }
+
+    pub fn open_count(&self) -> usize {
+        self.open.load(Ordering::Acquire)
+    }
+
+    /// Whether the cap was reached, so no more connections can be opened for now
+    pub fn is_full(&self) -> bool {
+        self.max_open
+            .is_some_and(|max_open| self.open_count() >= max_open)
+    }
+}
+
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        self.open.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slots_are_given_back_when_dropped() {
+        let limit = ConnectionLimit::new(Some(2));
+        let first = limit.try_acquire();
+        let second = limit.clone().try_acquire();
+
+        assert!(first.is_some() && second.is_some());
+        assert!(limit.try_acquire().is_none());
+        assert!(limit.is_full());
+
+        drop(first);
+        assert_eq!(limit.open_count(), 1);
+        assert!(limit.try_acquire().is_some());
+    }
+}
@@ -1,4 +1,8 @@
 pub const MESSAGE_TIMEOUT: u64 = 100;
+/// Seconds a requested block has to arrive in before the request is given up on
+pub const BLOCK_REQUEST_TIMEOUT: u64 = 20;
+/// Seconds without a block while unchoked and waiting for one before the peer is considered to be snubbing us
+pub const SNUB_TIMEOUT: u64 = 60;
 pub const MAX_RETRIES: u8 = 3;
 pub const PSTRLEN: u8 = 19;
 pub const HANDSHAKE_LENGTH: usize = 68;
@@ -0,0 +1,112 @@
+use super::constants::*;
+use super::errors::IPeerMessageServiceError;
+use super::types::*;
+use super::utils::is_keep_alive_message;
+
+/// Unit of the peer wire protocol found in the received bytes
+#[derive(Debug, Clone)]
+pub enum PeerFrame {
+    /// The handshake, always the first thing a peer sends
+    Handshake(Vec<u8>),
+    Message(PeerMessage),
+    KeepAlive,
+}
+
+/// Splits the bytes received from a peer, in whatever chunks they arrive, into handshake and messages.
+/// It is used where the connection isn't read through a blocking [`super::PeerMessageService`],
+/// like the connections the server handles from the reactor's events
+#[derive(Debug, Default)]
+pub struct PeerMessageDecoder {
+    buffer: Vec<u8>,
+    handshake_received: bool,
+}
+
+impl PeerMessageDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// Takes the next complete frame out of the received bytes.
+    /// Returns `None` if more bytes are needed to complete it
+    pub fn next_frame(&mut self) -> Result<Option<PeerFrame>, IPeerMessageServiceError> {
+        if !self.handshake_received {
+            return Ok(self.next_handshake());
+        }
+        if self.buffer.len() < MESSAGE_LENGTH_SIZE {
+            return Ok(None);
+        }
+
+        let mut message_length = [0u8; MESSAGE_LENGTH_SIZE];
+        message_length.copy_from_slice(&self.buffer[..MESSAGE_LENGTH_SIZE]);
+        let message_length = u32::from_be_bytes(message_length);
+        if is_keep_alive_message(message_length) {
+            self.buffer.drain(..MESSAGE_LENGTH_SIZE);
+            return Ok(Some(PeerFrame::KeepAlive));
+        }
+
+        let frame_length = MESSAGE_LENGTH_SIZE + message_length as usize;
+        if self.buffer.len() < frame_length {
+            return Ok(None);
+        }
+        let frame: Vec<u8> = self.buffer.drain(..frame_length).collect();
+        let id = PeerMessageId::from_u8(frame[MESSAGE_LENGTH_SIZE])
+            .map_err(|_| IPeerMessageServiceError::InvalidMessageId)?;
+
+        Ok(Some(PeerFrame::Message(PeerMessage {
+            id,
+            length: message_length,
+            payload: frame[MESSAGE_LENGTH_SIZE + MESSAGE_ID_SIZE..].to_vec(),
+        })))
+    }
+
+    fn next_handshake(&mut self) -> Option<PeerFrame> {
+        let pstrlen = *self.buffer.first()? as usize;
+        let handshake_length = HANDSHAKE_LENGTH - PSTRLEN as usize + pstrlen;
+        if self.buffer.len() < handshake_length {
+            return None;
+        }
+        self.handshake_received = true;
+        Some(PeerFrame::Handshake(
+            self.buffer.drain(..handshake_length).collect(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer::create_handshake_message;
+
+    #[test]
+    fn handshake_and_messages_are_split_even_if_they_arrive_in_pieces() {
+        let mut bytes = create_handshake_message(&[1; 20], &[2; 20]);
+        bytes.extend(PeerMessage::interested().to_bytes());
+        bytes.extend([0, 0, 0, 0]);
+        bytes.extend(PeerMessage::have(3).to_bytes());
+
+        let mut decoder = PeerMessageDecoder::new();
+        let mut frames = vec![];
+        for chunk in bytes.chunks(7) {
+            decoder.push(chunk);
+            while let Some(frame) = decoder.next_frame().unwrap() {
+                frames.push(frame);
+            }
+        }
+
+        assert_eq!(frames.len(), 4);
+        assert!(
+            matches!(&frames[0], PeerFrame::Handshake(handshake) if handshake.len() == HANDSHAKE_LENGTH)
+        );
+        assert!(
+            matches!(&frames[1], PeerFrame::Message(message) if message.id == PeerMessageId::Interested)
+        );
+        assert!(matches!(frames[2], PeerFrame::KeepAlive));
+        assert!(
+            matches!(&frames[3], PeerFrame::Message(message) if message.payload == vec![0, 0, 0, 3])
+        );
+    }
+}
@@ -12,6 +12,10 @@ pub enum PeerConnectionError {
     PieceSavingError(String),
     LoggingPieceError(String),
     JoiningError(String),
+    /// The peer rejected the request for a block of the piece with this index
+    RequestRejected(u32),
+    /// The peer didn't send a block of the piece with this index in time
+    RequestTimedOut(u32),
 }
 
 #[derive(Debug)]
@@ -99,6 +103,12 @@ impl fmt::Display for PeerConnectionError {
             PeerConnectionError::JoiningError(error) => {
                 write!(f, "Joining error: {}", error)
             }
+            PeerConnectionError::RequestRejected(index) => {
+                write!(f, "Request for piece {} rejected", index)
+            }
+            PeerConnectionError::RequestTimedOut(index) => {
+                write!(f, "Request for piece {} timed out", index)
+            }
         }
     }
 }
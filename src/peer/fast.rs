@@ -0,0 +1,71 @@
+use super::constants::PSTRLEN;
+use super::utils::sha1_of;
+use std::net::Ipv4Addr;
+
+/// Bit of the last reserved byte of the handshake that advertises the fast extension (BEP 6)
+pub const FAST_EXTENSION_BIT: u8 = 0x04;
+/// Pieces a choked peer is allowed to request
+pub const ALLOWED_FAST_SET_SIZE: usize = 10;
+
+/// Whether the peer that sent the handshake supports the fast extension
+pub fn supports_fast_extension(handshake: &[u8]) -> bool {
+    let pstrlen = handshake.first().copied().unwrap_or(PSTRLEN) as usize;
+    handshake
+        .get(1 + pstrlen + 7)
+        .is_some_and(|reserved| reserved & FAST_EXTENSION_BIT != 0)
+}
+
+/// Allowed fast set of the peer at `ip`, computed as BEP 6 does so both sides agree on it:
+/// repeated SHA-1 hashes of the peer's /24 network and the info_hash, read as piece indexes
+pub fn allowed_fast_set(ip: Ipv4Addr, info_hash: &[u8], piece_count: u32, size: usize) -> Vec<u32> {
+    let mut allowed = Vec::with_capacity(size);
+    if piece_count == 0 {
+        return allowed;
+    }
+    let size = size.min(piece_count as usize);
+    let network = u32::from(ip) & 0xFFFF_FF00;
+    let mut hash = [network.to_be_bytes().as_slice(), info_hash].concat();
+    while allowed.len() < size {
+        hash = sha1_of(&hash);
+        for chunk in hash.chunks(4) {
+            if allowed.len() == size {
+                break;
+            }
+            let index = u32::from_be_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) % piece_count;
+            if !allowed.contains(&index) {
+                allowed.push(index);
+            }
+        }
+    }
+    allowed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer::create_handshake_message;
+
+    #[test]
+    fn allowed_fast_set_matches_the_bep() {
+        let ip = Ipv4Addr::new(80, 4, 4, 200);
+        let info_hash = [0xaa; 20];
+        assert_eq!(
+            allowed_fast_set(ip, &info_hash, 1313, 7),
+            vec![1059, 431, 808, 1217, 287, 376, 1188]
+        );
+        assert_eq!(
+            allowed_fast_set(ip, &info_hash, 1313, 9),
+            vec![1059, 431, 808, 1217, 287, 376, 1188, 353, 508]
+        );
+        assert_eq!(allowed_fast_set(ip, &info_hash, 3, 10).len(), 3);
+    }
+
+    #[test]
+    fn our_handshake_advertises_the_extension() {
+        let handshake = create_handshake_message(&[1; 20], &[2; 20]);
+        assert!(supports_fast_extension(&handshake));
+        let mut plain = handshake.clone();
+        plain[1 + PSTRLEN as usize + 7] = 0;
+        assert!(!supports_fast_extension(&plain));
+    }
+}
@@ -1,15 +1,29 @@
+mod choker;
 mod connection;
+mod connection_limit;
 mod constants;
+mod decoder;
 mod errors;
+mod fast;
 mod handshake;
+mod peer_source;
 mod service;
 mod types;
+mod upload;
 mod utils;
+mod web_seed;
 
+pub use choker::{Choker, MAX_UNCHOKED_PEERS};
 pub use connection::PeerConnection;
+pub use connection_limit::{ConnectionLimit, ConnectionSlot};
+pub use decoder::{PeerFrame, PeerMessageDecoder};
 pub use errors::IPeerMessageServiceError;
 pub use errors::PeerConnectionError;
+pub use fast::*;
 pub use handshake::IHandshakeService;
+pub use peer_source::{PeerSource, PeerSourcePolicy};
 pub use service::*;
 pub use types::*;
+pub use upload::{BlockRequest, PendingBlock, RefusedRequest, UploadEvent, UploadState};
 pub use utils::*;
+pub use web_seed::WebSeedMessageService;
@@ -0,0 +1,112 @@
+use super::types::Peer;
+use crate::metainfo::Metainfo;
+
+/// Where the address of a peer was learned from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PeerSource {
+    /// handed out by one of the torrent's trackers
+    Tracker,
+    /// the peer connected to us
+    Incoming,
+    Dht,
+    /// told by another peer through peer exchange
+    Pex,
+    /// announced by a peer in the same local network
+    LocalDiscovery,
+    /// web server listed in the torrent's url-list
+    WebSeed,
+}
+
+/// Which sources of peers a torrent may use.
+/// Private torrents (BEP 27) only talk to the peers their trackers hand out and to the ones
+/// connecting to us, so every peer is accounted for by the trackers.
+/// Web seeds come with the torrent itself, so they are always allowed.
+/// Every subsystem finding peers for a torrent asks it before connecting to them
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PeerSourcePolicy {
+    private: bool,
+}
+
+impl PeerSourcePolicy {
+    pub fn for_torrent(metainfo: &Metainfo) -> Self {
+        Self {
+            private: metainfo.info.private,
+        }
+    }
+
+    pub fn is_private(&self) -> bool {
+        self.private
+    }
+
+    pub fn allows(&self, source: PeerSource) -> bool {
+        !self.private
+            || matches!(
+                source,
+                PeerSource::Tracker | PeerSource::Incoming | PeerSource::WebSeed
+            )
+    }
+
+    /// The peers if their source is allowed, none otherwise
+    pub fn filter(&self, source: PeerSource, peers: Vec<Peer>) -> Vec<Peer> {
+        if self.allows(source) {
+            peers
+        } else {
+            vec![]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metainfo::Info;
+    use crate::peer::mock_peer_message_service_provider;
+    use crate::tracker::{ITrackerService, MockTrackerService};
+
+    fn announced_peers() -> Vec<Peer> {
+        let mut tracker_service = MockTrackerService {
+            responses: vec![vec![Peer {
+                ip: "127.0.0.1".to_string(),
+                port: 6881,
+                peer_id: vec![1; 20],
+                peer_message_service_provider: mock_peer_message_service_provider,
+                web_seed: None,
+            }]],
+            response_index: 0,
+        };
+        tracker_service.announce(None).unwrap().peers
+    }
+
+    fn policy(private: bool) -> PeerSourcePolicy {
+        PeerSourcePolicy::for_torrent(&Metainfo {
+            info: Info {
+                private,
+                ..Default::default()
+            },
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn private_torrents_only_use_their_trackers() {
+        let private = policy(true);
+        assert!(private.is_private());
+        assert_eq!(
+            private.filter(PeerSource::Tracker, announced_peers()),
+            announced_peers()
+        );
+        assert!(private.allows(PeerSource::Incoming));
+        assert!(private.allows(PeerSource::WebSeed));
+        for source in [PeerSource::Dht, PeerSource::Pex, PeerSource::LocalDiscovery] {
+            assert!(private.filter(source, announced_peers()).is_empty());
+        }
+    }
+
+    #[test]
+    fn public_torrents_use_every_source() {
+        let public = policy(false);
+        for source in [PeerSource::Dht, PeerSource::Pex, PeerSource::LocalDiscovery] {
+            assert_eq!(public.filter(source, announced_peers()), announced_peers());
+        }
+    }
+}
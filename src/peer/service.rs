@@ -1,56 +1,149 @@
 use super::constants::*;
 use super::errors::*;
 use super::types::*;
-use super::utils::is_keep_alive_message;
+use super::utils::{create_handshake_message, is_keep_alive_message};
 use super::IPeerMessageServiceError;
+use crate::bandwidth::PeerBandwidth;
 use crate::boxed_result::BoxedResult;
+use crate::encryption::{negotiate_outgoing, CipherPair, EncryptionPolicy};
+use crate::reactor::{Reactor, ReactorStream, CONNECT_TIMEOUT};
 use crate::server::payload_from_request_message;
 use crate::server::RequestMessage;
+use crate::utp::{Transport, UtpStream, UTP_CONNECT_TIMEOUT};
 use log::*;
 use std::io::{Read, Write};
 use std::net::TcpStream;
 use std::net::{SocketAddr, SocketAddrV4};
-use std::time::Duration;
+use std::time::{Duration, Instant};
+
+/// Byte stream a [`PeerMessageService`] talks to the other peer through
+pub trait PeerStream: Read + Write + Send {
+    /// Whether there is something to read without blocking.
+    /// A closed connection also counts, so the next read reports it
+    fn has_pending_data(&mut self) -> bool;
+
+    /// Waits at most `timeout` for something to read, returning whether there is
+    fn wait_for_data(&mut self, timeout: Duration) -> bool;
+}
+
+impl PeerStream for TcpStream {
+    fn has_pending_data(&mut self) -> bool {
+        if self.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let peeked = self.peek(&mut [0u8; 1]);
+        let _ = self.set_nonblocking(false);
+        !matches!(peeked, Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock)
+    }
+
+    fn wait_for_data(&mut self, timeout: Duration) -> bool {
+        let read_timeout = self.read_timeout().ok().flatten();
+        if timeout.is_zero() || self.set_read_timeout(Some(timeout)).is_err() {
+            return self.has_pending_data();
+        }
+        let peeked = self.peek(&mut [0u8; 1]);
+        let _ = self.set_read_timeout(read_timeout);
+        !matches!(peeked, Err(ref err) if matches!(err.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut))
+    }
+}
 
 pub struct PeerMessageService {
-    stream: TcpStream,
+    stream: Box<dyn PeerStream>,
     max_retries: u8,
+    bandwidth: PeerBandwidth,
+    encryption: EncryptionPolicy,
+    // None while the connection is plaintext
+    ciphers: Option<CipherPair>,
+    // where the stream was opened to, to open it again in plaintext if the encrypted handshake fails
+    address: Option<SocketAddr>,
+    transport: Transport,
+    // whether the other peer's handshake advertised the fast extension
+    fast_extension: bool,
 }
 
 impl PeerMessageService {
-    pub fn connect_to_peer(ip: String, port: u16) -> Result<Self, PeerConnectionError> {
+    pub fn connect_to_peer(
+        ip: String,
+        port: u16,
+        transport: Transport,
+    ) -> Result<Self, PeerConnectionError> {
         trace!("Connecting to peer at IP: {}:{}", ip, port);
         let ipv4addr: SocketAddrV4 = format!("{}:{}", ip, port).parse().unwrap();
         let ipvaddr = SocketAddr::from(ipv4addr);
-        let stream = TcpStream::connect_timeout(&ipvaddr, Duration::from_secs(100))
-            .map_err(|e| PeerConnectionError::InitialConnectionError(e.to_string()))?;
-        stream
-            .set_write_timeout(Some(Duration::new(MESSAGE_TIMEOUT, 0)))
-            .map_err(|e| PeerConnectionError::InitialConnectionError(e.to_string()))?;
-        stream
-            .set_read_timeout(Some(Duration::new(MESSAGE_TIMEOUT, 0)))
+        let mut service = Self::from_boxed_stream(Self::open_stream(ipvaddr, transport)?);
+        service.address = Some(ipvaddr);
+        service.transport = transport;
+        Ok(service)
+    }
+
+    // With uTP the peer is tried over it first, peers that don't answer are connected to through TCP
+    fn open_stream(
+        address: SocketAddr,
+        transport: Transport,
+    ) -> Result<Box<dyn PeerStream>, PeerConnectionError> {
+        if transport == Transport::Utp {
+            match UtpStream::connect(address, Duration::from_secs(UTP_CONNECT_TIMEOUT)) {
+                Ok(mut stream) => {
+                    stream.set_read_timeout(Some(Duration::new(MESSAGE_TIMEOUT, 0)));
+                    return Ok(Box::new(stream));
+                }
+                Err(err) => debug!("uTP connection to {} failed, using TCP: {}", address, err),
+            }
+        }
+        let reactor = Reactor::global()
             .map_err(|e| PeerConnectionError::InitialConnectionError(e.to_string()))?;
-        Ok(Self {
-            stream,
-            max_retries: MAX_RETRIES,
-        })
+        let mut stream =
+            ReactorStream::connect(&reactor, address, Duration::from_secs(CONNECT_TIMEOUT))
+                .map_err(|e| PeerConnectionError::InitialConnectionError(e.to_string()))?;
+        stream.set_read_timeout(Some(Duration::new(MESSAGE_TIMEOUT, 0)));
+        Ok(Box::new(stream))
     }
 
     pub fn from_peer_connection(stream: TcpStream) -> Self {
+        Self::from_stream(stream)
+    }
+
+    pub fn from_stream(stream: impl PeerStream + 'static) -> Self {
+        Self::from_boxed_stream(Box::new(stream))
+    }
+
+    fn from_boxed_stream(stream: Box<dyn PeerStream>) -> Self {
         Self {
             stream,
             max_retries: MAX_RETRIES,
+            bandwidth: PeerBandwidth::unlimited(),
+            encryption: EncryptionPolicy::Disabled,
+            ciphers: None,
+            address: None,
+            transport: Transport::Tcp,
+            fast_extension: false,
         }
     }
 
-    fn create_handshake_message(&self, info_hash: &[u8], peer_id: &[u8]) -> Vec<u8> {
-        let mut handshake_message = Vec::new();
-        handshake_message.extend_from_slice(&[PSTRLEN]);
-        handshake_message.extend_from_slice(b"BitTorrent protocol");
-        handshake_message.extend_from_slice(&[0u8; 8]);
-        handshake_message.extend_from_slice(info_hash);
-        handshake_message.extend_from_slice(peer_id);
-        handshake_message
+    // Negotiates the encryption before the handshake, if the policy asks for it.
+    // Peers that don't support it are connected to again in plaintext, unless encryption is required
+    fn negotiate_encryption(&mut self, info_hash: &[u8]) -> Result<(), IPeerMessageServiceError> {
+        if self.encryption == EncryptionPolicy::Disabled {
+            return Ok(());
+        }
+        match negotiate_outgoing(self.stream.as_mut(), info_hash, self.encryption) {
+            Ok(ciphers) => {
+                self.ciphers = ciphers;
+                Ok(())
+            }
+            Err(err) => match self.address {
+                Some(address) if self.encryption == EncryptionPolicy::Prefer => {
+                    debug!("Encrypted handshake failed, retrying in plaintext: {}", err);
+                    self.stream = Self::open_stream(address, self.transport).map_err(|err| {
+                        IPeerMessageServiceError::PeerHandshakeError(err.to_string())
+                    })?;
+                    Ok(())
+                }
+                _ => Err(IPeerMessageServiceError::PeerHandshakeError(
+                    err.to_string(),
+                )),
+            },
+        }
     }
 
     fn try_read_exact(&mut self, buf: &mut [u8]) -> BoxedResult<()> {
@@ -64,9 +157,13 @@ impl PeerMessageService {
     }
 
     fn write_all(&mut self, buf: &[u8]) -> BoxedResult<()> {
+        let mut buf = buf.to_vec();
+        if let Some(ciphers) = self.ciphers.as_mut() {
+            ciphers.encrypt.apply(&mut buf);
+        }
         let mut retries = 0;
         loop {
-            match self.try_write_all(buf) {
+            match self.try_write_all(&buf) {
                 Ok(_) => return Ok(()),
                 Err(e) => {
                     if retries >= self.max_retries {
@@ -83,7 +180,12 @@ impl PeerMessageService {
         let mut retries = 0;
         loop {
             match self.try_read_exact(buf) {
-                Ok(_) => return Ok(()),
+                Ok(_) => {
+                    if let Some(ciphers) = self.ciphers.as_mut() {
+                        ciphers.decrypt.apply(buf);
+                    }
+                    return Ok(());
+                }
                 Err(e) => {
                     if retries >= self.max_retries {
                         return Err(e);
@@ -94,10 +196,9 @@ impl PeerMessageService {
             trace!("Attempt of reading message: {}", retries);
         }
     }
-}
 
-impl IPeerMessageService for PeerMessageService {
-    fn wait_for_message(&mut self) -> Result<PeerMessage, IPeerMessageServiceError> {
+    // Reads the next message, None if it was a keep alive
+    fn read_message(&mut self) -> Result<Option<PeerMessage>, IPeerMessageServiceError> {
         let mut message_length = [0u8; MESSAGE_LENGTH_SIZE];
 
         self.read_exact(&mut message_length).map_err(|err| {
@@ -110,7 +211,7 @@ impl IPeerMessageService for PeerMessageService {
         let message_length = u32::from_be_bytes(message_length);
 
         if is_keep_alive_message(message_length) {
-            return self.wait_for_message();
+            return Ok(None);
         }
 
         let mut message_id = [0u8; MESSAGE_ID_SIZE];
@@ -133,22 +234,58 @@ impl IPeerMessageService for PeerMessageService {
             length: message_length,
             payload,
         };
+        if msg.id == PeerMessageId::Piece {
+            self.bandwidth.throttle_download(msg.payload.len());
+        }
 
-        Ok(msg)
+        Ok(Some(msg))
+    }
+}
+
+impl IPeerMessageService for PeerMessageService {
+    fn wait_for_message(&mut self) -> Result<PeerMessage, IPeerMessageServiceError> {
+        loop {
+            if let Some(message) = self.read_message()? {
+                return Ok(message);
+            }
+        }
+    }
+
+    fn wait_for_message_within(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<PeerMessage>, IPeerMessageServiceError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            if !self.stream.wait_for_data(left) {
+                return Ok(None);
+            }
+            if let Some(message) = self.read_message()? {
+                return Ok(Some(message));
+            }
+        }
     }
 
     fn send_message(&mut self, message: &PeerMessage) -> Result<(), IPeerMessageServiceError> {
-        let mut bytes = Vec::with_capacity((message.length + 4) as usize);
-        bytes.extend_from_slice(&message.length.to_be_bytes());
-        bytes.extend_from_slice(&(message.id as u8).to_be_bytes());
-        bytes.extend_from_slice(&message.payload);
-        self.write_all(&bytes).map_err(|_| {
+        if message.id == PeerMessageId::Piece {
+            self.bandwidth.throttle_upload(message.payload.len());
+        }
+        self.write_all(&message.to_bytes()).map_err(|_| {
             IPeerMessageServiceError::SendingMessageError(
                 "Couldn't send message to other peer".to_string(),
             )
         })?;
         Ok(())
     }
+
+    fn has_pending_message(&mut self) -> bool {
+        self.stream.has_pending_data()
+    }
+
+    fn set_bandwidth(&mut self, bandwidth: PeerBandwidth) {
+        self.bandwidth = bandwidth;
+    }
 }
 
 impl IClientPeerMessageService for PeerMessageService {
@@ -157,7 +294,8 @@ impl IClientPeerMessageService for PeerMessageService {
         info_hash: &[u8],
         peer_id: &[u8],
     ) -> Result<(), IPeerMessageServiceError> {
-        let handshake_message = self.create_handshake_message(info_hash, peer_id);
+        self.negotiate_encryption(info_hash)?;
+        let handshake_message = create_handshake_message(info_hash, peer_id);
         self.write_all(&handshake_message).map_err(|_| {
             IPeerMessageServiceError::SendingMessageError(
                 "Couldn't send handshake message to other peer".to_string(),
@@ -169,9 +307,18 @@ impl IClientPeerMessageService for PeerMessageService {
                 "Couldn't read handshake from other peer".into(),
             )
         })?;
+        self.fast_extension = super::fast::supports_fast_extension(&handshake_response);
         debug!("client handshake successful");
         Ok(())
     }
+
+    fn set_encryption(&mut self, encryption: EncryptionPolicy) {
+        self.encryption = encryption;
+    }
+
+    fn supports_fast_extension(&self) -> bool {
+        self.fast_extension
+    }
 }
 
 impl IServerPeerMessageService for PeerMessageService {
@@ -186,7 +333,7 @@ impl IServerPeerMessageService for PeerMessageService {
                 "Couldn't read handshake from other peer".into(),
             )
         })?;
-        let handshake_message = self.create_handshake_message(info_hash, peer_id);
+        let handshake_message = create_handshake_message(info_hash, peer_id);
         self.write_all(&handshake_message).map_err(|_| {
             IPeerMessageServiceError::SendingMessageError(
                 "Couldn't send handshake message to other peer".to_string(),
@@ -235,6 +382,25 @@ impl IClientPeerMessageService for PeerMessageServiceMock {
 pub trait IPeerMessageService {
     fn wait_for_message(&mut self) -> Result<PeerMessage, IPeerMessageServiceError>;
     fn send_message(&mut self, message: &PeerMessage) -> Result<(), IPeerMessageServiceError>;
+
+    // Whether the other peer already sent something we can read without blocking.
+    // Services that can't tell answer false, so callers never block on them while idle
+    fn has_pending_message(&mut self) -> bool {
+        false
+    }
+
+    // Waits at most `timeout` for the next message, None if nothing arrived by then.
+    // Services that can't tell whether something is coming, like mocks, just wait for it
+    fn wait_for_message_within(
+        &mut self,
+        _timeout: Duration,
+    ) -> Result<Option<PeerMessage>, IPeerMessageServiceError> {
+        self.wait_for_message().map(Some)
+    }
+
+    // Limiters blocks sent and received through the service have to go through.
+    // Services that don't move real data, like mocks, ignore them
+    fn set_bandwidth(&mut self, _bandwidth: PeerBandwidth) {}
 }
 
 pub trait IClientPeerMessageService: IPeerMessageService {
@@ -243,6 +409,16 @@ pub trait IClientPeerMessageService: IPeerMessageService {
         info_hash: &[u8],
         peer_id: &[u8],
     ) -> Result<(), IPeerMessageServiceError>;
+
+    // Whether the handshake negotiates an encrypted connection first.
+    // Services that aren't a peer connection, like web seeds, are never encrypted
+    fn set_encryption(&mut self, _encryption: EncryptionPolicy) {}
+
+    // Whether the other peer's handshake advertised the fast extension, which ours always does.
+    // Services that aren't a peer connection answer false
+    fn supports_fast_extension(&self) -> bool {
+        false
+    }
 }
 
 pub trait IServerPeerMessageService: IPeerMessageService {
@@ -277,12 +453,17 @@ impl IPeerMessageService for ServerMessageServiceMock {
                     length: 8,
                 }),
             })
-        } else {
+        } else if self.times_called == 1 {
+            self.times_called += 1;
             Ok(PeerMessage {
                 id: PeerMessageId::Cancel,
                 length: 0,
                 payload: Vec::new(),
             })
+        } else {
+            Err(IPeerMessageServiceError::ReceivingMessageError(
+                "Connection closed by mock".to_string(),
+            ))
         }
     }
 }
@@ -308,12 +489,17 @@ impl IPeerMessageService for ServerMessageServiceUnsuccesfulMock {
                     length: 8,
                 }),
             })
-        } else {
+        } else if self.times_called == 1 {
+            self.times_called += 1;
             Ok(PeerMessage {
                 id: PeerMessageId::Cancel,
                 length: 0,
                 payload: Vec::new(),
             })
+        } else {
+            Err(IPeerMessageServiceError::ReceivingMessageError(
+                "Connection closed by mock".to_string(),
+            ))
         }
     }
 }
@@ -353,7 +539,9 @@ impl IPeerMessageService for ServerMessageBitfieldMock {
     }
 
     fn wait_for_message(&mut self) -> Result<PeerMessage, IPeerMessageServiceError> {
-        Ok(PeerMessage::choke())
+        Err(IPeerMessageServiceError::ReceivingMessageError(
+            "Connection closed by mock".to_string(),
+        ))
     }
 }
 
@@ -376,14 +564,16 @@ impl IServerPeerMessageService for ServerMessageBitfieldMock {
 pub fn peer_message_service_provider(
     ip: String,
     port: u16,
+    transport: Transport,
 ) -> Result<Box<dyn IClientPeerMessageService + Send>, PeerConnectionError> {
-    let peer_message_service = PeerMessageService::connect_to_peer(ip, port)?;
+    let peer_message_service = PeerMessageService::connect_to_peer(ip, port, transport)?;
     Ok(Box::new(peer_message_service))
 }
 
 pub fn mock_peer_message_service_provider(
     _ip: String,
     _port: u16,
+    _transport: Transport,
 ) -> Result<Box<dyn IClientPeerMessageService + Send>, PeerConnectionError> {
     Ok(Box::new(PeerMessageServiceMock {
         counter: 0,
@@ -1,16 +1,21 @@
 use super::errors::*;
 use super::service::*;
 use super::utils::bitmap_from_pieces_vector;
+use super::web_seed::WebSeedMessageService;
+use crate::metainfo::Metainfo;
+use crate::utp::Transport;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct PeerState {
     pub chocked: bool,
     pub interested: bool,
 }
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct PeerConnectionState {
     pub client: PeerState,
     pub peer: PeerState,
+    /// whether the peer stopped sending the blocks we asked for while unchoking us
+    pub snubbed: bool,
 }
 
 #[derive(Clone, Debug)]
@@ -67,23 +72,34 @@ impl Bitfield {
     }
 }
 
+/// Opens the connection to the peer at the given ip and port, through the given transport
+pub type PeerMessageServiceProvider =
+    fn(
+        ip: String,
+        port: u16,
+        transport: Transport,
+    ) -> Result<Box<dyn IClientPeerMessageService + Send>, PeerConnectionError>;
+
 #[derive(Debug, PartialEq, Clone)]
 pub struct Peer {
     pub ip: String,
     pub port: u16,
     pub peer_id: Vec<u8>,
-    pub peer_message_service_provider:
-        fn(
-            ip: String,
-            port: u16,
-        ) -> Result<Box<dyn IClientPeerMessageService + Send>, PeerConnectionError>,
+    pub peer_message_service_provider: PeerMessageServiceProvider,
+    /// URL of the web seed (BEP 19) this peer stands for, if it is one
+    pub web_seed: Option<String>,
 }
 
 impl Peer {
     pub fn connect(
         &self,
+        metainfo: &Metainfo,
+        transport: Transport,
     ) -> Result<Box<dyn IClientPeerMessageService + Send>, PeerConnectionError> {
-        (self.peer_message_service_provider)(self.ip.clone(), self.port)
+        match &self.web_seed {
+            Some(url) => Ok(Box::new(WebSeedMessageService::connect(url, metainfo)?)),
+            None => (self.peer_message_service_provider)(self.ip.clone(), self.port, transport),
+        }
     }
 }
 
@@ -100,6 +116,12 @@ pub enum PeerMessageId {
     Cancel,
     Port,
     KeepAlive,
+    // the fast extension (BEP 6), their ids come after a gap
+    SuggestPiece = 0x0d,
+    HaveAll,
+    HaveNone,
+    RejectRequest,
+    AllowedFast,
 }
 
 impl PeerMessageId {
@@ -115,6 +137,11 @@ impl PeerMessageId {
             7 => Ok(PeerMessageId::Piece),
             8 => Ok(PeerMessageId::Cancel),
             9 => Ok(PeerMessageId::Port),
+            0x0d => Ok(PeerMessageId::SuggestPiece),
+            0x0e => Ok(PeerMessageId::HaveAll),
+            0x0f => Ok(PeerMessageId::HaveNone),
+            0x10 => Ok(PeerMessageId::RejectRequest),
+            0x11 => Ok(PeerMessageId::AllowedFast),
             _ => Err(format!("Invalid message id: {}", id)),
         }
     }
@@ -182,6 +209,15 @@ impl PeerMessage {
         }
     }
 
+    /// Bytes of the message as they are sent through the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity((self.length + 4) as usize);
+        bytes.extend_from_slice(&self.length.to_be_bytes());
+        bytes.extend_from_slice(&(self.id as u8).to_be_bytes());
+        bytes.extend_from_slice(&self.payload);
+        bytes
+    }
+
     pub fn keep_alive() -> PeerMessage {
         PeerMessage {
             id: PeerMessageId::Choke,
@@ -202,7 +238,7 @@ impl PeerMessage {
     pub fn not_intersted() -> PeerMessage {
         PeerMessage {
             id: PeerMessageId::NotInterested,
-            length: 0,
+            length: 1,
             payload: vec![],
         }
     }
@@ -210,8 +246,66 @@ impl PeerMessage {
     pub fn choke() -> PeerMessage {
         PeerMessage {
             id: PeerMessageId::Choke,
-            length: 0,
+            length: 1,
+            payload: vec![],
+        }
+    }
+
+    pub fn have(piece_index: u32) -> PeerMessage {
+        PeerMessage {
+            id: PeerMessageId::Have,
+            length: 5,
+            payload: Self::u32_to_vec_be(piece_index),
+        }
+    }
+
+    pub fn have_all() -> PeerMessage {
+        PeerMessage {
+            id: PeerMessageId::HaveAll,
+            length: 1,
             payload: vec![],
         }
     }
+
+    pub fn have_none() -> PeerMessage {
+        PeerMessage {
+            id: PeerMessageId::HaveNone,
+            length: 1,
+            payload: vec![],
+        }
+    }
+
+    pub fn suggest_piece(piece_index: u32) -> PeerMessage {
+        PeerMessage {
+            id: PeerMessageId::SuggestPiece,
+            length: 5,
+            payload: Self::u32_to_vec_be(piece_index),
+        }
+    }
+
+    pub fn allowed_fast(piece_index: u32) -> PeerMessage {
+        PeerMessage {
+            id: PeerMessageId::AllowedFast,
+            length: 5,
+            payload: Self::u32_to_vec_be(piece_index),
+        }
+    }
+
+    /// Takes back the request with this payload
+    pub fn cancel(request_payload: &[u8]) -> PeerMessage {
+        PeerMessage {
+            id: PeerMessageId::Cancel,
+            length: (request_payload.len() + 1) as u32,
+            payload: request_payload.to_vec(),
+        }
+    }
+
+    /// Tells the peer the request with this payload won't be answered
+    pub fn reject_request(request_payload: &[u8]) -> PeerMessage {
+        PeerMessage {
+            id: PeerMessageId::RejectRequest,
+            length: (request_payload.len() + 1) as u32,
+            payload: request_payload.to_vec(),
+        }
+    }
 }
@@ -0,0 +1,525 @@
+use super::choker::Choker;
+use super::errors::IPeerMessageServiceError;
+use super::fast::{allowed_fast_set, ALLOWED_FAST_SET_SIZE};
+use super::service::IPeerMessageService;
+use super::types::*;
+use crate::server::{get_block_index, request_from_payload};
+use crate::storage::{SharedStorage, StorageError};
+use std::net::Ipv4Addr;
+
+/// What happened after handling a message sent by the remote peer
+#[derive(Debug, PartialEq, Eq)]
+pub enum UploadEvent {
+    /// A block was sent, holds the piece index and the block number
+    BlockSent(usize, usize),
+    /// A block could not be sent, holds the piece index and the block number
+    BlockFailedToSend(usize, usize),
+    /// The remote peer asked for a piece we don't have
+    MissingPiece(usize),
+    /// The remote peer asked for a piece while we were choking it
+    RequestWhileChoked(usize),
+    /// The remote peer got an upload slot
+    Unchoked,
+    /// The remote peer lost its upload slot
+    Choked,
+    /// The message has nothing to do with uploading
+    Ignored,
+}
+
+/// Result of reading the block a peer asked for
+pub enum BlockRequest {
+    /// The block was read and can be sent
+    Ready(PendingBlock),
+    /// The block won't be sent
+    Refused(RefusedRequest),
+}
+
+/// Request that won't be answered.
+/// With the fast extension the peer is told, so it can ask someone else right away
+pub struct RefusedRequest {
+    event: UploadEvent,
+    reject: Option<PeerMessage>,
+}
+
+impl RefusedRequest {
+    /// Sends the rejection if there is one, returning why the request was refused
+    pub fn send<S: IPeerMessageService + ?Sized>(self, service: &mut S) -> UploadEvent {
+        if let Some(reject) = &self.reject {
+            let _ = service.send_message(reject);
+        }
+        self.event
+    }
+}
+
+/// Block read from disk that still has to be sent to the peer that requested it.
+/// It doesn't borrow the [`UploadState`], so it can be sent without holding it
+/// while the service waits for the upload rate limits
+pub struct PendingBlock {
+    message: PeerMessage,
+    piece_index: usize,
+    block_number: usize,
+}
+
+impl PendingBlock {
+    pub fn send<S: IPeerMessageService + ?Sized>(self, service: &mut S) -> UploadEvent {
+        match service.send_message(&self.message) {
+            Ok(()) => UploadEvent::BlockSent(self.piece_index, self.block_number),
+            Err(_) => UploadEvent::BlockFailedToSend(self.piece_index, self.block_number),
+        }
+    }
+}
+
+/// Serving half of a peer connection.
+/// Both the connections the client opens and the ones the server accepts own one, so every
+/// connection answers `Interested`, `NotInterested`, `Request` and `Cancel` messages the same way,
+/// reading pieces from the same storage and taking upload slots from the same [`Choker`].
+pub struct UploadState {
+    storage: SharedStorage,
+    choker: Choker,
+    /// whether we are choking the remote peer
+    pub am_choking: bool,
+    /// whether the remote peer wants pieces from us
+    pub peer_interested: bool,
+    /// whether both sides support the fast extension (BEP 6)
+    pub fast_extension: bool,
+    // pieces the peer may request while choked
+    allowed_fast: Vec<u32>,
+    // whether the peer stopped sending us the blocks we asked for, it only gets the optimistic slot meanwhile
+    snubbed: bool,
+    // whether the slot the peer is unchoked with is the choker's optimistic one
+    optimistic: bool,
+}
+
+impl UploadState {
+    pub fn new(storage: SharedStorage, choker: Choker) -> Self {
+        Self {
+            storage,
+            choker,
+            am_choking: true,
+            peer_interested: false,
+            fast_extension: false,
+            allowed_fast: vec![],
+            snubbed: false,
+            optimistic: false,
+        }
+    }
+
+    /// The storage the served pieces are read from, where the downloaded blocks are written too
+    pub fn storage(&self) -> &SharedStorage {
+        &self.storage
+    }
+
+    /// Turns on the fast extension, once both handshakes advertised it.
+    /// A peer with an IPv4 address may then request the pieces of its allowed fast set while choked
+    pub fn enable_fast_extension(&mut self, ip: Option<Ipv4Addr>, info_hash: &[u8]) {
+        self.fast_extension = true;
+        let piece_count = self.storage.bitfield().len() as u32;
+        self.allowed_fast = ip
+            .map(|ip| allowed_fast_set(ip, info_hash, piece_count, ALLOWED_FAST_SET_SIZE))
+            .unwrap_or_default();
+    }
+
+    /// Bitfield message with the pieces currently stored on disk
+    pub fn bitfield(&self) -> PeerMessage {
+        PeerMessage::bitfield(self.storage.bitfield())
+    }
+
+    /// Message announcing the pieces stored on disk.
+    /// With the fast extension it is `HaveAll` or `HaveNone` when they say it all, the bitfield otherwise
+    pub fn have_message(&self) -> PeerMessage {
+        let pieces = self.storage.bitfield();
+        match self.fast_extension {
+            true if pieces.iter().all(|has| *has) => PeerMessage::have_all(),
+            true if !pieces.iter().any(|has| *has) => PeerMessage::have_none(),
+            _ => PeerMessage::bitfield(pieces),
+        }
+    }
+
+    /// Sends what the fast extension tells a peer after the pieces we have:
+    /// the ones of its allowed fast set, and suggests the ones served last, which are likely cached
+    pub fn send_fast_hints<S: IPeerMessageService + ?Sized>(
+        &self,
+        service: &mut S,
+    ) -> Result<(), IPeerMessageServiceError> {
+        if !self.fast_extension {
+            return Ok(());
+        }
+        let pieces = self.storage.bitfield();
+        let has = |index: &u32| pieces.get(*index as usize).copied().unwrap_or(false);
+        for index in self.allowed_fast.iter().filter(|index| has(index)) {
+            service.send_message(&PeerMessage::allowed_fast(*index))?;
+        }
+        for index in self
+            .choker
+            .recently_served()
+            .iter()
+            .filter(|index| has(index))
+        {
+            service.send_message(&PeerMessage::suggest_piece(*index))?;
+        }
+        Ok(())
+    }
+
+    /// Unchokes the remote peer if the choker has a free upload slot, the optimistic one if it snubs us.
+    /// Returns whether the peer is unchoked after the call
+    pub fn offer_unchoke<S: IPeerMessageService + ?Sized>(
+        &mut self,
+        service: &mut S,
+    ) -> Result<bool, IPeerMessageServiceError> {
+        if !self.am_choking {
+            return Ok(true);
+        }
+        if !self.take_slot() {
+            return Ok(false);
+        }
+        self.am_choking = false;
+        service.send_message(&PeerMessage::unchoke())?;
+        Ok(true)
+    }
+
+    /// Chokes the remote peer, giving its upload slot back to the choker
+    pub fn choke<S: IPeerMessageService + ?Sized>(
+        &mut self,
+        service: &mut S,
+    ) -> Result<(), IPeerMessageServiceError> {
+        if self.am_choking {
+            return Ok(());
+        }
+        self.release_slot();
+        service.send_message(&PeerMessage::choke())
+    }
+
+    /// A peer that snubs us moves to the choker's optimistic slot, or is choked if another one has it,
+    /// so its upload slot goes to a peer that sends us blocks.
+    /// Once it sends blocks again it gets a regular slot, if it still wants pieces from us
+    pub fn set_snubbed<S: IPeerMessageService + ?Sized>(
+        &mut self,
+        snubbed: bool,
+        service: &mut S,
+    ) -> Result<UploadEvent, IPeerMessageServiceError> {
+        if self.snubbed == snubbed {
+            return Ok(UploadEvent::Ignored);
+        }
+        self.snubbed = snubbed;
+        if self.am_choking {
+            if !snubbed && self.peer_interested && self.offer_unchoke(service)? {
+                return Ok(UploadEvent::Unchoked);
+            }
+            return Ok(UploadEvent::Ignored);
+        }
+        if snubbed {
+            // it kept the optimistic slot if no regular one was free when it stopped snubbing us
+            if self.optimistic {
+                return Ok(UploadEvent::Ignored);
+            }
+            self.release_slot();
+            if self.take_slot() {
+                self.am_choking = false;
+                return Ok(UploadEvent::Ignored);
+            }
+            service.send_message(&PeerMessage::choke())?;
+            return Ok(UploadEvent::Choked);
+        }
+        // without a regular slot free it keeps the optimistic one
+        if self.choker.try_unchoke() {
+            self.choker.release_optimistic();
+            self.optimistic = false;
+        }
+        Ok(UploadEvent::Ignored)
+    }
+
+    /// Handles a message sent by the remote peer, answering it through the received service
+    /// if it has to do with uploading
+    pub fn handle_message<S: IPeerMessageService + ?Sized>(
+        &mut self,
+        message: &PeerMessage,
+        service: &mut S,
+    ) -> Result<UploadEvent, IPeerMessageServiceError> {
+        match message.id {
+            PeerMessageId::Interested => {
+                self.peer_interested = true;
+                if self.offer_unchoke(service)? {
+                    Ok(UploadEvent::Unchoked)
+                } else {
+                    Ok(UploadEvent::Ignored)
+                }
+            }
+            PeerMessageId::NotInterested => {
+                self.peer_interested = false;
+                self.choke(service)?;
+                Ok(UploadEvent::Choked)
+            }
+            PeerMessageId::Request => match self.read_block(message)? {
+                BlockRequest::Ready(block) => Ok(block.send(service)),
+                BlockRequest::Refused(refused) => Ok(refused.send(service)),
+            },
+            // blocks are sent as soon as they are requested, so there is nothing queued to drop
+            PeerMessageId::Cancel => Ok(UploadEvent::Ignored),
+            _ => Ok(UploadEvent::Ignored),
+        }
+    }
+
+    /// Reads the block asked for in a `Request` message, unless the peer is choked, the torrent is paused
+    /// or we don't have the piece. Pieces of the allowed fast set are served to choked peers too.
+    /// Fails if the request is malformed
+    pub fn read_block(
+        &self,
+        message: &PeerMessage,
+    ) -> Result<BlockRequest, IPeerMessageServiceError> {
+        let request = request_from_payload(message.payload.clone())
+            .map_err(|err| IPeerMessageServiceError::InvalidResponse(err.to_string()))?;
+        let allowed_fast = self.allowed_fast.contains(&(request.index as u32));
+        if self.am_choking && !allowed_fast || self.choker.is_paused() {
+            return Ok(self.refuse(message, UploadEvent::RequestWhileChoked(request.index)));
+        }
+        let block =
+            match self
+                .storage
+                .read_block(request.index as u32, request.begin, request.length)
+            {
+                Ok(block) => block,
+                Err(StorageError::BlockOutOfPiece(index, begin)) => {
+                    return Err(IPeerMessageServiceError::InvalidResponse(format!(
+                        "Requested block at offset {} is out of piece {}",
+                        begin, index
+                    )))
+                }
+                Err(_) => return Ok(self.refuse(message, UploadEvent::MissingPiece(request.index))),
+            };
+        self.choker.served(request.index as u32);
+        let block_number: usize = get_block_index(request.begin, request.length);
+
+        Ok(BlockRequest::Ready(PendingBlock {
+            message: PeerMessage::piece(request.index, request.begin, block),
+            piece_index: request.index,
+            block_number,
+        }))
+    }
+
+    fn refuse(&self, request: &PeerMessage, event: UploadEvent) -> BlockRequest {
+        BlockRequest::Refused(RefusedRequest {
+            event,
+            reject: self
+                .fast_extension
+                .then(|| PeerMessage::reject_request(&request.payload)),
+        })
+    }
+
+    fn take_slot(&mut self) -> bool {
+        self.optimistic = self.snubbed;
+        match self.snubbed {
+            true => self.choker.try_unchoke_optimistically(),
+            false => self.choker.try_unchoke(),
+        }
+    }
+
+    fn release_slot(&mut self) {
+        if !self.am_choking {
+            match self.optimistic {
+                true => self.choker.release_optimistic(),
+                false => self.choker.release(),
+            }
+            self.optimistic = false;
+            self.am_choking = true;
+        }
+    }
+}
+
+impl Drop for UploadState {
+    fn drop(&mut self) {
+        self.release_slot();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{payload_from_request_message, RequestMessage};
+    use crate::storage::PieceFileStorage;
+    use std::sync::Arc;
+
+    fn storage(download_dir: &str) -> SharedStorage {
+        Arc::new(PieceFileStorage::new(download_dir, 2, "", true))
+    }
+
+    struct RecordingService {
+        sent: Vec<PeerMessage>,
+    }
+
+    impl IPeerMessageService for RecordingService {
+        fn wait_for_message(&mut self) -> Result<PeerMessage, IPeerMessageServiceError> {
+            Err(IPeerMessageServiceError::UnhandledMessage)
+        }
+
+        fn send_message(&mut self, message: &PeerMessage) -> Result<(), IPeerMessageServiceError> {
+            self.sent.push(message.clone());
+            Ok(())
+        }
+    }
+
+    fn request(index: usize, begin: usize, length: usize) -> PeerMessage {
+        PeerMessage {
+            id: PeerMessageId::Request,
+            length: 13,
+            payload: payload_from_request_message(RequestMessage {
+                index,
+                begin,
+                length,
+            }),
+        }
+    }
+
+    #[test]
+    fn interested_peer_is_unchoked_only_if_there_is_a_slot() {
+        let choker = Choker::new(1);
+        let mut service = RecordingService { sent: vec![] };
+        let mut first = UploadState::new(storage("./src/server/tests/test_1"), choker.clone());
+        let mut second = UploadState::new(storage("./src/server/tests/test_1"), choker.clone());
+
+        let first_event = first.handle_message(&PeerMessage::interested(), &mut service);
+        let second_event = second.handle_message(&PeerMessage::interested(), &mut service);
+
+        assert_eq!(first_event.unwrap(), UploadEvent::Unchoked);
+        assert_eq!(second_event.unwrap(), UploadEvent::Ignored);
+        assert_eq!(service.sent.len(), 1);
+        assert_eq!(service.sent[0].id, PeerMessageId::Unchoke);
+
+        drop(first);
+        assert_eq!(choker.unchoked_count(), 0);
+    }
+
+    #[test]
+    fn peers_snubbing_us_only_get_the_optimistic_slot() {
+        let choker = Choker::new(3);
+        let mut service = RecordingService { sent: vec![] };
+        let mut first = UploadState::new(storage("./src/server/tests/test_1"), choker.clone());
+        let mut second = UploadState::new(storage("./src/server/tests/test_1"), choker.clone());
+        for upload in [&mut first, &mut second] {
+            upload
+                .handle_message(&PeerMessage::interested(), &mut service)
+                .unwrap();
+        }
+
+        let first_snubbed = first.set_snubbed(true, &mut service).unwrap();
+        let second_snubbed = second.set_snubbed(true, &mut service).unwrap();
+
+        // the first one moved to the optimistic slot, there was none left for the second
+        assert_eq!(first_snubbed, UploadEvent::Ignored);
+        assert_eq!(second_snubbed, UploadEvent::Choked);
+        assert_eq!(choker.unchoked_count(), 1);
+        assert!(!second.offer_unchoke(&mut service).unwrap());
+
+        first.set_snubbed(false, &mut service).unwrap();
+        assert!(second.offer_unchoke(&mut service).unwrap());
+        assert_eq!(
+            second.set_snubbed(false, &mut service).unwrap(),
+            UploadEvent::Ignored
+        );
+        assert_eq!(choker.unchoked_count(), 2);
+        let sent: Vec<PeerMessageId> = service.sent.iter().map(|message| message.id).collect();
+        assert_eq!(
+            sent,
+            vec![
+                PeerMessageId::Unchoke,
+                PeerMessageId::Unchoke,
+                PeerMessageId::Choke,
+                PeerMessageId::Unchoke
+            ]
+        );
+
+        drop(first);
+        drop(second);
+        assert_eq!(choker.unchoked_count(), 0);
+        assert!(choker.try_unchoke_optimistically());
+    }
+
+    #[test]
+    fn peer_snubbing_us_again_keeps_the_optimistic_slot() {
+        let choker = Choker::new(2);
+        let mut service = RecordingService { sent: vec![] };
+        let mut upload = UploadState::new(storage("./src/server/tests/test_1"), choker.clone());
+        upload
+            .handle_message(&PeerMessage::interested(), &mut service)
+            .unwrap();
+        // the other regular slot goes to someone else
+        assert!(choker.try_unchoke());
+
+        upload.set_snubbed(true, &mut service).unwrap();
+        // no regular slot is free, so it stays in the optimistic one
+        upload.set_snubbed(false, &mut service).unwrap();
+        assert_eq!(
+            upload.set_snubbed(true, &mut service).unwrap(),
+            UploadEvent::Ignored
+        );
+        assert_eq!(choker.unchoked_count(), 2);
+
+        drop(upload);
+        choker.release();
+        assert_eq!(choker.unchoked_count(), 0);
+        assert!(choker.try_unchoke_optimistically());
+    }
+
+    #[test]
+    fn request_while_choked_is_not_served() {
+        let mut service = RecordingService { sent: vec![] };
+        let mut upload = UploadState::new(storage("./src/server/tests/test_1"), Choker::new(1));
+
+        let event = upload.handle_message(&request(0, 0, 8), &mut service);
+
+        assert_eq!(event.unwrap(), UploadEvent::RequestWhileChoked(0));
+        assert!(service.sent.is_empty());
+    }
+
+    #[test]
+    fn fast_peers_are_told_about_refused_requests() {
+        let mut service = RecordingService { sent: vec![] };
+        let mut upload = UploadState::new(storage("./src/server/tests/test_1"), Choker::new(1));
+        upload.enable_fast_extension(None, &[0; 20]);
+
+        let event = upload.handle_message(&request(0, 0, 8), &mut service);
+
+        assert_eq!(event.unwrap(), UploadEvent::RequestWhileChoked(0));
+        assert_eq!(service.sent[0].id, PeerMessageId::RejectRequest);
+        assert_eq!(service.sent[0].payload, request(0, 0, 8).payload);
+    }
+
+    #[test]
+    fn allowed_fast_pieces_are_served_while_choked() {
+        let choker = Choker::new(1);
+        let mut service = RecordingService { sent: vec![] };
+        let mut upload = UploadState::new(storage("./src/server/tests/test_1"), choker.clone());
+        // with two pieces both are in the set
+        upload.enable_fast_extension(Some(Ipv4Addr::new(10, 0, 0, 1)), &[0; 20]);
+        assert_eq!(upload.have_message().id, PeerMessageId::Bitfield);
+        upload.send_fast_hints(&mut service).unwrap();
+        assert_eq!(service.sent.len(), 1);
+        assert_eq!(service.sent[0].id, PeerMessageId::AllowedFast);
+
+        let event = upload.handle_message(&request(0, 0, 8), &mut service);
+
+        assert_eq!(event.unwrap(), UploadEvent::BlockSent(0, 0));
+        assert_eq!(service.sent[1].id, PeerMessageId::Piece);
+        assert_eq!(choker.recently_served(), vec![0]);
+    }
+
+    #[test]
+    fn request_for_missing_piece_is_reported() {
+        let mut service = RecordingService { sent: vec![] };
+        let mut upload = UploadState::new(storage("./src/server/tests/test_2"), Choker::new(1));
+        upload.offer_unchoke(&mut service).unwrap();
+
+        let event = upload.handle_message(&request(1, 0, 8), &mut service);
+
+        assert_eq!(event.unwrap(), UploadEvent::MissingPiece(1));
+    }
+
+    #[test]
+    fn fast_peers_get_have_none_when_we_have_nothing() {
+        let mut upload = UploadState::new(storage("./src/server/tests/test_2"), Choker::new(1));
+        assert_eq!(upload.have_message().id, PeerMessageId::Bitfield);
+
+        upload.enable_fast_extension(None, &[0; 20]);
+
+        assert_eq!(upload.have_message().id, PeerMessageId::HaveNone);
+    }
+}
@@ -1,4 +1,5 @@
 use super::constants::*;
+use super::fast::FAST_EXTENSION_BIT;
 use crate::metainfo::Metainfo;
 use sha1::{Digest, Sha1};
 
@@ -42,7 +43,7 @@ pub fn create_handshake_message(info_hash: &[u8], peer_id: &[u8]) -> Vec<u8> {
     let mut handshake_message = Vec::new();
     handshake_message.extend_from_slice(&[PSTRLEN]);
     handshake_message.extend_from_slice(b"BitTorrent protocol");
-    handshake_message.extend_from_slice(&[0u8; 8]);
+    handshake_message.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, FAST_EXTENSION_BIT]);
     handshake_message.extend_from_slice(info_hash);
     handshake_message.extend_from_slice(peer_id);
     handshake_message
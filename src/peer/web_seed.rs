@@ -0,0 +1,358 @@
+use super::errors::*;
+use super::service::*;
+use super::types::*;
+use super::utils::vec_be_to_u32;
+use crate::bandwidth::PeerBandwidth;
+use crate::http::{HttpsService, IHttpService};
+use crate::metainfo::Metainfo;
+use log::*;
+use std::collections::VecDeque;
+use std::ops::Range;
+
+const URN_SEPARATOR: &str = "://";
+// web seeds aren't reached through the peer port, so they are told apart by their URL
+const WEB_SEED_PORT: u16 = 0;
+
+/// A file of the torrent, as found in a web seed
+#[derive(Debug, Clone, PartialEq)]
+struct WebSeedFile {
+    path: String,
+    length: u64,
+}
+
+/// Web seed (BEP 19) holding the torrent's files in a HTTP server, talked to as if it was a peer.
+/// It has every piece and never chokes us, each block requested is read from the piece
+/// it gets with Range requests to the files the piece spans
+pub struct WebSeedMessageService {
+    http_service: Box<dyn IHttpService + Send>,
+    files: Vec<WebSeedFile>,
+    piece_length: u64,
+    piece_count: u32,
+    messages: VecDeque<PeerMessage>,
+    // the last piece fetched, blocks of a piece are requested one after the other
+    piece: Option<(u32, Vec<u8>)>,
+    bandwidth: PeerBandwidth,
+}
+
+impl WebSeedMessageService {
+    pub fn connect(url: &str, metainfo: &Metainfo) -> Result<Self, PeerConnectionError> {
+        trace!("Connecting to web seed: {}", url);
+        let http_service = HttpsService::from_url(url)
+            .map_err(|err| PeerConnectionError::InitialConnectionError(err.to_string()))?;
+        Ok(Self::new(Box::new(http_service), url, metainfo))
+    }
+
+    pub fn new(http_service: Box<dyn IHttpService + Send>, url: &str, metainfo: &Metainfo) -> Self {
+        let piece_count = metainfo.get_piece_count();
+        let mut messages = VecDeque::new();
+        messages.push_back(PeerMessage::bitfield(vec![true; piece_count as usize]));
+        messages.push_back(PeerMessage::unchoke());
+        Self {
+            http_service,
+            files: files_in_web_seed(url_path(url), metainfo),
+            piece_length: metainfo.info.piece_length as u64,
+            piece_count,
+            messages,
+            piece: None,
+            bandwidth: PeerBandwidth::unlimited(),
+        }
+    }
+
+    fn total_length(&self) -> u64 {
+        self.files.iter().map(|file| file.length).sum()
+    }
+
+    // bytes of the piece, the last one is usually shorter than the rest
+    fn piece_range(&self, index: u32) -> Range<u64> {
+        let start = index as u64 * self.piece_length;
+        start..(start + self.piece_length).min(self.total_length())
+    }
+
+    /// Gets the bytes in `range` of the torrent, from as many files as it spans
+    fn fetch(&mut self, range: Range<u64>) -> Result<Vec<u8>, IPeerMessageServiceError> {
+        let mut bytes = Vec::with_capacity((range.end - range.start) as usize);
+        let mut file_start = 0;
+        for file in &self.files {
+            let file_end = file_start + file.length;
+            let start = range.start.max(file_start);
+            let end = range.end.min(file_end);
+            if start < end {
+                let file_bytes = self
+                    .http_service
+                    .get_range(&file.path, start - file_start..end - file_start)
+                    .map_err(|err| IPeerMessageServiceError::ReceivingMessageError(err.0))?;
+                bytes.extend(file_bytes);
+            }
+            file_start = file_end;
+        }
+        Ok(bytes)
+    }
+
+    fn answer_request(&mut self, request: &PeerMessage) -> Result<(), IPeerMessageServiceError> {
+        let index = vec_be_to_u32(&request.payload[0..4]);
+        let begin = vec_be_to_u32(&request.payload[4..8]) as usize;
+        let length = vec_be_to_u32(&request.payload[8..12]) as usize;
+        if index >= self.piece_count {
+            return Err(IPeerMessageServiceError::InvalidResponse(format!(
+                "Requested piece {} of a torrent with {} pieces",
+                index, self.piece_count
+            )));
+        }
+        if self.piece.as_ref().is_none_or(|(piece, _)| *piece != index) {
+            debug!("Fetching piece {} from web seed", index);
+            let piece = self.fetch(self.piece_range(index))?;
+            self.piece = Some((index, piece));
+        }
+        let Some((_, piece)) = &self.piece else {
+            return Err(IPeerMessageServiceError::UnhandledMessage);
+        };
+        // past the end of the last piece there is nothing left to send
+        let block = piece
+            .get(begin.min(piece.len())..(begin + length).min(piece.len()))
+            .unwrap_or_default()
+            .to_vec();
+        self.bandwidth.throttle_download(block.len());
+        self.messages
+            .push_back(PeerMessage::piece(index as usize, begin, block));
+        Ok(())
+    }
+}
+
+impl IPeerMessageService for WebSeedMessageService {
+    fn wait_for_message(&mut self) -> Result<PeerMessage, IPeerMessageServiceError> {
+        self.messages.pop_front().ok_or_else(|| {
+            IPeerMessageServiceError::ReceivingMessageError(
+                "Web seeds only answer requests".to_string(),
+            )
+        })
+    }
+
+    // the server doesn't download from us, so only requests are answered
+    fn send_message(&mut self, message: &PeerMessage) -> Result<(), IPeerMessageServiceError> {
+        match message.id {
+            PeerMessageId::Request => self.answer_request(message),
+            _ => Ok(()),
+        }
+    }
+
+    fn set_bandwidth(&mut self, bandwidth: PeerBandwidth) {
+        self.bandwidth = bandwidth;
+    }
+}
+
+impl IClientPeerMessageService for WebSeedMessageService {
+    fn handshake(
+        &mut self,
+        _info_hash: &[u8],
+        _peer_id: &[u8],
+    ) -> Result<(), IPeerMessageServiceError> {
+        Ok(())
+    }
+}
+
+impl Peer {
+    /// Web seed at the URL, to connect to like any other peer
+    pub fn web_seed(url: &str) -> Peer {
+        let host = url
+            .split(URN_SEPARATOR)
+            .nth(1)
+            .and_then(|urn| urn.split('/').next())
+            .unwrap_or(url);
+        let (ip, port) = match host.split_once(':') {
+            Some((ip, port)) => (ip, port.parse().unwrap_or(WEB_SEED_PORT)),
+            None => (host, WEB_SEED_PORT),
+        };
+        Peer {
+            ip: ip.to_string(),
+            port,
+            peer_id: url.as_bytes().to_vec(),
+            peer_message_service_provider,
+            web_seed: Some(url.to_string()),
+        }
+    }
+}
+
+// the path of the URL in its server, with the starting /
+fn url_path(url: &str) -> &str {
+    let urn = url.split(URN_SEPARATOR).nth(1).unwrap_or(url);
+    urn.find('/').map(|i| &urn[i..]).unwrap_or("/")
+}
+
+// A URL ending with / is a directory holding the torrent's name.
+// Otherwise it is the file itself, for single file torrents
+fn files_in_web_seed(path: &str, metainfo: &Metainfo) -> Vec<WebSeedFile> {
+    let name = url_encode_path(&metainfo.info.name);
+    match &metainfo.info.files {
+        Some(files) => {
+            let directory = format!("{}/{}", path.trim_end_matches('/'), name);
+            files
+                .iter()
+                .map(|file| WebSeedFile {
+                    path: format!("{}/{}", directory, url_encode_path(&file.path)),
+                    length: file.length,
+                })
+                .collect()
+        }
+        None => {
+            let path = if path.ends_with('/') {
+                format!("{}{}", path, name)
+            } else {
+                path.to_string()
+            };
+            vec![WebSeedFile {
+                path,
+                length: metainfo.info.length,
+            }]
+        }
+    }
+}
+
+// Transforms a path into an url-encoded String, keeping the separators
+fn url_encode_path(path: &str) -> String {
+    path.bytes()
+        .map(|b| {
+            if b.is_ascii_alphanumeric() || b"/.-_~".contains(&b) {
+                String::from(b as char)
+            } else {
+                format!("%{:02X}", b)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http::HttpsServiceError;
+    use crate::metainfo::{File, Info};
+    use std::collections::HashMap;
+
+    // serves the files it has, remembering the paths asked for
+    struct MockWebServer {
+        files: HashMap<String, Vec<u8>>,
+        requested: Vec<String>,
+    }
+
+    impl IHttpService for MockWebServer {
+        fn get(&mut self, _path: &str, _query: &str) -> Result<Vec<u8>, HttpsServiceError> {
+            Err(HttpsServiceError("Only range requests".to_string()))
+        }
+
+        fn get_range(
+            &mut self,
+            path: &str,
+            range: Range<u64>,
+        ) -> Result<Vec<u8>, HttpsServiceError> {
+            self.requested.push(path.to_string());
+            self.files
+                .get(path)
+                .and_then(|file| file.get(range.start as usize..range.end as usize))
+                .map(|bytes| bytes.to_vec())
+                .ok_or_else(|| HttpsServiceError(format!("No {:?} in {}", range, path)))
+        }
+    }
+
+    fn multi_file_metainfo() -> Metainfo {
+        Metainfo {
+            info: Info {
+                piece_length: 8,
+                pieces: vec![vec![0; 20]; 3],
+                name: "linux mint".to_string(),
+                length: 20,
+                files: Some(vec![
+                    File {
+                        path: "docs/README".to_string(),
+                        length: 5,
+                        ..Default::default()
+                    },
+                    File {
+                        path: "mint.iso".to_string(),
+                        length: 15,
+                        ..Default::default()
+                    },
+                ]),
+                ..Default::default()
+            },
+            url_list: vec!["http://mirror.example.org/iso/".to_string()],
+            ..Default::default()
+        }
+    }
+
+    fn request(
+        service: &mut WebSeedMessageService,
+        index: u32,
+        begin: u32,
+        length: u32,
+    ) -> Vec<u8> {
+        service
+            .send_message(&PeerMessage::request(index, begin, length))
+            .unwrap();
+        let piece = service.wait_for_message().unwrap();
+        assert_eq!(piece.id, PeerMessageId::Piece);
+        assert_eq!(vec_be_to_u32(&piece.payload[0..4]), index);
+        assert_eq!(vec_be_to_u32(&piece.payload[4..8]), begin);
+        piece.payload[8..].to_vec()
+    }
+
+    #[test]
+    fn pieces_spanning_files_are_fetched_from_each_file_url() {
+        let metainfo = multi_file_metainfo();
+        let server = MockWebServer {
+            files: HashMap::from([
+                (
+                    "/iso/linux%20mint/docs/README".to_string(),
+                    b"hello".to_vec(),
+                ),
+                (
+                    "/iso/linux%20mint/mint.iso".to_string(),
+                    b"0123456789abcde".to_vec(),
+                ),
+            ]),
+            requested: vec![],
+        };
+        let mut service =
+            WebSeedMessageService::new(Box::new(server), &metainfo.url_list[0], &metainfo);
+
+        let bitfield = service.wait_for_message().unwrap();
+        assert_eq!(bitfield.id, PeerMessageId::Bitfield);
+        assert_eq!(bitfield.payload, vec![0b1110_0000]);
+        assert_eq!(
+            service.wait_for_message().unwrap().id,
+            PeerMessageId::Unchoke
+        );
+
+        assert_eq!(request(&mut service, 0, 0, 4), b"hell".to_vec());
+        assert_eq!(request(&mut service, 0, 4, 4), b"o012".to_vec());
+        assert_eq!(request(&mut service, 2, 0, 8), b"bcde".to_vec());
+        // blocks past the end of the torrent are empty
+        assert_eq!(request(&mut service, 2, 4, 4), Vec::<u8>::new());
+        assert!(service.wait_for_message().is_err());
+    }
+
+    #[test]
+    fn single_file_urls_point_to_the_file_or_its_directory() {
+        let metainfo = Metainfo {
+            info: Info {
+                name: "debian.iso".to_string(),
+                length: 3,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(
+            files_in_web_seed(url_path("http://cdimage.debian.org/cd/"), &metainfo)[0].path,
+            "/cd/debian.iso"
+        );
+        assert_eq!(
+            files_in_web_seed(url_path("https://example.org/latest.iso"), &metainfo)[0].path,
+            "/latest.iso"
+        );
+
+        let peer = Peer::web_seed("http://example.org:8080/cd/");
+        assert_eq!((peer.ip.as_str(), peer.port), ("example.org", 8080));
+        assert_eq!(
+            peer.web_seed,
+            Some("http://example.org:8080/cd/".to_string())
+        );
+    }
+}
@@ -0,0 +1,198 @@
+use crate::peer::Peer;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Time to wait before connecting again to a peer that failed once, it doubles with every failure in a row
+pub const INITIAL_RECONNECT_BACKOFF: Duration = Duration::from_secs(15);
+pub const MAX_RECONNECT_BACKOFF: Duration = Duration::from_secs(30 * 60);
+
+// a peer is told apart by its address, the peer_ids trackers hand out in compact responses are made up
+fn address_of(peer: &Peer) -> String {
+    match &peer.web_seed {
+        Some(url) => url.clone(),
+        None => format!("{}:{}", peer.ip, peer.port),
+    }
+}
+
+#[derive(Debug)]
+struct Candidate {
+    peer: Peer,
+    connected: bool,
+    failures_in_a_row: u32,
+    retry_at: Option<Instant>,
+    pieces_downloaded: u32,
+}
+
+/// Every peer a torrent has heard of, connected or not.
+/// Peers are deduplicated by address, the ones that failed are tried again after a backoff
+/// that grows with every failure in a row, and the ones that served us the most pieces go first
+#[derive(Debug, Default)]
+pub struct PeerCandidates {
+    candidates: HashMap<String, Candidate>,
+}
+
+impl PeerCandidates {
+    /// Adds the peers whose address isn't known yet
+    pub fn add(&mut self, peers: Vec<Peer>) {
+        for peer in peers {
+            self.candidates
+                .entry(address_of(&peer))
+                .or_insert(Candidate {
+                    peer,
+                    connected: false,
+                    failures_in_a_row: 0,
+                    retry_at: None,
+                    pieces_downloaded: 0,
+                });
+        }
+    }
+
+    /// Whether a peer with the same address was already added
+    pub fn contains(&self, peer: &Peer) -> bool {
+        self.candidates.contains_key(&address_of(peer))
+    }
+
+    /// Up to `count` peers to connect to, the best first: the ones that aren't connected
+    /// and aren't waiting for their backoff to pass
+    pub fn ready(&self, now: Instant, count: usize) -> Vec<Peer> {
+        let mut ready: Vec<&Candidate> = self
+            .candidates
+            .values()
+            .filter(|candidate| Self::is_ready(candidate, now))
+            .collect();
+        ready.sort_by_key(|candidate| {
+            (
+                std::cmp::Reverse(candidate.pieces_downloaded),
+                candidate.failures_in_a_row,
+            )
+        });
+        ready
+            .into_iter()
+            .take(count)
+            .map(|candidate| candidate.peer.clone())
+            .collect()
+    }
+
+    /// Whether some peer can be connected to
+    pub fn any_ready(&self, now: Instant) -> bool {
+        self.candidates
+            .values()
+            .any(|candidate| Self::is_ready(candidate, now))
+    }
+
+    pub fn connected(&mut self, peer: &Peer) {
+        if let Some(candidate) = self.candidates.get_mut(&address_of(peer)) {
+            candidate.connected = true;
+            candidate.retry_at = None;
+        }
+    }
+
+    /// The connection to the peer couldn't be opened or was lost, it is tried again after the backoff
+    pub fn failed(&mut self, peer_id: &[u8], now: Instant) {
+        let Some(candidate) = self
+            .candidates
+            .values_mut()
+            .find(|candidate| candidate.peer.peer_id == peer_id)
+        else {
+            return;
+        };
+        candidate.connected = false;
+        candidate.failures_in_a_row += 1;
+        let backoff = INITIAL_RECONNECT_BACKOFF
+            .saturating_mul(1 << (candidate.failures_in_a_row - 1).min(16))
+            .min(MAX_RECONNECT_BACKOFF);
+        candidate.retry_at = Some(now + backoff);
+    }
+
+    /// The peer sent us a valid piece, so it is worth connecting to again before the others
+    pub fn downloaded_piece(&mut self, peer_id: &[u8]) {
+        if let Some(candidate) = self
+            .candidates
+            .values_mut()
+            .find(|candidate| candidate.peer.peer_id == peer_id)
+        {
+            candidate.pieces_downloaded += 1;
+            candidate.failures_in_a_row = 0;
+        }
+    }
+
+    fn is_ready(candidate: &Candidate, now: Instant) -> bool {
+        !candidate.connected && candidate.retry_at.is_none_or(|retry_at| retry_at <= now)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::peer::mock_peer_message_service_provider;
+
+    fn peer(ip: &str, port: u16, peer_id: u8) -> Peer {
+        Peer {
+            ip: ip.to_string(),
+            port,
+            peer_id: vec![peer_id; 20],
+            peer_message_service_provider: mock_peer_message_service_provider,
+            web_seed: None,
+        }
+    }
+
+    fn ready_ports(candidates: &PeerCandidates, now: Instant) -> Vec<u16> {
+        candidates
+            .ready(now, usize::MAX)
+            .into_iter()
+            .map(|peer| peer.port)
+            .collect()
+    }
+
+    #[test]
+    fn peers_are_deduplicated_by_address() {
+        let mut candidates = PeerCandidates::default();
+        candidates.add(vec![peer("10.0.0.1", 6881, 1), peer("10.0.0.1", 6882, 2)]);
+        // announced again with another made up peer_id
+        candidates.add(vec![peer("10.0.0.1", 6881, 3)]);
+
+        let ready = candidates.ready(Instant::now(), usize::MAX);
+        assert_eq!(ready.len(), 2);
+        assert_eq!(candidates.ready(Instant::now(), 1).len(), 1);
+        assert!(candidates.contains(&peer("10.0.0.1", 6882, 4)));
+        assert!(!candidates.contains(&peer("10.0.0.2", 6881, 1)));
+    }
+
+    #[test]
+    fn failed_peers_wait_longer_after_every_failure() {
+        let now = Instant::now();
+        let mut candidates = PeerCandidates::default();
+        candidates.add(vec![peer("10.0.0.1", 6881, 1)]);
+        candidates.connected(&peer("10.0.0.1", 6881, 1));
+        assert!(!candidates.any_ready(now));
+
+        candidates.failed(&[1; 20], now);
+        assert!(!candidates.any_ready(now));
+        assert!(candidates.any_ready(now + INITIAL_RECONNECT_BACKOFF));
+
+        candidates.failed(&[1; 20], now);
+        assert!(!candidates.any_ready(now + INITIAL_RECONNECT_BACKOFF));
+        assert!(candidates.any_ready(now + 2 * INITIAL_RECONNECT_BACKOFF));
+
+        for _ in 0..20 {
+            candidates.failed(&[1; 20], now);
+        }
+        assert!(candidates.any_ready(now + MAX_RECONNECT_BACKOFF));
+    }
+
+    #[test]
+    fn peers_that_served_us_go_first() {
+        let now = Instant::now();
+        let mut candidates = PeerCandidates::default();
+        candidates.add(vec![
+            peer("10.0.0.1", 1, 1),
+            peer("10.0.0.2", 2, 2),
+            peer("10.0.0.3", 3, 3),
+        ]);
+        candidates.downloaded_piece(&[3; 20]);
+        candidates.downloaded_piece(&[3; 20]);
+        candidates.downloaded_piece(&[2; 20]);
+
+        assert_eq!(ready_ports(&candidates, now), vec![3, 2, 1]);
+    }
+}
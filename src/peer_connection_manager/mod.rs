@@ -1,8 +1,10 @@
+mod candidates;
 mod open_peer_connection;
 pub mod sender;
 pub mod types;
 pub mod worker;
 
+pub use candidates::{PeerCandidates, INITIAL_RECONNECT_BACKOFF, MAX_RECONNECT_BACKOFF};
 pub use open_peer_connection::*;
 pub use sender::PeerConnectionManagerSender;
 pub use types::*;
@@ -4,4 +4,4 @@ mod types;
 mod worker;
 pub use errors::OpenPeerConnectionError;
 pub use sender::OpenPeerConnectionSender;
-pub use types::new_open_peer_connection;
+pub use types::{new_open_peer_connection, ConnectionContext};
@@ -15,6 +15,12 @@ impl OpenPeerConnectionSender {
         let _ = self.sender.send(OpenPeerConnectionMessage::SendBitfield);
     }
 
+    pub fn send_have(&self, piece_index: u32) {
+        let _ = self
+            .sender
+            .send(OpenPeerConnectionMessage::SendHave(piece_index));
+    }
+
     pub fn download_piece(&self, piece_index: u32) {
         let _ = self
             .sender
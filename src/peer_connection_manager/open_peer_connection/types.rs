@@ -1,12 +1,17 @@
 use super::errors::OpenPeerConnectionError;
 use super::sender::*;
 use super::worker::*;
+use crate::bandwidth::TorrentBandwidth;
+use crate::encryption::EncryptionPolicy;
 use crate::metainfo::Metainfo;
 use crate::peer::*;
 use crate::peer_connection_manager::PeerConnectionManagerSender;
 use crate::piece_manager::sender::PieceManagerSender;
 use crate::piece_saver::sender::PieceSaverSender;
+use crate::storage::SharedStorage;
 use crate::ui::UIMessageSender;
+use crate::utp::Transport;
+use std::collections::VecDeque;
 use std::sync::mpsc;
 
 #[derive(Debug, Clone)]
@@ -15,12 +20,51 @@ pub enum OpenPeerConnectionMessage {
     DownloadPiece(u32),
     //Orders worker to send bitfield via piece manager sender
     SendBitfield,
+    //Orders worker to tell the peer we now have the piece with said index
+    SendHave(u32),
     //Orders worker to close connection with peer
     CloseConnection,
 }
 
+/// What every connection of a torrent shares, and how they are opened
+#[derive(Clone)]
+pub struct ConnectionContext {
+    /// the pieces served to the peers are read from here
+    pub storage: SharedStorage,
+    /// hands out the upload slots, shared with the server
+    pub choker: Choker,
+    /// rate limits of the torrent
+    pub bandwidth: TorrentBandwidth,
+    /// whether the connections opened are encrypted
+    pub encryption: EncryptionPolicy,
+    /// whether the connections opened try uTP before TCP
+    pub transport: Transport,
+    pub ui_message_sender: UIMessageSender,
+}
+
+impl ConnectionContext {
+    /// Connections are opened over TCP in plaintext until the encryption or the transport are set
+    pub fn new(
+        storage: SharedStorage,
+        choker: Choker,
+        bandwidth: TorrentBandwidth,
+        ui_message_sender: UIMessageSender,
+    ) -> Self {
+        Self {
+            storage,
+            choker,
+            bandwidth,
+            encryption: EncryptionPolicy::default(),
+            transport: Transport::default(),
+            ui_message_sender,
+        }
+    }
+}
+
 //Creates Sender and Worker for OpenPeerConnection. Opens connection with received peer
-//before returning.
+//before returning, the way the context says. The connection serves the pieces found in
+//the context's storage to the peer, taking upload slots from its choker, and its transfers
+//go through the rate limits of the torrent.
 pub fn new_open_peer_connection(
     peer: Peer,
     piece_manager_sender: PieceManagerSender,
@@ -28,15 +72,19 @@ pub fn new_open_peer_connection(
     peer_connection_manager_sender: PeerConnectionManagerSender,
     metainfo: &Metainfo,
     client_peer_id: &[u8],
-    ui_message_sender: UIMessageSender,
+    context: ConnectionContext,
 ) -> Result<(OpenPeerConnectionSender, OpenPeerConnectionWorker), OpenPeerConnectionError> {
-    let peer_message_stream = peer.connect()?;
+    let mut peer_message_stream = peer.connect(metainfo, context.transport)?;
+    peer_message_stream.set_encryption(context.encryption);
+    let upload = UploadState::new(context.storage, context.choker);
     let mut connection = PeerConnection::new(
         peer,
         client_peer_id,
         metainfo,
         peer_message_stream,
-        ui_message_sender,
+        context.ui_message_sender,
+        upload,
+        context.bandwidth.for_peer(),
     );
     connection.open_connection()?;
     let (tx, rx) = mpsc::channel();
@@ -50,6 +98,7 @@ pub fn new_open_peer_connection(
             peer_connection_manager_sender,
             failed_download_in_a_row: 0,
             is_open: true,
+            deferred: VecDeque::new(),
         },
     ))
 }
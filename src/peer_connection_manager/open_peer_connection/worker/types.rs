@@ -6,8 +6,12 @@ use crate::peer_connection_manager::PeerConnectionManagerSender;
 use crate::piece_manager::sender::PieceManagerSender;
 use crate::piece_saver::sender::PieceSaverSender;
 use log::*;
-use std::sync::mpsc::Receiver;
+use std::collections::VecDeque;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
 const MIN_FAILED_CONNECTIONS: u32 = 1;
+// how often an idle connection checks whether the peer asked us for something
+const UPLOAD_POLL_INTERVAL: Duration = Duration::from_millis(200);
 const LOGGER: CustomLogger = CustomLogger::init("Open Peer Connection");
 use crate::ui::PeerStatistics;
 pub struct OpenPeerConnectionWorker {
@@ -18,6 +22,8 @@ pub struct OpenPeerConnectionWorker {
     pub peer_connection_manager_sender: PeerConnectionManagerSender,
     pub failed_download_in_a_row: u32,
     pub is_open: bool,
+    /// messages received while giving back the queued downloads, handled before any new one
+    pub deferred: VecDeque<OpenPeerConnectionMessage>,
 }
 
 impl OpenPeerConnectionWorker {
@@ -36,10 +42,12 @@ impl OpenPeerConnectionWorker {
                 BLOCK_SIZE,
                 self.connection.ui_message_sender.clone(),
             )
-            .map_err(|_| {
-                PeerConnectionError::PieceRequestingError(
+            .map_err(|err| match err {
+                PeerConnectionError::RequestRejected(_)
+                | PeerConnectionError::RequestTimedOut(_) => err,
+                _ => PeerConnectionError::PieceRequestingError(
                     "Error trying to request piece".to_string(),
-                )
+                ),
             })?;
 
         LOGGER.info(format!(
@@ -55,6 +63,41 @@ impl OpenPeerConnectionWorker {
         Ok(())
     }
 
+    // The peer stopped sending blocks, so the pieces queued for it are given back to be asked to others.
+    // The rest of the queued messages are kept for later
+    fn give_back_queued_downloads(&mut self) {
+        let queued: Vec<OpenPeerConnectionMessage> = self.receiver.try_iter().collect();
+        for message in queued {
+            match message {
+                OpenPeerConnectionMessage::DownloadPiece(piece_index) => self
+                    .piece_manager_sender
+                    .failed_download(piece_index, self.connection.get_peer_id()),
+                message => self.deferred.push_back(message),
+            }
+        }
+        self.piece_manager_sender
+            .snubbed(self.connection.get_peer_id());
+    }
+
+    // notifies everyone that the connection is gone, giving back queued downloads
+    fn close_failed_connection(&mut self, reason: String) -> (String, Vec<u8>) {
+        self.is_open = false;
+        self.connection
+            .ui_message_sender
+            .send_closed_connection(self.connection.get_peer_id());
+        self.piece_manager_sender
+            .failed_connection(self.connection.get_peer_id());
+        self.peer_connection_manager_sender
+            .failed_connection(self.connection.get_peer_id());
+        self.receiver.try_iter().for_each(|message| {
+            if let OpenPeerConnectionMessage::DownloadPiece(piece_index) = message {
+                self.piece_manager_sender
+                    .failed_download(piece_index, self.connection.get_peer_id());
+            }
+        });
+        (reason, self.connection.get_peer_id())
+    }
+
     pub fn listen(&mut self) -> Result<(), (String, Vec<u8>)> {
         self.connection.ui_message_sender.send_new_connection();
         let peer_statistics = PeerStatistics {
@@ -64,34 +107,32 @@ impl OpenPeerConnectionWorker {
             port: self.connection.peer.port,
             uploadrate: 0,
             downloadrate: 0,
-            state: PeerConnectionState {
-                client: PeerState {
-                    chocked: self.connection.peer_choking,
-                    interested: self.connection._am_interested,
-                },
-                peer: PeerState {
-                    chocked: self.connection._am_choking,
-                    interested: self.connection._peer_interested,
-                },
-            },
+            state: self.connection.connection_state(),
         };
         self.connection
             .ui_message_sender
             .send_peer_statistics(peer_statistics);
         loop {
-            let message = self.receiver.recv().map_err(|_| {
-                self.connection
-                    .ui_message_sender
-                    .send_closed_connection(self.connection.get_peer_id());
-                self.piece_manager_sender
-                    .failed_connection(self.connection.get_peer_id());
-                self.peer_connection_manager_sender
-                    .failed_connection(self.connection.get_peer_id());
-                (
-                    "Error trying to receive message from OpenPeerConnectionWorker".to_string(),
-                    self.connection.get_peer_id().to_vec(),
-                )
-            })?;
+            let received = match self.deferred.pop_front() {
+                Some(message) => Ok(message),
+                None => self.receiver.recv_timeout(UPLOAD_POLL_INTERVAL),
+            };
+            let message = match received {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => {
+                    if self.connection.serve_pending_messages().is_err() {
+                        return Err(self.close_failed_connection(
+                            "Peer closed the connection while idle".to_string(),
+                        ));
+                    }
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    return Err(self.close_failed_connection(
+                        "Error trying to receive message from OpenPeerConnectionWorker".to_string(),
+                    ));
+                }
+            };
 
             trace!(
                 "peer connection worker with ip: {:?} received message: {:?}",
@@ -100,8 +141,29 @@ impl OpenPeerConnectionWorker {
             );
             match message {
                 OpenPeerConnectionMessage::SendBitfield => self.send_bitfield(),
+                OpenPeerConnectionMessage::SendHave(piece_index) => {
+                    if self.connection.send_have(piece_index).is_err() {
+                        return Err(self.close_failed_connection(format!(
+                            "Failed sending have {} to peer",
+                            piece_index
+                        )));
+                    }
+                }
                 OpenPeerConnectionMessage::DownloadPiece(piece_index) => {
-                    if self.download_piece(piece_index).is_err() {
+                    let was_snubbed = self.connection.snubbed;
+                    let downloaded = self.download_piece(piece_index);
+                    if let Err(
+                        PeerConnectionError::RequestRejected(_)
+                        | PeerConnectionError::RequestTimedOut(_),
+                    ) = downloaded
+                    {
+                        // the peer is still there, it just won't give us this piece for now
+                        self.piece_manager_sender
+                            .failed_download(piece_index, self.connection.get_peer_id());
+                        if self.connection.snubbed && !was_snubbed {
+                            self.give_back_queued_downloads();
+                        }
+                    } else if downloaded.is_err() {
                         self.piece_manager_sender
                             .failed_download(piece_index, self.connection.get_peer_id());
                         self.failed_download_in_a_row += MIN_FAILED_CONNECTIONS;
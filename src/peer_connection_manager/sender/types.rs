@@ -1,4 +1,6 @@
+use crate::peer::{Peer, PeerSource};
 use crate::peer_connection_manager::types::PeerConnectionManagerMessage;
+use crate::peer_connection_manager::OpenPeerConnectionSender;
 use std::sync::mpsc::Sender;
 
 #[derive(Clone, Debug)]
@@ -23,6 +25,35 @@ impl PeerConnectionManagerSender {
             ));
     }
 
+    pub fn broadcast_have(&self, piece_index: u32) {
+        let _ = self
+            .sender
+            .send(PeerConnectionManagerMessage::BroadcastHave(piece_index));
+    }
+
+    /// Connects to the peers, if the torrent allows peers from their source
+    pub fn add_peers(&self, source: PeerSource, peers: Vec<Peer>) {
+        let _ = self
+            .sender
+            .send(PeerConnectionManagerMessage::AddPeers(source, peers));
+    }
+
+    /// Tells the peer sent us a valid piece, so it is preferred when reconnecting
+    pub fn piece_downloaded(&self, peer_id: Vec<u8>) {
+        let _ = self
+            .sender
+            .send(PeerConnectionManagerMessage::PieceDownloaded(peer_id));
+    }
+
+    /// Reports how the attempt to connect to the peer went, without a sender if it failed
+    pub fn connection_attempted(&self, peer_id: Vec<u8>, sender: Option<OpenPeerConnectionSender>) {
+        let _ = self
+            .sender
+            .send(PeerConnectionManagerMessage::ConnectionAttempted(
+                peer_id, sender,
+            ));
+    }
+
     pub fn failed_connection(&self, peer_id: Vec<u8>) {
         let _ = self
             .sender
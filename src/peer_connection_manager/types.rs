@@ -1,9 +1,11 @@
+use super::candidates::PeerCandidates;
+use super::open_peer_connection::{ConnectionContext, OpenPeerConnectionSender};
 use super::sender::*;
 use super::worker::*;
 use crate::metainfo::Metainfo;
+use crate::peer::{ConnectionLimit, Peer, PeerSource, PeerSourcePolicy};
 use crate::piece_manager::sender::PieceManagerSender;
 use crate::piece_saver::sender::PieceSaverSender;
-use crate::ui::UIMessageSender;
 use std::collections::HashMap;
 use std::sync::mpsc;
 use std::time::Instant;
@@ -12,7 +14,14 @@ use std::time::Instant;
 pub enum PeerConnectionManagerMessage {
     DownloadPiece(Vec<u8>, u32),
     FailedConnection(Vec<u8>),
+    BroadcastHave(u32),
     CloseConnections,
+    /// peers found after the first announce, by a source the torrent has to allow
+    AddPeers(PeerSource, Vec<Peer>),
+    /// the peer sent us a valid piece
+    PieceDownloaded(Vec<u8>),
+    /// the attempt to connect to the peer is over, without a connection if it couldn't be opened
+    ConnectionAttempted(Vec<u8>, Option<OpenPeerConnectionSender>),
 }
 
 pub fn new_peer_connection_manager(
@@ -20,7 +29,8 @@ pub fn new_peer_connection_manager(
     piece_saver_sender: PieceSaverSender,
     metainfo: &Metainfo,
     client_peer_id: &[u8],
-    ui_message_sender: UIMessageSender,
+    context: ConnectionContext,
+    connections: ConnectionLimit,
 ) -> (PeerConnectionManagerSender, PeerConnectionManagerWorker) {
     let (tx, rx) = mpsc::channel();
     (
@@ -32,8 +42,14 @@ pub fn new_peer_connection_manager(
             peer_connections: HashMap::new(),
             metainfo: metainfo.clone(),
             client_peer_id: client_peer_id.to_vec(),
-            ui_message_sender,
             last_announce: Instant::now(),
+            context,
+            connections,
+            peer_sources: PeerSourcePolicy::for_torrent(metainfo),
+            candidates: PeerCandidates::default(),
+            max_peers: None,
+            attempts: HashMap::new(),
+            establishing: false,
         },
     )
 }
@@ -1,17 +1,15 @@
 use crate::logger::CustomLogger;
 use crate::metainfo::Metainfo;
 use crate::peer::*;
+use crate::peer_connection_manager::candidates::PeerCandidates;
 use crate::peer_connection_manager::types::PeerConnectionManagerMessage;
 use crate::peer_connection_manager::{open_peer_connection::*, PeerConnectionManagerSender};
 use crate::piece_manager::sender::PieceManagerSender;
 use crate::piece_saver::sender::PieceSaverSender;
 use crate::tracker::ITrackerService;
-use crate::ui::UIMessageSender;
 use log::*;
 use std::collections::HashMap;
-use std::sync::mpsc::{Receiver, RecvError};
-use std::sync::Arc;
-use std::sync::Mutex;
+use std::sync::mpsc::{Receiver, RecvError, RecvTimeoutError};
 use std::thread::JoinHandle;
 use std::time::Duration;
 use std::time::Instant;
@@ -21,6 +19,10 @@ const LOGGER: CustomLogger = CustomLogger::init("Peer Connection Manager");
 pub const FIRST_MIN_CONNECTIONS: usize = 2;
 pub const MAX_TRACKER_REQUESTS: u32 = 3;
 pub const MIN_CONNECTIONS: usize = 10;
+/// Connections being opened at the same time at most, the rest of the peers wait for them
+pub const MAX_HALF_OPEN_CONNECTIONS: usize = 8;
+/// How often failed peers whose backoff passed are connected to again
+pub const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
 
 #[derive(Debug)]
 pub struct PeerConnection {
@@ -29,6 +31,17 @@ pub struct PeerConnection {
     handle: JoinHandle<()>,
     is_open: bool,
     piece_request_count: u32,
+    // counts the connection towards the session's cap until it is closed
+    slot: Option<ConnectionSlot>,
+}
+
+/// A connection being opened, on the thread that handles it once opened
+#[derive(Debug)]
+pub struct ConnectionAttempt {
+    peer: Peer,
+    handle: JoinHandle<()>,
+    // counts the connection towards the session's cap from the start
+    slot: ConnectionSlot,
 }
 
 pub struct PeerConnectionManagerWorker {
@@ -38,41 +51,23 @@ pub struct PeerConnectionManagerWorker {
     pub peer_connections: HashMap<Vec<u8>, PeerConnection>,
     pub metainfo: Metainfo,
     pub client_peer_id: Vec<u8>,
-    pub ui_message_sender: UIMessageSender,
     pub last_announce: Instant,
+    /// what the connections opened share, and how they are opened
+    pub context: ConnectionContext,
+    pub connections: ConnectionLimit,
+    /// which peers the torrent may connect to, depending on where they were found
+    pub peer_sources: PeerSourcePolicy,
+    /// every peer found for the torrent, to connect and reconnect to
+    pub candidates: PeerCandidates,
+    /// connections the torrent keeps open at most, on top of the session's cap. None if unlimited
+    pub max_peers: Option<usize>,
+    /// connections being opened, by peer_id
+    pub attempts: HashMap<Vec<u8>, ConnectionAttempt>,
+    /// whether a batch of attempts is going on, the piece manager is told once they are all over
+    pub establishing: bool,
 }
 
 impl PeerConnectionManagerWorker {
-    fn open_connection_from_peer(
-        peer: Peer,
-        piece_manager_sender: PieceManagerSender,
-        piece_saver_sender: PieceSaverSender,
-        peer_connection_manager_sender: PeerConnectionManagerSender,
-        metainfo: Metainfo,
-        client_peer_id: &[u8],
-        ui_message_sender: UIMessageSender,
-    ) -> Result<(OpenPeerConnectionSender, JoinHandle<()>), OpenPeerConnectionError> {
-        let (open_peer_connection_sender, mut open_peer_connection_worker) =
-            new_open_peer_connection(
-                peer,
-                piece_manager_sender,
-                piece_saver_sender,
-                peer_connection_manager_sender,
-                &metainfo,
-                client_peer_id,
-                ui_message_sender,
-            )?;
-
-        let handle = std::thread::spawn(move || {
-            if let Err((err, _)) = open_peer_connection_worker.listen() {
-                LOGGER.error(err);
-            }
-        });
-
-        open_peer_connection_sender.send_bitfield();
-        Ok((open_peer_connection_sender, handle))
-    }
-
     fn _open_peer_connection_count(&self) -> usize {
         self.peer_connections
             .values()
@@ -83,69 +78,213 @@ impl PeerConnectionManagerWorker {
     fn set_peer_connection_to_closed(&mut self, peer_id: Vec<u8>) {
         if let Some(peer_connection) = self.peer_connections.get_mut(&peer_id) {
             peer_connection.is_open = false;
+            peer_connection.slot = None;
         }
     }
 
-    pub fn start_peer_connections(
+    /// Adds the peers to the candidates if the torrent allows peers from their source, and connects
+    /// to the best candidates there is room for
+    pub fn connect_to(
         &mut self,
+        source: PeerSource,
         peers: Vec<Peer>,
         peer_connection_manager_sender: PeerConnectionManagerSender,
     ) {
+        let peers = self.peer_sources.filter(source, peers);
+        if peers.is_empty() {
+            LOGGER.info(format!("No peers to connect to from {:?}", source));
+            return;
+        }
+        self.candidates.add(peers);
+        self.start_peer_connections(peer_connection_manager_sender);
+    }
+
+    // connections the torrent may still open before reaching its own cap, counting the ones being opened
+    fn room_for_connections(&self) -> usize {
+        match self.max_peers {
+            Some(max_peers) => {
+                max_peers.saturating_sub(self._open_peer_connection_count() + self.attempts.len())
+            }
+            None => usize::MAX,
+        }
+    }
+
+    // done every `RECONNECT_INTERVAL`
+    fn check_connections(&mut self, peer_connection_manager_sender: PeerConnectionManagerSender) {
+        self.connect_to_web_seeds(peer_connection_manager_sender.clone());
+        self.reconnect(peer_connection_manager_sender);
+    }
+
+    // connects again to the peers whose backoff passed, if there is room for them
+    fn reconnect(&mut self, peer_connection_manager_sender: PeerConnectionManagerSender) {
+        if self.room_for_connections() > 0 && self.candidates.any_ready(Instant::now()) {
+            self.start_peer_connections(peer_connection_manager_sender);
+        }
+    }
+
+    /// Connects to the web seeds of the torrent when the peers found are not enough to download it.
+    /// It is checked again every `RECONNECT_INTERVAL`, so they are used too if the swarm thins later on.
+    /// Once added, they are reconnected to like any other peer
+    pub fn connect_to_web_seeds(
+        &mut self,
+        peer_connection_manager_sender: PeerConnectionManagerSender,
+    ) {
+        if self._open_peer_connection_count() >= MIN_CONNECTIONS {
+            return;
+        }
+        let web_seeds: Vec<Peer> = self
+            .metainfo
+            .url_list
+            .iter()
+            .map(|url| Peer::web_seed(url))
+            .filter(|web_seed| !self.candidates.contains(web_seed))
+            .collect();
+        if web_seeds.is_empty() {
+            return;
+        }
         LOGGER.info(format!(
-            "Attempting connections with {:?} peers...",
-            peers.len()
+            "Few peers in the swarm, using {} web seeds",
+            web_seeds.len()
         ));
-        let mut connection_attempts = vec![];
-        let open_peer_connections = Arc::new(Mutex::new(HashMap::new()));
+        self.connect_to(
+            PeerSource::WebSeed,
+            web_seeds,
+            peer_connection_manager_sender,
+        );
+    }
+
+    /// Connects to the best candidates the torrent and the session have room for,
+    /// opening at most `MAX_HALF_OPEN_CONNECTIONS` connections at the same time.
+    /// Each attempt runs on its own thread and reports back through the sender once it's over,
+    /// so the messages of the open connections aren't held up meanwhile
+    pub fn start_peer_connections(
+        &mut self,
+        peer_connection_manager_sender: PeerConnectionManagerSender,
+    ) {
+        let free = MAX_HALF_OPEN_CONNECTIONS.saturating_sub(self.attempts.len());
+        let peers = self
+            .candidates
+            .ready(Instant::now(), self.room_for_connections().min(free));
+        if !peers.is_empty() {
+            LOGGER.info(format!(
+                "Attempting connections with {:?} peers...",
+                peers.len()
+            ));
+        }
         for peer in peers {
-            let piece_manager_sender = self.piece_manager_sender.clone();
-            let piece_saver_sender = self.piece_saver_sender.clone();
-            let metainfo = self.metainfo.clone();
-            let client_peer_id = self.client_peer_id.clone();
-            let ui_message_sender = self.ui_message_sender.clone();
-            let open_peer_connections = open_peer_connections.clone();
-            let peer_connection_manager_sender_clone = peer_connection_manager_sender.clone();
-            connection_attempts.push(std::thread::spawn(move || {
-                if let Ok((open_peer_connection_sender, handle)) = Self::open_connection_from_peer(
-                    peer.clone(),
-                    piece_manager_sender.clone(),
-                    piece_saver_sender,
-                    peer_connection_manager_sender_clone,
-                    metainfo,
-                    &client_peer_id,
-                    ui_message_sender,
-                ) {
-                    if let Ok(mut lock) = open_peer_connections.lock() {
-                        lock.insert(
-                            peer.peer_id.clone(),
-                            PeerConnection {
-                                sender: open_peer_connection_sender,
-                                handle,
-                                is_open: true,
-                                peer: peer.clone(),
-                                piece_request_count: 0,
-                            },
-                        );
-                    }
-                }
-            }));
+            let Some(slot) = self.connections.try_acquire() else {
+                LOGGER.info(format!(
+                    "Reached the connection limit, not connecting to {}:{}",
+                    peer.ip, peer.port
+                ));
+                break;
+            };
+            // it isn't ready to connect to again while the attempt lasts
+            self.candidates.connected(&peer);
+            self.attempt_connection(peer, slot, peer_connection_manager_sender.clone());
+            self.establishing = true;
         }
-        for connection_attempt in connection_attempts {
-            let _ = connection_attempt.join();
+
+        if self.establishing && self.attempts.is_empty() {
+            self.establishing = false;
+            LOGGER.info(format!(
+                "Connected successfully to {:?} peers",
+                self._open_peer_connection_count()
+            ));
+            self.piece_manager_sender
+                .finished_stablishing_connections(self.peer_connections.len());
         }
+    }
 
-        let lock = Arc::try_unwrap(open_peer_connections)
-            .expect("no one should have a reference to open_peer_connections");
-        self.peer_connections = lock
-            .into_inner()
-            .expect("should be able to lock open_peer_connections");
-        LOGGER.info(format!(
-            "Connected successfully to {:?} peers",
-            self.peer_connections.len()
-        ));
+    // opens the connection in a new thread, which reports how it went through the sender
+    // and then goes on handling the connection, if it could be opened
+    fn attempt_connection(
+        &mut self,
+        peer: Peer,
+        slot: ConnectionSlot,
+        peer_connection_manager_sender: PeerConnectionManagerSender,
+    ) {
+        let piece_manager_sender = self.piece_manager_sender.clone();
+        let piece_saver_sender = self.piece_saver_sender.clone();
+        let metainfo = self.metainfo.clone();
+        let client_peer_id = self.client_peer_id.clone();
+        let context = self.context.clone();
+        let attempted_peer = peer.clone();
+        let handle = std::thread::spawn(move || {
+            let peer_id = attempted_peer.peer_id.clone();
+            let opened = new_open_peer_connection(
+                attempted_peer,
+                piece_manager_sender,
+                piece_saver_sender,
+                peer_connection_manager_sender.clone(),
+                &metainfo,
+                &client_peer_id,
+                context,
+            );
+            let (open_peer_connection_sender, mut open_peer_connection_worker) = match opened {
+                Ok(opened) => opened,
+                Err(err) => {
+                    LOGGER.info(format!("Couldn't connect to peer: {}", err));
+                    peer_connection_manager_sender.connection_attempted(peer_id, None);
+                    return;
+                }
+            };
+            open_peer_connection_sender.send_bitfield();
+            // reported before listening, so the manager knows the connection before it can fail
+            peer_connection_manager_sender
+                .connection_attempted(peer_id, Some(open_peer_connection_sender));
+            if let Err((err, _)) = open_peer_connection_worker.listen() {
+                LOGGER.error(err);
+            }
+        });
+        self.attempts.insert(
+            peer.peer_id.clone(),
+            ConnectionAttempt { peer, handle, slot },
+        );
+    }
+
+    // keeps the connection if it could be opened, returning whether it could
+    fn finish_attempt(
+        &mut self,
+        peer_id: Vec<u8>,
+        sender: Option<OpenPeerConnectionSender>,
+    ) -> bool {
+        let Some(attempt) = self.attempts.remove(&peer_id) else {
+            return false;
+        };
+        match sender {
+            Some(sender) => {
+                self.peer_connections.insert(
+                    peer_id,
+                    PeerConnection {
+                        peer: attempt.peer,
+                        sender,
+                        handle: attempt.handle,
+                        is_open: true,
+                        piece_request_count: 0,
+                        slot: Some(attempt.slot),
+                    },
+                );
+                true
+            }
+            None => {
+                let _ = attempt.handle.join();
+                false
+            }
+        }
+    }
 
-        self.piece_manager_sender
-            .finished_stablishing_connections(self.peer_connections.len());
+    // goes on with the peers waiting for a free attempt
+    fn connection_attempted(
+        &mut self,
+        peer_id: Vec<u8>,
+        sender: Option<OpenPeerConnectionSender>,
+        peer_connection_manager_sender: PeerConnectionManagerSender,
+    ) {
+        if !self.finish_attempt(peer_id.clone(), sender) {
+            self.candidates.failed(&peer_id, Instant::now());
+        }
+        self.start_peer_connections(peer_connection_manager_sender);
     }
 
     fn download_piece(&self, peer_id: Vec<u8>, piece_index: u32) {
@@ -153,7 +292,25 @@ impl PeerConnectionManagerWorker {
         peer_connection.sender.download_piece(piece_index);
     }
 
-    fn close_connections(self) {
+    // lets every open connection's peer know we can now serve the piece
+    fn broadcast_have(&self, piece_index: u32) {
+        self.peer_connections
+            .values()
+            .filter(|peer_connection| peer_connection.is_open)
+            .for_each(|peer_connection| peer_connection.sender.send_have(piece_index));
+    }
+
+    fn close_connections(mut self) {
+        // the connections still being opened are waited for, so they are closed too
+        while !self.attempts.is_empty() {
+            match self.receiver.recv() {
+                Ok(PeerConnectionManagerMessage::ConnectionAttempted(peer_id, sender)) => {
+                    self.finish_attempt(peer_id, sender);
+                }
+                Ok(_) => {}
+                Err(_) => break,
+            }
+        }
         for (_, peer_connection) in self.peer_connections.into_iter() {
             peer_connection.sender.close_connection();
             peer_connection.handle.join().unwrap();
@@ -171,10 +328,19 @@ impl PeerConnectionManagerWorker {
         mut self,
         _tracker_service: &mut impl ITrackerService,
         interval: Option<Duration>,
-        _peer_connection_manager_sender: PeerConnectionManagerSender,
+        peer_connection_manager_sender: PeerConnectionManagerSender,
     ) -> Result<(), RecvError> {
+        let mut last_reconnect = Instant::now();
         loop {
-            let message = self.receiver.recv()?;
+            if last_reconnect.elapsed() >= RECONNECT_INTERVAL {
+                self.check_connections(peer_connection_manager_sender.clone());
+                last_reconnect = Instant::now();
+            }
+            let message = match self.receiver.recv_timeout(RECONNECT_INTERVAL) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return Err(RecvError),
+            };
             trace!("Peer connection manager received message: {:?}", message);
 
             match message {
@@ -218,12 +384,206 @@ impl PeerConnectionManagerWorker {
                     }
                 }
 
+                PeerConnectionManagerMessage::BroadcastHave(piece_index) => {
+                    self.broadcast_have(piece_index);
+                }
+
+                PeerConnectionManagerMessage::AddPeers(source, peers) => {
+                    self.connect_to(source, peers, peer_connection_manager_sender.clone());
+                }
+
                 PeerConnectionManagerMessage::FailedConnection(peer_id) => {
                     self.set_peer_connection_to_closed(peer_id.clone());
+                    self.candidates.failed(&peer_id, Instant::now());
                     self.piece_manager_sender.failed_connection(peer_id);
                 }
+
+                PeerConnectionManagerMessage::PieceDownloaded(peer_id) => {
+                    self.candidates.downloaded_piece(&peer_id);
+                }
+
+                PeerConnectionManagerMessage::ConnectionAttempted(peer_id, sender) => {
+                    self.connection_attempted(
+                        peer_id,
+                        sender,
+                        peer_connection_manager_sender.clone(),
+                    );
+                }
             }
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bandwidth::TorrentBandwidth;
+    use crate::metainfo::Info;
+    use crate::peer_connection_manager::new_peer_connection_manager;
+    use crate::piece_manager::PieceManagerMessage;
+    use crate::storage::PieceFileStorage;
+    use crate::tracker::MockTrackerService;
+    use crate::ui::UIMessageSender;
+    use crate::utp::Transport;
+    use std::sync::{mpsc, Arc};
+
+    const SLOW_CONNECTION: Duration = Duration::from_millis(500);
+
+    fn slow_unreachable_peer(
+        _ip: String,
+        _port: u16,
+        _transport: Transport,
+    ) -> Result<Box<dyn IClientPeerMessageService + Send>, PeerConnectionError> {
+        std::thread::sleep(SLOW_CONNECTION);
+        Err(PeerConnectionError::InitialConnectionError(
+            "Unreachable".to_string(),
+        ))
+    }
+
+    fn manager(
+        metainfo: Metainfo,
+    ) -> (
+        PeerConnectionManagerSender,
+        PeerConnectionManagerWorker,
+        mpsc::Receiver<PieceManagerMessage>,
+    ) {
+        let (piece_manager_sender, piece_manager) = mpsc::channel();
+        let (piece_saver_sender, _piece_saver) = mpsc::channel();
+        let storage = PieceFileStorage::new("./downloads/attempts", 1, "attempts", false);
+        let (sender, worker) = new_peer_connection_manager(
+            PieceManagerSender {
+                sender: piece_manager_sender,
+            },
+            PieceSaverSender {
+                sender: piece_saver_sender,
+            },
+            &metainfo,
+            &[0; 20],
+            ConnectionContext::new(
+                Arc::new(storage),
+                Choker::default(),
+                TorrentBandwidth::unlimited(),
+                UIMessageSender::no_ui(),
+            ),
+            ConnectionLimit::unlimited(),
+        );
+        (sender, worker, piece_manager)
+    }
+
+    fn metainfo() -> Metainfo {
+        Metainfo {
+            info: Info {
+                piece_length: 8,
+                pieces: vec![vec![0; 20]],
+                length: 8,
+                name: "attempts".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn unreachable_peer(id: u8) -> Peer {
+        Peer {
+            ip: format!("10.0.0.{}", id),
+            port: 6881,
+            peer_id: vec![id; 20],
+            peer_message_service_provider: slow_unreachable_peer,
+            web_seed: None,
+        }
+    }
+
+    #[test]
+    fn messages_are_handled_while_connections_are_being_opened() {
+        let (sender, worker, piece_manager) = manager(metainfo());
+        let listen_sender = sender.clone();
+        let handle = std::thread::spawn(move || {
+            let mut tracker_service = MockTrackerService {
+                responses: vec![],
+                response_index: 0,
+            };
+            worker.listen(&mut tracker_service, None, listen_sender)
+        });
+
+        sender.add_peers(PeerSource::Tracker, vec![unreachable_peer(1)]);
+        sender.failed_connection(vec![2; 20]);
+
+        // handled while the peer is still being connected to
+        let handled = piece_manager.recv_timeout(SLOW_CONNECTION / 2);
+        assert!(matches!(
+            handled,
+            Ok(PieceManagerMessage::FailedConnection(_))
+        ));
+        let attempted = piece_manager.recv_timeout(SLOW_CONNECTION * 4);
+        assert!(matches!(
+            attempted,
+            Ok(PieceManagerMessage::FinishedEstablishingConnections(0))
+        ));
+        sender.close_connections();
+        assert!(handle.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn piece_manager_is_only_told_when_a_batch_of_attempts_is_over() {
+        let (sender, mut worker, piece_manager) = manager(metainfo());
+        worker.connections = ConnectionLimit::new(Some(1));
+        let taken = worker.connections.try_acquire();
+
+        // the peer is ready, but there is no room in the session to attempt a connection
+        worker.connect_to(
+            PeerSource::Tracker,
+            vec![unreachable_peer(1)],
+            sender.clone(),
+        );
+        worker.check_connections(sender.clone());
+        assert!(piece_manager.try_recv().is_err());
+
+        drop(taken);
+        worker.check_connections(sender.clone());
+        assert!(piece_manager.try_recv().is_err());
+        let Ok(PeerConnectionManagerMessage::ConnectionAttempted(peer_id, None)) =
+            worker.receiver.recv()
+        else {
+            panic!("the attempt wasn't reported");
+        };
+        worker.connection_attempted(peer_id, None, sender.clone());
+        assert!(matches!(
+            piece_manager.try_recv(),
+            Ok(PieceManagerMessage::FinishedEstablishingConnections(0))
+        ));
+
+        // the failed peer waits for its backoff, so the tick is idle
+        worker.check_connections(sender);
+        assert!(piece_manager.try_recv().is_err());
+    }
+
+    #[test]
+    fn the_web_seeds_are_used_once_the_swarm_thins_out() {
+        // nothing listens on the port, the attempt to the web seed is just started
+        let url = "http://127.0.0.1:1/attempts";
+        let mut metainfo = metainfo();
+        metainfo.url_list = vec![url.to_string()];
+        let (sender, mut worker, _piece_manager) = manager(metainfo);
+        let web_seed = Peer::web_seed(url);
+
+        worker.connect_to(
+            PeerSource::Tracker,
+            vec![unreachable_peer(1)],
+            sender.clone(),
+        );
+        assert!(!worker.candidates.contains(&web_seed));
+        // the only peer of the swarm goes away mid-download
+        let Ok(PeerConnectionManagerMessage::ConnectionAttempted(peer_id, None)) =
+            worker.receiver.recv()
+        else {
+            panic!("the attempt wasn't reported");
+        };
+        worker.connection_attempted(peer_id, None, sender.clone());
+        assert!(worker.attempts.is_empty());
+
+        worker.check_connections(sender);
+        assert!(worker.candidates.contains(&web_seed));
+        assert!(worker.attempts.contains_key(&web_seed.peer_id));
+    }
+}
@@ -1,7 +1,11 @@
+mod priorities;
+mod selection;
 pub mod sender;
 pub mod types;
 mod worker;
 
+pub use priorities::*;
+pub use selection::*;
 pub use sender::PieceManagerSender;
 pub use types::*;
 pub use worker::PieceManagerWorker;
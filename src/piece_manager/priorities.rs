@@ -0,0 +1,124 @@
+use crate::metainfo::Metainfo;
+
+/// How much a file of the torrent is wanted, from not at all to before anything else
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum FilePriority {
+    /// The file isn't downloaded, nor created on disk unless it shares a piece with a wanted file
+    Skip,
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl FilePriority {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim() {
+            "skip" => Some(FilePriority::Skip),
+            "low" => Some(FilePriority::Low),
+            "normal" => Some(FilePriority::Normal),
+            "high" => Some(FilePriority::High),
+            _ => None,
+        }
+    }
+}
+
+/// Turns the priority of every file of the torrent into the priority of every piece.
+/// A piece takes the highest priority of the files it holds data of, so a piece straddling
+/// a skipped file and a wanted one is still downloaded.
+/// Files without a priority in `file_priorities` are normal, a single file torrent counts as one file
+pub fn piece_priorities(
+    metainfo: &Metainfo,
+    file_priorities: &[FilePriority],
+) -> Vec<FilePriority> {
+    let piece_length = metainfo.info.piece_length as u64;
+    let mut priorities = vec![FilePriority::Skip; metainfo.info.pieces.len()];
+    let files = match &metainfo.info.files {
+        Some(files) => files.iter().map(|file| file.length).collect(),
+        None => vec![metainfo.info.length],
+    };
+
+    let mut offset = 0;
+    for (index, length) in files.into_iter().enumerate() {
+        let priority = file_priorities.get(index).copied().unwrap_or_default();
+        if length > 0 && piece_length > 0 {
+            let first_piece = (offset / piece_length) as usize;
+            let last_piece = ((offset + length - 1) / piece_length) as usize;
+            for piece in priorities.iter_mut().take(last_piece + 1).skip(first_piece) {
+                *piece = (*piece).max(priority);
+            }
+        }
+        offset += length;
+    }
+    priorities
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metainfo::{File, Info};
+
+    fn metainfo_with_files(lengths: &[u64], piece_length: u32) -> Metainfo {
+        let length: u64 = lengths.iter().sum();
+        let piece_count = (length as usize).div_ceil(piece_length as usize);
+        Metainfo {
+            announce: "".to_string(),
+            info_hash: vec![],
+            info: Info {
+                piece_length,
+                pieces: vec![vec![0; 20]; piece_count],
+                name: "priorities".to_string(),
+                length,
+                files: Some(
+                    lengths
+                        .iter()
+                        .enumerate()
+                        .map(|(index, length)| File {
+                            path: index.to_string(),
+                            length: *length,
+                            ..Default::default()
+                        })
+                        .collect(),
+                ),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn pieces_straddling_files_take_the_highest_priority() {
+        // pieces of 10 bytes: file 0 is in pieces 0-1, file 1 in 1-2 and file 2 in 2-3
+        let metainfo = metainfo_with_files(&[15, 10, 15], 10);
+        let priorities = piece_priorities(
+            &metainfo,
+            &[FilePriority::High, FilePriority::Skip, FilePriority::Low],
+        );
+
+        assert_eq!(
+            priorities,
+            vec![
+                FilePriority::High,
+                FilePriority::High,
+                FilePriority::Low,
+                FilePriority::Low
+            ]
+        );
+    }
+
+    #[test]
+    fn only_pieces_of_skipped_files_alone_are_skipped() {
+        let metainfo = metainfo_with_files(&[20, 20], 10);
+        let priorities = piece_priorities(&metainfo, &[FilePriority::Skip]);
+
+        assert_eq!(
+            priorities,
+            vec![
+                FilePriority::Skip,
+                FilePriority::Skip,
+                FilePriority::Normal,
+                FilePriority::Normal
+            ]
+        );
+    }
+}
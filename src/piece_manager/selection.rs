@@ -0,0 +1,287 @@
+use super::priorities::FilePriority;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Pieces ahead of the playback position prioritized by default in streaming mode
+pub const DEFAULT_STREAMING_WINDOW: u32 = 8;
+
+/// Time given to download each piece of the window by default, counted from when the position is set.
+/// The first piece of the window is due after this long, the second after twice as long, and so on.
+/// A piece joining the window as it moves forward is due this long after the last one
+pub const DEFAULT_PIECE_DEADLINE: Duration = Duration::from_secs(2);
+
+// pieces available from this many peers or more are left for last by rarest-first
+const MAX_PEERS_PER_PIECE: usize = 50;
+
+/// Order in which the piece manager asks for the pieces of a torrent
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DownloadMode {
+    /// The pieces fewer peers have go first, so they don't disappear from the swarm
+    #[default]
+    RarestFirst,
+    /// Pieces are asked for in index order, so the file can be read while it downloads
+    Sequential,
+    /// The pieces right after the playback position go first, in order of their deadlines.
+    /// The window moves past the pieces downloaded, and a piece late for its deadline is asked to a second peer.
+    /// The rest of the torrent is downloaded rarest-first
+    Streaming(StreamingWindow),
+}
+
+impl DownloadMode {
+    /// Parses the `download_mode` config value: `rarest`, `sequential` or `streaming`.
+    /// Streaming starts at the first piece with the default window
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim() {
+            "rarest" => Some(DownloadMode::RarestFirst),
+            "sequential" => Some(DownloadMode::Sequential),
+            "streaming" => Some(DownloadMode::Streaming(StreamingWindow::default())),
+            _ => None,
+        }
+    }
+}
+
+/// Pieces prioritized while streaming, starting at the one being played
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamingWindow {
+    /// index of the piece being played
+    pub position: u32,
+    /// amount of pieces prioritized from the position on
+    pub length: u32,
+    /// time given to download each piece of the window
+    pub piece_deadline: Duration,
+}
+
+impl Default for StreamingWindow {
+    fn default() -> Self {
+        Self {
+            position: 0,
+            length: DEFAULT_STREAMING_WINDOW,
+            piece_deadline: DEFAULT_PIECE_DEADLINE,
+        }
+    }
+}
+
+/// Chooses the next piece to ask for according to the download mode and the piece priorities
+#[derive(Debug, Clone, Default)]
+pub struct PieceSelector {
+    mode: DownloadMode,
+    // when each piece of the streaming window has to be downloaded by, the window holds the pieces missing
+    deadlines: HashMap<u32, Instant>,
+    // priority of each piece, every piece is normal if empty
+    priorities: Vec<FilePriority>,
+}
+
+impl PieceSelector {
+    pub fn new(mode: DownloadMode) -> Self {
+        let mut selector = Self::default();
+        selector.set_mode(mode);
+        selector
+    }
+
+    pub fn mode(&self) -> DownloadMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: DownloadMode) {
+        self.mode = mode;
+        self.deadlines.clear();
+        if let DownloadMode::Streaming(window) = mode {
+            let now = Instant::now();
+            for offset in 0..window.length {
+                let due = now + window.piece_deadline * (offset + 1);
+                self.deadlines.insert(window.position + offset, due);
+            }
+        }
+    }
+
+    /// Moves the streaming window to start at `position`, the deadlines start counting again.
+    /// Does nothing unless streaming
+    pub fn set_playback_position(&mut self, position: u32) {
+        if let DownloadMode::Streaming(window) = self.mode {
+            self.set_mode(DownloadMode::Streaming(StreamingWindow {
+                position,
+                ..window
+            }));
+        }
+    }
+
+    /// Replaces the priority of every piece, as given by [`piece_priorities`](super::piece_priorities)
+    pub fn set_priorities(&mut self, priorities: Vec<FilePriority>) {
+        self.priorities = priorities;
+    }
+
+    pub fn priority(&self, piece: u32) -> FilePriority {
+        self.priorities
+            .get(piece as usize)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Whether the piece only holds data of skipped files, so it isn't downloaded
+    pub fn is_skipped(&self, piece: u32) -> bool {
+        self.priority(piece) == FilePriority::Skip
+    }
+
+    /// Keeps the streaming window `length` pieces long, from the playback position on, with the pieces
+    /// below `pieces` that `is_missing`. The pieces no longer missing leave it, so the window moves forward
+    /// as they are downloaded, and the ones joining it are due one piece deadline after the last of the window
+    pub fn refill_window(&mut self, pieces: u32, is_missing: impl Fn(u32) -> bool) {
+        let DownloadMode::Streaming(window) = self.mode else {
+            return;
+        };
+        let priorities = &self.priorities;
+        let wanted = |piece: u32| {
+            is_missing(piece) && priorities.get(piece as usize) != Some(&FilePriority::Skip)
+        };
+        self.deadlines.retain(|piece, _| wanted(*piece));
+
+        let mut last_due = self
+            .deadlines
+            .values()
+            .max()
+            .map_or(Instant::now(), |due| (*due).max(Instant::now()));
+        let mut piece = window.position;
+        while (self.deadlines.len() as u32) < window.length && piece < pieces {
+            if wanted(piece) && !self.deadlines.contains_key(&piece) {
+                last_due += window.piece_deadline;
+                self.deadlines.insert(piece, last_due);
+            }
+            piece += 1;
+        }
+    }
+
+    /// Whether the piece is in the streaming window and its deadline already passed
+    pub fn is_late(&self, piece: u32) -> bool {
+        self.deadlines
+            .get(&piece)
+            .is_some_and(|due| *due < Instant::now())
+    }
+
+    /// Picks among `candidates`, the pieces ready to be asked for along with how many peers have them.
+    /// Skipped pieces are never picked, and the streaming window goes before the highest priority
+    pub fn select(&self, candidates: impl Iterator<Item = (u32, usize)>) -> Option<u32> {
+        let wanted: Vec<(u32, usize)> = candidates
+            .filter(|(piece, _)| !self.is_skipped(*piece))
+            .collect();
+        if let DownloadMode::Streaming(_) = self.mode {
+            let in_window = wanted
+                .iter()
+                .map(|(piece, _)| *piece)
+                .filter(|piece| self.deadlines.contains_key(piece))
+                .min_by_key(|piece| (self.deadlines.get(piece).copied(), *piece));
+            if in_window.is_some() {
+                return in_window;
+            }
+        }
+
+        let highest = wanted
+            .iter()
+            .map(|(piece, _)| self.priority(*piece))
+            .max()?;
+        let candidates = wanted
+            .into_iter()
+            .filter(|(piece, _)| self.priority(*piece) == highest);
+        match self.mode {
+            DownloadMode::RarestFirst => rarest(candidates),
+            DownloadMode::Sequential => candidates.map(|(piece, _)| piece).min(),
+            // nothing in the window is left to ask for
+            DownloadMode::Streaming(_) => rarest(candidates),
+        }
+    }
+}
+
+fn rarest(candidates: impl Iterator<Item = (u32, usize)>) -> Option<u32> {
+    candidates
+        .filter(|(_, peers)| *peers < MAX_PEERS_PER_PIECE)
+        .min_by_key(|(piece, peers)| (*peers, *piece))
+        .map(|(piece, _)| piece)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // (piece, peers that have it)
+    const CANDIDATES: [(u32, usize); 5] = [(7, 1), (2, 3), (3, 2), (0, 4), (12, 2)];
+
+    #[test]
+    fn rarest_first_picks_the_piece_fewer_peers_have() {
+        let selector = PieceSelector::new(DownloadMode::RarestFirst);
+        assert_eq!(selector.select(CANDIDATES.into_iter()), Some(7));
+    }
+
+    #[test]
+    fn sequential_picks_the_lowest_index() {
+        let selector = PieceSelector::new(DownloadMode::Sequential);
+        assert_eq!(selector.select(CANDIDATES.into_iter()), Some(0));
+    }
+
+    #[test]
+    fn streaming_prioritizes_the_window_and_falls_back_to_rarest_first() {
+        let mut selector = PieceSelector::new(DownloadMode::Streaming(StreamingWindow {
+            position: 1,
+            length: 4,
+            piece_deadline: DEFAULT_PIECE_DEADLINE,
+        }));
+        assert_eq!(selector.select(CANDIDATES.into_iter()), Some(2));
+
+        selector.set_playback_position(9);
+        assert_eq!(selector.select(CANDIDATES.into_iter()), Some(12));
+
+        selector.set_playback_position(20);
+        assert_eq!(selector.select(CANDIDATES.into_iter()), Some(7));
+    }
+
+    #[test]
+    fn skipped_pieces_are_never_picked_and_higher_priorities_go_first() {
+        let mut selector = PieceSelector::new(DownloadMode::Sequential);
+        let mut priorities = vec![FilePriority::Normal; 13];
+        priorities[0] = FilePriority::Skip;
+        priorities[2] = FilePriority::Skip;
+        priorities[7] = FilePriority::High;
+        priorities[12] = FilePriority::High;
+        selector.set_priorities(priorities);
+
+        assert_eq!(selector.select(CANDIDATES.into_iter()), Some(7));
+        assert_eq!(
+            selector.select(CANDIDATES.into_iter().filter(|(piece, _)| *piece < 3)),
+            None
+        );
+    }
+
+    #[test]
+    fn window_pieces_are_late_once_their_deadline_passes() {
+        let selector = PieceSelector::new(DownloadMode::Streaming(StreamingWindow {
+            position: 0,
+            length: 2,
+            piece_deadline: Duration::ZERO,
+        }));
+        std::thread::sleep(Duration::from_millis(1));
+
+        assert!(selector.is_late(0));
+        assert!(!selector.is_late(5));
+    }
+
+    #[test]
+    fn streaming_window_moves_past_the_downloaded_pieces() {
+        let mut selector = PieceSelector::new(DownloadMode::Streaming(StreamingWindow {
+            position: 2,
+            length: 2,
+            piece_deadline: DEFAULT_PIECE_DEADLINE,
+        }));
+        let mut priorities = vec![FilePriority::Normal; 10];
+        priorities[4] = FilePriority::Skip;
+        selector.set_priorities(priorities);
+        let candidates = [(5, 3), (6, 3), (7, 3), (9, 1)];
+
+        // 2 and 3 are downloaded and 4 is skipped
+        selector.refill_window(10, |piece| piece > 3);
+        assert_eq!(selector.select(candidates.into_iter()), Some(5));
+
+        selector.refill_window(10, |piece| piece > 5);
+        assert_eq!(
+            selector.select(candidates.into_iter().filter(|(piece, _)| *piece != 6)),
+            Some(7)
+        );
+    }
+}
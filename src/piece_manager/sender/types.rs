@@ -1,4 +1,6 @@
 use crate::peer::Bitfield;
+use crate::piece_manager::priorities::FilePriority;
+use crate::piece_manager::selection::DownloadMode;
 use crate::piece_manager::types::PieceManagerMessage;
 use std::sync::mpsc::Sender;
 
@@ -33,6 +35,11 @@ impl PieceManagerSender {
             .send(PieceManagerMessage::FailedConnection(peer_id));
     }
 
+    /// The peer stopped sending blocks, its pieces are asked to others until it sends one again
+    pub fn snubbed(&self, peer_id: Vec<u8>) {
+        let _ = self.sender.send(PieceManagerMessage::Snubbed(peer_id));
+    }
+
     pub fn have(&self, peer_id: Vec<u8>, piece_index: u32) {
         let _ = self
             .sender
@@ -43,6 +50,39 @@ impl PieceManagerSender {
         let _ = self.sender.send(PieceManagerMessage::ReaskedTracker());
     }
 
+    /// Changes the order the remaining pieces are asked for in
+    pub fn set_download_mode(&self, mode: DownloadMode) {
+        let _ = self.sender.send(PieceManagerMessage::SetDownloadMode(mode));
+    }
+
+    /// Moves the streaming window to the piece being played, ignored unless streaming
+    pub fn set_playback_position(&self, piece_index: u32) {
+        let _ = self
+            .sender
+            .send(PieceManagerMessage::SetPlaybackPosition(piece_index));
+    }
+
+    /// Replaces the priority of every piece, see [`piece_priorities`](crate::piece_manager::piece_priorities)
+    pub fn set_piece_priorities(&self, priorities: Vec<FilePriority>) {
+        let _ = self
+            .sender
+            .send(PieceManagerMessage::SetPiecePriorities(priorities));
+    }
+
+    /// Stops asking for pieces, the ones already asked for still arrive
+    pub fn pause(&self) {
+        let _ = self.sender.send(PieceManagerMessage::Pause);
+    }
+
+    pub fn resume(&self) {
+        let _ = self.sender.send(PieceManagerMessage::Resume);
+    }
+
+    /// Stops downloading before every piece is there, closing the peer connections
+    pub fn stop(&self) {
+        let _ = self.sender.send(PieceManagerMessage::Stop);
+    }
+
     pub fn finished_stablishing_connections(&self, connection_established: usize) {
         let _ = self
             .sender
@@ -1,3 +1,5 @@
+use super::priorities::FilePriority;
+use super::selection::{DownloadMode, PieceSelector};
 use super::sender::types::PieceManagerSender;
 use super::worker::types::PieceManagerWorker;
 use crate::peer::Bitfield;
@@ -16,15 +18,32 @@ pub enum PieceManagerMessage {
     SuccessfulDownload(PieceId, PeerId),
     FailedDownload(PieceId, PeerId),
     FailedConnection(PeerId),
+    Snubbed(PeerId),
     Have(PeerId, PieceId),
     ReaskedTracker(),
     FinishedEstablishingConnections(usize),
+    SetDownloadMode(DownloadMode),
+    SetPlaybackPosition(PieceId),
+    SetPiecePriorities(Vec<FilePriority>),
+    Pause,
+    Resume,
+    Stop,
+}
+
+/// What the piece manager tells the client about the download as it goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DownloadProgress {
+    /// Every wanted piece is downloaded, only the pieces of skipped files may be left
+    WantedPiecesDownloaded,
+    /// A skipped file is wanted again, so there are pieces to download once more
+    Downloading,
 }
 
 pub fn new_piece_manager(
     number_of_pieces: u32,
     ui_message_sender: UIMessageSender,
     initial_pieces: Vec<u32>,
+    download_mode: DownloadMode,
 ) -> (PieceManagerSender, PieceManagerWorker) {
     let (tx, rx) = mpsc::channel();
 
@@ -58,6 +77,13 @@ pub fn new_piece_manager(
             recieved_bitfields: 0,
             established_connections: 0,
             is_asking_tracker: false,
+            is_paused: false,
+            snubbed_peers: HashSet::new(),
+            late_piece_asked_to: HashMap::new(),
+            late_piece_tried: HashMap::new(),
+            progress: None,
+            wanted_pieces_reported: false,
+            selector: PieceSelector::new(download_mode),
         },
     )
 }
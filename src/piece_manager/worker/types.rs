@@ -1,15 +1,21 @@
 use crate::logger::CustomLogger;
 use crate::peer::Bitfield;
 use crate::peer_connection_manager::PeerConnectionManagerSender;
-use crate::piece_manager::types::PieceManagerMessage;
+use crate::piece_manager::selection::PieceSelector;
+use crate::piece_manager::types::{DownloadProgress, PieceManagerMessage};
 use crate::ui::UIMessageSender;
 use log::*;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::sync::mpsc::Receiver;
 use std::sync::mpsc::RecvError;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
 
 const LOGGER: CustomLogger = CustomLogger::init("Piece Manager");
+// how often the pieces of the streaming window are checked for being late, when no message arrives
+const LATE_PIECES_CHECK_INTERVAL: Duration = Duration::from_secs(1);
 type PeerId = Vec<u8>;
 pub struct PieceManagerWorker {
     pub reciever: Receiver<PieceManagerMessage>,
@@ -23,13 +29,34 @@ pub struct PieceManagerWorker {
     pub recieved_bitfields: usize,
     pub established_connections: usize,
     pub is_asking_tracker: bool,
+    pub selector: PieceSelector,
+    pub is_paused: bool,
+    /// peers that stopped sending us blocks, they are only asked for the pieces no one else has
+    pub snubbed_peers: HashSet<PeerId>,
+    /// second peer each piece of the streaming window that was late for its deadline is asked to
+    pub late_piece_asked_to: HashMap<u32, PeerId>,
+    /// peers each late piece was already asked to, it isn't asked to any of them again while it's late
+    pub late_piece_tried: HashMap<u32, HashSet<PeerId>>,
+    /// where the client is told when every wanted piece is downloaded, and when there are pieces to download again
+    pub progress: Option<Sender<DownloadProgress>>,
+    /// whether the wanted pieces were last reported as downloaded
+    pub wanted_pieces_reported: bool,
 }
 
 impl PieceManagerWorker {
     fn update_after_succesfull_download(&mut self, piece_index: u32, peerd_id: PeerId) {
+        if self.selector.is_late(piece_index) {
+            LOGGER.info(format!(
+                "Piece {} of the streaming window arrived after its deadline",
+                piece_index
+            ));
+        }
         self.ready_to_download_pieces.remove(&piece_index);
         self.allowed_peers_to_download_piece.remove(&piece_index);
         self.piece_asked_to.remove(&piece_index);
+        self.late_piece_asked_to.remove(&piece_index);
+        self.late_piece_tried.remove(&piece_index);
+        self.refill_streaming_window();
 
         // this unwrap would never happen peer would only be removed once the connection fails
         let count = self
@@ -45,7 +72,18 @@ impl PieceManagerWorker {
         peerd_id: PeerId,
         peer_connection_manager_sender: &PeerConnectionManagerSender,
     ) {
-        self.update_after_succesfull_download(piece_index, peerd_id);
+        if !self
+            .allowed_peers_to_download_piece
+            .contains_key(&piece_index)
+        {
+            // the other peer a late piece was asked to already sent it
+            self.finish_duplicate_request(&peerd_id, peer_connection_manager_sender);
+            return;
+        }
+        self.update_after_succesfull_download(piece_index, peerd_id.clone());
+        self.snubbed_peers.remove(&peerd_id);
+        peer_connection_manager_sender.piece_downloaded(peerd_id);
+        peer_connection_manager_sender.broadcast_have(piece_index);
         self.ask_for_pieces(peer_connection_manager_sender);
     }
 
@@ -68,10 +106,96 @@ impl PieceManagerWorker {
         peer_id: PeerId,
         peer_connection_manager_sender: &PeerConnectionManagerSender,
     ) {
+        if !self
+            .allowed_peers_to_download_piece
+            .contains_key(&piece_index)
+        {
+            self.finish_duplicate_request(&peer_id, peer_connection_manager_sender);
+            return;
+        }
+        // a late piece is still on its way from the other peer it was asked to
+        if let Some(second_peer) = self.late_piece_asked_to.remove(&piece_index) {
+            if second_peer != peer_id {
+                self.piece_asked_to.insert(piece_index, second_peer);
+            }
+            self.finish_duplicate_request(&peer_id, peer_connection_manager_sender);
+            return;
+        }
         self.update_after_failed_download(piece_index, peer_id);
         self.ask_for_pieces(peer_connection_manager_sender);
     }
 
+    // one of the two requests of a late piece ended, the piece is downloaded or the other one is still going
+    fn finish_duplicate_request(
+        &mut self,
+        peer_id: &PeerId,
+        peer_connection_manager_sender: &PeerConnectionManagerSender,
+    ) {
+        if let Some(count) = self.peer_pieces_to_download_count.get_mut(peer_id) {
+            *count -= 1;
+        }
+        self.ask_for_pieces(peer_connection_manager_sender);
+    }
+
+    // keeps the streaming window full of missing pieces, so it moves forward as they are downloaded
+    fn refill_streaming_window(&mut self) {
+        let remaining = &self.allowed_peers_to_download_piece;
+        let pieces = remaining.keys().max().map_or(0, |piece| piece + 1);
+        self.selector
+            .refill_window(pieces, |piece| remaining.contains_key(&piece));
+    }
+
+    // asks every piece of the streaming window late for its deadline to a second peer, the least busy one
+    // that has it and wasn't asked for it yet, whichever of the two sends it first wins
+    fn ask_late_pieces_again(
+        &mut self,
+        peer_connection_manager_sender: &PeerConnectionManagerSender,
+    ) {
+        if self.is_paused {
+            return;
+        }
+        let late_pieces: Vec<(u32, PeerId)> = self
+            .piece_asked_to
+            .iter()
+            .filter(|(piece, _)| {
+                self.selector.is_late(**piece) && !self.late_piece_asked_to.contains_key(piece)
+            })
+            .map(|(piece, peer_id)| (*piece, peer_id.clone()))
+            .collect();
+        for (piece, first_peer) in late_pieces {
+            let load = |peer: &&PeerId| {
+                (
+                    self.snubbed_peers.contains(*peer),
+                    self.peer_pieces_to_download_count
+                        .get(*peer)
+                        .copied()
+                        .unwrap_or_default(),
+                )
+            };
+            let tried = self.late_piece_tried.get(&piece);
+            let second_peer = self.allowed_peers_to_download_piece[&piece]
+                .iter()
+                .filter(|peer| **peer != first_peer)
+                .filter(|peer| tried.is_none_or(|tried| !tried.contains(*peer)))
+                .min_by_key(load)
+                .cloned();
+            if let Some(second_peer) = second_peer {
+                LOGGER.info(format!(
+                    "Piece {} of the streaming window is late, asking it to a second peer",
+                    piece
+                ));
+                if let Some(count) = self.peer_pieces_to_download_count.get_mut(&second_peer) {
+                    *count += 1;
+                }
+                let tried = self.late_piece_tried.entry(piece).or_default();
+                tried.insert(first_peer);
+                tried.insert(second_peer.clone());
+                self.late_piece_asked_to.insert(piece, second_peer.clone());
+                peer_connection_manager_sender.download_piece(second_peer, piece);
+            }
+        }
+    }
+
     fn last_piece_downloaded(&self) -> bool {
         if self.allowed_peers_to_download_piece.is_empty() {
             info!("All pieces downloaded");
@@ -80,6 +204,39 @@ impl PieceManagerWorker {
         false
     }
 
+    // pieces of skipped files don't count as remaining, though their files may be wanted later
+    fn wanted_pieces_downloaded(&self) -> bool {
+        self.allowed_peers_to_download_piece
+            .keys()
+            .all(|piece| self.selector.is_skipped(*piece))
+    }
+
+    // tells the client when the wanted pieces become downloaded, or not anymore because a skipped file is wanted
+    fn report_progress(&mut self) {
+        let downloaded = self.wanted_pieces_downloaded();
+        if downloaded == self.wanted_pieces_reported {
+            return;
+        }
+        self.wanted_pieces_reported = downloaded;
+        let progress = match downloaded {
+            true => {
+                if !self.allowed_peers_to_download_piece.is_empty() {
+                    LOGGER.info_str(
+                        "Every wanted piece downloaded, seeding until skipped files are wanted",
+                    );
+                }
+                DownloadProgress::WantedPiecesDownloaded
+            }
+            false => {
+                LOGGER.info_str("Skipped files are wanted, downloading again");
+                DownloadProgress::Downloading
+            }
+        };
+        if let Some(sender) = &self.progress {
+            let _ = sender.send(progress);
+        }
+    }
+
     fn update_peers_per_piece(&mut self, bitfield: &Bitfield, peer_id: Vec<u8>) {
         self.allowed_peers_to_download_piece
             .iter_mut()
@@ -95,18 +252,14 @@ impl PieceManagerWorker {
     }
 
     fn get_optimal_piece_to_download(&self) -> Option<u32> {
-        let mut piece_with_less_peers_available_index = None;
-        let mut max_peers = 50;
-        for (piece_index, peer_ids) in &self.allowed_peers_to_download_piece {
-            if self.ready_to_download_pieces.contains(piece_index)
-                && !peer_ids.is_empty()
-                && peer_ids.len() < max_peers
-            {
-                max_peers = peer_ids.len();
-                piece_with_less_peers_available_index = Some(*piece_index);
-            }
-        }
-        piece_with_less_peers_available_index
+        let candidates = self
+            .allowed_peers_to_download_piece
+            .iter()
+            .filter(|(piece_index, peer_ids)| {
+                self.ready_to_download_pieces.contains(piece_index) && !peer_ids.is_empty()
+            })
+            .map(|(piece_index, peer_ids)| (*piece_index, peer_ids.len()));
+        self.selector.select(candidates)
     }
 
     fn execute_asking_piece(
@@ -135,12 +288,17 @@ impl PieceManagerWorker {
     fn choose_best_peer_to_download_piece(&self, piece: u32) -> PeerId {
         let peers_of_piece = &self.allowed_peers_to_download_piece[&piece];
 
+        // peers that are snubbing us go last, no matter how few pieces they have to download
+        let load = |peer: &PeerId| {
+            (
+                self.snubbed_peers.contains(peer),
+                self.peer_pieces_to_download_count[peer],
+            )
+        };
         let mut peer_id_of_less_pieces_to_download = peers_of_piece[0].clone();
 
         for peer in peers_of_piece {
-            if self.peer_pieces_to_download_count[&peer.clone()]
-                < self.peer_pieces_to_download_count[&peer_id_of_less_pieces_to_download]
-            {
+            if load(peer) < load(&peer_id_of_less_pieces_to_download) {
                 peer_id_of_less_pieces_to_download = peer.clone();
             }
         }
@@ -148,6 +306,9 @@ impl PieceManagerWorker {
     }
 
     fn ask_for_pieces(&mut self, peer_connection_manager_sender: &PeerConnectionManagerSender) {
+        if self.is_paused {
+            return;
+        }
         while self
             .peer_pieces_to_download_count
             .values()
@@ -172,9 +333,16 @@ impl PieceManagerWorker {
                 }
             });
         self.peer_pieces_to_download_count.remove(&peer_id);
+        self.snubbed_peers.remove(&peer_id);
+        self.late_piece_asked_to
+            .retain(|_, second_peer| *second_peer != peer_id);
         for (piece, peer_aked_to_id) in self.piece_asked_to.clone() {
             if *peer_aked_to_id == peer_id {
-                self.piece_asked_to.remove(&piece);
+                // a late piece asked to a second peer is still on its way
+                match self.late_piece_asked_to.remove(&piece) {
+                    Some(second_peer) => self.piece_asked_to.insert(piece, second_peer),
+                    None => self.piece_asked_to.remove(&piece),
+                };
             }
         }
     }
@@ -219,7 +387,14 @@ impl PieceManagerWorker {
     }
 
     fn no_peers_to_give_pieces(&self) -> bool {
-        if self.pieces_without_peer.len() == self.allowed_peers_to_download_piece.len() {
+        let wanted_pieces = self
+            .allowed_peers_to_download_piece
+            .keys()
+            .filter(|piece| !self.selector.is_skipped(**piece));
+        if wanted_pieces
+            .into_iter()
+            .all(|piece| self.pieces_without_peer.contains(piece))
+        {
             LOGGER.info_str("No peers to send remaining pieces");
             return true;
         }
@@ -261,8 +436,17 @@ impl PieceManagerWorker {
         &mut self,
         peer_connection_manager_sender: PeerConnectionManagerSender,
     ) -> Result<(), RecvError> {
+        self.refill_streaming_window();
+        self.report_progress();
         loop {
-            let message = self.reciever.recv()?;
+            let message = match self.reciever.recv_timeout(LATE_PIECES_CHECK_INTERVAL) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => {
+                    self.ask_late_pieces_again(&peer_connection_manager_sender);
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err(RecvError),
+            };
             trace!("Piece manager received message: {:?}", message);
             match message {
                 PieceManagerMessage::PeerPieces(peer_id, bitfield) => {
@@ -317,13 +501,61 @@ impl PieceManagerWorker {
                     ));
                     self.remove_peer_data(peer_id);
                 }
+                PieceManagerMessage::Snubbed(peer_id) => {
+                    LOGGER.info(format!("Peer {:?} is snubbing us", peer_id));
+                    self.snubbed_peers.insert(peer_id);
+                }
+                PieceManagerMessage::SetDownloadMode(mode) => {
+                    info!("Piece manager switched to download mode {:?}", mode);
+                    self.selector.set_mode(mode);
+                    self.refill_streaming_window();
+                    if self.is_downloading {
+                        self.ask_for_pieces(&peer_connection_manager_sender);
+                    }
+                }
+                PieceManagerMessage::SetPlaybackPosition(piece_index) => {
+                    trace!("Piece manager moved playback position to {}", piece_index);
+                    self.selector.set_playback_position(piece_index);
+                    self.refill_streaming_window();
+                    if self.is_downloading {
+                        self.ask_for_pieces(&peer_connection_manager_sender);
+                    }
+                }
+                PieceManagerMessage::SetPiecePriorities(priorities) => {
+                    info!("Piece manager received new piece priorities");
+                    self.selector.set_priorities(priorities);
+                    self.refill_streaming_window();
+                    if self.is_downloading {
+                        self.ask_for_pieces(&peer_connection_manager_sender);
+                    }
+                }
+                PieceManagerMessage::Pause => {
+                    info!("Piece manager paused");
+                    self.is_paused = true;
+                }
+                PieceManagerMessage::Resume => {
+                    info!("Piece manager resumed");
+                    self.is_paused = false;
+                    if self.is_downloading {
+                        self.ask_for_pieces(&peer_connection_manager_sender);
+                    }
+                }
+                PieceManagerMessage::Stop => {
+                    info!("Piece manager stopped before finishing the download");
+                    peer_connection_manager_sender.close_connections();
+                    break;
+                }
                 PieceManagerMessage::ReaskedTracker() => {
                     info!("Piece manager received reasked tracker msg");
                     self.is_asking_tracker = true;
                 }
             }
+            self.ask_late_pieces_again(&peer_connection_manager_sender);
+            self.report_progress();
+            // with only skipped pieces left it keeps running while the torrent seeds, their files may be wanted
             if !self.is_asking_tracker
-                && (self.last_piece_downloaded() || self.no_peers_to_give_pieces())
+                && (self.last_piece_downloaded()
+                    || (!self.wanted_pieces_downloaded() && self.no_peers_to_give_pieces()))
             {
                 info!("Piece manager finished downloading");
                 peer_connection_manager_sender.close_connections();
@@ -338,6 +570,9 @@ impl PieceManagerWorker {
 mod tests {
 
     use super::*;
+    use crate::peer_connection_manager::PeerConnectionManagerMessage;
+    use crate::piece_manager::priorities::FilePriority;
+    use crate::piece_manager::selection::{DownloadMode, StreamingWindow};
     use rand::Rng;
 
     #[test]
@@ -440,4 +675,177 @@ mod tests {
             }
         });
     }
+
+    #[test]
+    fn snubbing_peers_are_asked_last() {
+        let (_sender, mut worker) = crate::piece_manager::types::new_piece_manager(
+            2,
+            UIMessageSender::no_ui(),
+            vec![],
+            Default::default(),
+        );
+        let (busy, snubbing) = (vec![1], vec![2]);
+        worker
+            .allowed_peers_to_download_piece
+            .insert(0, vec![snubbing.clone(), busy.clone()]);
+        worker
+            .allowed_peers_to_download_piece
+            .insert(1, vec![snubbing.clone()]);
+        worker.peer_pieces_to_download_count.insert(busy.clone(), 3);
+        worker
+            .peer_pieces_to_download_count
+            .insert(snubbing.clone(), 0);
+        worker.snubbed_peers.insert(snubbing.clone());
+
+        assert_eq!(worker.choose_best_peer_to_download_piece(0), busy);
+        // no one else has it
+        assert_eq!(worker.choose_best_peer_to_download_piece(1), snubbing);
+    }
+
+    #[test]
+    fn skipped_files_can_be_wanted_after_the_wanted_ones_are_downloaded() {
+        let (sender, mut worker) = crate::piece_manager::types::new_piece_manager(
+            2,
+            UIMessageSender::no_ui(),
+            vec![],
+            Default::default(),
+        );
+        let (progress_sender, progress) = std::sync::mpsc::channel();
+        worker.progress = Some(progress_sender);
+        let (manager_sender, manager) = std::sync::mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            worker.listen(PeerConnectionManagerSender {
+                sender: manager_sender,
+            })
+        });
+        let next_download = || loop {
+            match manager.recv().unwrap() {
+                PeerConnectionManagerMessage::DownloadPiece(_, piece) => return Some(piece),
+                PeerConnectionManagerMessage::CloseConnections => return None,
+                _ => {}
+            }
+        };
+        let peer = vec![1];
+        let mut bitfield = Bitfield::new();
+        bitfield.set_bitfield(&[0b1100_0000]);
+
+        sender.set_piece_priorities(vec![FilePriority::Normal, FilePriority::Skip]);
+        sender.peer_pieces(peer.clone(), bitfield);
+        sender.finished_stablishing_connections(1);
+        assert_eq!(next_download(), Some(0));
+        sender.successful_download(0, peer.clone());
+        // the torrent seeds while the skipped file is left
+        assert_eq!(
+            progress.recv().unwrap(),
+            DownloadProgress::WantedPiecesDownloaded
+        );
+
+        sender.set_piece_priorities(vec![FilePriority::Normal, FilePriority::Normal]);
+        assert_eq!(next_download(), Some(1));
+        assert_eq!(progress.recv().unwrap(), DownloadProgress::Downloading);
+        sender.successful_download(1, peer);
+        assert_eq!(next_download(), None);
+        assert!(handle.join().unwrap().is_ok());
+        assert_eq!(
+            progress.iter().collect::<Vec<_>>(),
+            vec![DownloadProgress::WantedPiecesDownloaded]
+        );
+    }
+
+    #[test]
+    fn late_window_pieces_are_asked_to_a_second_peer() {
+        let (sender, mut worker) = crate::piece_manager::types::new_piece_manager(
+            1,
+            UIMessageSender::no_ui(),
+            vec![],
+            DownloadMode::Streaming(StreamingWindow {
+                position: 0,
+                length: 1,
+                piece_deadline: Duration::ZERO,
+            }),
+        );
+        let (manager_sender, manager) = std::sync::mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            worker.listen(PeerConnectionManagerSender {
+                sender: manager_sender,
+            })
+        });
+        let next_download = || loop {
+            match manager.recv().unwrap() {
+                PeerConnectionManagerMessage::DownloadPiece(peer, piece) => {
+                    return Some((peer, piece))
+                }
+                PeerConnectionManagerMessage::CloseConnections => return None,
+                _ => {}
+            }
+        };
+        let mut bitfield = Bitfield::new();
+        bitfield.set_bitfield(&[0b1000_0000]);
+
+        sender.peer_pieces(vec![1], bitfield.clone());
+        sender.peer_pieces(vec![2], bitfield);
+        sender.finished_stablishing_connections(2);
+        let (first_peer, piece) = next_download().unwrap();
+        assert_eq!(piece, 0);
+        let (second_peer, piece) = next_download().unwrap();
+        assert_eq!(piece, 0);
+        assert_ne!(first_peer, second_peer);
+
+        // whichever sends it first wins
+        sender.successful_download(0, second_peer);
+        assert_eq!(next_download(), None);
+        assert!(handle.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn a_failed_second_request_isnt_sent_again_to_the_same_peer() {
+        let (sender, mut worker) = crate::piece_manager::types::new_piece_manager(
+            1,
+            UIMessageSender::no_ui(),
+            vec![],
+            DownloadMode::Streaming(StreamingWindow {
+                position: 0,
+                length: 1,
+                piece_deadline: Duration::ZERO,
+            }),
+        );
+        let (manager_sender, manager) = std::sync::mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            worker.listen(PeerConnectionManagerSender {
+                sender: manager_sender,
+            })
+        });
+        let next_download = || loop {
+            match manager.recv().unwrap() {
+                PeerConnectionManagerMessage::DownloadPiece(peer, piece) => {
+                    return Some((peer, piece))
+                }
+                PeerConnectionManagerMessage::CloseConnections => return None,
+                _ => {}
+            }
+        };
+        let mut bitfield = Bitfield::new();
+        bitfield.set_bitfield(&[0b1000_0000]);
+
+        for peer in 1..=3 {
+            sender.peer_pieces(vec![peer], bitfield.clone());
+        }
+        sender.finished_stablishing_connections(3);
+        let (first_peer, _) = next_download().unwrap();
+        let (second_peer, _) = next_download().unwrap();
+
+        // the piece is still late, it goes to the only peer that wasn't asked for it
+        sender.failed_download(0, second_peer.clone());
+        let (third_peer, piece) = next_download().unwrap();
+        assert_eq!(piece, 0);
+        assert_ne!(third_peer, first_peer);
+        assert_ne!(third_peer, second_peer);
+
+        // every peer was tried, it's left to the first one
+        sender.failed_download(0, third_peer);
+        std::thread::sleep(LATE_PIECES_CHECK_INTERVAL * 2);
+        sender.successful_download(0, first_peer);
+        assert_eq!(next_download(), None);
+        assert!(handle.join().unwrap().is_ok());
+    }
 }
@@ -1,6 +1,8 @@
 use super::sender::types::PieceSaverSender;
 use super::worker::types::PieceSaverWorker;
+use crate::metainfo::PieceHashV2;
 use crate::piece_manager::sender::PieceManagerSender;
+use crate::storage::{ResumeWriter, SharedStorage, RESUME_SAVE_INTERVAL};
 use crate::ui::UIMessageSender;
 use std::sync::mpsc;
 
@@ -13,7 +15,9 @@ pub enum PieceSaverMessage {
 pub fn new_piece_saver(
     piece_manager_sender: PieceManagerSender,
     sha1_pieces: Vec<Vec<u8>>,
-    download_path: String,
+    v2_pieces: Vec<Option<PieceHashV2>>,
+    storage: SharedStorage,
+    resume: ResumeWriter,
     ui_message_sender: UIMessageSender,
 ) -> (PieceSaverSender, PieceSaverWorker) {
     let (tx, rx) = mpsc::channel();
@@ -24,8 +28,11 @@ pub fn new_piece_saver(
             receiver: rx,
             piece_manager_sender,
             sha1_pieces,
-            download_path,
+            v2_pieces,
+            storage,
+            resume,
             ui_message_sender,
+            resume_save_interval: RESUME_SAVE_INTERVAL,
         },
     )
 }
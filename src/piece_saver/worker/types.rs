@@ -1,13 +1,14 @@
-use crate::download_manager::save_piece_in_disk;
-use crate::download_manager::Piece;
 use crate::logger::{CustomLogger, Logger};
+use crate::metainfo::PieceHashV2;
 use crate::piece_manager::sender::PieceManagerSender;
 use crate::piece_saver::types::PieceSaverMessage;
+use crate::storage::{ResumeWriter, SharedStorage};
 use crate::ui::UIMessageSender;
 use log::*;
 use sha1::{Digest, Sha1};
 use std::sync::mpsc::Receiver;
-use std::sync::mpsc::RecvError;
+use std::sync::mpsc::{RecvError, RecvTimeoutError};
+use std::time::{Duration, Instant};
 
 const LOGGER: CustomLogger = CustomLogger::init("Piece Saver");
 
@@ -15,8 +16,13 @@ pub struct PieceSaverWorker {
     pub receiver: Receiver<PieceSaverMessage>,
     pub piece_manager_sender: PieceManagerSender,
     pub sha1_pieces: Vec<Vec<u8>>,
-    pub download_path: String,
+    /// merkle hashes of the pieces of hybrid torrents, checked too
+    pub v2_pieces: Vec<Option<PieceHashV2>>,
+    pub storage: SharedStorage,
+    pub resume: ResumeWriter,
     pub ui_message_sender: UIMessageSender,
+    /// how often the resume data is written while pieces come in or the torrent seeds
+    pub resume_save_interval: Duration,
 }
 
 impl PieceSaverWorker {
@@ -30,22 +36,26 @@ impl PieceSaverWorker {
         let real_piece_sha1 = self.sha1_pieces[piece_index as usize].to_vec();
         let recieved_piece_sha1 = self.sha1_of(piece_bytes);
         recieved_piece_sha1 == real_piece_sha1
+            && self
+                .v2_pieces
+                .get(piece_index as usize)
+                .and_then(Option::as_ref)
+                .is_none_or(|hash| hash.verify(piece_bytes))
     }
 
     fn make_validation_and_save_piece(&self, piece_index: u32, piece_bytes: Vec<u8>) -> bool {
         if !self.valid_piece(&piece_bytes, piece_index) {
+            // some of the blocks kept on disk may be the broken ones
+            self.storage.discard_blocks(piece_index);
             return false;
         }
 
-        let piece = Piece {
-            piece_number: piece_index,
-            data: piece_bytes,
-        };
-
-        let download_path = format!("{}/pieces", String::from(&self.download_path));
-        match save_piece_in_disk(&piece, &download_path) {
+        match self.storage.write_piece(piece_index, &piece_bytes) {
             Ok(()) => true,
-            Err(_) => false,
+            Err(err) => {
+                LOGGER.error(format!("Couldn't save piece {}: {}", piece_index, err));
+                false
+            }
         }
     }
 
@@ -57,15 +67,35 @@ impl PieceSaverWorker {
         let _ = logger.log_piece(piece_index);
     }
 
+    fn save_resume_data(&self) {
+        if let Err(err) = self.resume.save() {
+            LOGGER.error(format!("Couldn't save resume data: {}", err));
+        }
+    }
+
     pub fn listen(&self) -> Result<(), RecvError> {
         let (logger, handle) = Logger::new("./logs").unwrap();
+        let mut last_resume_save = Instant::now();
 
         loop {
-            let message = self.receiver.recv()?;
+            // the resume data is saved on a timer too, so a seeding torrent keeps its upload total
+            let timeout = self
+                .resume_save_interval
+                .saturating_sub(last_resume_save.elapsed());
+            let message = match self.receiver.recv_timeout(timeout) {
+                Ok(message) => message,
+                Err(RecvTimeoutError::Timeout) => {
+                    self.save_resume_data();
+                    last_resume_save = Instant::now();
+                    continue;
+                }
+                Err(RecvTimeoutError::Disconnected) => return Err(RecvError),
+            };
 
             match message {
                 PieceSaverMessage::StopSaving => {
                     LOGGER.info_str("Stopping Piece Saver Worker");
+                    self.save_resume_data();
                     break;
                 }
                 PieceSaverMessage::ValidateAndSavePiece(piece_index, peer_id, piece_bytes) => {
@@ -75,6 +105,10 @@ impl PieceSaverWorker {
 
                     if successfuly_downloaded {
                         self.downloaded_piece_successfully(piece_index, peer_id, &logger);
+                        if last_resume_save.elapsed() >= self.resume_save_interval {
+                            self.save_resume_data();
+                            last_resume_save = Instant::now();
+                        }
                     } else {
                         self.piece_manager_sender
                             .failed_download(piece_index, peer_id);
@@ -88,3 +122,51 @@ impl PieceSaverWorker {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bandwidth::TorrentBandwidth;
+    use crate::piece_manager::sender::PieceManagerSender;
+    use crate::storage::{PieceFileStorage, ResumeData};
+    use std::fs;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[test]
+    fn the_resume_data_is_saved_once_the_interval_elapses() {
+        let dir = std::env::temp_dir().join("piece_saver_resume");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let resume_path = dir.join("resume").to_string_lossy().to_string();
+        let storage: SharedStorage = Arc::new(PieceFileStorage::new(
+            &dir.to_string_lossy(),
+            2,
+            "target",
+            true,
+        ));
+        let (piece_manager_sender, _piece_manager_receiver) = mpsc::channel();
+        let (sender, receiver) = mpsc::channel();
+        let worker = PieceSaverWorker {
+            receiver,
+            piece_manager_sender: PieceManagerSender {
+                sender: piece_manager_sender,
+            },
+            sha1_pieces: vec![vec![0; 20]; 2],
+            v2_pieces: vec![],
+            storage: storage.clone(),
+            resume: ResumeWriter::new(&resume_path, storage, TorrentBandwidth::unlimited(), None),
+            ui_message_sender: UIMessageSender::no_ui(),
+            resume_save_interval: Duration::from_millis(50),
+        };
+        let listening = thread::spawn(move || worker.listen());
+
+        // no piece comes in, the timer alone has to write the file
+        thread::sleep(Duration::from_millis(500));
+        assert!(ResumeData::load(&resume_path).is_ok());
+
+        sender.send(PieceSaverMessage::StopSaving).unwrap();
+        assert!(listening.join().unwrap().is_ok());
+    }
+}
@@ -0,0 +1,11 @@
+/// Amount of readiness events handled on each iteration of the event loop
+pub const EVENTS_CAPACITY: usize = 1024;
+
+/// Size of the buffer used to read from the sockets
+pub const READ_BUFFER_SIZE: usize = 32 * 1024;
+
+/// Seconds to wait for an outgoing connection to be established
+pub const CONNECT_TIMEOUT: u64 = 10;
+
+/// Milliseconds the threads serving uTP sockets wait before checking whether they were closed
+pub const UTP_POLL_INTERVAL: u64 = 200;
@@ -0,0 +1,37 @@
+use std::fmt;
+
+#[derive(Debug)]
+/// Error type for the reactor that drives every socket
+pub enum ReactorError {
+    /// The poll couldn't be created or a socket couldn't be registered on it
+    IoError(std::io::Error),
+
+    /// The event loop is no longer running, so it can't take new commands
+    Stopped,
+}
+
+impl From<std::io::Error> for ReactorError {
+    fn from(error: std::io::Error) -> Self {
+        ReactorError::IoError(error)
+    }
+}
+
+impl From<ReactorError> for std::io::Error {
+    fn from(error: ReactorError) -> Self {
+        match error {
+            ReactorError::IoError(error) => error,
+            ReactorError::Stopped => {
+                std::io::Error::new(std::io::ErrorKind::BrokenPipe, error.to_string())
+            }
+        }
+    }
+}
+
+impl fmt::Display for ReactorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReactorError::IoError(error) => write!(f, "Reactor IO error: {}", error),
+            ReactorError::Stopped => write!(f, "Reactor is not running"),
+        }
+    }
+}
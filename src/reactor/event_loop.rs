@@ -0,0 +1,337 @@
+use super::constants::*;
+use super::types::*;
+use crate::utp::UtpStream;
+use log::*;
+use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Token};
+use std::collections::HashMap;
+use std::io::{ErrorKind, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::Receiver;
+use std::sync::Arc;
+
+pub(super) const WAKER_TOKEN: Token = Token(0);
+
+struct Connection {
+    stream: TcpStream,
+    events: ConnectionEventSender,
+    outgoing: Vec<u8>,
+    connecting: bool,
+}
+
+struct Listener {
+    listener: TcpListener,
+    events: ConnectionEventSender,
+}
+
+/// Single thread that owns every socket registered on the reactor.
+/// It reads whatever the sockets have ready, reports it through the connection's event sender,
+/// and writes the bytes queued by the handles as soon as the sockets can take them
+pub(super) struct EventLoop {
+    pub poll: Poll,
+    pub commands: Receiver<ReactorCommand>,
+    pub next_id: Arc<AtomicUsize>,
+    connections: HashMap<ConnectionId, Connection>,
+    listeners: HashMap<ConnectionId, Listener>,
+    // uTP sockets run on threads of their own, the flags let them know once they are closed
+    utp_connections: HashMap<ConnectionId, (UtpStream, Arc<AtomicBool>)>,
+    utp_listeners: HashMap<ConnectionId, Arc<AtomicBool>>,
+}
+
+impl EventLoop {
+    pub fn new(poll: Poll, commands: Receiver<ReactorCommand>, next_id: Arc<AtomicUsize>) -> Self {
+        Self {
+            poll,
+            commands,
+            next_id,
+            connections: HashMap::new(),
+            listeners: HashMap::new(),
+            utp_connections: HashMap::new(),
+            utp_listeners: HashMap::new(),
+        }
+    }
+
+    pub fn run(mut self) {
+        let mut events = Events::with_capacity(EVENTS_CAPACITY);
+        loop {
+            if let Err(err) = self.poll.poll(&mut events, None) {
+                if err.kind() == ErrorKind::Interrupted {
+                    continue;
+                }
+                error!("Reactor stopped polling: {}", err);
+                break;
+            }
+
+            for event in events.iter() {
+                if event.token() == WAKER_TOKEN {
+                    continue;
+                }
+                let id = ConnectionId(event.token().0);
+                if self.listeners.contains_key(&id) {
+                    self.accept(id);
+                    continue;
+                }
+                if event.is_writable() {
+                    self.on_writable(id);
+                }
+                if event.is_readable() || event.is_read_closed() || event.is_error() {
+                    self.on_readable(id);
+                }
+            }
+
+            if !self.run_commands() {
+                break;
+            }
+        }
+        let ids: Vec<ConnectionId> = self.utp_listeners.keys().copied().collect();
+        ids.into_iter().for_each(|id| self.remove(id));
+        trace!("Reactor event loop finished");
+    }
+
+    // runs the commands sent by the handles, returns false once the loop has to stop
+    fn run_commands(&mut self) -> bool {
+        while let Ok(command) = self.commands.try_recv() {
+            match command {
+                ReactorCommand::Connect(id, address, events) => self.connect(id, address, events),
+                ReactorCommand::Listen(id, listener, events) => self.listen(id, listener, events),
+                ReactorCommand::ListenUtp(id, stopped) => {
+                    self.utp_listeners.insert(id, stopped);
+                }
+                ReactorCommand::AttachUtp(id, stream, closed) => {
+                    self.utp_connections.insert(id, (stream, closed));
+                }
+                ReactorCommand::Send(id, bytes) => {
+                    if let Some((stream, _)) = self.utp_connections.get_mut(&id) {
+                        // uTP writes never block, the stream sends them as its window allows
+                        if let Err(err) = stream.write_all(&bytes) {
+                            trace!("Couldn't write to uTP connection: {}", err);
+                        }
+                    }
+                    if let Some(connection) = self.connections.get_mut(&id) {
+                        connection.outgoing.extend_from_slice(&bytes);
+                    }
+                    self.flush(id);
+                }
+                ReactorCommand::Close(id) => {
+                    self.flush(id);
+                    self.remove(id);
+                }
+                ReactorCommand::Stop => return false,
+            }
+        }
+        true
+    }
+
+    fn connect(
+        &mut self,
+        id: ConnectionId,
+        address: std::net::SocketAddr,
+        events: ConnectionEventSender,
+    ) {
+        let mut stream = match TcpStream::connect(address) {
+            Ok(stream) => stream,
+            Err(err) => {
+                let _ = events.send((id, ConnectionEvent::Closed(err.to_string())));
+                return;
+            }
+        };
+        if let Err(err) = self.poll.registry().register(
+            &mut stream,
+            Token(id.0),
+            Interest::READABLE | Interest::WRITABLE,
+        ) {
+            let _ = events.send((id, ConnectionEvent::Closed(err.to_string())));
+            return;
+        }
+        self.connections.insert(
+            id,
+            Connection {
+                stream,
+                events,
+                outgoing: vec![],
+                connecting: true,
+            },
+        );
+    }
+
+    fn listen(
+        &mut self,
+        id: ConnectionId,
+        listener: std::net::TcpListener,
+        events: ConnectionEventSender,
+    ) {
+        let mut listener = TcpListener::from_std(listener);
+        if let Err(err) =
+            self.poll
+                .registry()
+                .register(&mut listener, Token(id.0), Interest::READABLE)
+        {
+            let _ = events.send((id, ConnectionEvent::Closed(err.to_string())));
+            return;
+        }
+        self.listeners.insert(id, Listener { listener, events });
+    }
+
+    fn accept(&mut self, listener_id: ConnectionId) {
+        loop {
+            let listener = match self.listeners.get(&listener_id) {
+                Some(listener) => listener,
+                None => return,
+            };
+            match listener.listener.accept() {
+                Ok((mut stream, address)) => {
+                    let id = ConnectionId(self.next_id.fetch_add(1, Ordering::SeqCst));
+                    let events = listener.events.clone();
+                    if self
+                        .poll
+                        .registry()
+                        .register(
+                            &mut stream,
+                            Token(id.0),
+                            Interest::READABLE | Interest::WRITABLE,
+                        )
+                        .is_err()
+                    {
+                        continue;
+                    }
+                    if events
+                        .send((id, ConnectionEvent::Accepted(address)))
+                        .is_err()
+                    {
+                        let _ = self.poll.registry().deregister(&mut stream);
+                        continue;
+                    }
+                    self.connections.insert(
+                        id,
+                        Connection {
+                            stream,
+                            events,
+                            outgoing: vec![],
+                            connecting: false,
+                        },
+                    );
+                }
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    warn!("Reactor failed accepting connection: {}", err);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn on_writable(&mut self, id: ConnectionId) {
+        let connection = match self.connections.get_mut(&id) {
+            Some(connection) => connection,
+            None => return,
+        };
+        if connection.connecting {
+            if let Ok(Some(err)) | Err(err) = connection.stream.take_error() {
+                self.close(id, err.to_string());
+                return;
+            }
+            match connection.stream.peer_addr() {
+                Ok(_) => {
+                    connection.connecting = false;
+                    if connection
+                        .events
+                        .send((id, ConnectionEvent::Connected))
+                        .is_err()
+                    {
+                        self.remove(id);
+                        return;
+                    }
+                }
+                // spurious wake up, the connection is still being established
+                Err(ref err) if err.kind() == ErrorKind::NotConnected => return,
+                Err(err) => {
+                    self.close(id, err.to_string());
+                    return;
+                }
+            }
+        }
+        self.flush(id);
+    }
+
+    fn on_readable(&mut self, id: ConnectionId) {
+        let mut buffer = [0u8; READ_BUFFER_SIZE];
+        loop {
+            let connection = match self.connections.get_mut(&id) {
+                Some(connection) if !connection.connecting => connection,
+                _ => return,
+            };
+            match connection.stream.read(&mut buffer) {
+                Ok(0) => {
+                    self.close(id, "Connection closed by peer".to_string());
+                    return;
+                }
+                Ok(read) => {
+                    let data = ConnectionEvent::Data(buffer[..read].to_vec());
+                    if connection.events.send((id, data)).is_err() {
+                        // nobody is listening to this connection anymore
+                        self.remove(id);
+                        return;
+                    }
+                }
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.close(id, err.to_string());
+                    return;
+                }
+            }
+        }
+    }
+
+    // writes as much of the queued bytes as the socket takes without blocking
+    fn flush(&mut self, id: ConnectionId) {
+        let connection = match self.connections.get_mut(&id) {
+            Some(connection) if !connection.connecting => connection,
+            _ => return,
+        };
+        while !connection.outgoing.is_empty() {
+            match connection.stream.write(&connection.outgoing) {
+                Ok(0) => {
+                    self.close(id, "Connection closed while writing".to_string());
+                    return;
+                }
+                Ok(written) => {
+                    connection.outgoing.drain(..written);
+                }
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => {
+                    self.close(id, err.to_string());
+                    return;
+                }
+            }
+        }
+    }
+
+    // removes the connection letting whoever listens to it know why
+    fn close(&mut self, id: ConnectionId, reason: String) {
+        if let Some(connection) = self.connections.get(&id) {
+            let _ = connection
+                .events
+                .send((id, ConnectionEvent::Closed(reason)));
+        }
+        self.remove(id);
+    }
+
+    fn remove(&mut self, id: ConnectionId) {
+        if let Some(mut connection) = self.connections.remove(&id) {
+            let _ = self.poll.registry().deregister(&mut connection.stream);
+        }
+        if let Some(mut listener) = self.listeners.remove(&id) {
+            let _ = self.poll.registry().deregister(&mut listener.listener);
+        }
+        if let Some((stream, closed)) = self.utp_connections.remove(&id) {
+            closed.store(true, Ordering::SeqCst);
+            stream.shutdown();
+        }
+        if let Some(stopped) = self.utp_listeners.remove(&id) {
+            stopped.store(true, Ordering::SeqCst);
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use super::errors::ReactorError;
+use super::event_loop::{EventLoop, WAKER_TOKEN};
+use super::types::*;
+use super::utp;
+use crate::utp::{UtpSocket, UtpStream};
+use mio::{Poll, Waker};
+use once_cell::sync::OnceCell;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+
+static GLOBAL_REACTOR: OnceCell<Reactor> = OnceCell::new();
+
+/// Handle to an event loop that drives sockets from a single thread.
+/// Registering a socket, queueing bytes to write and closing it are done through the handle,
+/// and everything that happens to the socket is reported through the sender it was registered with.
+/// Handles can be cloned and shared between threads.
+///
+/// The server's listeners and the inbound connections it accepts are handled on the event loop alone.
+/// The sockets of outbound peer connections are driven by it too, but each of those connections is
+/// still handled by a thread of its own through a blocking [`ReactorStream`](super::ReactorStream).
+/// Tracker announces don't go through it: they are blocking HTTP(S) requests, made from the threads
+/// of the clients and from the server's announcer.
+#[derive(Debug, Clone)]
+pub struct Reactor {
+    commands: Sender<ReactorCommand>,
+    waker: Arc<Waker>,
+    next_id: Arc<AtomicUsize>,
+}
+
+impl Reactor {
+    /// Starts a new event loop on its own thread
+    pub fn start() -> Result<Self, ReactorError> {
+        let poll = Poll::new()?;
+        let waker = Arc::new(Waker::new(poll.registry(), WAKER_TOKEN)?);
+        let next_id = Arc::new(AtomicUsize::new(WAKER_TOKEN.0 + 1));
+        let (tx, rx) = mpsc::channel();
+        let event_loop = EventLoop::new(poll, rx, next_id.clone());
+        std::thread::Builder::new()
+            .name("reactor".to_string())
+            .spawn(move || event_loop.run())?;
+
+        Ok(Self {
+            commands: tx,
+            waker,
+            next_id,
+        })
+    }
+
+    /// Reactor shared by the whole process, started the first time it is asked for
+    pub fn global() -> Result<Self, ReactorError> {
+        GLOBAL_REACTOR.get_or_try_init(Self::start).cloned()
+    }
+
+    /// Starts connecting to the given address.
+    /// `ConnectionEvent::Connected` is reported once the connection is established,
+    /// or `ConnectionEvent::Closed` if it couldn't be
+    pub fn connect(
+        &self,
+        address: SocketAddr,
+        events: ConnectionEventSender,
+    ) -> Result<ConnectionId, ReactorError> {
+        let id = self.new_id();
+        self.command(ReactorCommand::Connect(id, address, events))?;
+        Ok(id)
+    }
+
+    /// Accepts connections on the given listener.
+    /// Each accepted connection is reported with `ConnectionEvent::Accepted` under its own id,
+    /// and its events are sent through the same sender
+    pub fn listen(
+        &self,
+        listener: std::net::TcpListener,
+        events: ConnectionEventSender,
+    ) -> Result<ConnectionId, ReactorError> {
+        listener.set_nonblocking(true)?;
+        let id = self.new_id();
+        self.command(ReactorCommand::Listen(id, listener, events))?;
+        Ok(id)
+    }
+
+    /// Accepts uTP connections on the given socket, reported like the ones of [`Reactor::listen`].
+    /// Closing the returned id stops accepting
+    pub fn listen_utp(
+        &self,
+        socket: UtpSocket,
+        events: ConnectionEventSender,
+    ) -> Result<ConnectionId, ReactorError> {
+        let id = self.new_id();
+        let stopped = Arc::new(AtomicBool::new(false));
+        self.command(ReactorCommand::ListenUtp(id, stopped.clone()))?;
+        let reactor = self.clone();
+        std::thread::Builder::new()
+            .name("utp-acceptor".to_string())
+            .spawn(move || utp::accept(reactor, socket, events, stopped))?;
+        Ok(id)
+    }
+
+    pub(super) fn attach_utp(
+        &self,
+        stream: UtpStream,
+        closed: Arc<AtomicBool>,
+    ) -> Result<ConnectionId, ReactorError> {
+        let id = self.new_id();
+        self.command(ReactorCommand::AttachUtp(id, stream, closed))?;
+        Ok(id)
+    }
+
+    /// Queues bytes to be written to the connection
+    pub fn send(&self, id: ConnectionId, bytes: Vec<u8>) -> Result<(), ReactorError> {
+        self.command(ReactorCommand::Send(id, bytes))
+    }
+
+    /// Closes a connection or a listener, after trying to write what was queued for it
+    pub fn close(&self, id: ConnectionId) {
+        let _ = self.command(ReactorCommand::Close(id));
+    }
+
+    /// Stops the event loop, dropping every socket registered on it
+    pub fn stop(&self) {
+        let _ = self.command(ReactorCommand::Stop);
+    }
+
+    fn new_id(&self) -> ConnectionId {
+        ConnectionId(self.next_id.fetch_add(1, Ordering::SeqCst))
+    }
+
+    fn command(&self, command: ReactorCommand) -> Result<(), ReactorError> {
+        self.commands
+            .send(command)
+            .map_err(|_| ReactorError::Stopped)?;
+        self.waker.wake()?;
+        Ok(())
+    }
+}
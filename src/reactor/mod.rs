@@ -0,0 +1,13 @@
+mod constants;
+mod errors;
+mod event_loop;
+mod handle;
+mod stream;
+mod types;
+mod utp;
+
+pub use constants::*;
+pub use errors::ReactorError;
+pub use handle::Reactor;
+pub use stream::ReactorStream;
+pub use types::*;
@@ -0,0 +1,205 @@
+use super::handle::Reactor;
+use super::types::*;
+use crate::peer::PeerStream;
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::time::Duration;
+
+/// Blocking `Read + Write` view of a connection driven by the [`Reactor`].
+/// Reads wait for the data the reactor reports, and writes are queued on the reactor,
+/// so code written for a `TcpStream` can run on top of the event loop.
+/// Outbound peer connections use it, so while their sockets are driven by the reactor,
+/// each of them is still handled by a worker thread of its own that blocks on these reads.
+pub struct ReactorStream {
+    id: ConnectionId,
+    reactor: Reactor,
+    events: Receiver<(ConnectionId, ConnectionEvent)>,
+    buffer: VecDeque<u8>,
+    closed: bool,
+    read_timeout: Option<Duration>,
+}
+
+impl ReactorStream {
+    /// Connects to the given address through the reactor, waiting at most `timeout` for it
+    pub fn connect(reactor: &Reactor, address: SocketAddr, timeout: Duration) -> io::Result<Self> {
+        let (tx, rx) = mpsc::channel();
+        let id = reactor.connect(address, tx)?;
+        let stream = Self {
+            id,
+            reactor: reactor.clone(),
+            events: rx,
+            buffer: VecDeque::new(),
+            closed: false,
+            read_timeout: None,
+        };
+
+        match stream.events.recv_timeout(timeout) {
+            Ok((_, ConnectionEvent::Connected)) => Ok(stream),
+            Ok((_, ConnectionEvent::Closed(reason))) => {
+                Err(io::Error::new(ErrorKind::ConnectionRefused, reason))
+            }
+            Ok((_, event)) => Err(io::Error::other(format!(
+                "Unexpected event while connecting: {:?}",
+                event
+            ))),
+            Err(_) => Err(io::Error::new(
+                ErrorKind::TimedOut,
+                format!("Couldn't connect to {} in time", address),
+            )),
+        }
+    }
+
+    /// How long a read waits for data before failing, `None` waits forever
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    fn handle_event(&mut self, event: ConnectionEvent) {
+        match event {
+            ConnectionEvent::Data(data) => self.buffer.extend(data),
+            ConnectionEvent::Closed(_) => self.closed = true,
+            ConnectionEvent::Connected | ConnectionEvent::Accepted(_) => {}
+        }
+    }
+
+    // blocks until the reactor reports something about the connection
+    fn wait_for_event(&mut self) -> io::Result<()> {
+        let received = match self.read_timeout {
+            Some(timeout) => self.events.recv_timeout(timeout),
+            None => self
+                .events
+                .recv()
+                .map_err(|_| RecvTimeoutError::Disconnected),
+        };
+        match received {
+            Ok((_, event)) => {
+                self.handle_event(event);
+                Ok(())
+            }
+            Err(RecvTimeoutError::Timeout) => Err(io::Error::new(
+                ErrorKind::TimedOut,
+                "Timed out waiting for data from peer",
+            )),
+            Err(RecvTimeoutError::Disconnected) => {
+                self.closed = true;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Read for ReactorStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.buffer.is_empty() && !self.closed {
+            self.wait_for_event()?;
+        }
+        // once the buffer is drained a closed connection reads as end of file
+        self.buffer.read(buf)
+    }
+}
+
+impl Write for ReactorStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.closed {
+            return Err(io::Error::new(
+                ErrorKind::BrokenPipe,
+                "Connection closed by peer",
+            ));
+        }
+        self.reactor.send(self.id, buf.to_vec())?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl PeerStream for ReactorStream {
+    fn has_pending_data(&mut self) -> bool {
+        loop {
+            match self.events.try_recv() {
+                Ok((_, event)) => self.handle_event(event),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+        // a closed connection also counts, so the next read reports it
+        !self.buffer.is_empty() || self.closed
+    }
+
+    fn wait_for_data(&mut self, timeout: Duration) -> bool {
+        if !self.has_pending_data() {
+            match self.events.recv_timeout(timeout) {
+                Ok((_, event)) => self.handle_event(event),
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => self.closed = true,
+            }
+        }
+        self.has_pending_data()
+    }
+}
+
+impl Drop for ReactorStream {
+    fn drop(&mut self) {
+        self.reactor.close(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn bytes_written_on_one_end_are_read_on_the_other() {
+        let reactor = Reactor::start().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (tx, accepted) = mpsc::channel();
+        reactor.listen(listener, tx).unwrap();
+
+        let mut client = ReactorStream::connect(&reactor, address, Duration::from_secs(5)).unwrap();
+        client.write_all(b"ping").unwrap();
+
+        let (server_id, event) = accepted.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(event, ConnectionEvent::Accepted(_)));
+        let mut received = vec![];
+        while received.len() < 4 {
+            match accepted.recv_timeout(Duration::from_secs(5)).unwrap() {
+                (id, ConnectionEvent::Data(data)) if id == server_id => received.extend(data),
+                other => panic!("unexpected event {:?}", other),
+            }
+        }
+        assert_eq!(received, b"ping");
+
+        reactor.send(server_id, b"pong".to_vec()).unwrap();
+        let mut response = [0u8; 4];
+        client.read_exact(&mut response).unwrap();
+        assert_eq!(&response, b"pong");
+        reactor.stop();
+    }
+
+    #[test]
+    fn reading_from_a_closed_connection_fails() {
+        let reactor = Reactor::start().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let (tx, accepted) = mpsc::channel();
+        reactor.listen(listener, tx).unwrap();
+
+        let mut client = ReactorStream::connect(&reactor, address, Duration::from_secs(5)).unwrap();
+        let (server_id, _) = accepted.recv_timeout(Duration::from_secs(5)).unwrap();
+        reactor.close(server_id);
+
+        let mut buf = [0u8; 1];
+        assert!(client.read_exact(&mut buf).is_err());
+        assert!(client.has_pending_data());
+        reactor.stop();
+    }
+}
@@ -0,0 +1,39 @@
+use crate::utp::UtpStream;
+use std::net::SocketAddr;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Sender;
+use std::sync::Arc;
+
+/// Identifies a socket registered on the reactor
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ConnectionId(pub usize);
+
+/// Something that happened to a socket registered on the reactor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionEvent {
+    /// An outgoing connection was established
+    Connected,
+    /// A listener accepted a connection from the given address.
+    /// The id the event comes with is the one of the new connection
+    Accepted(SocketAddr),
+    /// Bytes read from the socket
+    Data(Vec<u8>),
+    /// The connection was closed or failed, holds the reason
+    Closed(String),
+}
+
+/// Where the reactor reports the events of a socket, tagged with the socket's id.
+/// Many sockets can share the same sender, so a single thread can serve all of them
+pub type ConnectionEventSender = Sender<(ConnectionId, ConnectionEvent)>;
+
+pub(super) enum ReactorCommand {
+    Connect(ConnectionId, SocketAddr, ConnectionEventSender),
+    Listen(ConnectionId, std::net::TcpListener, ConnectionEventSender),
+    /// a uTP listener, accepting while the flag is down
+    ListenUtp(ConnectionId, Arc<AtomicBool>),
+    /// a uTP connection, the flag tells its reader thread once it is closed
+    AttachUtp(ConnectionId, UtpStream, Arc<AtomicBool>),
+    Send(ConnectionId, Vec<u8>),
+    Close(ConnectionId),
+    Stop,
+}
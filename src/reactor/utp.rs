@@ -0,0 +1,122 @@
+use super::constants::*;
+use super::handle::Reactor;
+use super::types::*;
+use crate::utp::{UtpSocket, UtpStream};
+use log::*;
+use std::io::{ErrorKind, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+// uTP streams aren't registered on the poll, each one is read from a thread of its own
+// that reports what it reads like the event loop does for TCP connections
+
+/// Accepts connections on the socket until `stopped`, attaching them to the reactor
+pub(super) fn accept(
+    reactor: Reactor,
+    socket: UtpSocket,
+    events: ConnectionEventSender,
+    stopped: Arc<AtomicBool>,
+) {
+    while !stopped.load(Ordering::SeqCst) {
+        let stream = match socket.accept_timeout(Duration::from_millis(UTP_POLL_INTERVAL)) {
+            Ok(Some(stream)) => stream,
+            Ok(None) => continue,
+            Err(err) => {
+                warn!("Reactor failed accepting uTP connection: {}", err);
+                return;
+            }
+        };
+        let Ok(reader) = stream.try_clone() else {
+            continue;
+        };
+        let address = stream.peer_addr();
+        let closed = Arc::new(AtomicBool::new(false));
+        let Ok(id) = reactor.attach_utp(stream, closed.clone()) else {
+            return;
+        };
+        if events
+            .send((id, ConnectionEvent::Accepted(address)))
+            .is_err()
+        {
+            reactor.close(id);
+            return;
+        }
+        let (reactor, events) = (reactor.clone(), events.clone());
+        let spawned = std::thread::Builder::new()
+            .name("utp-reader".to_string())
+            .spawn(move || read(reactor, id, reader, events, closed));
+        if let Err(err) = spawned {
+            warn!("Couldn't start reading uTP connection: {}", err);
+        }
+    }
+}
+
+// reports what the stream reads until it is closed by either side
+fn read(
+    reactor: Reactor,
+    id: ConnectionId,
+    mut stream: UtpStream,
+    events: ConnectionEventSender,
+    closed: Arc<AtomicBool>,
+) {
+    stream.set_read_timeout(Some(Duration::from_millis(UTP_POLL_INTERVAL)));
+    let mut buffer = [0u8; READ_BUFFER_SIZE];
+    let reason = loop {
+        // closed through the reactor, whoever listened to it already forgot it
+        if closed.load(Ordering::SeqCst) {
+            return;
+        }
+        match stream.read(&mut buffer) {
+            Ok(0) => break "Connection closed by peer".to_string(),
+            Ok(read) => {
+                let data = ConnectionEvent::Data(buffer[..read].to_vec());
+                if events.send((id, data)).is_err() {
+                    break "Nobody is listening to the connection".to_string();
+                }
+            }
+            Err(ref err) if err.kind() == ErrorKind::TimedOut => continue,
+            Err(err) => break err.to_string(),
+        }
+    };
+    let _ = events.send((id, ConnectionEvent::Closed(reason)));
+    reactor.close(id);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::mpsc;
+
+    #[test]
+    fn utp_connections_are_served_like_tcp_ones() {
+        let reactor = Reactor::start().unwrap();
+        let socket = UtpSocket::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = socket.local_addr().unwrap();
+        let (tx, accepted) = mpsc::channel();
+        let listener_id = reactor.listen_utp(socket, tx).unwrap();
+
+        let mut client = UtpStream::connect(address, Duration::from_secs(5)).unwrap();
+        client.write_all(b"ping").unwrap();
+
+        let (server_id, event) = accepted.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(matches!(event, ConnectionEvent::Accepted(_)));
+        let mut received = vec![];
+        while received.len() < 4 {
+            match accepted.recv_timeout(Duration::from_secs(5)).unwrap() {
+                (id, ConnectionEvent::Data(data)) if id == server_id => received.extend(data),
+                other => panic!("unexpected event {:?}", other),
+            }
+        }
+        assert_eq!(received, b"ping");
+
+        reactor.send(server_id, b"pong".to_vec()).unwrap();
+        reactor.close(server_id);
+        let mut response = vec![];
+        client.read_to_end(&mut response).unwrap();
+        assert_eq!(response, b"pong");
+        reactor.close(listener_id);
+        reactor.stop();
+    }
+}
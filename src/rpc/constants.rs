@@ -0,0 +1,28 @@
+use std::time::Duration;
+
+/// Methods of the control API
+pub const ADD_TORRENT: &str = "add_torrent";
+pub const LIST_TORRENTS: &str = "list_torrents";
+pub const PAUSE_TORRENT: &str = "pause_torrent";
+pub const RESUME_TORRENT: &str = "resume_torrent";
+pub const REMOVE_TORRENT: &str = "remove_torrent";
+pub const LIST_PEERS: &str = "list_peers";
+pub const SET_LIMITS: &str = "set_limits";
+
+/// Error codes defined by JSON-RPC 2.0
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+pub const INTERNAL_ERROR: i64 = -32603;
+
+/// Error codes of the control API
+pub const UNKNOWN_TORRENT: i64 = -32001;
+pub const DUPLICATE_TORRENT: i64 = -32002;
+pub const UNSUPPORTED: i64 = -32003;
+
+/// Time a caller has to send its whole request
+pub const RPC_READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Requests longer than this many bytes are refused
+pub const MAX_REQUEST_LENGTH: usize = 1024 * 1024;
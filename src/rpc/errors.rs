@@ -0,0 +1,44 @@
+use super::constants::*;
+use crate::magnet::MagnetError;
+use crate::session::SessionError;
+use std::fmt;
+
+/// An error answered to the caller as the `error` member of the response
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: &str) -> Self {
+        Self {
+            code,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn invalid_params(message: &str) -> Self {
+        Self::new(INVALID_PARAMS, message)
+    }
+}
+
+impl From<SessionError> for RpcError {
+    fn from(error: SessionError) -> Self {
+        let code = match error {
+            SessionError::UnknownTorrent => UNKNOWN_TORRENT,
+            SessionError::DuplicateTorrent(_) => DUPLICATE_TORRENT,
+            SessionError::MetainfoError(_) => INVALID_PARAMS,
+            SessionError::MagnetError(MagnetError::InvalidLink(_)) => INVALID_PARAMS,
+            SessionError::UnsupportedTorrent(_) => UNSUPPORTED,
+            _ => INTERNAL_ERROR,
+        };
+        Self::new(code, &error.to_string())
+    }
+}
+
+impl fmt::Display for RpcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RPC Error {} - {}", self.code, self.message)
+    }
+}
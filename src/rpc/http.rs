@@ -0,0 +1,143 @@
+use super::constants::MAX_REQUEST_LENGTH;
+use std::io::{self, ErrorKind, Read, Write};
+
+const HEADER_END: &[u8] = b"\r\n\r\n";
+const CONTENT_LENGTH: &str = "content-length";
+const CONTENT_TYPE: &str = "content-type";
+const ORIGIN: &str = "origin";
+
+/// The parts of an HTTP request the control API looks at
+#[derive(Debug, PartialEq, Eq)]
+pub struct HttpRequest {
+    pub method: String,
+    /// The media type of `Content-Type`, without its parameters
+    pub content_type: Option<String>,
+    /// Sent by browsers with the requests of web pages
+    pub origin: Option<String>,
+    pub body: Vec<u8>,
+}
+
+/// Reads the request line, the headers and as many body bytes as `Content-Length` says
+pub fn read_request(stream: &mut impl Read) -> io::Result<HttpRequest> {
+    let mut data = Vec::new();
+    let mut buffer = [0; 4096];
+    let header_length = loop {
+        if let Some(position) = find(&data, HEADER_END) {
+            break position + HEADER_END.len();
+        }
+        if data.len() > MAX_REQUEST_LENGTH {
+            return Err(invalid("The request headers are too long"));
+        }
+        let read = stream.read(&mut buffer)?;
+        if read == 0 {
+            return Err(invalid("The connection closed before the request ended"));
+        }
+        data.extend_from_slice(&buffer[..read]);
+    };
+
+    let head = String::from_utf8_lossy(&data[..header_length]).to_string();
+    let mut lines = head.lines();
+    let method = lines
+        .next()
+        .and_then(|request_line| request_line.split_whitespace().next())
+        .ok_or_else(|| invalid("Missing request line"))?
+        .to_string();
+    let headers: Vec<(&str, &str)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim(), value.trim()))
+        .collect();
+    let header = |wanted: &str| {
+        headers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(wanted))
+            .map(|(_, value)| *value)
+    };
+    let content_type = header(CONTENT_TYPE).map(|value| {
+        let media_type = value.split(';').next().unwrap_or_default();
+        media_type.trim().to_ascii_lowercase()
+    });
+    let origin = header(ORIGIN).map(str::to_string);
+    let content_length = header(CONTENT_LENGTH)
+        .map(str::parse::<usize>)
+        .transpose()
+        .map_err(|_| invalid("Invalid Content-Length"))?
+        .unwrap_or(0);
+    if content_length > MAX_REQUEST_LENGTH {
+        return Err(invalid("The request body is too long"));
+    }
+
+    let mut body = data.split_off(header_length);
+    if body.len() < content_length {
+        let mut rest = vec![0; content_length - body.len()];
+        stream.read_exact(&mut rest)?;
+        body.extend(rest);
+    }
+    body.truncate(content_length);
+    Ok(HttpRequest {
+        method,
+        content_type,
+        origin,
+        body,
+    })
+}
+
+/// Writes a response with the given status line ending and body, closing the connection after it
+pub fn write_response(
+    stream: &mut impl Write,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+fn find(data: &[u8], pattern: &[u8]) -> Option<usize> {
+    data.windows(pattern.len())
+        .position(|window| window == pattern)
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn reads_the_method_and_the_body() {
+        let mut stream = Cursor::new(
+            b"POST / HTTP/1.1\r\nHost: localhost\r\ncontent-length: 4\r\n\r\nbodyignored".to_vec(),
+        );
+        let request = read_request(&mut stream).unwrap();
+        assert_eq!(request.method, "POST");
+        assert_eq!(request.content_type, None);
+        assert_eq!(request.origin, None);
+        assert_eq!(request.body, b"body");
+    }
+
+    #[test]
+    fn reads_the_content_type_and_the_origin() {
+        let mut stream = Cursor::new(
+            b"POST / HTTP/1.1\r\nContent-Type: Application/JSON; charset=utf-8\r\nOrigin: http://example.com\r\n\r\n".to_vec(),
+        );
+        let request = read_request(&mut stream).unwrap();
+        assert_eq!(request.content_type.as_deref(), Some("application/json"));
+        assert_eq!(request.origin.as_deref(), Some("http://example.com"));
+    }
+
+    #[test]
+    fn fails_when_the_request_is_cut() {
+        let mut stream = Cursor::new(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n".to_vec());
+        assert!(read_request(&mut stream).is_err());
+    }
+}
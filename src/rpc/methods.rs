@@ -0,0 +1,282 @@
+use super::constants::*;
+use super::errors::RpcError;
+use crate::bandwidth::limit_from_kib;
+use crate::client::TorrentState;
+use crate::session::{Session, SessionTorrent};
+use crate::utils::{from_hex, to_hex};
+use serde_json::{json, Value};
+
+/// Answers a JSON-RPC 2.0 request.
+/// Returns None for notifications, the requests without an `id`, which get no answer
+pub fn handle_request(session: &Session, body: &[u8]) -> Option<String> {
+    let request: Value = match serde_json::from_slice(body) {
+        Ok(request) => request,
+        Err(err) => {
+            return Some(response(
+                Value::Null,
+                Err(RpcError::new(PARSE_ERROR, &err.to_string())),
+            ))
+        }
+    };
+    let id = request.get("id").cloned();
+    let result = match (
+        request.get("jsonrpc").and_then(Value::as_str),
+        request.get("method").and_then(Value::as_str),
+    ) {
+        (Some("2.0"), Some(method)) => {
+            let params = request.get("params").cloned().unwrap_or(Value::Null);
+            call(session, method, &params)
+        }
+        _ => Err(RpcError::new(
+            INVALID_REQUEST,
+            "Requests must be objects with \"jsonrpc\": \"2.0\" and a method",
+        )),
+    };
+    id.map(|id| response(id, result))
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> String {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": id }),
+        Err(error) => json!({
+            "jsonrpc": "2.0",
+            "error": { "code": error.code, "message": error.message },
+            "id": id,
+        }),
+    }
+    .to_string()
+}
+
+fn call(session: &Session, method: &str, params: &Value) -> Result<Value, RpcError> {
+    match method {
+        ADD_TORRENT => add_torrent(session, params),
+        LIST_TORRENTS => Ok(list_torrents(session)),
+        PAUSE_TORRENT => {
+            torrent(session, params)?.handle.pause();
+            Ok(Value::Null)
+        }
+        RESUME_TORRENT => {
+            torrent(session, params)?.handle.resume();
+            Ok(Value::Null)
+        }
+        REMOVE_TORRENT => {
+            session.remove_torrent(&info_hash(params)?)?;
+            Ok(Value::Null)
+        }
+        LIST_PEERS => Ok(list_peers(&torrent(session, params)?)),
+        SET_LIMITS => set_limits(session, params),
+        _ => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            &format!("Unknown method {}", method),
+        )),
+    }
+}
+
+fn add_torrent(session: &Session, params: &Value) -> Result<Value, RpcError> {
+    if let Some(path) = params.get("path").and_then(Value::as_str) {
+        let info_hash = session.add_torrent(path)?;
+        return Ok(json!({ "info_hash": to_hex(&info_hash) }));
+    }
+    match params.get("magnet").and_then(Value::as_str) {
+        Some(magnet) => {
+            let info_hash = session.add_magnet(magnet)?;
+            Ok(json!({ "info_hash": to_hex(&info_hash) }))
+        }
+        None => Err(RpcError::invalid_params(
+            "Expected the \"path\" of a .torrent file or a \"magnet\" link",
+        )),
+    }
+}
+
+fn list_torrents(session: &Session) -> Value {
+    let running = session.running_torrents().into_iter().map(|torrent| {
+        json!({
+            "info_hash": to_hex(&torrent.metainfo.info_hash),
+            "name": torrent.metainfo.info.name,
+            "state": state_name(torrent.handle.state()),
+            "progress": torrent.stats.progress(),
+            "completed_pieces": torrent.stats.completed_pieces(),
+            "total_pieces": torrent.stats.piece_count(),
+            "download_rate": torrent.stats.download_rate(),
+            "upload_rate": torrent.stats.upload_rate(),
+            "downloaded": torrent.stats.downloaded(),
+            "uploaded": torrent.stats.uploaded(),
+            "peers": torrent.stats.peers().len(),
+        })
+    });
+    let queued = session.queued_torrents().into_iter().map(|metainfo| {
+        json!({
+            "info_hash": to_hex(&metainfo.info_hash),
+            "name": metainfo.info.name,
+            "state": "queued",
+        })
+    });
+    let fetching = session.fetching_torrents().into_iter().map(|link| {
+        json!({
+            "info_hash": to_hex(&link.info_hash),
+            "name": link.display_name(),
+            "state": "fetching_metadata",
+        })
+    });
+    Value::Array(running.chain(queued).chain(fetching).collect())
+}
+
+fn list_peers(torrent: &SessionTorrent) -> Value {
+    Value::Array(
+        torrent
+            .stats
+            .peers()
+            .into_iter()
+            .map(|peer| {
+                json!({
+                    "peer_id": to_hex(&peer.peerid),
+                    "ip": peer.ip,
+                    "port": peer.port,
+                    "download_rate": peer.downloadrate,
+                    "upload_rate": peer.uploadrate,
+                    "client_choked": peer.state.client.chocked,
+                    "client_interested": peer.state.client.interested,
+                    "peer_choked": peer.state.peer.chocked,
+                    "peer_interested": peer.state.peer.interested,
+                })
+            })
+            .collect(),
+    )
+}
+
+// limits are given in KiB/s, missing or zero means unlimited
+fn set_limits(session: &Session, params: &Value) -> Result<Value, RpcError> {
+    let max_upload_rate = kib_param(params, "max_upload_rate")?;
+    let max_download_rate = kib_param(params, "max_download_rate")?;
+    if params.get("info_hash").is_some() {
+        torrent(session, params)?
+            .handle
+            .set_rate_limits(max_upload_rate, max_download_rate);
+    } else {
+        session.set_rate_limits(max_upload_rate, max_download_rate);
+    }
+    Ok(Value::Null)
+}
+
+fn kib_param(params: &Value, name: &str) -> Result<Option<u64>, RpcError> {
+    match params.get(name) {
+        None | Some(Value::Null) => Ok(None),
+        Some(value) => value.as_u64().map(limit_from_kib).ok_or_else(|| {
+            RpcError::invalid_params(&format!("{} must be a number of KiB/s", name))
+        }),
+    }
+}
+
+fn torrent(session: &Session, params: &Value) -> Result<SessionTorrent, RpcError> {
+    let info_hash = info_hash(params)?;
+    session.torrent(&info_hash).ok_or_else(|| {
+        RpcError::new(
+            UNKNOWN_TORRENT,
+            &format!("No running torrent with info_hash {}", to_hex(&info_hash)),
+        )
+    })
+}
+
+fn info_hash(params: &Value) -> Result<Vec<u8>, RpcError> {
+    params
+        .get("info_hash")
+        .and_then(Value::as_str)
+        .and_then(from_hex)
+        .filter(|info_hash| info_hash.len() == 20)
+        .ok_or_else(|| RpcError::invalid_params("Expected a hexadecimal \"info_hash\""))
+}
+
+fn state_name(state: TorrentState) -> &'static str {
+    match state {
+        TorrentState::Running => "downloading",
+        TorrentState::Seeding => "seeding",
+        TorrentState::Paused => "paused",
+        TorrentState::Stopped => "stopped",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG_PATH: &str = "src/config/test_files/session_config.txt";
+
+    fn answer(session: &Session, request: &str) -> Value {
+        serde_json::from_str(&handle_request(session, request.as_bytes()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn answers_errors_with_their_json_rpc_code() {
+        let session = Session::new(CONFIG_PATH, None).unwrap();
+
+        let not_json = answer(&session, "{");
+        assert_eq!(not_json["error"]["code"], PARSE_ERROR);
+        let unknown = answer(&session, r#"{"jsonrpc": "2.0", "method": "nope", "id": 1}"#);
+        assert_eq!(unknown["error"]["code"], METHOD_NOT_FOUND);
+        assert_eq!(unknown["id"], 1);
+        let paused = answer(
+            &session,
+            r#"{"jsonrpc": "2.0", "method": "pause_torrent", "params": {"info_hash": "0000000000000000000000000000000000000000"}, "id": 2}"#,
+        );
+        assert_eq!(paused["error"]["code"], UNKNOWN_TORRENT);
+        let magnet = answer(
+            &session,
+            r#"{"jsonrpc": "2.0", "method": "add_torrent", "params": {"magnet": "magnet:?xt=urn:btih:00"}, "id": 3}"#,
+        );
+        assert_eq!(magnet["error"]["code"], INVALID_PARAMS);
+
+        session.stop();
+    }
+
+    #[test]
+    fn magnet_links_are_listed_while_their_metadata_is_fetched() {
+        let session = Session::new(CONFIG_PATH, None).unwrap();
+        // a tracker that never answers, so the metadata is still being fetched
+        let tracker = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let add = format!(
+            r#"{{"jsonrpc": "2.0", "method": "add_torrent", "params": {{"magnet": "magnet:?xt=urn:btih:c9e15763f722f23e98a29decdfae341b98d53056&dn=magnet&tr=http://{}/announce"}}, "id": 1}}"#,
+            tracker.local_addr().unwrap()
+        );
+
+        let added = answer(&session, &add);
+        assert_eq!(
+            added["result"]["info_hash"],
+            "c9e15763f722f23e98a29decdfae341b98d53056"
+        );
+        assert_eq!(answer(&session, &add)["error"]["code"], DUPLICATE_TORRENT);
+        let list = answer(
+            &session,
+            r#"{"jsonrpc": "2.0", "method": "list_torrents", "id": 2}"#,
+        );
+        assert_eq!(list["result"][0]["name"], "magnet");
+        assert_eq!(list["result"][0]["state"], "fetching_metadata");
+
+        let removed = answer(
+            &session,
+            r#"{"jsonrpc": "2.0", "method": "remove_torrent", "params": {"info_hash": "c9e15763f722f23e98a29decdfae341b98d53056"}, "id": 3}"#,
+        );
+        assert_eq!(removed["result"], Value::Null);
+        assert!(session.is_empty());
+
+        session.stop();
+    }
+
+    #[test]
+    fn lists_no_torrents_and_ignores_notifications() {
+        let session = Session::new(CONFIG_PATH, None).unwrap();
+
+        let list = answer(
+            &session,
+            r#"{"jsonrpc": "2.0", "method": "list_torrents", "id": "a"}"#,
+        );
+        assert_eq!(list["result"], json!([]));
+        assert_eq!(list["id"], "a");
+        assert!(handle_request(
+            &session,
+            br#"{"jsonrpc": "2.0", "method": "set_limits", "params": {"max_upload_rate": 100}}"#
+        )
+        .is_none());
+
+        session.stop();
+    }
+}
@@ -0,0 +1,10 @@
+mod constants;
+mod errors;
+mod http;
+mod methods;
+mod server;
+
+pub use constants::*;
+pub use errors::RpcError;
+pub use methods::handle_request;
+pub use server::RpcServer;
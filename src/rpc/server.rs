@@ -0,0 +1,160 @@
+use super::constants::RPC_READ_TIMEOUT;
+use super::http::{read_request, write_response};
+use super::methods::handle_request;
+use crate::constants::TIME_BETWEEN_ACCEPTS;
+use crate::server::LOCALHOST;
+use crate::session::Session;
+use log::*;
+use std::io::{self, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+const JSON: &str = "application/json";
+const TEXT: &str = "text/plain";
+
+/// Answers JSON-RPC requests to control the session, only from the local machine.
+/// Requests are answered one at a time, in the order they arrive.
+/// Only `application/json` requests without an `Origin` are answered, so web pages open in a browser
+/// can't send requests to it
+pub struct RpcServer {
+    stop_sender: Sender<()>,
+    handle: JoinHandle<()>,
+}
+
+impl RpcServer {
+    /// Binds `127.0.0.1:port` and starts answering in a new thread
+    pub fn listen(port: u16, session: Arc<Session>) -> io::Result<RpcServer> {
+        let listener = TcpListener::bind((LOCALHOST, port))?;
+        listener.set_nonblocking(true)?;
+        info!("Control API listening on {}:{}", LOCALHOST, port);
+
+        let (stop_sender, stop_receiver) = mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            while stop_receiver.try_recv().is_err() {
+                match listener.accept() {
+                    Ok((stream, address)) => {
+                        trace!("Control API request from {}", address);
+                        if let Err(err) = serve(stream, &session) {
+                            warn!("Couldn't answer control API request: {}", err);
+                        }
+                    }
+                    Err(err) if err.kind() == ErrorKind::WouldBlock => {
+                        std::thread::sleep(TIME_BETWEEN_ACCEPTS)
+                    }
+                    Err(err) => error!("Control API couldn't accept connection: {}", err),
+                }
+            }
+        });
+        Ok(RpcServer {
+            stop_sender,
+            handle,
+        })
+    }
+
+    /// Stops answering and waits until the request being answered, if any, is done
+    pub fn stop(self) {
+        let _ = self.stop_sender.send(());
+        let _ = self.handle.join();
+    }
+}
+
+fn serve(mut stream: TcpStream, session: &Session) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(RPC_READ_TIMEOUT))?;
+    let request = match read_request(&mut stream) {
+        Ok(request) => request,
+        Err(err) => {
+            return write_response(
+                &mut stream,
+                "400 Bad Request",
+                TEXT,
+                err.to_string().as_bytes(),
+            )
+        }
+    };
+    if request.method != "POST" {
+        return write_response(
+            &mut stream,
+            "405 Method Not Allowed",
+            TEXT,
+            b"Requests must be sent with POST",
+        );
+    }
+    // browsers send the Origin of the page with its requests, and can't send JSON from another site without asking first
+    if request.origin.is_some() {
+        return write_response(
+            &mut stream,
+            "403 Forbidden",
+            TEXT,
+            b"Requests from web pages aren't allowed",
+        );
+    }
+    if request.content_type.as_deref() != Some(JSON) {
+        return write_response(
+            &mut stream,
+            "415 Unsupported Media Type",
+            TEXT,
+            b"Requests must be sent with Content-Type: application/json",
+        );
+    }
+    match handle_request(session, &request.body) {
+        Some(response) => write_response(&mut stream, "200 OK", JSON, response.as_bytes()),
+        None => write_response(&mut stream, "204 No Content", JSON, b""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+
+    const CONFIG_PATH: &str = "src/config/test_files/session_config.txt";
+    const LIST_TORRENTS: &str = r#"{"jsonrpc": "2.0", "method": "list_torrents", "id": 1}"#;
+
+    // sends the request with the given headers and returns the status line of the response
+    fn status(session: &Session, headers: &str) -> String {
+        let listener = TcpListener::bind((LOCALHOST, 0)).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        write!(
+            client,
+            "POST / HTTP/1.1\r\n{}Content-Length: {}\r\n\r\n{}",
+            headers,
+            LIST_TORRENTS.len(),
+            LIST_TORRENTS
+        )
+        .unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        serve(stream, session).unwrap();
+
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn only_json_requests_without_an_origin_are_answered() {
+        let session = Session::new(CONFIG_PATH, None).unwrap();
+
+        assert_eq!(
+            status(&session, "Content-Type: application/json\r\n"),
+            "HTTP/1.1 200 OK"
+        );
+        // what a web page can send without the browser asking first
+        assert_eq!(
+            status(&session, "Content-Type: text/plain\r\n"),
+            "HTTP/1.1 415 Unsupported Media Type"
+        );
+        assert_eq!(status(&session, ""), "HTTP/1.1 415 Unsupported Media Type");
+        assert_eq!(
+            status(
+                &session,
+                "Content-Type: application/json\r\nOrigin: http://example.com\r\n"
+            ),
+            "HTTP/1.1 403 Forbidden"
+        );
+
+        session.stop();
+    }
+}
@@ -1,44 +1,86 @@
-use super::connection::ServerConnection;
+use super::announcer::Announcer;
 use super::constants::*;
 use super::errors::ServerError;
+use super::inbound::InboundConnection;
 use super::thread_pool::ThreadPool;
 use super::ServerLogger;
+use crate::bandwidth::TorrentBandwidth;
+use crate::encryption::EncryptionPolicy;
 use crate::metainfo::Metainfo;
-use crate::peer::PeerMessageService;
-use crate::tracker::Event;
-use crate::tracker::ITrackerService;
+use crate::peer::{Choker, ConnectionLimit};
+use crate::reactor::{ConnectionEvent, ConnectionId, Reactor, ReactorError};
+use crate::storage::SharedStorage;
 use crate::tracker::TrackerService;
+use crate::utp::{Transport, UtpSocket};
 use log::*;
+use std::collections::HashMap;
 use std::net::IpAddr;
 use std::net::SocketAddr;
 use std::net::TcpListener;
-use std::net::TcpStream;
 use std::sync::mpsc;
-use std::sync::mpsc::{Receiver, Sender};
-use std::thread;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender};
 use std::thread::JoinHandle;
 use std::time::Duration;
 
 const TRACKER_INTERVAL_IN_SECONDS: u64 = 20;
 
 enum ServerMessage {
+    AddTorrent(Box<ServedTorrent>),
+    RemoveTorrent(Vec<u8>),
     Stop,
 }
 
+/// A torrent the server accepts connections for, peers ask for it by its info_hash in their handshake
+pub struct ServedTorrent {
+    pub metainfo: Metainfo,
+    /// The storage the requested pieces are read from, shared with the client.
+    pub storage: SharedStorage,
+    /// Announces the torrent periodically, and once stopped.
+    pub tracker_service: TrackerService,
+    /// Shared with the connections the client opens, decides who we upload to.
+    pub choker: Choker,
+    /// The rate limits of the torrent, shared with the connections the client opens.
+    pub bandwidth: TorrentBandwidth,
+}
+
+/// Adds and removes the torrents of a running server, it can be cloned and used from any thread
+#[derive(Clone)]
+pub struct ServerSender {
+    sender: Sender<ServerMessage>,
+}
+
+impl ServerSender {
+    pub fn add_torrent(&self, torrent: ServedTorrent) {
+        let _ = self
+            .sender
+            .send(ServerMessage::AddTorrent(Box::new(torrent)));
+    }
+
+    /// Closes the connections of the torrent and announces its tracker that it stopped
+    pub fn remove_torrent(&self, info_hash: &[u8]) {
+        let _ = self
+            .sender
+            .send(ServerMessage::RemoveTorrent(info_hash.to_vec()));
+    }
+}
+
 /// Struct that handles the server's acceptor thread.
-/// It accepts connections and spawns a thread for each connection.
+/// The listener and every accepted connection are driven by the shared [`Reactor`],
+/// the acceptor thread handles what they receive and the thread pool serves the requested blocks.
+/// The torrents are announced to their trackers from a thread of their own.
+/// A single server can serve every torrent of a session through the same port.
 pub struct Server {
-    sender: Sender<ServerMessage>,
+    sender: ServerSender,
     handle: JoinHandle<Result<(), ServerError>>,
 }
 
 impl Server {
-    /// Creates a new server.
+    /// Creates a new server serving a single torrent.
     /// The server starts running and listening inmediatly after created
     ///
     /// # Arguments
-    /// * `metainfo` - The metainfo struct of the torrent file.
     /// * `client_peer_id` - The peer_id the client generated in order to identify itself.
+    /// * `torrent` - The torrent served, with the storage its pieces are read from, its tracker, choker and rate limits.
     ///
     /// # Returns
     /// A new server, of type `Server`.
@@ -47,7 +89,7 @@ impl Server {
     ///
     ///  ```no_compile
     ///
-    ///  use bittorrent_rustico::server::Server;
+    ///  use bittorrent_rustico::server::{ServedTorrent, Server};
     ///  use bittorrent_rustico::metainfo::Metainfo;   
     ///  use rand::Rng;
     ///  use std::time::Duration;
@@ -55,121 +97,172 @@ impl Server {
     ///  let metainfo = Metainfo::from_torrent("debian.torrent").unwrap();
     ///  let client_peer_id = rand::thread_rng().gen::<[u8; 20]>().to_vec();
     ///
-    ///  let server: Server = Server::run(client_peer_id, metainfo, 6687, Duration::from_secs(10), "./downloads/pieces");
+    ///  let torrent = ServedTorrent { metainfo, storage, tracker_service, choker: Choker::default(), bandwidth: TorrentBandwidth::unlimited() };
+    ///  let server: Server = Server::run(client_peer_id, 6687, Duration::from_secs(10), torrent);
     ///  
     ///  server.stop().unwrap();
     ///  ```
     ///
     pub fn run(
         client_peer_id: Vec<u8>,
-        metainfo: Metainfo,
         port: u16,
         time_to_sleep: Duration,
-        pieces_dir: &str,
-        tracker_service: TrackerService,
+        torrent: ServedTorrent,
+    ) -> Server {
+        let server = Self::listen(
+            client_peer_id,
+            port,
+            time_to_sleep,
+            ConnectionLimit::unlimited(),
+            EncryptionPolicy::Disabled,
+            Transport::Tcp,
+        );
+        server.sender().add_torrent(torrent);
+        server
+    }
+
+    /// Creates a new server without torrents, they are added and removed through its [`ServerSender`].
+    /// Inbound connections are refused once `connections` reaches its cap,
+    /// and the encrypted or plaintext ones the `encryption` policy doesn't allow.
+    /// With the uTP `transport` the port also accepts uTP connections over UDP
+    pub fn listen(
+        client_peer_id: Vec<u8>,
+        port: u16,
+        time_to_sleep: Duration,
+        connections: ConnectionLimit,
+        encryption: EncryptionPolicy,
+        transport: Transport,
     ) -> Server {
         let (tx, rx) = mpsc::channel();
-        let pieces_dir_clone = String::from(pieces_dir);
         let address: SocketAddr = socket_from_address(LOCALHOST.to_string(), port);
 
         let handle = std::thread::spawn(move || {
-            Self::listen(
+            Self::accept(
                 address,
                 client_peer_id,
-                metainfo,
                 rx,
                 time_to_sleep,
-                &pieces_dir_clone,
-                tracker_service,
+                connections,
+                encryption,
+                transport,
             )
         });
 
-        Server { sender: tx, handle }
+        Server {
+            sender: ServerSender { sender: tx },
+            handle,
+        }
+    }
+
+    pub fn sender(&self) -> ServerSender {
+        self.sender.clone()
     }
 
-    fn listen(
+    fn accept(
         address: SocketAddr,
         client_peer_id: Vec<u8>,
-        metainfo: Metainfo,
         receiver: Receiver<ServerMessage>,
         time_to_sleep: Duration,
-        pieces_dir: &str,
-        mut tracker_service: TrackerService,
+        connection_limit: ConnectionLimit,
+        encryption: EncryptionPolicy,
+        transport: Transport,
     ) -> Result<(), ServerError> {
         let (logger, handle) = ServerLogger::new(LOGS_DIR)?;
         let address = format!("{}:{}", address.ip(), address.port());
-        let mut last_announce = std::time::Instant::now();
         let listener: TcpListener = TcpListener::bind(&address)?;
-        listener.set_nonblocking(true).map_err(|_| {
-            ServerError::ServerCreationError("Couldn't set non blocking mode on server".to_string())
-        })?;
-        let pool: ThreadPool = ThreadPool::new(25)?;
-        for stream in listener.incoming() {
-            if receiver.try_recv().is_ok() {
-                info!("Server received stop message");
-                break;
+        let reactor = Reactor::global()?;
+        let (events_sender, events) = mpsc::channel();
+        let utp_listener_id = match transport {
+            Transport::Utp => {
+                let socket = UtpSocket::bind(listener.local_addr()?)?;
+                Some(reactor.listen_utp(socket, events_sender.clone())?)
             }
+            Transport::Tcp => None,
+        };
+        let listener_id = reactor.listen(listener, events_sender)?;
+        let pool: ThreadPool = ThreadPool::new(25)?;
+        let announcer = Announcer::start(Duration::from_secs(TRACKER_INTERVAL_IN_SECONDS));
+        let mut torrents: HashMap<Vec<u8>, ServedTorrent> = HashMap::new();
+        let mut connections: HashMap<ConnectionId, InboundConnection> = HashMap::new();
 
-            match stream {
-                Ok(stream) => {
-                    info!("Server: Incoming connection");
-                    println!(
-                        "handle incomming connection return data:{:?}",
-                        Server::handle_incoming_connection(
-                            stream,
-                            metainfo.clone(),
-                            client_peer_id.clone(),
-                            logger.clone(),
-                            &pool,
-                            pieces_dir,
-                        )
-                    );
-                }
-                Err(ref err) if err.kind() == std::io::ErrorKind::WouldBlock => {
-                    // This doesen't mean an error ocurred, there just wasn't a connection at the moment
-                    if last_announce.elapsed().as_secs() > TRACKER_INTERVAL_IN_SECONDS {
-                        println!("announcing");
-                        let _ = tracker_service.announce(None);
-                        last_announce = std::time::Instant::now();
+        'serving: loop {
+            for message in receiver.try_iter() {
+                match message {
+                    ServerMessage::AddTorrent(torrent) => {
+                        announcer.add(
+                            torrent.metainfo.info_hash.clone(),
+                            torrent.tracker_service.clone(),
+                        );
+                        torrents.insert(torrent.metainfo.info_hash.clone(), *torrent);
+                    }
+                    ServerMessage::RemoveTorrent(info_hash) => {
+                        connections.retain(|id, connection| {
+                            let removed = connection.info_hash() == Some(&info_hash[..]);
+                            if removed {
+                                reactor.close(*id);
+                            }
+                            !removed
+                        });
+                        torrents.remove(&info_hash);
+                        announcer.remove(info_hash);
+                    }
+                    ServerMessage::Stop => {
+                        info!("Server received stop message");
+                        break 'serving;
                     }
-
-                    thread::sleep(time_to_sleep);
                 }
-                Err(err) => return Err(ServerError::TcpStreamError(err)),
+            }
+            // waiting at most time_to_sleep lets the loop notice stop messages in time
+            let (id, event) = match events.recv_timeout(time_to_sleep) {
+                Ok(received) => received,
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => return Err(ReactorError::Stopped.into()),
             };
+            match event {
+                ConnectionEvent::Accepted(peer_address) => {
+                    let Some(slot) = connection_limit.try_acquire() else {
+                        info!("Server: Refusing {}, too many connections", peer_address);
+                        reactor.close(id);
+                        continue;
+                    };
+                    info!("Server: Incoming connection from {}", peer_address);
+                    let connection =
+                        InboundConnection::new(reactor.clone(), id, peer_address, slot, encryption);
+                    connections.insert(id, connection);
+                }
+                ConnectionEvent::Data(data) => {
+                    let received = match connections.get_mut(&id) {
+                        Some(connection) => {
+                            connection.receive(&data, &torrents, &client_peer_id, &logger, &pool)
+                        }
+                        None => continue,
+                    };
+                    if let Err(err) = received {
+                        debug!("Closing inbound connection: {}", err);
+                        connections.remove(&id);
+                        reactor.close(id);
+                    }
+                }
+                ConnectionEvent::Closed(reason) => {
+                    debug!("Server connection was closed: {}", reason);
+                    connections.remove(&id);
+                }
+                ConnectionEvent::Connected => {}
+            }
         }
 
+        reactor.close(listener_id);
+        if let Some(utp_listener_id) = utp_listener_id {
+            reactor.close(utp_listener_id);
+        }
+        connections.keys().for_each(|id| reactor.close(*id));
         logger.stop();
         handle.join().unwrap();
-
-        let _ = tracker_service.announce(Some(Event::Stopped));
-        Ok(())
-    }
-
-    fn handle_incoming_connection(
-        stream: TcpStream,
-        metainfo: Metainfo,
-        client_id: Vec<u8>,
-        logger: ServerLogger,
-        pool: &ThreadPool,
-        pieces_dir: &str,
-    ) -> Result<(), ServerError> {
-        stream.set_nonblocking(false)?;
-        stream.set_read_timeout(Some(Duration::from_secs(100)))?;
-        stream.set_write_timeout(Some(Duration::from_secs(100)))?;
-        let connection_logger = logger;
-        let dir_clone = String::from(pieces_dir);
-        pool.execute(move || {
-            info!("inside pool execution");
-            let message_service = PeerMessageService::from_peer_connection(stream);
-            let _ = ServerConnection::new(client_id, metainfo, Box::new(message_service))
-                .run(connection_logger, &dir_clone);
-        });
-
+        announcer.stop();
         Ok(())
     }
 
-    /// Stops the server.
+    /// Stops the server, announcing every torrent it still served as stopped.
     /// If the server is in the middle of creating a connection, it may take a little while for it to finish.
     /// # Returns
     ///
@@ -183,7 +276,7 @@ impl Server {
     /// Check the example at the `run` method of the Server
     ///
     pub fn stop(self) -> Result<(), ServerError> {
-        let _ = self.sender.send(ServerMessage::Stop);
+        let _ = self.sender.sender.send(ServerMessage::Stop);
         self.handle.join().map_err(|_| ServerError::JoinError)??;
 
         Ok(())
@@ -0,0 +1,141 @@
+use crate::tracker::{Event, ITrackerService};
+use log::*;
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+enum AnnouncerMessage<T> {
+    Add(Vec<u8>, T),
+    Remove(Vec<u8>),
+    Stop,
+}
+
+/// Announces the torrents of the server to their trackers from its own thread,
+/// so a slow tracker never holds up the connections the acceptor serves.
+/// The announces are blocking HTTP(S) requests, they aren't driven by the reactor.
+pub struct Announcer<T: ITrackerService + Send + 'static> {
+    sender: Sender<AnnouncerMessage<T>>,
+    handle: JoinHandle<()>,
+}
+
+impl<T: ITrackerService + Send + 'static> Announcer<T> {
+    /// Starts announcing every torrent added, once every `interval`
+    pub fn start(interval: Duration) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let handle = std::thread::spawn(move || announce_periodically(receiver, interval));
+        Self { sender, handle }
+    }
+
+    pub fn add(&self, info_hash: Vec<u8>, tracker_service: T) {
+        let _ = self
+            .sender
+            .send(AnnouncerMessage::Add(info_hash, tracker_service));
+    }
+
+    /// Stops announcing the torrent, letting its tracker know it stopped
+    pub fn remove(&self, info_hash: Vec<u8>) {
+        let _ = self.sender.send(AnnouncerMessage::Remove(info_hash));
+    }
+
+    /// Announces every torrent left as stopped and waits for the announces to finish
+    pub fn stop(self) {
+        let _ = self.sender.send(AnnouncerMessage::Stop);
+        if self.handle.join().is_err() {
+            error!("The announcer thread panicked");
+        }
+    }
+}
+
+fn announce_periodically<T: ITrackerService>(
+    receiver: Receiver<AnnouncerMessage<T>>,
+    interval: Duration,
+) {
+    let mut trackers: HashMap<Vec<u8>, T> = HashMap::new();
+    let mut last_announce = Instant::now();
+    loop {
+        match receiver.recv_timeout(interval.saturating_sub(last_announce.elapsed())) {
+            Ok(AnnouncerMessage::Add(info_hash, tracker_service)) => {
+                trackers.insert(info_hash, tracker_service);
+            }
+            Ok(AnnouncerMessage::Remove(info_hash)) => {
+                if let Some(mut tracker_service) = trackers.remove(&info_hash) {
+                    let _ = tracker_service.announce(Some(Event::Stopped));
+                }
+            }
+            Ok(AnnouncerMessage::Stop) | Err(RecvTimeoutError::Disconnected) => break,
+            Err(RecvTimeoutError::Timeout) => {
+                for tracker_service in trackers.values_mut() {
+                    let _ = tracker_service.announce(None);
+                }
+                last_announce = Instant::now();
+            }
+        }
+    }
+
+    for tracker_service in trackers.values_mut() {
+        let _ = tracker_service.announce(Some(Event::Stopped));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tracker::{TrackerError, TrackerResponse};
+    use std::sync::{Arc, Mutex};
+
+    // every announce made, by tracker name
+    type Announces = Arc<Mutex<Vec<(&'static str, Option<Event>)>>>;
+
+    #[derive(Clone)]
+    struct RecordingTracker {
+        name: &'static str,
+        announces: Announces,
+    }
+
+    impl ITrackerService for RecordingTracker {
+        fn announce(&mut self, event: Option<Event>) -> Result<TrackerResponse, TrackerError> {
+            self.announces.lock().unwrap().push((self.name, event));
+            Ok(TrackerResponse {
+                peers: vec![],
+                interval: None,
+            })
+        }
+    }
+
+    #[test]
+    fn trackers_are_announced_periodically_and_once_stopped() {
+        let announces = Arc::new(Mutex::new(vec![]));
+        let tracker = |name| RecordingTracker {
+            name,
+            announces: announces.clone(),
+        };
+        let announcer = Announcer::start(Duration::from_millis(50));
+        announcer.add(vec![1], tracker("first"));
+        announcer.add(vec![2], tracker("second"));
+        std::thread::sleep(Duration::from_millis(120));
+        announcer.remove(vec![1]);
+        announcer.stop();
+
+        let announces = announces.lock().unwrap();
+        let periodic = |name| {
+            announces
+                .iter()
+                .filter(|announce| **announce == (name, None))
+                .count()
+        };
+        assert!(periodic("first") >= 1);
+        assert!(periodic("second") >= 1);
+        let stopped: Vec<_> = announces
+            .iter()
+            .filter(|(_, event)| *event == Some(Event::Stopped))
+            .collect();
+        assert_eq!(
+            stopped,
+            vec![
+                &("first", Some(Event::Stopped)),
+                &("second", Some(Event::Stopped))
+            ]
+        );
+    }
+}
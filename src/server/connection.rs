@@ -1,21 +1,21 @@
 use super::errors::ServerError;
+use super::inbound::log_upload_event;
 use super::logger::ServerLogger;
-use super::utils::*;
 use crate::metainfo::Metainfo;
 use crate::peer::IServerPeerMessageService;
 use crate::peer::PeerMessage;
-use crate::peer::PeerMessageId;
+use crate::peer::{Choker, UploadState};
+use crate::storage::SharedStorage;
 use log::*;
 
-pub const SEED_DELAY: f64 = 2_f64 * 100000_f64;
-
-/// Struct that handles the server's acceptor thread.
-/// It is spawned each time a connection is accepted.
-/// It handles the connection's messages and answers them accordingly.
+/// Serves a single peer through a blocking message service, answering its messages accordingly.
+/// The server itself drives its connections from the reactor's events (see [`super::inbound::InboundConnection`]),
+/// this is meant for services that have to be read from, like mocks and tools.
 pub struct ServerConnection {
     message_service: Box<dyn IServerPeerMessageService>,
     metainfo: Metainfo,
     client_peer_id: Vec<u8>,
+    choker: Choker,
 }
 
 /// Struct representing the content of a request message
@@ -30,15 +30,18 @@ pub struct RequestMessage {
 
 impl ServerConnection {
     /// Creates a new server connection.
+    /// The choker is the one shared with the connections the client opens to other peers
     pub fn new(
         client_peer_id: Vec<u8>,
         metainfo: Metainfo,
         message_service: Box<dyn IServerPeerMessageService>,
+        choker: Choker,
     ) -> Self {
         Self {
             client_peer_id: client_peer_id.to_vec(),
             metainfo,
             message_service,
+            choker,
         }
     }
 
@@ -46,14 +49,14 @@ impl ServerConnection {
     /// The connectcion starts listening inmediatly after calling this method
     ///
     /// The connection has a timeout of 120 seconds, so that it can be automatically closed if no message is received after that interval
-    /// Messagges requesting a block are answered, while Choke, Cancel and NotInterested messges close the connection.
-    /// Every other message is ignored.
+    /// Messages are handled by the same [`UploadState`] outgoing connections use: requested blocks are answered,
+    /// Interested and NotInterested messages unchoke and choke the peer, and every other message is ignored.
     ///
     ///  If an invalid request is received, the connection is terminated
     ///
     /// # Arguments
     /// * `logger` - The server logger to use for logging.
-    /// * `pieces_dir` - The directory where the pieces are stored.
+    /// * `storage` - The storage the requested pieces are read from.
     ///
     /// # Return value
     /// ## On succes
@@ -62,9 +65,10 @@ impl ServerConnection {
     /// ## On error
     /// A `Result` with the `Err` value being a `ServerError`, indicating the underlying cause of the failure
     ///
-    pub fn run(&mut self, logger: ServerLogger, pieces_dir: &str) -> Result<(), ServerError> {
+    pub fn run(&mut self, logger: ServerLogger, storage: SharedStorage) -> Result<(), ServerError> {
+        let mut upload = UploadState::new(storage, self.choker.clone());
         info!("before init messages");
-        self.send_init_messages(pieces_dir)?;
+        self.send_init_messages(&mut upload)?;
         info!("after init messages, about to wait for message from client");
 
         loop {
@@ -81,71 +85,19 @@ impl ServerConnection {
                 }
             };
 
-            let cloned_logger = logger.clone();
-            match message.id {
-                PeerMessageId::Request => {
-                    self.handle_request(message, cloned_logger, pieces_dir)?;
-                    continue;
-                }
-                PeerMessageId::KeepAlive => continue,
-                PeerMessageId::Interested => continue,
-                PeerMessageId::Unchoke => continue,
-                PeerMessageId::Bitfield => continue,
-                PeerMessageId::Have => continue,
-                PeerMessageId::Piece => continue,
-                PeerMessageId::Port => continue,
-                PeerMessageId::Cancel => break,
-                PeerMessageId::Choke => break,
-                PeerMessageId::NotInterested => break,
-            };
+            let event = upload.handle_message(&message, &mut *self.message_service)?;
+            log_upload_event(&logger, event);
         }
 
         Ok(())
     }
 
-    fn send_init_messages(&mut self, download_path: &str) -> Result<(), ServerError> {
+    fn send_init_messages(&mut self, upload: &mut UploadState) -> Result<(), ServerError> {
         self.message_service
             .handshake(&self.metainfo.info_hash, &self.client_peer_id)?;
 
-        self.message_service.send_message(&PeerMessage::unchoke())?;
-
-        let piece_vector: Vec<bool> =
-            get_pieces_vector(self.metainfo.info.pieces.len(), download_path);
-        let bitfield_message: PeerMessage = PeerMessage::bitfield(piece_vector);
-
-        self.message_service.send_message(&bitfield_message)?;
-        Ok(())
-    }
-
-    fn handle_request(
-        &mut self,
-        message: PeerMessage,
-        logger: ServerLogger,
-        pieces_dir: &str,
-    ) -> Result<(), ServerError> {
-        let request: RequestMessage = request_from_payload(message.payload)?;
-        if !client_has_piece(request.index, pieces_dir) {
-            let _ = logger.client_doesnt_have_piece(request.index);
-            return Ok(());
-        }
-
-        let piece_path = format!("{}/{}", pieces_dir, request.index);
-        let piece_data: Vec<u8> = read_piece(&piece_path)?;
-        let block: Vec<u8> = get_block_from_piece(piece_data, request.begin, request.length)?;
-        let block_number: usize = get_block_index(request.begin, request.length);
-        let random = rand::random::<f64>();
-        let delay = random * SEED_DELAY / self.metainfo.info.pieces.len() as f64;
-        std::thread::sleep(std::time::Duration::from_millis(delay as u64));
-        let response_message = PeerMessage::piece(request.index, request.begin, block);
-        match self.message_service.send_message(&response_message) {
-            Ok(()) => {
-                let _ = logger.block_sent_succesfully(request.index, block_number);
-            }
-            Err(_) => {
-                let _ = logger.failed_sending_block(request.index, block_number);
-            }
-        }
-
+        upload.offer_unchoke(&mut *self.message_service)?;
+        self.message_service.send_message(&upload.bitfield())?;
         Ok(())
     }
 }
@@ -156,6 +108,7 @@ mod tests {
     use super::*;
     use crate::metainfo::Info;
     use crate::peer::ServerMessageServiceMock;
+    use crate::storage::PieceFileStorage;
     use sha1::{Digest, Sha1};
 
     pub fn sha1_of(vec: &[u8]) -> Vec<u8> {
@@ -185,11 +138,17 @@ mod tests {
                 length: 16,
                 name: "".to_string(),
                 files: None,
+                ..Default::default()
             },
             info_hash: vec![],
+            ..Default::default()
         }
     }
 
+    fn storage_of(download_dir: &str) -> SharedStorage {
+        std::sync::Arc::new(PieceFileStorage::new(download_dir, 2, "", true))
+    }
+
     fn get_mock_message_service() -> Box<dyn IServerPeerMessageService> {
         Box::new(ServerMessageServiceMock { times_called: 0 })
     }
@@ -221,16 +180,18 @@ mod tests {
         let metainfo = get_fake_metainfo();
 
         let message_service = get_mock_message_service();
-        let mut connection = ServerConnection::new(peer_id, metainfo, message_service);
+        let mut connection =
+            ServerConnection::new(peer_id, metainfo, message_service, Choker::default());
 
-        let pieces_dir: &str = "./src/server/tests/test_2/pieces";
         let logs_dir: &str = "./src/server/tests/test_2/logs";
 
         let (logger, handle) = ServerLogger::new(logs_dir).unwrap();
         let logger_clone = logger.clone();
 
         // act
-        connection.run(logger_clone, pieces_dir).unwrap();
+        connection
+            .run(logger_clone, storage_of("./src/server/tests/test_2"))
+            .unwrap();
         logger.stop();
         handle.join().unwrap();
 
@@ -246,7 +207,8 @@ mod tests {
         let metainfo = get_fake_metainfo();
 
         let message_service = get_mock_message_service();
-        let mut connection = ServerConnection::new(peer_id, metainfo, message_service);
+        let mut connection =
+            ServerConnection::new(peer_id, metainfo, message_service, Choker::default());
 
         let pieces_dir: &str = "./src/server/tests/test_1/pieces";
         let logs_dir: &str = "./src/server/tests/test_1/logs";
@@ -256,7 +218,9 @@ mod tests {
         let logger_clone = logger.clone();
 
         // act
-        connection.run(logger_clone, pieces_dir).unwrap();
+        connection
+            .run(logger_clone, storage_of("./src/server/tests/test_1"))
+            .unwrap();
         logger.stop();
         handle.join().unwrap();
 
@@ -273,17 +237,22 @@ mod tests {
         let metainfo = get_fake_metainfo();
 
         use crate::peer::ServerMessageBitfieldMock;
-        let mut connection =
-            ServerConnection::new(peer_id, metainfo, Box::new(ServerMessageBitfieldMock));
+        let mut connection = ServerConnection::new(
+            peer_id,
+            metainfo,
+            Box::new(ServerMessageBitfieldMock),
+            Choker::default(),
+        );
 
-        let pieces_dir: &str = "./src/server/tests/test_3/pieces";
         let logs_dir: &str = "./src/server/tests/test_3/logs";
 
         let (logger, handle) = ServerLogger::new(logs_dir).unwrap();
         let logger_clone = logger.clone();
 
         // act
-        connection.run(logger_clone, pieces_dir).unwrap();
+        connection
+            .run(logger_clone, storage_of("./src/server/tests/test_3"))
+            .unwrap();
         logger.stop();
         handle.join().unwrap();
 
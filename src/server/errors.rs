@@ -1,5 +1,6 @@
 use crate::logger::LoggerError;
 use crate::peer::IPeerMessageServiceError;
+use crate::reactor::ReactorError;
 use std::fmt;
 
 #[derive(Debug)]
@@ -22,6 +23,9 @@ pub enum ServerError {
 
     /// Other errors related to the server creation, includes a message with the reason of failure
     ServerCreationError(String),
+
+    /// The reactor driving the server's sockets failed or stopped
+    ReactorError(ReactorError),
 }
 
 #[derive(Debug)]
@@ -51,6 +55,12 @@ impl From<IPeerMessageServiceError> for ServerError {
     }
 }
 
+impl From<ReactorError> for ServerError {
+    fn from(error: ReactorError) -> Self {
+        ServerError::ReactorError(error)
+    }
+}
+
 impl From<LoggerError> for ServerError {
     fn from(error: LoggerError) -> Self {
         ServerError::LoggerCreationError(error)
@@ -79,6 +89,7 @@ impl fmt::Display for ServerError {
             ServerError::ServerCreationError(reason) => {
                 write!(f, "Server creation error: {}", reason)
             }
+            ServerError::ReactorError(error) => write!(f, "Reactor error: {}", error),
         }
     }
 }
@@ -0,0 +1,319 @@
+use super::logger::ServerLogger;
+use super::thread_pool::ThreadPool;
+use super::ServedTorrent;
+use crate::bandwidth::PeerBandwidth;
+use crate::encryption::{EncryptionPolicy, InboundNegotiation, Rc4};
+use crate::peer::*;
+use crate::reactor::{ConnectionId, Reactor};
+use log::*;
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Sends messages to a connection by queueing them on the reactor.
+/// Messages of an inbound connection arrive as reactor events, so it can't wait for them
+#[derive(Clone)]
+struct ReactorMessageSender {
+    reactor: Reactor,
+    id: ConnectionId,
+    bandwidth: PeerBandwidth,
+    // shared by the clones, None if the connection is plaintext
+    cipher: Option<Arc<Mutex<Rc4>>>,
+}
+
+impl ReactorMessageSender {
+    fn send_bytes(&self, mut bytes: Vec<u8>) -> Result<(), IPeerMessageServiceError> {
+        // the keystream is applied in the order the bytes are queued, so both happen under the lock
+        let _cipher = match &self.cipher {
+            Some(cipher) => {
+                let mut cipher = cipher.lock().map_err(|_| {
+                    IPeerMessageServiceError::SendingMessageError(
+                        "Cipher lock poisoned".to_string(),
+                    )
+                })?;
+                cipher.apply(&mut bytes);
+                Some(cipher)
+            }
+            None => None,
+        };
+        self.reactor
+            .send(self.id, bytes)
+            .map_err(|err| IPeerMessageServiceError::SendingMessageError(err.to_string()))
+    }
+}
+
+impl IPeerMessageService for ReactorMessageSender {
+    fn wait_for_message(&mut self) -> Result<PeerMessage, IPeerMessageServiceError> {
+        Err(IPeerMessageServiceError::ReceivingMessageError(
+            "Messages of inbound connections are delivered by the reactor".to_string(),
+        ))
+    }
+
+    fn send_message(&mut self, message: &PeerMessage) -> Result<(), IPeerMessageServiceError> {
+        if message.id == PeerMessageId::Piece {
+            self.bandwidth.throttle_upload(message.payload.len());
+        }
+        self.send_bytes(message.to_bytes())
+    }
+}
+
+/// A connection accepted by the server.
+/// It isn't bound to a thread: the acceptor feeds it the bytes the reactor reads from the socket,
+/// answers the handshake and the choking messages right away, and hands block requests to the
+/// thread pool, so a few threads can serve every inbound peer.
+/// Unless encryption is disabled, the connection may start with an encryption negotiation.
+pub struct InboundConnection {
+    decoder: PeerMessageDecoder,
+    reactor: Reactor,
+    id: ConnectionId,
+    peer_address: SocketAddr,
+    // None once the negotiation is done, or if encryption is disabled
+    negotiation: Option<InboundNegotiation>,
+    decrypt: Option<Rc4>,
+    encrypt: Option<Arc<Mutex<Rc4>>>,
+    // the torrent the peer asked for in its handshake, None until it arrives
+    torrent: Option<TorrentConnection>,
+    _slot: ConnectionSlot,
+}
+
+struct TorrentConnection {
+    info_hash: Vec<u8>,
+    sender: ReactorMessageSender,
+    upload: Arc<Mutex<UploadState>>,
+}
+
+impl InboundConnection {
+    /// `slot` counts the connection towards the connection cap until it is dropped
+    pub fn new(
+        reactor: Reactor,
+        id: ConnectionId,
+        peer_address: SocketAddr,
+        slot: ConnectionSlot,
+        encryption: EncryptionPolicy,
+    ) -> Self {
+        Self {
+            decoder: PeerMessageDecoder::new(),
+            reactor,
+            id,
+            peer_address,
+            negotiation: (encryption != EncryptionPolicy::Disabled)
+                .then(|| InboundNegotiation::new(encryption)),
+            decrypt: None,
+            encrypt: None,
+            torrent: None,
+            _slot: slot,
+        }
+    }
+
+    /// The torrent the connection is for, once the peer sent its handshake
+    pub fn info_hash(&self) -> Option<&[u8]> {
+        self.torrent
+            .as_ref()
+            .map(|torrent| torrent.info_hash.as_slice())
+    }
+
+    /// Handles the bytes received from the peer.
+    /// Returns an error if the peer sent something invalid or asked for a torrent that isn't served,
+    /// in which case the connection should be closed
+    pub fn receive(
+        &mut self,
+        data: &[u8],
+        torrents: &HashMap<Vec<u8>, ServedTorrent>,
+        client_peer_id: &[u8],
+        logger: &ServerLogger,
+        pool: &ThreadPool,
+    ) -> Result<(), IPeerMessageServiceError> {
+        let Some(data) = self.decrypt_received(data, torrents)? else {
+            return Ok(());
+        };
+        self.decoder.push(&data);
+        while let Some(frame) = self.decoder.next_frame()? {
+            match frame {
+                PeerFrame::Handshake(handshake) => {
+                    self.answer_handshake(&handshake, torrents, client_peer_id)?
+                }
+                PeerFrame::Message(message) => {
+                    let _ = logger.received_message(message.clone());
+                    self.handle_message(message, logger, pool)?;
+                }
+                PeerFrame::KeepAlive => {}
+            }
+        }
+        Ok(())
+    }
+
+    // The bytes received in plaintext, None while the encryption is being negotiated
+    fn decrypt_received(
+        &mut self,
+        data: &[u8],
+        torrents: &HashMap<Vec<u8>, ServedTorrent>,
+    ) -> Result<Option<Vec<u8>>, IPeerMessageServiceError> {
+        let Some(negotiation) = self.negotiation.as_mut() else {
+            let mut data = data.to_vec();
+            if let Some(decrypt) = self.decrypt.as_mut() {
+                decrypt.apply(&mut data);
+            }
+            return Ok(Some(data));
+        };
+        let (reply, negotiated) = negotiation
+            .receive(data, &served_info_hashes(torrents))
+            .map_err(|err| IPeerMessageServiceError::PeerHandshakeError(err.to_string()))?;
+        if !reply.is_empty() {
+            self.reactor
+                .send(self.id, reply)
+                .map_err(|err| IPeerMessageServiceError::SendingMessageError(err.to_string()))?;
+        }
+        let Some(negotiated) = negotiated else {
+            return Ok(None);
+        };
+        self.negotiation = None;
+        if let Some(ciphers) = negotiated.ciphers {
+            self.decrypt = Some(ciphers.decrypt);
+            self.encrypt = Some(Arc::new(Mutex::new(ciphers.encrypt)));
+        }
+        Ok(Some(negotiated.payload))
+    }
+
+    fn answer_handshake(
+        &mut self,
+        handshake: &[u8],
+        torrents: &HashMap<Vec<u8>, ServedTorrent>,
+        client_peer_id: &[u8],
+    ) -> Result<(), IPeerMessageServiceError> {
+        let requested = info_hash_of(handshake).ok_or_else(|| {
+            IPeerMessageServiceError::PeerHandshakeError("Handshake too short".to_string())
+        })?;
+        // peers of the v2 swarm of a hybrid torrent ask for it by its truncated v2 hash
+        let served = torrents
+            .get(requested)
+            .or_else(|| {
+                torrents.values().find(|torrent| {
+                    torrent.metainfo.truncated_info_hash_v2().as_deref() == Some(requested)
+                })
+            })
+            .ok_or_else(|| {
+                IPeerMessageServiceError::PeerHandshakeError(
+                    "Handshake for a torrent that isn't served".to_string(),
+                )
+            })?;
+        let info_hash = served.metainfo.info_hash.clone();
+        let mut sender = ReactorMessageSender {
+            reactor: self.reactor.clone(),
+            id: self.id,
+            bandwidth: served.bandwidth.for_peer(),
+            cipher: self.encrypt.clone(),
+        };
+        sender.send_bytes(create_handshake_message(requested, client_peer_id))?;
+
+        let mut upload = UploadState::new(served.storage.clone(), served.choker.clone());
+        if supports_fast_extension(handshake) {
+            let ip = match self.peer_address.ip() {
+                IpAddr::V4(ip) => Some(ip),
+                IpAddr::V6(_) => None,
+            };
+            upload.enable_fast_extension(ip, &info_hash);
+        }
+        let torrent = self.torrent.insert(TorrentConnection {
+            info_hash,
+            sender: sender.clone(),
+            upload: Arc::new(Mutex::new(upload)),
+        });
+        let mut upload = lock_upload(&torrent.upload)?;
+        // with the fast extension HaveAll and HaveNone must follow the handshake right away
+        sender.send_message(&upload.have_message())?;
+        upload.offer_unchoke(&mut sender)?;
+        upload.send_fast_hints(&mut sender)
+    }
+
+    fn handle_message(
+        &mut self,
+        message: PeerMessage,
+        logger: &ServerLogger,
+        pool: &ThreadPool,
+    ) -> Result<(), IPeerMessageServiceError> {
+        let torrent = self.torrent.as_mut().ok_or_else(|| {
+            IPeerMessageServiceError::InvalidResponse("Message before the handshake".to_string())
+        })?;
+        if message.id != PeerMessageId::Request {
+            let event =
+                lock_upload(&torrent.upload)?.handle_message(&message, &mut torrent.sender)?;
+            log_upload_event(logger, event);
+            return Ok(());
+        }
+
+        // reading the block and waiting for the upload limits is done by the pool, so the acceptor never blocks
+        let upload = torrent.upload.clone();
+        let mut sender = torrent.sender.clone();
+        let logger = logger.clone();
+        pool.execute(move || {
+            let request = match upload.lock() {
+                Ok(upload) => upload.read_block(&message),
+                Err(_) => return,
+            };
+            match request {
+                Ok(BlockRequest::Ready(block)) => {
+                    log_upload_event(&logger, block.send(&mut sender))
+                }
+                Ok(BlockRequest::Refused(refused)) => {
+                    log_upload_event(&logger, refused.send(&mut sender))
+                }
+                Err(err) => {
+                    debug!("Closing inbound connection after invalid request: {}", err);
+                    sender.reactor.close(sender.id);
+                }
+            }
+        });
+        Ok(())
+    }
+}
+
+// the info hashes peers may ask for, the truncated v2 ones of hybrid torrents included
+fn served_info_hashes(torrents: &HashMap<Vec<u8>, ServedTorrent>) -> Vec<Vec<u8>> {
+    torrents
+        .values()
+        .flat_map(|torrent| torrent.metainfo.swarm_info_hashes())
+        .collect()
+}
+
+// the info_hash follows the protocol name and the 8 reserved bytes
+fn info_hash_of(handshake: &[u8]) -> Option<&[u8]> {
+    let start = 1 + *handshake.first()? as usize + 8;
+    handshake.get(start..start + 20)
+}
+
+fn lock_upload(
+    upload: &Mutex<UploadState>,
+) -> Result<MutexGuard<'_, UploadState>, IPeerMessageServiceError> {
+    upload.lock().map_err(|_| {
+        IPeerMessageServiceError::InvalidResponse("Upload state lock poisoned".to_string())
+    })
+}
+
+pub(super) fn log_upload_event(logger: &ServerLogger, event: UploadEvent) {
+    match event {
+        UploadEvent::BlockSent(piece_index, block_number) => {
+            let _ = logger.block_sent_succesfully(piece_index, block_number);
+        }
+        UploadEvent::BlockFailedToSend(piece_index, block_number) => {
+            let _ = logger.failed_sending_block(piece_index, block_number);
+        }
+        UploadEvent::MissingPiece(piece_index) => {
+            let _ = logger.client_doesnt_have_piece(piece_index);
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_info_hash_is_read_from_the_handshake() {
+        let info_hash = [7u8; 20];
+        let handshake = create_handshake_message(&info_hash, &[1u8; 20]);
+
+        assert_eq!(info_hash_of(&handshake), Some(&info_hash[..]));
+        assert_eq!(info_hash_of(&handshake[..30]), None);
+    }
+}
@@ -1,12 +1,14 @@
 mod acceptor;
+mod announcer;
 mod connection;
 mod constants;
 mod errors;
+mod inbound;
 mod logger;
 mod thread_pool;
 mod utils;
 
-pub use acceptor::Server;
+pub use acceptor::{ServedTorrent, Server, ServerSender};
 pub use connection::RequestMessage;
 pub use connection::ServerConnection;
 pub use constants::*;
@@ -16,3 +18,6 @@ use logger::*;
 pub use thread_pool::ThreadPool;
 pub use utils::client_has_piece;
 pub use utils::payload_from_request_message;
+pub use utils::{
+    get_block_from_piece, get_block_index, get_pieces_vector, read_piece, request_from_payload,
+};
@@ -0,0 +1,13 @@
+use std::time::Duration;
+
+/// How often the session starts queued torrents and forgets the ones that finished running
+pub const QUEUE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Subfolder of the watch folder where the torrent files already added are moved to
+pub const PROCESSED_FOLDER: &str = "processed";
+
+/// Subfolder of the watch folder where the files that aren't valid torrents are moved to
+pub const INVALID_FOLDER: &str = "invalid";
+
+/// Files modified this recently may still be being written, they are read on a later check
+pub const WATCH_FOLDER_SETTLE_TIME: Duration = Duration::from_secs(2);
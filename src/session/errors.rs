@@ -0,0 +1,69 @@
+use crate::application_errors::ApplicationError;
+use crate::config::ConfigError;
+use crate::magnet::MagnetError;
+use crate::metainfo::MetainfoParserError;
+use std::fmt;
+
+#[derive(Debug)]
+/// Errors that can occur when adding or removing the torrents of a session
+pub enum SessionError {
+    ConfigError(ConfigError),
+    MetainfoError(MetainfoParserError),
+    /// the magnet link is invalid, or the metadata of its torrent couldn't be fetched
+    MagnetError(MagnetError),
+    /// the torrent couldn't start running
+    ApplicationError(ApplicationError),
+    /// a torrent with the same info_hash was already added, holds its name
+    DuplicateTorrent(String),
+    /// no torrent of the session has the given info_hash
+    UnknownTorrent,
+    /// the torrent can't be downloaded by this client, holds why
+    UnsupportedTorrent(String),
+    /// the watch folder couldn't be created or read
+    WatchFolderError(std::io::Error),
+    LockError,
+}
+
+impl From<ConfigError> for SessionError {
+    fn from(error: ConfigError) -> Self {
+        SessionError::ConfigError(error)
+    }
+}
+
+impl From<MetainfoParserError> for SessionError {
+    fn from(error: MetainfoParserError) -> Self {
+        SessionError::MetainfoError(error)
+    }
+}
+
+impl From<MagnetError> for SessionError {
+    fn from(error: MagnetError) -> Self {
+        SessionError::MagnetError(error)
+    }
+}
+
+impl From<ApplicationError> for SessionError {
+    fn from(error: ApplicationError) -> Self {
+        SessionError::ApplicationError(error)
+    }
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SessionError::ConfigError(error) => write!(f, "Config Error - {}", error),
+            SessionError::MetainfoError(error) => write!(f, "Metainfo Error - {}", error),
+            SessionError::MagnetError(error) => write!(f, "{}", error),
+            SessionError::ApplicationError(error) => write!(f, "{}", error),
+            SessionError::DuplicateTorrent(name) => {
+                write!(f, "Torrent {} was already added", name)
+            }
+            SessionError::UnknownTorrent => write!(f, "The torrent isn't part of the session"),
+            SessionError::UnsupportedTorrent(reason) => {
+                write!(f, "The torrent isn't supported: {}", reason)
+            }
+            SessionError::WatchFolderError(error) => write!(f, "Watch folder Error - {}", error),
+            SessionError::LockError => write!(f, "Session lock poisoned"),
+        }
+    }
+}
@@ -0,0 +1,9 @@
+mod constants;
+mod errors;
+mod types;
+mod watcher;
+
+pub use constants::*;
+pub use errors::SessionError;
+pub use types::{Session, SessionTorrent};
+pub use watcher::WatchFolder;
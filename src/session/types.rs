@@ -0,0 +1,545 @@
+use super::constants::QUEUE_CHECK_INTERVAL;
+use super::errors::SessionError;
+use super::watcher::WatchFolder;
+use crate::application::{start_torrent_in, RunningTorrent, TorrentContext};
+use crate::bandwidth::Bandwidth;
+use crate::client::{
+    generate_peer_id_from_config_path, ClientInfo, TorrentHandle, TorrentState, TorrentStats,
+};
+use crate::config::Config;
+use crate::constants::TIME_BETWEEN_ACCEPTS;
+use crate::lsd::LocalDiscovery;
+use crate::magnet::{download_metainfo, MagnetLink};
+use crate::metainfo::{MetaVersion, Metainfo};
+use crate::peer::ConnectionLimit;
+use crate::server::Server;
+use crate::ui::UIMessage;
+use gtk::glib;
+use log::*;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+
+/// Runs many torrents from one process.
+/// Every torrent shares the config, the peer_id, the server listening on the configured port,
+/// the local service discovery, the client wide rate limits and the cap on open connections.
+/// At most `max_active_downloads` torrents download at the same time, the rest wait in a queue
+/// and start as the others finish downloading or are removed.
+/// With a `watch_dir` configured, the torrent files dropped there are added as well.
+/// Torrents added from a magnet link are queued once their metadata is fetched from their peers
+pub struct Session {
+    config: Config,
+    context: TorrentContext,
+    server: Server,
+    local_discovery: LocalDiscovery,
+    torrents: Arc<Mutex<Torrents>>,
+    scheduler: (Sender<()>, JoinHandle<()>),
+}
+
+/// A running torrent of the session
+#[derive(Clone)]
+pub struct SessionTorrent {
+    pub metainfo: Metainfo,
+    pub handle: TorrentHandle,
+    pub stats: TorrentStats,
+}
+
+// the torrents of the session, running or waiting to
+struct Torrents {
+    peer_id: [u8; 20],
+    config: Config,
+    running: HashMap<Vec<u8>, RunningTorrent>,
+    queued: VecDeque<ClientInfo>,
+    // taken out of the queue and being started, without the lock, removing one cancels it
+    starting: HashSet<Vec<u8>>,
+    // magnet links whose metadata is being fetched, removing one cancels it
+    fetching: HashMap<Vec<u8>, MagnetLink>,
+    max_active_downloads: Option<usize>,
+    context: TorrentContext,
+    ui_message_sender: Option<glib::Sender<UIMessage>>,
+}
+
+impl Session {
+    /// Reads the config and starts listening for peers, without any torrent
+    pub fn new(
+        config_path: &str,
+        ui_message_sender: Option<glib::Sender<UIMessage>>,
+    ) -> Result<Session, SessionError> {
+        let config = Config::from_path(config_path)?;
+        let peer_id = generate_peer_id_from_config_path(config_path);
+        let connections = ConnectionLimit::new(config.max_connections);
+        let server = Server::listen(
+            peer_id.to_vec(),
+            config.listen_port,
+            TIME_BETWEEN_ACCEPTS,
+            connections.clone(),
+            config.encryption,
+            config.transport,
+        );
+        let local_discovery = LocalDiscovery::start(config.listen_port);
+        let context = TorrentContext {
+            server: server.sender(),
+            local_discovery: local_discovery.sender(),
+            bandwidth: Bandwidth::global(&config),
+            connections,
+        };
+        let watch_folder = config
+            .watch_dir
+            .as_deref()
+            .map(WatchFolder::new)
+            .transpose()
+            .map_err(SessionError::WatchFolderError)?;
+        let torrents = Arc::new(Mutex::new(Torrents {
+            peer_id,
+            config: config.clone(),
+            running: HashMap::new(),
+            queued: VecDeque::new(),
+            starting: HashSet::new(),
+            fetching: HashMap::new(),
+            max_active_downloads: config.max_active_downloads,
+            context: context.clone(),
+            ui_message_sender,
+        }));
+
+        Ok(Session {
+            config,
+            context,
+            server,
+            local_discovery,
+            scheduler: Self::run_scheduler(torrents.clone(), watch_folder),
+            torrents,
+        })
+    }
+
+    // every so often, adds the torrents dropped in the watch folder,
+    // forgets the torrents that stopped and starts the queued ones there is room for
+    fn run_scheduler(
+        torrents: Arc<Mutex<Torrents>>,
+        watch_folder: Option<WatchFolder>,
+    ) -> (Sender<()>, JoinHandle<()>) {
+        let (stop_sender, stop_receiver) = mpsc::channel();
+        let handle = std::thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) =
+                stop_receiver.recv_timeout(QUEUE_CHECK_INTERVAL)
+            {
+                if let Some(watch_folder) = &watch_folder {
+                    add_watched_torrents(watch_folder, &torrents);
+                }
+                if let Ok(mut torrents) = torrents.lock() {
+                    torrents.remove_finished();
+                }
+                start_queued(&torrents);
+            }
+        });
+        (stop_sender, handle)
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Adds the torrent file at `torrent_path` and returns its info_hash.
+    /// It starts right away, unless `max_active_downloads` torrents are already downloading
+    pub fn add_torrent(&self, torrent_path: &str) -> Result<Vec<u8>, SessionError> {
+        let metainfo = Metainfo::from_torrent(torrent_path)?;
+        let info_hash = self.lock()?.add(metainfo)?;
+        start_queued(&self.torrents);
+        Ok(info_hash)
+    }
+
+    /// Adds the torrent of the magnet link and returns its info_hash.
+    /// Its metadata is fetched from its peers in a new thread, then it's queued like the torrent files are
+    pub fn add_magnet(&self, link: &str) -> Result<Vec<u8>, SessionError> {
+        let link = MagnetLink::parse(link)?;
+        let info_hash = link.info_hash.clone();
+        let peer_id = {
+            let mut torrents = self.lock()?;
+            if torrents.contains(&info_hash) {
+                return Err(SessionError::DuplicateTorrent(link.display_name()));
+            }
+            torrents.fetching.insert(info_hash.clone(), link.clone());
+            torrents.peer_id
+        };
+        info!("Fetching the metadata of {}", link.display_name());
+        let torrents = self.torrents.clone();
+        let config = self.config.clone();
+        std::thread::spawn(move || {
+            let still_wanted = || {
+                torrents
+                    .lock()
+                    .map(|torrents| torrents.fetching.contains_key(&link.info_hash))
+                    .unwrap_or(false)
+            };
+            let metainfo = download_metainfo(&link, peer_id, &config, still_wanted);
+            let added = {
+                let Ok(mut torrents) = torrents.lock() else {
+                    return;
+                };
+                // removed, or the session stopped, while its metadata was fetched
+                if torrents.fetching.remove(&link.info_hash).is_none() {
+                    return;
+                }
+                metainfo
+                    .map_err(SessionError::from)
+                    .and_then(|metainfo| torrents.add(metainfo))
+            };
+            match added {
+                Ok(_) => start_queued(&torrents),
+                Err(err) => {
+                    error!("Couldn't add magnet link {}", link.display_name());
+                    error!("{}", err);
+                }
+            }
+        });
+        Ok(info_hash)
+    }
+
+    /// Stops the torrent, or takes it out of the queue, and waits until it finished running.
+    /// A torrent that is still starting is stopped as soon as it started, without waiting for it
+    pub fn remove_torrent(&self, info_hash: &[u8]) -> Result<(), SessionError> {
+        let torrent = {
+            let mut torrents = self.lock()?;
+            if let Some(position) = torrents
+                .queued
+                .iter()
+                .position(|client_info| client_info.metainfo.info_hash == info_hash)
+            {
+                torrents.queued.remove(position);
+                return Ok(());
+            }
+            if torrents.starting.remove(info_hash) || torrents.fetching.remove(info_hash).is_some()
+            {
+                return Ok(());
+            }
+            torrents
+                .running
+                .remove(info_hash)
+                .ok_or(SessionError::UnknownTorrent)?
+        };
+        torrent.handle.stop();
+        torrent.join()?;
+        start_queued(&self.torrents);
+        Ok(())
+    }
+
+    /// Handle of the torrent with the given info_hash, None if it isn't running
+    pub fn handle(&self, info_hash: &[u8]) -> Option<TorrentHandle> {
+        let torrents = self.lock().ok()?;
+        torrents
+            .running
+            .get(info_hash)
+            .map(|torrent| torrent.handle.clone())
+    }
+
+    /// The running torrent with the given info_hash
+    pub fn torrent(&self, info_hash: &[u8]) -> Option<SessionTorrent> {
+        let torrents = self.lock().ok()?;
+        torrents.running.get(info_hash).map(SessionTorrent::from)
+    }
+
+    /// Every running torrent
+    pub fn running_torrents(&self) -> Vec<SessionTorrent> {
+        match self.lock() {
+            Ok(torrents) => torrents
+                .running
+                .values()
+                .map(SessionTorrent::from)
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    /// The metainfo of every torrent waiting to start, in the order they will
+    pub fn queued_torrents(&self) -> Vec<Metainfo> {
+        match self.lock() {
+            Ok(torrents) => torrents
+                .queued
+                .iter()
+                .map(|client_info| client_info.metainfo.clone())
+                .collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    /// The magnet links whose metadata is being fetched, before they are queued
+    pub fn fetching_torrents(&self) -> Vec<MagnetLink> {
+        match self.lock() {
+            Ok(torrents) => torrents.fetching.values().cloned().collect(),
+            Err(_) => vec![],
+        }
+    }
+
+    /// Whether no torrent is running nor queued
+    pub fn is_empty(&self) -> bool {
+        self.lock()
+            .map(|torrents| {
+                torrents.running.is_empty()
+                    && torrents.queued.is_empty()
+                    && torrents.starting.is_empty()
+                    && torrents.fetching.is_empty()
+            })
+            .unwrap_or(true)
+    }
+
+    /// Changes the client wide rate limits in bytes per second, `None` means unlimited
+    pub fn set_rate_limits(&self, max_upload_rate: Option<u64>, max_download_rate: Option<u64>) {
+        self.context
+            .bandwidth
+            .set_limits(max_upload_rate, max_download_rate);
+    }
+
+    /// Stops every torrent, waits until they finished running and stops the server and the local service discovery
+    pub fn stop(self) {
+        let (stop_scheduler, scheduler) = self.scheduler;
+        let _ = stop_scheduler.send(());
+        let _ = scheduler.join();
+
+        let running: Vec<RunningTorrent> = match self.torrents.lock() {
+            Ok(mut torrents) => {
+                torrents.queued.clear();
+                torrents.starting.clear();
+                torrents.fetching.clear();
+                torrents
+                    .running
+                    .drain()
+                    .map(|(_, torrent)| torrent)
+                    .collect()
+            }
+            Err(_) => vec![],
+        };
+        running.iter().for_each(|torrent| torrent.handle.stop());
+        for torrent in running {
+            if let Err(err) = torrent.join() {
+                error!("{}", err);
+            }
+        }
+        if let Err(err) = self.server.stop() {
+            error!("Server stopped with error: {}", err);
+        }
+        self.local_discovery.stop();
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Torrents>, SessionError> {
+        self.torrents.lock().map_err(|_| SessionError::LockError)
+    }
+}
+
+// starts the queued torrents there is room for. Opening their storage may hash every piece,
+// so it is done without holding the lock
+fn start_queued(torrents: &Mutex<Torrents>) {
+    let (startable, context, ui_message_sender) = match torrents.lock() {
+        Ok(mut torrents) => (
+            torrents.take_startable(),
+            torrents.context.clone(),
+            torrents.ui_message_sender.clone(),
+        ),
+        Err(_) => return,
+    };
+    for client_info in startable {
+        start(torrents, client_info, &context, ui_message_sender.clone());
+    }
+}
+
+// starts a torrent taken out of the queue, and stops it right away if it was removed meanwhile
+fn start(
+    torrents: &Mutex<Torrents>,
+    client_info: ClientInfo,
+    context: &TorrentContext,
+    ui_message_sender: Option<glib::Sender<UIMessage>>,
+) {
+    let info_hash = client_info.metainfo.info_hash.clone();
+    let name = client_info.metainfo.info.name.clone();
+    let torrent = match start_torrent_in(client_info, context, ui_message_sender) {
+        Ok(torrent) => torrent,
+        Err(err) => {
+            error!("Couldn't start torrent {}", name);
+            error!("{}", err);
+            if let Ok(mut torrents) = torrents.lock() {
+                torrents.starting.remove(&info_hash);
+            }
+            return;
+        }
+    };
+    let cancelled = match torrents.lock() {
+        Ok(mut torrents) => match torrents.starting.remove(&info_hash) {
+            true => {
+                torrents.running.insert(info_hash, torrent);
+                None
+            }
+            false => Some(torrent),
+        },
+        Err(_) => Some(torrent),
+    };
+    // removed, or the session stopped, while it was starting
+    if let Some(torrent) = cancelled {
+        torrent.handle.stop();
+        if let Err(err) = torrent.join() {
+            error!("{}", err);
+        }
+    }
+}
+
+// reads every torrent file dropped in the watch folder and moves it away, so it isn't read again
+fn add_watched_torrents(watch_folder: &WatchFolder, torrents: &Mutex<Torrents>) {
+    for path in watch_folder.new_torrents() {
+        let added = match Metainfo::from_torrent(&path.to_string_lossy()) {
+            Ok(metainfo) => match torrents.lock() {
+                Ok(mut torrents) => torrents.add(metainfo),
+                Err(_) => return,
+            },
+            Err(err) => {
+                error!(
+                    "Invalid torrent file {} in the watch folder",
+                    path.display()
+                );
+                error!("{}", err);
+                if let Err(err) = watch_folder.mark_invalid(&path) {
+                    error!("Couldn't move {}: {}", path.display(), err);
+                }
+                continue;
+            }
+        };
+        if let Err(err) = added {
+            info!("Not adding {}: {}", path.display(), err);
+        }
+        if let Err(err) = watch_folder.mark_processed(&path) {
+            error!("Couldn't move {}: {}", path.display(), err);
+        }
+    }
+}
+
+impl Torrents {
+    // queues the torrent, unless one with the same info_hash was already added
+    fn add(&mut self, metainfo: Metainfo) -> Result<Vec<u8>, SessionError> {
+        let info_hash = metainfo.info_hash.clone();
+        if self.contains(&info_hash) {
+            return Err(SessionError::DuplicateTorrent(metainfo.info.name));
+        }
+        // their files are stored piece aligned, without padding files telling where
+        if metainfo.version() == MetaVersion::V2 {
+            return Err(SessionError::UnsupportedTorrent(format!(
+                "{} is a v2 only torrent, only v1 and hybrid ones can be downloaded",
+                metainfo.info.name
+            )));
+        }
+
+        info!("Adding torrent {} to the session", metainfo.info.name);
+        self.queued.push_back(ClientInfo {
+            peer_id: self.peer_id,
+            config: self.config.clone(),
+            metainfo,
+        });
+        Ok(info_hash)
+    }
+
+    fn contains(&self, info_hash: &[u8]) -> bool {
+        self.running.contains_key(info_hash)
+            || self.starting.contains(info_hash)
+            || self.fetching.contains_key(info_hash)
+            || self
+                .queued
+                .iter()
+                .any(|client_info| client_info.metainfo.info_hash == info_hash)
+    }
+
+    // torrents still downloading or about to, paused ones don't take a place
+    fn active_downloads(&self) -> usize {
+        self.running
+            .values()
+            .filter(|torrent| torrent.handle.state() == TorrentState::Running)
+            .count()
+            + self.starting.len()
+    }
+
+    fn has_room(&self) -> bool {
+        self.max_active_downloads
+            .is_none_or(|max_active_downloads| self.active_downloads() < max_active_downloads)
+    }
+
+    // takes out of the queue the torrents there is room for, they count as active until started
+    fn take_startable(&mut self) -> Vec<ClientInfo> {
+        let mut startable = vec![];
+        while self.has_room() {
+            let Some(client_info) = self.queued.pop_front() else {
+                break;
+            };
+            self.starting.insert(client_info.metainfo.info_hash.clone());
+            startable.push(client_info);
+        }
+        startable
+    }
+
+    // forgets the torrents that stopped on their own, like the ones that reached a seeding limit
+    fn remove_finished(&mut self) {
+        let finished: Vec<Vec<u8>> = self
+            .running
+            .iter()
+            .filter(|(_, torrent)| torrent.is_finished())
+            .map(|(info_hash, _)| info_hash.clone())
+            .collect();
+        for info_hash in finished {
+            if let Some(torrent) = self.running.remove(&info_hash) {
+                if let Err(err) = torrent.join() {
+                    error!("{}", err);
+                }
+            }
+        }
+    }
+}
+
+impl From<&RunningTorrent> for SessionTorrent {
+    fn from(torrent: &RunningTorrent) -> Self {
+        Self {
+            metainfo: torrent.metainfo.clone(),
+            handle: torrent.handle.clone(),
+            stats: torrent.stats.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metainfo::Info;
+    use std::net::TcpListener;
+
+    const CONFIG_PATH: &str = "src/config/test_files/session_config.txt";
+
+    #[test]
+    fn a_torrent_removed_while_starting_is_stopped_once_started() {
+        let session = Session::new(CONFIG_PATH, None).unwrap();
+        // nothing listens there, so the torrent finds no peer
+        let tracker = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let metainfo = Metainfo {
+            info: Info {
+                piece_length: 8,
+                pieces: vec![vec![0; 20]],
+                name: "starting".to_string(),
+                length: 8,
+                ..Default::default()
+            },
+            info_hash: vec![7; 20],
+            announce: format!("http://{}/announce", tracker),
+            ..Default::default()
+        };
+        let info_hash = session.lock().unwrap().add(metainfo).unwrap();
+        let mut startable = session.lock().unwrap().take_startable();
+        assert_eq!(startable.len(), 1);
+        let mut client_info = startable.remove(0);
+        let download_dir = std::env::temp_dir().join("session_starting");
+        client_info.config.download_path = download_dir.to_string_lossy().to_string();
+
+        session.remove_torrent(&info_hash).unwrap();
+        assert!(session.is_empty());
+        start(&session.torrents, client_info, &session.context, None);
+
+        assert!(session.is_empty());
+        assert!(session.torrent(&info_hash).is_none());
+        let _ = std::fs::remove_dir_all(download_dir);
+        session.stop();
+    }
+}
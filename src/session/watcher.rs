@@ -0,0 +1,105 @@
+use super::constants::*;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+const TORRENT_EXTENSION: &str = "torrent";
+
+/// A directory where torrent files are dropped to be added to the session.
+/// Once read, each file is moved to the `processed` subfolder, or to `invalid` if it couldn't be parsed,
+/// so it's never read twice
+pub struct WatchFolder {
+    dir: PathBuf,
+}
+
+impl WatchFolder {
+    /// Creates the directory and its subfolders if they don't exist yet
+    pub fn new(dir: &str) -> io::Result<Self> {
+        let dir = PathBuf::from(dir);
+        fs::create_dir_all(dir.join(PROCESSED_FOLDER))?;
+        fs::create_dir_all(dir.join(INVALID_FOLDER))?;
+        Ok(Self { dir })
+    }
+
+    /// The .torrent files waiting to be added, in name order.
+    /// Files still being written, the ones modified within `WATCH_FOLDER_SETTLE_TIME`, are left for later
+    pub fn new_torrents(&self) -> Vec<PathBuf> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+        let mut torrents: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_type().is_ok_and(|file_type| file_type.is_file()))
+            .filter(|entry| {
+                entry
+                    .metadata()
+                    .and_then(|metadata| metadata.modified())
+                    .is_ok_and(is_settled)
+            })
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == TORRENT_EXTENSION)
+            })
+            .collect();
+        torrents.sort();
+        torrents
+    }
+
+    /// Moves the file to the `processed` subfolder
+    pub fn mark_processed(&self, path: &Path) -> io::Result<PathBuf> {
+        self.move_to(path, PROCESSED_FOLDER)
+    }
+
+    /// Moves the file to the `invalid` subfolder
+    pub fn mark_invalid(&self, path: &Path) -> io::Result<PathBuf> {
+        self.move_to(path, INVALID_FOLDER)
+    }
+
+    fn move_to(&self, path: &Path, subfolder: &str) -> io::Result<PathBuf> {
+        let file_name = path
+            .file_name()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Not a file"))?;
+        let destination = self.dir.join(subfolder).join(file_name);
+        fs::rename(path, &destination)?;
+        Ok(destination)
+    }
+}
+
+fn is_settled(modified: SystemTime) -> bool {
+    modified
+        .elapsed()
+        .is_ok_and(|elapsed| elapsed >= WATCH_FOLDER_SETTLE_TIME)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_picks_settled_torrent_files_and_moves_them_away() {
+        let dir = std::env::temp_dir().join(format!("watch_folder_test_{}", std::process::id()));
+        let watch_folder = WatchFolder::new(dir.to_str().unwrap()).unwrap();
+        let torrent = dir.join("a.torrent");
+        fs::write(&torrent, b"not bencoded").unwrap();
+        fs::write(dir.join("notes.txt"), b"").unwrap();
+
+        assert!(watch_folder.new_torrents().is_empty());
+        let old = SystemTime::now() - WATCH_FOLDER_SETTLE_TIME * 2;
+        fs::File::options()
+            .write(true)
+            .open(&torrent)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        assert_eq!(watch_folder.new_torrents(), vec![torrent.clone()]);
+
+        let moved = watch_folder.mark_invalid(&torrent).unwrap();
+        assert_eq!(moved, dir.join(INVALID_FOLDER).join("a.torrent"));
+        assert!(watch_folder.new_torrents().is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,7 @@
+use std::time::Duration;
+
+/// How often the piece saver writes the resume file, while downloading and seeding
+pub const RESUME_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Name of the resume file, kept next to the target files of each torrent
+pub const RESUME_FILE_NAME: &str = "resume";
@@ -0,0 +1,61 @@
+use crate::bencode::BencodeDecoderError;
+use crate::download_manager::DownloadManagerError;
+use std::fmt;
+
+#[derive(Debug)]
+/// Error type for the storages where the pieces of a torrent are kept
+pub enum StorageError {
+    IoError(std::io::Error),
+
+    /// The legacy piece files couldn't be saved or joined
+    DownloadManagerError(DownloadManagerError),
+
+    /// The piece with the given index isn't stored, or doesn't exist in the torrent
+    MissingPiece(u32),
+
+    /// The piece with the given index doesn't have the size the torrent says it has
+    InvalidPieceSize(u32),
+
+    /// The block asked for starts past the end of the piece, holds the piece index and the offset
+    BlockOutOfPiece(u32, usize),
+
+    /// The resume file couldn't be parsed, holds what was wrong with it
+    InvalidResumeData(String),
+}
+
+impl From<std::io::Error> for StorageError {
+    fn from(error: std::io::Error) -> Self {
+        StorageError::IoError(error)
+    }
+}
+
+impl From<DownloadManagerError> for StorageError {
+    fn from(error: DownloadManagerError) -> Self {
+        StorageError::DownloadManagerError(error)
+    }
+}
+
+impl From<BencodeDecoderError> for StorageError {
+    fn from(error: BencodeDecoderError) -> Self {
+        StorageError::InvalidResumeData(error.to_string())
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StorageError::IoError(error) => write!(f, "Storage IO error: {}", error),
+            StorageError::DownloadManagerError(error) => {
+                write!(f, "Piece files error: {}", error)
+            }
+            StorageError::MissingPiece(index) => write!(f, "Piece {} is not stored", index),
+            StorageError::InvalidPieceSize(index) => {
+                write!(f, "Piece {} doesn't have the expected size", index)
+            }
+            StorageError::BlockOutOfPiece(index, begin) => {
+                write!(f, "Block at offset {} is out of piece {}", begin, index)
+            }
+            StorageError::InvalidResumeData(cause) => write!(f, "Invalid resume data: {}", cause),
+        }
+    }
+}
@@ -0,0 +1,611 @@
+use super::errors::StorageError;
+use super::recheck::{recheck, RecheckProgress};
+use super::resume::{FileState, ResumeData};
+use super::types::IStorage;
+use crate::constants::BLOCK_SIZE;
+use crate::logger::CustomLogger;
+use crate::metainfo::Metainfo;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+const LOGGER: CustomLogger = CustomLogger::init("Storage");
+
+// Part of the torrent's data that lives in one of the target files
+#[derive(Debug)]
+struct FileSpan {
+    path: PathBuf,
+    offset: u64,
+    length: u64,
+}
+
+/// Storage that writes every piece straight into the target files at its offset.
+/// Each file is created with its final size (sparse where the filesystem allows it) when the first
+/// piece with data of it is written, so nothing has to be copied once the download ends,
+/// seeding reads from the same files, and files nobody asked for are never created.
+/// Empty files hold no piece, so they are created as soon as the storage is opened.
+/// The blocks of pieces that aren't complete yet are written in place too, and tracked
+/// in blocks of `BLOCK_SIZE`, so an interrupted piece is resumed instead of downloaded again.
+///
+/// A single file torrent is stored at `<target_dir>/<name>`,
+/// and each file of a multi-file torrent at `<target_dir>/<name>/<path>`.
+#[derive(Debug)]
+pub struct FileStorage {
+    piece_length: u64,
+    piece_count: u32,
+    total_length: u64,
+    spans: Vec<FileSpan>,
+    // None until the file is first written to
+    files: Mutex<Vec<Option<File>>>,
+    completed: Mutex<Vec<bool>>,
+    // blocks written of each piece that isn't complete yet
+    partial: Mutex<BTreeMap<u32, Vec<bool>>>,
+}
+
+impl FileStorage {
+    /// Opens the target files of the torrent that already exist, fixing their size if it changed.
+    /// If the resume data still matches the files, the pieces it lists are taken as stored.
+    /// Otherwise pieces found in files that already had data are verified against their hashes,
+    /// so an interrupted download goes on from where it was
+    pub fn open(
+        target_dir: &str,
+        metainfo: &Metainfo,
+        resume: Option<&ResumeData>,
+    ) -> Result<Self, StorageError> {
+        let (storage, had_data) = Self::open_files(target_dir, metainfo)?;
+        match resume {
+            Some(resume) if resume.matches(storage.piece_count, &storage.file_states()?) => {
+                LOGGER.info_str("Resuming from the saved state of the pieces");
+                *storage.lock_completed()? = resume.pieces.clone();
+                *storage.lock_partial()? = resume
+                    .partial
+                    .iter()
+                    .filter(|(index, _)| resume.pieces.get(**index as usize) == Some(&false))
+                    .map(|(index, blocks)| (*index, blocks.clone()))
+                    .collect();
+            }
+            _ if had_data => storage.verify_pieces(&metainfo.info.pieces)?,
+            _ => {}
+        }
+        Ok(storage)
+    }
+
+    /// Opens the target files like [`open`](Self::open) does, but starts with no pieces stored
+    /// instead of finding out which ones are there
+    pub fn open_unchecked(target_dir: &str, metainfo: &Metainfo) -> Result<Self, StorageError> {
+        Ok(Self::open_files(target_dir, metainfo)?.0)
+    }
+
+    // also tells whether any of the files already had data
+    fn open_files(target_dir: &str, metainfo: &Metainfo) -> Result<(Self, bool), StorageError> {
+        let spans = spans_of(target_dir, metainfo);
+        let mut files = Vec::with_capacity(spans.len());
+        let mut had_data = false;
+        for span in &spans {
+            if !span.path.exists() && span.length > 0 {
+                files.push(None);
+                continue;
+            }
+            let file = open_span(span)?;
+            let current_length = file.metadata()?.len();
+            had_data |= current_length > 0;
+            if current_length != span.length {
+                file.set_len(span.length)?;
+            }
+            files.push(Some(file));
+        }
+
+        let storage = Self {
+            piece_length: metainfo.info.piece_length as u64,
+            piece_count: metainfo.get_piece_count(),
+            total_length: metainfo.info.length,
+            spans,
+            files: Mutex::new(files),
+            completed: Mutex::new(vec![false; metainfo.info.pieces.len()]),
+            partial: Mutex::new(BTreeMap::new()),
+        };
+        Ok((storage, had_data))
+    }
+
+    fn verify_pieces(&self, hashes: &[Vec<u8>]) -> Result<(), StorageError> {
+        LOGGER.info(format!("Verifying {} pieces found on disk", hashes.len()));
+        let report = recheck(self, hashes, |_: RecheckProgress| {})?;
+        LOGGER.info(format!(
+            "{} pieces were already downloaded",
+            report.completed_pieces().len()
+        ));
+        Ok(())
+    }
+
+    fn piece_offset(&self, index: u32) -> u64 {
+        index as u64 * self.piece_length
+    }
+
+    // the last piece is usually shorter than the rest
+    fn piece_size(&self, index: u32) -> usize {
+        let offset = self.piece_offset(index);
+        self.piece_length
+            .min(self.total_length.saturating_sub(offset)) as usize
+    }
+
+    fn lock_files(&self) -> Result<MutexGuard<'_, Vec<Option<File>>>, StorageError> {
+        self.files.lock().map_err(|_| poisoned())
+    }
+
+    fn lock_completed(&self) -> Result<MutexGuard<'_, Vec<bool>>, StorageError> {
+        self.completed.lock().map_err(|_| poisoned())
+    }
+
+    fn lock_partial(&self) -> Result<MutexGuard<'_, BTreeMap<u32, Vec<bool>>>, StorageError> {
+        self.partial.lock().map_err(|_| poisoned())
+    }
+
+    // the index of the block starting at `begin` and its size, if it is a whole block of the piece.
+    // Blocks are tracked in BLOCK_SIZE units, the last one of a piece may be shorter
+    fn block_of(&self, index: u32, begin: u32) -> Option<(usize, usize)> {
+        let piece_size = self.piece_size(index);
+        let begin = begin as usize;
+        let block_size = BLOCK_SIZE as usize;
+        if index >= self.piece_count || !begin.is_multiple_of(block_size) || begin >= piece_size {
+            return None;
+        }
+        Some((begin / block_size, block_size.min(piece_size - begin)))
+    }
+
+    // reads the bytes at the given offset of the torrent, going through as many files as needed.
+    // The piece is only used for the error if some of those files wasn't created yet
+    fn read_at(&self, piece: u32, offset: u64, buf: &mut [u8]) -> Result<(), StorageError> {
+        let mut files = self.lock_files()?;
+        let mut done = 0;
+        for (file_index, file_offset, length) in self.spans_for(offset, buf.len()) {
+            let file = files[file_index]
+                .as_mut()
+                .ok_or(StorageError::MissingPiece(piece))?;
+            file.seek(SeekFrom::Start(file_offset))?;
+            file.read_exact(&mut buf[done..done + length])?;
+            done += length;
+        }
+        Ok(())
+    }
+
+    fn write_at(&self, offset: u64, data: &[u8]) -> Result<(), StorageError> {
+        let mut files = self.lock_files()?;
+        let mut done = 0;
+        for (file_index, file_offset, length) in self.spans_for(offset, data.len()) {
+            if files[file_index].is_none() {
+                let span = &self.spans[file_index];
+                let file = open_span(span)?;
+                file.set_len(span.length)?;
+                files[file_index] = Some(file);
+            }
+            // just created if it was missing
+            let file = files[file_index].as_mut().ok_or_else(poisoned)?;
+            file.seek(SeekFrom::Start(file_offset))?;
+            file.write_all(&data[done..done + length])?;
+            done += length;
+        }
+        Ok(())
+    }
+
+    // splits a range of the torrent into (file index, offset in the file, length) parts
+    fn spans_for(&self, offset: u64, length: usize) -> Vec<(usize, u64, usize)> {
+        let end = offset + length as u64;
+        self.spans
+            .iter()
+            .enumerate()
+            .filter(|(_, span)| span.offset < end && span.offset + span.length > offset)
+            .map(|(file_index, span)| {
+                let start = offset.max(span.offset);
+                let stop = end.min(span.offset + span.length);
+                (file_index, start - span.offset, (stop - start) as usize)
+            })
+            .collect()
+    }
+}
+
+impl IStorage for FileStorage {
+    fn piece_count(&self) -> u32 {
+        self.piece_count
+    }
+
+    fn has_piece(&self, index: u32) -> bool {
+        self.completed
+            .lock()
+            .map(|completed| completed.get(index as usize) == Some(&true))
+            .unwrap_or(false)
+    }
+
+    fn write_piece(&self, index: u32, data: &[u8]) -> Result<(), StorageError> {
+        if index >= self.piece_count() {
+            return Err(StorageError::MissingPiece(index));
+        }
+        if data.len() != self.piece_size(index) {
+            return Err(StorageError::InvalidPieceSize(index));
+        }
+        self.write_at(self.piece_offset(index), data)?;
+        self.lock_completed()?[index as usize] = true;
+        self.lock_partial()?.remove(&index);
+        Ok(())
+    }
+
+    // blocks that aren't a whole BLOCK_SIZE block of the piece can't be tracked, so they aren't kept
+    fn write_block(&self, index: u32, begin: u32, data: &[u8]) -> Result<(), StorageError> {
+        let Some((block, length)) = self.block_of(index, begin) else {
+            return Ok(());
+        };
+        if data.len() != length || self.has_piece(index) {
+            return Ok(());
+        }
+        self.write_at(self.piece_offset(index) + begin as u64, data)?;
+        let block_count = self.piece_size(index).div_ceil(BLOCK_SIZE as usize);
+        self.lock_partial()?
+            .entry(index)
+            .or_insert_with(|| vec![false; block_count])[block] = true;
+        Ok(())
+    }
+
+    fn read_written_block(&self, index: u32, begin: u32, length: u32) -> Option<Vec<u8>> {
+        let (block, block_length) = self.block_of(index, begin)?;
+        let written = self.lock_partial().ok()?.get(&index)?.get(block) == Some(&true);
+        if !written || length != BLOCK_SIZE {
+            return None;
+        }
+        let mut data = vec![0; block_length];
+        self.read_at(index, self.piece_offset(index) + begin as u64, &mut data)
+            .ok()?;
+        Some(data)
+    }
+
+    fn discard_blocks(&self, index: u32) {
+        if let Ok(mut partial) = self.lock_partial() {
+            partial.remove(&index);
+        }
+    }
+
+    fn partial_pieces(&self) -> BTreeMap<u32, Vec<bool>> {
+        self.lock_partial()
+            .map(|partial| partial.clone())
+            .unwrap_or_default()
+    }
+
+    fn read_block(&self, index: u32, begin: usize, length: usize) -> Result<Vec<u8>, StorageError> {
+        if !self.has_piece(index) {
+            return Err(StorageError::MissingPiece(index));
+        }
+        let piece_size = self.piece_size(index);
+        if begin >= piece_size {
+            return Err(StorageError::BlockOutOfPiece(index, begin));
+        }
+        let mut block = vec![0; length.min(piece_size - begin)];
+        self.read_at(index, self.piece_offset(index) + begin as u64, &mut block)?;
+        Ok(block)
+    }
+
+    fn read_piece(&self, index: u32) -> Result<Vec<u8>, StorageError> {
+        if index >= self.piece_count() {
+            return Err(StorageError::MissingPiece(index));
+        }
+        let mut piece = vec![0; self.piece_size(index)];
+        self.read_at(index, self.piece_offset(index), &mut piece)?;
+        Ok(piece)
+    }
+
+    fn set_completed(&self, pieces: &[bool]) -> Result<(), StorageError> {
+        let mut completed = self.lock_completed()?;
+        for (index, stored) in completed.iter_mut().enumerate() {
+            *stored = pieces.get(index) == Some(&true);
+        }
+        // nothing tells whether the blocks written before are still there
+        self.lock_partial()?.clear();
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<(), StorageError> {
+        for file in self.lock_files()?.iter().flatten() {
+            file.sync_all()?;
+        }
+        Ok(())
+    }
+
+    // the files are flushed first, so their modification time doesn't change after being read.
+    // Files not created yet are saved as empty
+    fn file_states(&self) -> Result<Vec<FileState>, StorageError> {
+        self.finish()?;
+        self.spans
+            .iter()
+            .map(|span| match span.path.exists() {
+                true => FileState::of(&span.path),
+                false => Ok(FileState {
+                    length: 0,
+                    modified: 0,
+                }),
+            })
+            .collect()
+    }
+}
+
+fn spans_of(target_dir: &str, metainfo: &Metainfo) -> Vec<FileSpan> {
+    let root = Path::new(target_dir);
+    match &metainfo.info.files {
+        Some(files) => {
+            let mut offset = 0;
+            files
+                .iter()
+                .map(|file| {
+                    let span = FileSpan {
+                        path: root.join(&metainfo.info.name).join(&file.path),
+                        offset,
+                        length: file.length,
+                    };
+                    offset += file.length;
+                    span
+                })
+                .collect()
+        }
+        None => vec![FileSpan {
+            path: root.join(&metainfo.info.name),
+            offset: 0,
+            length: metainfo.info.length,
+        }],
+    }
+}
+
+fn open_span(span: &FileSpan) -> Result<File, StorageError> {
+    if let Some(parent) = span.path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&span.path)?)
+}
+
+fn poisoned() -> StorageError {
+    StorageError::IoError(std::io::Error::other("Storage lock poisoned"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metainfo::{File as MetainfoFile, Info};
+    use sha1::{Digest, Sha1};
+
+    fn sha1_of(data: &[u8]) -> Vec<u8> {
+        let mut hasher = Sha1::new();
+        hasher.update(data);
+        hasher.finalize().to_vec()
+    }
+
+    fn metainfo_of(data: &[u8], piece_length: usize, files: Option<Vec<MetainfoFile>>) -> Metainfo {
+        Metainfo {
+            announce: "".to_string(),
+            info_hash: vec![],
+            info: Info {
+                piece_length: piece_length as u32,
+                pieces: data.chunks(piece_length).map(sha1_of).collect(),
+                name: "storage_test".to_string(),
+                length: data.len() as u64,
+                files,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    fn target_dir(test_name: &str) -> String {
+        let dir = std::env::temp_dir().join(format!("file_storage_{}", test_name));
+        let _ = fs::remove_dir_all(&dir);
+        dir.to_string_lossy().to_string()
+    }
+
+    #[test]
+    fn target_file_is_preallocated_and_pieces_are_written_at_their_offset() {
+        let data: Vec<u8> = (0..20).collect();
+        let metainfo = metainfo_of(&data, 8, None);
+        let dir = target_dir("single");
+        let storage = FileStorage::open(&dir, &metainfo, None).unwrap();
+
+        let target = Path::new(&dir).join("storage_test");
+        assert!(!target.exists());
+
+        storage.write_piece(2, &data[16..]).unwrap();
+        assert_eq!(fs::metadata(&target).unwrap().len(), 20);
+        storage.write_piece(0, &data[..8]).unwrap();
+
+        assert_eq!(storage.completed_pieces(), vec![0, 2]);
+        assert_eq!(storage.read_block(0, 4, 8).unwrap(), data[4..8].to_vec());
+        assert_eq!(storage.read_block(2, 0, 8).unwrap(), data[16..].to_vec());
+        assert!(matches!(
+            storage.read_block(1, 0, 8),
+            Err(StorageError::MissingPiece(1))
+        ));
+        assert_eq!(fs::read(&target).unwrap()[16..], data[16..]);
+    }
+
+    #[test]
+    fn pieces_spanning_several_files_are_split_between_them() {
+        let data: Vec<u8> = (0..16).collect();
+        let files = vec![
+            MetainfoFile {
+                path: "a".to_string(),
+                length: 5,
+                ..Default::default()
+            },
+            MetainfoFile {
+                path: "dir/b".to_string(),
+                length: 11,
+                ..Default::default()
+            },
+        ];
+        let metainfo = metainfo_of(&data, 8, Some(files));
+        let dir = target_dir("multi");
+        let storage = FileStorage::open(&dir, &metainfo, None).unwrap();
+
+        storage.write_piece(0, &data[..8]).unwrap();
+        storage.write_piece(1, &data[8..]).unwrap();
+        storage.finish().unwrap();
+
+        let root = Path::new(&dir).join("storage_test");
+        assert_eq!(fs::read(root.join("a")).unwrap(), data[..5].to_vec());
+        assert_eq!(fs::read(root.join("dir/b")).unwrap(), data[5..].to_vec());
+        assert_eq!(storage.read_block(0, 3, 4).unwrap(), data[3..7].to_vec());
+    }
+
+    #[test]
+    fn files_without_written_pieces_are_not_created() {
+        let data: Vec<u8> = (0..24).collect();
+        let files = vec![
+            MetainfoFile {
+                path: "wanted".to_string(),
+                length: 10,
+                ..Default::default()
+            },
+            MetainfoFile {
+                path: "straddled".to_string(),
+                length: 6,
+                ..Default::default()
+            },
+            MetainfoFile {
+                path: "skipped".to_string(),
+                length: 8,
+                ..Default::default()
+            },
+        ];
+        let metainfo = metainfo_of(&data, 8, Some(files));
+        let dir = target_dir("lazy");
+        let storage = FileStorage::open(&dir, &metainfo, None).unwrap();
+
+        storage.write_piece(0, &data[..8]).unwrap();
+        storage.write_piece(1, &data[8..16]).unwrap();
+
+        let root = Path::new(&dir).join("storage_test");
+        assert_eq!(fs::read(root.join("wanted")).unwrap(), data[..10].to_vec());
+        assert_eq!(
+            fs::read(root.join("straddled")).unwrap(),
+            data[10..16].to_vec()
+        );
+        assert!(!root.join("skipped").exists());
+        assert!(matches!(
+            storage.read_piece(2),
+            Err(StorageError::MissingPiece(2))
+        ));
+    }
+
+    #[test]
+    fn empty_files_are_created_when_opened() {
+        let data: Vec<u8> = (0..8).collect();
+        let files = vec![
+            MetainfoFile {
+                path: "data".to_string(),
+                length: 8,
+                ..Default::default()
+            },
+            MetainfoFile {
+                path: "dir/empty".to_string(),
+                length: 0,
+                ..Default::default()
+            },
+        ];
+        let metainfo = metainfo_of(&data, 8, Some(files));
+        let dir = target_dir("empty");
+
+        let storage = FileStorage::open(&dir, &metainfo, None).unwrap();
+
+        let root = Path::new(&dir).join("storage_test");
+        assert_eq!(fs::read(root.join("dir/empty")).unwrap(), Vec::<u8>::new());
+        assert!(!root.join("data").exists());
+        let states = storage.file_states().unwrap();
+        assert_eq!(states[1], FileState::of(&root.join("dir/empty")).unwrap());
+    }
+
+    #[test]
+    fn reopening_keeps_only_the_pieces_that_match_their_hash() {
+        let data: Vec<u8> = (0..24).collect();
+        let metainfo = metainfo_of(&data, 8, None);
+        let dir = target_dir("reopen");
+        let storage = FileStorage::open(&dir, &metainfo, None).unwrap();
+        storage.write_piece(1, &data[8..16]).unwrap();
+        storage.finish().unwrap();
+        drop(storage);
+
+        let storage = FileStorage::open(&dir, &metainfo, None).unwrap();
+
+        assert_eq!(storage.bitfield(), vec![false, true, false]);
+    }
+
+    #[test]
+    fn matching_resume_data_is_trusted_and_stale_one_is_rechecked() {
+        let data: Vec<u8> = (0..16).collect();
+        let metainfo = metainfo_of(&data, 8, None);
+        let dir = target_dir("resume");
+        let storage = FileStorage::open(&dir, &metainfo, None).unwrap();
+        storage.write_piece(0, &data[..8]).unwrap();
+        let resume = ResumeData {
+            pieces: vec![true, true],
+            files: storage.file_states().unwrap(),
+            ..ResumeData::default()
+        };
+        drop(storage);
+
+        let resumed = FileStorage::open(&dir, &metainfo, Some(&resume)).unwrap();
+        assert_eq!(resumed.bitfield(), vec![true, true]);
+        drop(resumed);
+
+        let stale = ResumeData {
+            files: vec![FileState {
+                length: 16,
+                modified: 0,
+            }],
+            ..resume
+        };
+        let rechecked = FileStorage::open(&dir, &metainfo, Some(&stale)).unwrap();
+        assert_eq!(rechecked.bitfield(), vec![true, false]);
+    }
+
+    #[test]
+    fn blocks_of_incomplete_pieces_are_kept_between_runs() {
+        let block = BLOCK_SIZE as usize;
+        let data: Vec<u8> = (0..3 * block + 100).map(|byte| byte as u8).collect();
+        let metainfo = metainfo_of(&data, 2 * block, None);
+        let dir = target_dir("blocks");
+        let storage = FileStorage::open(&dir, &metainfo, None).unwrap();
+        storage.write_block(0, 0, &data[..block]).unwrap();
+        storage
+            .write_block(1, BLOCK_SIZE, &data[3 * block..])
+            .unwrap();
+        // not a whole block, it can't be tracked
+        storage
+            .write_block(1, 0, &data[2 * block..2 * block + 10])
+            .unwrap();
+        let resume = ResumeData {
+            pieces: storage.bitfield(),
+            partial: storage.partial_pieces(),
+            files: storage.file_states().unwrap(),
+            ..ResumeData::default()
+        };
+        drop(storage);
+
+        let resumed = FileStorage::open(&dir, &metainfo, Some(&resume)).unwrap();
+        assert_eq!(
+            resumed.partial_pieces(),
+            BTreeMap::from([(0, vec![true, false]), (1, vec![false, true])])
+        );
+        assert_eq!(
+            resumed.read_written_block(0, 0, BLOCK_SIZE),
+            Some(data[..block].to_vec())
+        );
+        assert_eq!(
+            resumed.read_written_block(1, BLOCK_SIZE, BLOCK_SIZE),
+            Some(data[3 * block..].to_vec())
+        );
+        assert_eq!(resumed.read_written_block(0, BLOCK_SIZE, BLOCK_SIZE), None);
+
+        resumed.write_piece(0, &data[..2 * block]).unwrap();
+        resumed.discard_blocks(1);
+        assert!(resumed.partial_pieces().is_empty());
+    }
+}
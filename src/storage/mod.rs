@@ -0,0 +1,15 @@
+mod constants;
+mod errors;
+mod file;
+mod pieces;
+mod recheck;
+mod resume;
+mod types;
+
+pub use constants::*;
+pub use errors::StorageError;
+pub use file::FileStorage;
+pub use pieces::PieceFileStorage;
+pub use recheck::{recheck, RecheckProgress, RecheckReport};
+pub use resume::{FileState, ResumeData, ResumeWriter};
+pub use types::*;
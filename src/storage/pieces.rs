@@ -0,0 +1,101 @@
+use super::errors::StorageError;
+use super::types::IStorage;
+use crate::download_manager::{make_target_file, save_piece_in_disk, Piece};
+use std::path::Path;
+
+/// Legacy storage: every piece is a file named after its index inside `<download_dir>/pieces`,
+/// and they are copied one after the other into `<download_dir>/target/<target_name>` when the download ends
+#[derive(Debug, Clone)]
+pub struct PieceFileStorage {
+    download_dir: String,
+    piece_count: u32,
+    target_name: String,
+    persist_pieces: bool,
+}
+
+impl PieceFileStorage {
+    /// If `persist_pieces` is false the piece files are deleted once joined
+    pub fn new(
+        download_dir: &str,
+        piece_count: u32,
+        target_name: &str,
+        persist_pieces: bool,
+    ) -> Self {
+        Self {
+            download_dir: download_dir.to_string(),
+            piece_count,
+            target_name: target_name.to_string(),
+            persist_pieces,
+        }
+    }
+
+    fn pieces_dir(&self) -> String {
+        format!("{}/pieces", self.download_dir)
+    }
+
+    fn piece_path(&self, index: u32) -> String {
+        format!("{}/{}", self.pieces_dir(), index)
+    }
+}
+
+impl IStorage for PieceFileStorage {
+    fn piece_count(&self) -> u32 {
+        self.piece_count
+    }
+
+    fn has_piece(&self, index: u32) -> bool {
+        Path::new(&self.piece_path(index)).exists()
+    }
+
+    fn write_piece(&self, index: u32, data: &[u8]) -> Result<(), StorageError> {
+        let piece = Piece {
+            piece_number: index,
+            data: data.to_vec(),
+        };
+        save_piece_in_disk(&piece, &self.pieces_dir())?;
+        Ok(())
+    }
+
+    fn read_block(&self, index: u32, begin: usize, length: usize) -> Result<Vec<u8>, StorageError> {
+        let piece =
+            std::fs::read(self.piece_path(index)).map_err(|_| StorageError::MissingPiece(index))?;
+        if begin >= piece.len() {
+            return Err(StorageError::BlockOutOfPiece(index, begin));
+        }
+        let end = piece.len().min(begin + length);
+        Ok(piece[begin..end].to_vec())
+    }
+
+    fn read_piece(&self, index: u32) -> Result<Vec<u8>, StorageError> {
+        std::fs::read(self.piece_path(index)).map_err(|_| StorageError::MissingPiece(index))
+    }
+
+    // there is no bitfield apart from the piece files, so the bad ones are deleted
+    fn set_completed(&self, pieces: &[bool]) -> Result<(), StorageError> {
+        for (index, stored) in pieces.iter().enumerate() {
+            if !stored && self.has_piece(index as u32) {
+                std::fs::remove_file(self.piece_path(index as u32))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&self) -> Result<(), StorageError> {
+        let target_path = format!("{}/target/{}", self.download_dir, self.target_name);
+        // a persisted download was already joined by a previous run
+        if self.persist_pieces && Path::new(&target_path).exists() {
+            return Ok(());
+        }
+        // pieces of skipped files were never downloaded, so there is nothing to join them into
+        if self.completed_pieces().len() as u32 != self.piece_count {
+            return Ok(());
+        }
+        make_target_file(
+            self.piece_count,
+            &self.target_name,
+            &self.download_dir,
+            self.persist_pieces,
+        )?;
+        Ok(())
+    }
+}
@@ -0,0 +1,253 @@
+use super::errors::StorageError;
+use super::types::IStorage;
+use sha1::{Digest, Sha1};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+/// How far a recheck got, reported after every piece hashed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecheckProgress {
+    pub checked: usize,
+    pub total: usize,
+    /// pieces checked so far that matched their hash
+    pub valid: usize,
+}
+
+/// Outcome of hashing every piece on disk against the torrent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecheckReport {
+    /// whether each piece matched its hash, the bitfield the storage was left with
+    pub pieces: Vec<bool>,
+    /// pieces the storage had as stored that didn't match their hash, they have to be downloaded again
+    pub bad_pieces: Vec<u32>,
+}
+
+impl RecheckReport {
+    pub fn completed_pieces(&self) -> Vec<u32> {
+        self.pieces
+            .iter()
+            .enumerate()
+            .filter(|(_, valid)| **valid)
+            .map(|(index, _)| index as u32)
+            .collect()
+    }
+}
+
+/// Hashes every piece of the data on disk against `hashes` (the `pieces` of the torrent's info),
+/// spreading the pieces between as many threads as cores there are.
+/// `on_progress` is called from the calling thread after each piece.
+/// Once done, the storage is left holding only the pieces that matched
+pub fn recheck(
+    storage: &dyn IStorage,
+    hashes: &[Vec<u8>],
+    mut on_progress: impl FnMut(RecheckProgress),
+) -> Result<RecheckReport, StorageError> {
+    let stored_before = storage.bitfield();
+    let workers = thread::available_parallelism()
+        .map(|cores| cores.get())
+        .unwrap_or(1)
+        .min(hashes.len())
+        .max(1);
+    let next_piece = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel();
+
+    let pieces = thread::scope(|scope| {
+        for _ in 0..workers {
+            let sender = sender.clone();
+            let next_piece = &next_piece;
+            scope.spawn(move || loop {
+                let index = next_piece.fetch_add(1, Ordering::Relaxed);
+                if index >= hashes.len() {
+                    break;
+                }
+                let result = storage
+                    .read_piece(index as u32)
+                    .map(|piece| sha1_of(&piece) == hashes[index][..]);
+                if sender.send((index, result)).is_err() {
+                    break;
+                }
+            });
+        }
+        drop(sender);
+
+        let mut pieces = vec![false; hashes.len()];
+        let mut progress = RecheckProgress {
+            checked: 0,
+            total: hashes.len(),
+            valid: 0,
+        };
+        for (index, result) in receiver {
+            pieces[index] = match result {
+                Ok(valid) => valid,
+                // never written, so it just isn't there
+                Err(StorageError::MissingPiece(_)) => false,
+                Err(err) => {
+                    // makes the rest of the workers stop at their next piece
+                    next_piece.store(hashes.len(), Ordering::Relaxed);
+                    return Err(err);
+                }
+            };
+            progress.checked += 1;
+            progress.valid += pieces[index] as usize;
+            on_progress(progress);
+        }
+        Ok(pieces)
+    })?;
+
+    storage.set_completed(&pieces)?;
+    let bad_pieces = stored_before
+        .iter()
+        .zip(&pieces)
+        .enumerate()
+        .filter(|(_, (was_stored, valid))| **was_stored && !**valid)
+        .map(|(index, _)| index as u32)
+        .collect();
+    Ok(RecheckReport { pieces, bad_pieces })
+}
+
+fn sha1_of(data: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha1::new();
+    hasher.update(data);
+    hasher.finalize().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bandwidth::TorrentBandwidth;
+    use crate::metainfo::{File as MetainfoFile, Info, Metainfo};
+    use crate::storage::{FileStorage, PieceFileStorage, ResumeData, ResumeWriter, SharedStorage};
+    use std::fs;
+    use std::sync::Arc;
+
+    fn metainfo_of(data: &[u8], files: Option<Vec<MetainfoFile>>) -> Metainfo {
+        Metainfo {
+            info: Info {
+                piece_length: 8,
+                pieces: data.chunks(8).map(sha1_of).collect(),
+                name: "recheck".to_string(),
+                length: data.len() as u64,
+                files,
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    // downloads every piece and saves the resume data, the way a finished torrent leaves them
+    fn download(dir: &str, resume_path: &str, metainfo: &Metainfo, data: &[u8]) {
+        let storage: SharedStorage = Arc::new(FileStorage::open(dir, metainfo, None).unwrap());
+        for (index, piece) in data.chunks(8).enumerate() {
+            storage.write_piece(index as u32, piece).unwrap();
+        }
+        ResumeWriter::new(resume_path, storage, TorrentBandwidth::unlimited(), None)
+            .save()
+            .unwrap();
+    }
+
+    // rechecks the data on disk like `recheck_torrent` does, returning the resume data it leaves
+    fn recheck_on_disk(
+        dir: &str,
+        resume_path: &str,
+        metainfo: &Metainfo,
+    ) -> (RecheckReport, ResumeData) {
+        let previous = ResumeData::load(resume_path).unwrap();
+        let storage: SharedStorage = Arc::new(FileStorage::open_unchecked(dir, metainfo).unwrap());
+        storage.set_completed(&previous.pieces).unwrap();
+        let report = recheck(storage.as_ref(), &metainfo.info.pieces, |_| {}).unwrap();
+        ResumeWriter::new(
+            resume_path,
+            storage,
+            TorrentBandwidth::unlimited(),
+            Some(&previous),
+        )
+        .save()
+        .unwrap();
+        (report, ResumeData::load(resume_path).unwrap())
+    }
+
+    fn test_dir(name: &str) -> (String, String) {
+        let dir = std::env::temp_dir().join(name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let resume_path = dir.join("resume").to_string_lossy().to_string();
+        (dir.to_string_lossy().to_string(), resume_path)
+    }
+
+    #[test]
+    fn bad_and_missing_pieces_are_dropped_from_the_storage() {
+        let dir = std::env::temp_dir().join("recheck_pieces");
+        let _ = fs::remove_dir_all(&dir);
+        let storage = PieceFileStorage::new(&dir.to_string_lossy(), 4, "target", true);
+        let pieces: Vec<Vec<u8>> = (0..4).map(|index| vec![index; 8]).collect();
+        let hashes: Vec<Vec<u8>> = pieces.iter().map(|piece| sha1_of(piece)).collect();
+        storage.write_piece(0, &pieces[0]).unwrap();
+        storage.write_piece(1, &[9; 8]).unwrap();
+        storage.write_piece(3, &pieces[3]).unwrap();
+
+        let mut progress = vec![];
+        let report = recheck(&storage, &hashes, |update| progress.push(update)).unwrap();
+
+        assert_eq!(report.pieces, vec![true, false, false, true]);
+        assert_eq!(report.bad_pieces, vec![1]);
+        assert_eq!(storage.completed_pieces(), vec![0, 3]);
+        assert_eq!(progress.len(), 4);
+        assert_eq!(
+            progress.last(),
+            Some(&RecheckProgress {
+                checked: 4,
+                total: 4,
+                valid: 2
+            })
+        );
+    }
+
+    #[test]
+    fn a_corrupted_piece_is_dropped_from_the_storage_and_the_resume_data() {
+        let (dir, resume_path) = test_dir("recheck_corrupted");
+        let data: Vec<u8> = (0..24).collect();
+        let metainfo = metainfo_of(&data, None);
+        download(&dir, &resume_path, &metainfo, &data);
+        let target = std::path::Path::new(&dir).join("recheck");
+        let mut corrupted = fs::read(&target).unwrap();
+        corrupted[10] ^= 0xff;
+        fs::write(&target, corrupted).unwrap();
+
+        let (report, resume) = recheck_on_disk(&dir, &resume_path, &metainfo);
+
+        assert_eq!(report.pieces, vec![true, false, true]);
+        assert_eq!(report.bad_pieces, vec![1]);
+        assert_eq!(resume.pieces, vec![true, false, true]);
+        // the next run trusts the resume data, so the piece is downloaded again
+        let reopened = FileStorage::open(&dir, &metainfo, Some(&resume)).unwrap();
+        assert_eq!(reopened.completed_pieces(), vec![0, 2]);
+    }
+
+    #[test]
+    fn pieces_of_missing_or_short_files_are_dropped() {
+        let (dir, resume_path) = test_dir("recheck_missing_files");
+        let data: Vec<u8> = (0..24).collect();
+        let files = ["a", "b", "c"]
+            .iter()
+            .map(|path| MetainfoFile {
+                path: path.to_string(),
+                length: 8,
+                ..Default::default()
+            })
+            .collect();
+        let metainfo = metainfo_of(&data, Some(files));
+        download(&dir, &resume_path, &metainfo, &data);
+        let root = std::path::Path::new(&dir).join("recheck");
+        fs::remove_file(root.join("b")).unwrap();
+        fs::write(root.join("c"), &data[16..19]).unwrap();
+
+        let (report, resume) = recheck_on_disk(&dir, &resume_path, &metainfo);
+
+        assert_eq!(report.pieces, vec![true, false, false]);
+        assert_eq!(report.bad_pieces, vec![1, 2]);
+        assert_eq!(resume.pieces, vec![true, false, false]);
+        assert!(!root.join("b").exists());
+        assert_eq!(fs::metadata(root.join("c")).unwrap().len(), 8);
+    }
+}
@@ -0,0 +1,291 @@
+use super::errors::StorageError;
+use super::types::SharedStorage;
+use crate::bandwidth::TorrentBandwidth;
+use crate::bencode::{decode, encode, BencodeDecodedValue};
+use crate::peer::{bitmap_from_pieces_vector, Bitfield};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+const PIECES: &[u8] = b"pieces";
+const PIECE_COUNT: &[u8] = b"piece count";
+const FILES: &[u8] = b"files";
+const LENGTH: &[u8] = b"length";
+const MODIFIED: &[u8] = b"mtime";
+const UPLOADED: &[u8] = b"uploaded";
+const DOWNLOADED: &[u8] = b"downloaded";
+const PARTIAL: &[u8] = b"partial";
+const PIECE: &[u8] = b"piece";
+const BLOCKS: &[u8] = b"blocks";
+const BLOCK_COUNT: &[u8] = b"block count";
+
+/// Size and last modification of a file holding pieces,
+/// used to tell whether it changed since the resume file was written
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileState {
+    pub length: u64,
+    /// nanoseconds since the unix epoch
+    pub modified: i64,
+}
+
+impl FileState {
+    pub fn of(path: &Path) -> Result<Self, StorageError> {
+        let metadata = std::fs::metadata(path)?;
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .map(|since_epoch| since_epoch.as_nanos() as i64)
+            .unwrap_or(0);
+        Ok(Self {
+            length: metadata.len(),
+            modified,
+        })
+    }
+}
+
+/// State of a torrent saved between runs, so the client doesn't have to hash
+/// every piece on disk again to know which ones it has, nor download again the blocks it already wrote.
+/// It is stored bencoded in the resume file of the torrent.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ResumeData {
+    /// whether each piece was stored
+    pub pieces: Vec<bool>,
+    /// state of the files the pieces were stored in when the data was saved
+    pub files: Vec<FileState>,
+    /// bytes uploaded adding up every session
+    pub uploaded: u64,
+    /// bytes downloaded adding up every session
+    pub downloaded: u64,
+    /// which blocks were written of each piece that wasn't complete yet
+    pub partial: BTreeMap<u32, Vec<bool>>,
+}
+
+impl ResumeData {
+    pub fn load(path: &str) -> Result<Self, StorageError> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    /// Writes the data to a temporary file first, so a crash while saving doesn't leave a broken resume file
+    pub fn save(&self, path: &str) -> Result<(), StorageError> {
+        let temporary_path = format!("{}.tmp", path);
+        std::fs::write(&temporary_path, self.to_bytes())?;
+        std::fs::rename(&temporary_path, path)?;
+        Ok(())
+    }
+
+    /// Whether the data still describes the files: same amount of pieces, and the files
+    /// have the same size and modification time they had when it was saved
+    pub fn matches(&self, piece_count: u32, files: &[FileState]) -> bool {
+        self.pieces.len() == piece_count as usize && !files.is_empty() && self.files == files
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let files = self
+            .files
+            .iter()
+            .map(|file| {
+                BencodeDecodedValue::Dictionary(HashMap::from([
+                    (LENGTH.to_vec(), integer(file.length)),
+                    (
+                        MODIFIED.to_vec(),
+                        BencodeDecodedValue::Integer(file.modified),
+                    ),
+                ]))
+            })
+            .collect();
+        let partial = self
+            .partial
+            .iter()
+            .map(|(index, blocks)| {
+                BencodeDecodedValue::Dictionary(HashMap::from([
+                    (PIECE.to_vec(), integer(*index as u64)),
+                    (
+                        BLOCKS.to_vec(),
+                        BencodeDecodedValue::String(bitmap_from_pieces_vector(blocks)),
+                    ),
+                    (BLOCK_COUNT.to_vec(), integer(blocks.len() as u64)),
+                ]))
+            })
+            .collect();
+        let dictionary = HashMap::from([
+            (
+                PIECES.to_vec(),
+                BencodeDecodedValue::String(bitmap_from_pieces_vector(&self.pieces)),
+            ),
+            (PIECE_COUNT.to_vec(), integer(self.pieces.len() as u64)),
+            (FILES.to_vec(), BencodeDecodedValue::List(files)),
+            (UPLOADED.to_vec(), integer(self.uploaded)),
+            (DOWNLOADED.to_vec(), integer(self.downloaded)),
+            (PARTIAL.to_vec(), BencodeDecodedValue::List(partial)),
+        ]);
+        encode(&BencodeDecodedValue::Dictionary(dictionary))
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, StorageError> {
+        let decoded = decode(bytes)?;
+        let dictionary = decoded.get_as_dictionary()?;
+
+        let mut bitfield = Bitfield::new();
+        bitfield.set_bitfield(get(dictionary, PIECES)?.get_as_string()?);
+        let piece_count = get_u64(dictionary, PIECE_COUNT)? as usize;
+        let pieces = (0..piece_count)
+            .map(|index| bitfield.has_piece(index))
+            .collect();
+
+        // resume files written before blocks were kept have no partial pieces
+        let mut partial = BTreeMap::new();
+        if let Some(pieces) = dictionary.get(PARTIAL) {
+            for piece in pieces.get_as_list()? {
+                let piece = piece.get_as_dictionary()?;
+                let mut blocks = Bitfield::new();
+                blocks.set_bitfield(get(piece, BLOCKS)?.get_as_string()?);
+                let block_count = get_u64(piece, BLOCK_COUNT)? as usize;
+                partial.insert(
+                    get_u64(piece, PIECE)? as u32,
+                    (0..block_count)
+                        .map(|index| blocks.has_piece(index))
+                        .collect(),
+                );
+            }
+        }
+
+        let mut files = vec![];
+        for file in get(dictionary, FILES)?.get_as_list()? {
+            let file = file.get_as_dictionary()?;
+            files.push(FileState {
+                length: get_u64(file, LENGTH)?,
+                modified: *get(file, MODIFIED)?.get_as_integer()?,
+            });
+        }
+
+        Ok(Self {
+            pieces,
+            files,
+            uploaded: get_u64(dictionary, UPLOADED)?,
+            downloaded: get_u64(dictionary, DOWNLOADED)?,
+            partial,
+        })
+    }
+}
+
+/// Keeps the resume file of a torrent up to date with its storage and the bytes transferred
+#[derive(Clone)]
+pub struct ResumeWriter {
+    path: String,
+    storage: SharedStorage,
+    bandwidth: TorrentBandwidth,
+    uploaded_before: u64,
+    downloaded_before: u64,
+}
+
+impl ResumeWriter {
+    /// `previous` is the data loaded when the torrent was opened, its totals are added to this session's
+    pub fn new(
+        path: &str,
+        storage: SharedStorage,
+        bandwidth: TorrentBandwidth,
+        previous: Option<&ResumeData>,
+    ) -> Self {
+        Self {
+            path: path.to_string(),
+            storage,
+            bandwidth,
+            uploaded_before: previous.map(|data| data.uploaded).unwrap_or(0),
+            downloaded_before: previous.map(|data| data.downloaded).unwrap_or(0),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), StorageError> {
+        // the pieces are taken before the files, so a piece written in between makes the files
+        // look modified and the data is rechecked instead of trusted
+        let pieces = self.storage.bitfield();
+        let partial = self.storage.partial_pieces();
+        let data = ResumeData {
+            pieces,
+            files: self.storage.file_states()?,
+            uploaded: self.uploaded_before + self.bandwidth.uploaded(),
+            downloaded: self.downloaded_before + self.bandwidth.downloaded(),
+            partial,
+        };
+        data.save(&self.path)
+    }
+}
+
+fn integer(value: u64) -> BencodeDecodedValue {
+    BencodeDecodedValue::Integer(value as i64)
+}
+
+fn get<'a>(
+    dictionary: &'a HashMap<Vec<u8>, BencodeDecodedValue>,
+    key: &[u8],
+) -> Result<&'a BencodeDecodedValue, StorageError> {
+    dictionary.get(key).ok_or_else(|| {
+        StorageError::InvalidResumeData(format!("missing {}", String::from_utf8_lossy(key)))
+    })
+}
+
+fn get_u64(
+    dictionary: &HashMap<Vec<u8>, BencodeDecodedValue>,
+    key: &[u8],
+) -> Result<u64, StorageError> {
+    let value = *get(dictionary, key)?.get_as_integer()?;
+    u64::try_from(value).map_err(|_| {
+        StorageError::InvalidResumeData(format!("negative {}", String::from_utf8_lossy(key)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resume_data_is_the_same_after_encoding_and_decoding() {
+        let data = ResumeData {
+            pieces: vec![
+                true, false, false, true, true, false, true, false, true, true,
+            ],
+            files: vec![
+                FileState {
+                    length: 100,
+                    modified: 1_650_000_000_123_456_789,
+                },
+                FileState {
+                    length: 3,
+                    modified: 7,
+                },
+            ],
+            uploaded: 4096,
+            downloaded: 65536,
+            partial: BTreeMap::from([(2, vec![true, false, true]), (7, vec![false; 9])]),
+        };
+
+        assert_eq!(ResumeData::from_bytes(&data.to_bytes()).unwrap(), data);
+    }
+
+    #[test]
+    fn data_without_pieces_is_invalid() {
+        let result = ResumeData::from_bytes(b"d8:uploadedi0ee");
+        assert!(matches!(result, Err(StorageError::InvalidResumeData(_))));
+    }
+
+    #[test]
+    fn data_of_modified_files_does_not_match() {
+        let file = FileState {
+            length: 10,
+            modified: 5,
+        };
+        let data = ResumeData {
+            pieces: vec![true, true],
+            files: vec![file.clone()],
+            ..ResumeData::default()
+        };
+        let modified = FileState {
+            modified: 6,
+            ..file.clone()
+        };
+
+        assert!(data.matches(2, &[file]));
+        assert!(!data.matches(2, &[modified]));
+        assert!(!data.matches(3, &data.files));
+    }
+}
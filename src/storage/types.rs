@@ -0,0 +1,149 @@
+use super::constants::RESUME_FILE_NAME;
+use super::errors::StorageError;
+use super::file::FileStorage;
+use super::pieces::PieceFileStorage;
+use super::resume::{FileState, ResumeData};
+use crate::config::Config;
+use crate::metainfo::Metainfo;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Where the pieces of a torrent are written to once verified, and read from when seeding.
+/// Implementations are shared by the piece saver and every connection uploading, so they synchronize themselves
+pub trait IStorage: Send + Sync {
+    fn piece_count(&self) -> u32;
+
+    fn has_piece(&self, index: u32) -> bool;
+
+    /// Writes a piece that was already verified against its hash
+    fn write_piece(&self, index: u32, data: &[u8]) -> Result<(), StorageError>;
+
+    /// Reads `length` bytes of the piece starting at `begin`.
+    /// The block is cut at the end of the piece if it goes past it
+    fn read_block(&self, index: u32, begin: usize, length: usize) -> Result<Vec<u8>, StorageError>;
+
+    /// Reads the whole piece as it is on disk, whether it was marked as stored or not,
+    /// so it can be checked against its hash
+    fn read_piece(&self, index: u32) -> Result<Vec<u8>, StorageError>;
+
+    /// Replaces which pieces are stored with the result of checking them.
+    /// Pieces marked as not stored are downloaded again
+    fn set_completed(&self, pieces: &[bool]) -> Result<(), StorageError>;
+
+    /// Called once every piece was downloaded, leaves the target files ready to be used
+    fn finish(&self) -> Result<(), StorageError>;
+
+    /// Writes a block of a piece that isn't complete yet, before it can be verified,
+    /// so it doesn't have to be downloaded again after a restart. Storages that can't keep blocks ignore it
+    fn write_block(&self, _index: u32, _begin: u32, _data: &[u8]) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    /// Reads back a block written with [`write_block`](Self::write_block), `None` if it wasn't
+    fn read_written_block(&self, _index: u32, _begin: u32, _length: u32) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Forgets the blocks written of a piece, called once the piece failed its hash check
+    fn discard_blocks(&self, _index: u32) {}
+
+    /// Which blocks were written of each piece that isn't complete yet, saved in the resume file
+    fn partial_pieces(&self) -> BTreeMap<u32, Vec<bool>> {
+        BTreeMap::new()
+    }
+
+    /// Size and modification time of the files the pieces are stored in, saved in the resume file.
+    /// Storages that aren't restored from the resume file return an empty list
+    fn file_states(&self) -> Result<Vec<FileState>, StorageError> {
+        Ok(vec![])
+    }
+
+    /// Indexes of the pieces currently stored
+    fn completed_pieces(&self) -> Vec<u32> {
+        (0..self.piece_count())
+            .filter(|index| self.has_piece(*index))
+            .collect()
+    }
+
+    /// Whether each piece is stored, ready to be sent in a bitfield message
+    fn bitfield(&self) -> Vec<bool> {
+        (0..self.piece_count())
+            .map(|index| self.has_piece(index))
+            .collect()
+    }
+}
+
+pub type SharedStorage = Arc<dyn IStorage>;
+
+/// Kind of storage the client keeps the pieces in, set with the `storage` key of the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum StorageKind {
+    /// The target files are created with their final size and every piece is written at its offset
+    #[default]
+    File,
+    /// Legacy layout: a file per piece, joined into the target file once the download is over
+    Pieces,
+}
+
+impl StorageKind {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim() {
+            "file" => Some(StorageKind::File),
+            "pieces" => Some(StorageKind::Pieces),
+            _ => None,
+        }
+    }
+}
+
+/// Opens the storage the config asks for, inside `<download_path>/<torrent name>`.
+/// The resume data loaded for the torrent, if any, saves checking the pieces already on disk
+pub fn open_storage(
+    config: &Config,
+    metainfo: &Metainfo,
+    resume: Option<&ResumeData>,
+) -> Result<SharedStorage, StorageError> {
+    let download_dir = download_dir(config, metainfo);
+    match config.storage {
+        StorageKind::File => Ok(Arc::new(FileStorage::open(
+            &format!("{}/target", download_dir),
+            metainfo,
+            resume,
+        )?)),
+        StorageKind::Pieces => Ok(Arc::new(PieceFileStorage::new(
+            &download_dir,
+            metainfo.get_piece_count(),
+            &metainfo.info.name,
+            config.persist_pieces,
+        ))),
+    }
+}
+
+/// Opens the storage without checking what's on disk, every piece starts as not stored.
+/// Meant to be followed by a [`recheck`](super::recheck)
+pub fn open_unchecked_storage(
+    config: &Config,
+    metainfo: &Metainfo,
+) -> Result<SharedStorage, StorageError> {
+    let download_dir = download_dir(config, metainfo);
+    match config.storage {
+        StorageKind::File => Ok(Arc::new(FileStorage::open_unchecked(
+            &format!("{}/target", download_dir),
+            metainfo,
+        )?)),
+        StorageKind::Pieces => Ok(Arc::new(PieceFileStorage::new(
+            &download_dir,
+            metainfo.get_piece_count(),
+            &metainfo.info.name,
+            config.persist_pieces,
+        ))),
+    }
+}
+
+/// Path of the resume file of the torrent
+pub fn resume_path(config: &Config, metainfo: &Metainfo) -> String {
+    format!("{}/{}", download_dir(config, metainfo), RESUME_FILE_NAME)
+}
+
+fn download_dir(config: &Config, metainfo: &Metainfo) -> String {
+    format!("{}/{}", config.download_path, metainfo.info.name)
+}
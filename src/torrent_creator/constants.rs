@@ -0,0 +1,11 @@
+/// Pieces are never shorter than this, a piece length is a power of two in bytes
+pub const MIN_PIECE_LENGTH: u32 = 16 * 1024;
+
+/// Pieces are never longer than this
+pub const MAX_PIECE_LENGTH: u32 = 16 * 1024 * 1024;
+
+/// The piece length is chosen so the torrent has about this many pieces
+pub const TARGET_PIECE_COUNT: u64 = 1500;
+
+/// Written as `created by` unless told otherwise
+pub const CREATED_BY: &str = concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION"));
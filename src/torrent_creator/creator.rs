@@ -0,0 +1,311 @@
+use super::constants::*;
+use super::errors::CreateTorrentError;
+use super::hasher::{hash_pieces, SourceFile};
+use super::types::TorrentOptions;
+use crate::bencode::{encode, BencodeDecodedValue};
+use crate::metainfo::{parse, Metainfo};
+use log::*;
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Hashes the file, or every file under the directory, and returns the bencoded torrent sharing them.
+/// The dictionaries are written with their keys sorted, so the info_hash of the torrent is the one
+/// [`parse`] computes from it
+///
+/// ## Example
+///
+/// ```
+/// use bittorrent_rustico::metainfo::parse;
+/// use bittorrent_rustico::torrent_creator::{create_torrent, TorrentOptions};
+///
+/// let options = TorrentOptions::new("http://tracker.example/announce").with_private(true);
+/// let torrent = create_torrent("example_torrents/sample.torrent", &options).unwrap();
+/// let metainfo = parse(&torrent).unwrap();
+/// assert_eq!(metainfo.info.name, "sample.torrent");
+/// ```
+pub fn create_torrent(
+    source: &str,
+    options: &TorrentOptions,
+) -> Result<Vec<u8>, CreateTorrentError> {
+    let source = Path::new(source);
+    let name = utf8_name(source)?;
+    let files = source_files(source)?;
+    let total_length: u64 = files.iter().map(|(file, _)| file.length).sum();
+    if total_length == 0 {
+        return Err(CreateTorrentError::EmptySource);
+    }
+    let piece_length = match options.piece_length {
+        Some(piece_length)
+            if piece_length < MIN_PIECE_LENGTH || !piece_length.is_power_of_two() =>
+        {
+            return Err(CreateTorrentError::InvalidPieceLength(piece_length))
+        }
+        Some(piece_length) => piece_length,
+        None => piece_length_for(total_length),
+    };
+
+    info!(
+        "Hashing {} bytes of {} in pieces of {} bytes",
+        total_length, name, piece_length
+    );
+    let source_files: Vec<SourceFile> = files.iter().map(|(file, _)| file.clone()).collect();
+    let pieces = hash_pieces(&source_files, piece_length as u64)?;
+
+    let mut info = HashMap::from([
+        (b"name".to_vec(), string(&name)),
+        (
+            b"piece length".to_vec(),
+            BencodeDecodedValue::Integer(piece_length as i64),
+        ),
+        (b"pieces".to_vec(), BencodeDecodedValue::String(pieces)),
+    ]);
+    if source.is_dir() {
+        let files = files
+            .into_iter()
+            .map(|(file, path)| {
+                BencodeDecodedValue::Dictionary(HashMap::from([
+                    (
+                        b"length".to_vec(),
+                        BencodeDecodedValue::Integer(file.length as i64),
+                    ),
+                    (
+                        b"path".to_vec(),
+                        BencodeDecodedValue::List(path.iter().map(|part| string(part)).collect()),
+                    ),
+                ]))
+            })
+            .collect();
+        info.insert(b"files".to_vec(), BencodeDecodedValue::List(files));
+    } else {
+        info.insert(
+            b"length".to_vec(),
+            BencodeDecodedValue::Integer(total_length as i64),
+        );
+    }
+    if options.private {
+        info.insert(b"private".to_vec(), BencodeDecodedValue::Integer(1));
+    }
+
+    let mut torrent = HashMap::from([
+        (b"announce".to_vec(), string(&options.announce)),
+        (b"created by".to_vec(), string(&options.created_by)),
+        (
+            b"creation date".to_vec(),
+            BencodeDecodedValue::Integer(options.creation_date.unwrap_or_else(now)),
+        ),
+        (b"info".to_vec(), BencodeDecodedValue::Dictionary(info)),
+    ]);
+    if !options.announce_list.is_empty() {
+        torrent.insert(b"announce-list".to_vec(), announce_list(options));
+    }
+    if let Some(comment) = &options.comment {
+        torrent.insert(b"comment".to_vec(), string(comment));
+    }
+    if !options.web_seeds.is_empty() {
+        let urls = options.web_seeds.iter().map(|url| string(url)).collect();
+        torrent.insert(b"url-list".to_vec(), BencodeDecodedValue::List(urls));
+    }
+    Ok(encode(&BencodeDecodedValue::Dictionary(torrent)))
+}
+
+/// Creates the torrent like [`create_torrent`] does and writes it to `torrent_path`.
+/// Returns the metainfo read back from it
+pub fn write_torrent(
+    source: &str,
+    torrent_path: &str,
+    options: &TorrentOptions,
+) -> Result<Metainfo, CreateTorrentError> {
+    let torrent = create_torrent(source, options)?;
+    let metainfo = parse(&torrent)?;
+    fs::write(torrent_path, torrent)?;
+    info!("Torrent {} written to {}", metainfo.info.name, torrent_path);
+    Ok(metainfo)
+}
+
+// the power of two giving about TARGET_PIECE_COUNT pieces, within the allowed lengths
+fn piece_length_for(total_length: u64) -> u32 {
+    (total_length / TARGET_PIECE_COUNT)
+        .next_power_of_two()
+        .clamp(MIN_PIECE_LENGTH as u64, MAX_PIECE_LENGTH as u64) as u32
+}
+
+// the file itself, or every file under the directory sorted by path, along with their path inside it
+fn source_files(source: &Path) -> Result<Vec<(SourceFile, Vec<String>)>, CreateTorrentError> {
+    if !source.is_dir() {
+        let file = SourceFile {
+            path: source.to_path_buf(),
+            length: fs::metadata(source)?.len(),
+        };
+        return Ok(vec![(file, vec![])]);
+    }
+    let mut files = vec![];
+    add_files_under(source, &mut vec![], &mut files)?;
+    Ok(files)
+}
+
+fn add_files_under(
+    dir: &Path,
+    parents: &mut Vec<String>,
+    files: &mut Vec<(SourceFile, Vec<String>)>,
+) -> Result<(), CreateTorrentError> {
+    let mut entries = fs::read_dir(dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+    for path in entries {
+        parents.push(utf8_name(&path)?);
+        if path.is_dir() {
+            add_files_under(&path, parents, files)?;
+        } else {
+            let file = SourceFile {
+                length: fs::metadata(&path)?.len(),
+                path,
+            };
+            files.push((file, parents.clone()));
+        }
+        parents.pop();
+    }
+    Ok(())
+}
+
+fn utf8_name(path: &Path) -> Result<String, CreateTorrentError> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.to_string())
+        .ok_or_else(|| CreateTorrentError::InvalidPath(path.to_string_lossy().to_string()))
+}
+
+// the announce URL goes in the first tier if no tier has it already
+fn announce_list(options: &TorrentOptions) -> BencodeDecodedValue {
+    let mut tiers = options.announce_list.clone();
+    if !tiers.iter().flatten().any(|url| *url == options.announce) {
+        tiers.insert(0, vec![options.announce.clone()]);
+    }
+    BencodeDecodedValue::List(
+        tiers
+            .iter()
+            .map(|tier| BencodeDecodedValue::List(tier.iter().map(|url| string(url)).collect()))
+            .collect(),
+    )
+}
+
+fn string(value: &str) -> BencodeDecodedValue {
+    BencodeDecodedValue::String(value.as_bytes().to_vec())
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bencode::decode;
+    use sha1::{Digest, Sha1};
+
+    const ANNOUNCE: &str = "http://tracker.example/announce";
+
+    fn source_dir(test_name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(test_name);
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("shared/nested")).unwrap();
+        dir.join("shared")
+    }
+
+    #[test]
+    fn pieces_span_the_files_of_a_directory_in_path_order() {
+        let source = source_dir("create_torrent_directory");
+        let first = vec![1; 20 * 1024];
+        let second = vec![2; 30 * 1024];
+        fs::write(source.join("b.bin"), &second).unwrap();
+        fs::write(source.join("nested/a.bin"), &first).unwrap();
+        fs::write(source.join("a.bin"), &first).unwrap();
+
+        let options = TorrentOptions::new(ANNOUNCE)
+            .with_piece_length(MIN_PIECE_LENGTH)
+            .with_creation_date(1);
+        let torrent = create_torrent(&source.to_string_lossy(), &options).unwrap();
+        let metainfo = parse(&torrent).unwrap();
+
+        let paths: Vec<String> = metainfo
+            .info
+            .files
+            .unwrap()
+            .into_iter()
+            .map(|file| file.path)
+            .collect();
+        assert_eq!(paths, vec!["a.bin", "b.bin", "nested/a.bin"]);
+        let data = [first.clone(), second, first].concat();
+        let hashes: Vec<Vec<u8>> = data
+            .chunks(MIN_PIECE_LENGTH as usize)
+            .map(|piece| Sha1::digest(piece).to_vec())
+            .collect();
+        assert_eq!(metainfo.info.pieces, hashes);
+        assert_eq!(metainfo.info.length, data.len() as u64);
+        assert_eq!(metainfo.info.name, "shared");
+    }
+
+    #[test]
+    fn writes_every_option_and_keeps_the_info_hash() {
+        let source = source_dir("create_torrent_options");
+        fs::write(source.join("file.bin"), vec![7; 1000]).unwrap();
+
+        let options = TorrentOptions::new(ANNOUNCE)
+            .with_tier(vec!["udp://backup.example:80".to_string()])
+            .with_comment("made for a test")
+            .with_private(true)
+            .with_web_seed("http://mirror.example/files/");
+        let torrent = create_torrent(&source.join("file.bin").to_string_lossy(), &options).unwrap();
+        let decoded = decode(&torrent).unwrap();
+        let dictionary = decoded.get_as_dictionary().unwrap();
+
+        assert_eq!(encode(&decoded), torrent);
+        assert_eq!(
+            parse(&torrent).unwrap().info_hash,
+            Sha1::digest(encode(&dictionary[&b"info".to_vec()])).to_vec()
+        );
+        let info = dictionary[&b"info".to_vec()].get_as_dictionary().unwrap();
+        assert_eq!(info[&b"private".to_vec()], BencodeDecodedValue::Integer(1));
+        assert_eq!(
+            info[&b"length".to_vec()],
+            BencodeDecodedValue::Integer(1000)
+        );
+        assert_eq!(
+            dictionary[&b"announce-list".to_vec()]
+                .get_as_list()
+                .unwrap()
+                .len(),
+            2
+        );
+        assert_eq!(dictionary[&b"comment".to_vec()], string("made for a test"));
+        assert!(dictionary.contains_key(&b"url-list".to_vec()));
+        assert!(dictionary.contains_key(&b"creation date".to_vec()));
+    }
+
+    #[test]
+    fn piece_length_grows_with_the_torrent() {
+        assert_eq!(piece_length_for(1000), MIN_PIECE_LENGTH);
+        assert_eq!(piece_length_for(1500 * 1024 * 1024), 1024 * 1024);
+        assert_eq!(piece_length_for(u64::MAX / 2), MAX_PIECE_LENGTH);
+    }
+
+    #[test]
+    fn empty_sources_and_invalid_piece_lengths_are_refused() {
+        let source = source_dir("create_torrent_empty");
+        let options = TorrentOptions::new(ANNOUNCE);
+        assert!(matches!(
+            create_torrent(&source.to_string_lossy(), &options),
+            Err(CreateTorrentError::EmptySource)
+        ));
+        fs::write(source.join("file.bin"), b"data").unwrap();
+        assert!(matches!(
+            create_torrent(&source.to_string_lossy(), &options.with_piece_length(1000)),
+            Err(CreateTorrentError::InvalidPieceLength(1000))
+        ));
+    }
+}
@@ -0,0 +1,44 @@
+use crate::metainfo::MetainfoParserError;
+use std::fmt;
+
+#[derive(Debug)]
+/// Errors that can occur when creating a torrent file
+pub enum CreateTorrentError {
+    IoError(std::io::Error),
+    /// the file or directory doesn't hold any byte to share
+    EmptySource,
+    /// the path can't be written in a torrent, because it isn't UTF-8 or has no name
+    InvalidPath(String),
+    /// the piece length given isn't a power of two of at least 16 KiB
+    InvalidPieceLength(u32),
+    /// the torrent written couldn't be read back
+    MetainfoError(MetainfoParserError),
+}
+
+impl From<std::io::Error> for CreateTorrentError {
+    fn from(error: std::io::Error) -> Self {
+        CreateTorrentError::IoError(error)
+    }
+}
+
+impl From<MetainfoParserError> for CreateTorrentError {
+    fn from(error: MetainfoParserError) -> Self {
+        CreateTorrentError::MetainfoError(error)
+    }
+}
+
+impl fmt::Display for CreateTorrentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CreateTorrentError::IoError(error) => write!(f, "IO Error - {}", error),
+            CreateTorrentError::EmptySource => write!(f, "There is no data to share"),
+            CreateTorrentError::InvalidPath(path) => {
+                write!(f, "Path {} can't be written in a torrent", path)
+            }
+            CreateTorrentError::InvalidPieceLength(length) => {
+                write!(f, "Invalid piece length {}", length)
+            }
+            CreateTorrentError::MetainfoError(error) => write!(f, "Metainfo Error - {}", error),
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use sha1::{Digest, Sha1};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+/// A file whose data goes in the torrent, in the order its bytes are laid out
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub length: u64,
+}
+
+/// Hashes the data of the files, one after the other, split in pieces of `piece_length` bytes.
+/// Pieces are spread between as many threads as cores there are.
+/// Returns the concatenated SHA-1 hashes, the way the `pieces` of an info dictionary holds them
+pub fn hash_pieces(files: &[SourceFile], piece_length: u64) -> io::Result<Vec<u8>> {
+    let total_length: u64 = files.iter().map(|file| file.length).sum();
+    let piece_count = total_length.div_ceil(piece_length) as usize;
+    let workers = thread::available_parallelism()
+        .map(|cores| cores.get())
+        .unwrap_or(1)
+        .min(piece_count)
+        .max(1);
+    let next_piece = AtomicUsize::new(0);
+
+    let hashed = thread::scope(|scope| {
+        let handles: Vec<_> = (0..workers)
+            .map(|_| {
+                let next_piece = &next_piece;
+                scope.spawn(move || {
+                    let mut reader = PieceReader::new(files);
+                    let mut hashes = vec![];
+                    loop {
+                        let index = next_piece.fetch_add(1, Ordering::Relaxed);
+                        if index >= piece_count {
+                            return Ok(hashes);
+                        }
+                        let offset = index as u64 * piece_length;
+                        let length = piece_length.min(total_length - offset) as usize;
+                        match reader.read(offset, length) {
+                            Ok(piece) => hashes.push((index, Sha1::digest(&piece).to_vec())),
+                            Err(err) => {
+                                // makes the rest of the workers stop at their next piece
+                                next_piece.store(piece_count, Ordering::Relaxed);
+                                return Err(err);
+                            }
+                        }
+                    }
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| {
+                handle
+                    .join()
+                    .unwrap_or_else(|_| Err(io::Error::other("Hashing thread panicked")))
+            })
+            .collect::<io::Result<Vec<_>>>()
+    })?;
+
+    let mut hashes = vec![vec![]; piece_count];
+    for (index, hash) in hashed.into_iter().flatten() {
+        hashes[index] = hash;
+    }
+    Ok(hashes.concat())
+}
+
+// reads bytes of the data going through as many files as needed, keeping the last file open
+struct PieceReader<'a> {
+    files: &'a [SourceFile],
+    open: Option<(usize, File)>,
+}
+
+impl<'a> PieceReader<'a> {
+    fn new(files: &'a [SourceFile]) -> Self {
+        Self { files, open: None }
+    }
+
+    fn read(&mut self, offset: u64, length: usize) -> io::Result<Vec<u8>> {
+        let mut piece = vec![0; length];
+        let mut read = 0;
+        let mut file_start = 0;
+        for (index, file) in self.files.iter().enumerate() {
+            let file_end = file_start + file.length;
+            let position = offset + read as u64;
+            if read < length && position < file_end {
+                let to_read = (length - read).min((file_end - position) as usize);
+                let handle = self.file(index)?;
+                handle.seek(SeekFrom::Start(position - file_start))?;
+                handle.read_exact(&mut piece[read..read + to_read])?;
+                read += to_read;
+            }
+            file_start = file_end;
+        }
+        Ok(piece)
+    }
+
+    fn file(&mut self, index: usize) -> io::Result<&mut File> {
+        if !matches!(&self.open, Some((open, _)) if *open == index) {
+            self.open = Some((index, File::open(&self.files[index].path)?));
+        }
+        match &mut self.open {
+            Some((_, file)) => Ok(file),
+            None => Err(io::Error::other("No file open")),
+        }
+    }
+}
@@ -0,0 +1,10 @@
+mod constants;
+mod creator;
+mod errors;
+mod hasher;
+mod types;
+
+pub use constants::*;
+pub use creator::{create_torrent, write_torrent};
+pub use errors::CreateTorrentError;
+pub use types::TorrentOptions;
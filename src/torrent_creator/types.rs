@@ -0,0 +1,71 @@
+use super::constants::CREATED_BY;
+
+/// What goes in a created torrent besides its files.
+/// Built with the announce URL of the tracker, everything else is optional
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TorrentOptions {
+    pub announce: String,
+    /// tiers of trackers, the `announce` URL is written first if it isn't in any tier
+    pub announce_list: Vec<Vec<String>>,
+    pub comment: Option<String>,
+    pub created_by: String,
+    /// seconds since the unix epoch, now unless told otherwise
+    pub creation_date: Option<i64>,
+    pub private: bool,
+    /// URLs of HTTP servers holding the same files
+    pub web_seeds: Vec<String>,
+    /// bytes in each piece, chosen from the torrent's size if None
+    pub piece_length: Option<u32>,
+}
+
+impl TorrentOptions {
+    pub fn new(announce: &str) -> Self {
+        Self {
+            announce: announce.to_string(),
+            announce_list: vec![],
+            comment: None,
+            created_by: CREATED_BY.to_string(),
+            creation_date: None,
+            private: false,
+            web_seeds: vec![],
+            piece_length: None,
+        }
+    }
+
+    /// Adds a tier of backup trackers, tried after the ones added before
+    pub fn with_tier(mut self, trackers: Vec<String>) -> Self {
+        self.announce_list.push(trackers);
+        self
+    }
+
+    pub fn with_comment(mut self, comment: &str) -> Self {
+        self.comment = Some(comment.to_string());
+        self
+    }
+
+    pub fn with_created_by(mut self, created_by: &str) -> Self {
+        self.created_by = created_by.to_string();
+        self
+    }
+
+    pub fn with_creation_date(mut self, seconds_since_epoch: i64) -> Self {
+        self.creation_date = Some(seconds_since_epoch);
+        self
+    }
+
+    /// Private torrents are only shared through their trackers, not DHT nor PEX
+    pub fn with_private(mut self, private: bool) -> Self {
+        self.private = private;
+        self
+    }
+
+    pub fn with_web_seed(mut self, url: &str) -> Self {
+        self.web_seeds.push(url.to_string());
+        self
+    }
+
+    pub fn with_piece_length(mut self, piece_length: u32) -> Self {
+        self.piece_length = Some(piece_length);
+        self
+    }
+}
@@ -4,6 +4,7 @@ use super::types::RequestParameters;
 use super::types::TrackerResponse;
 use super::types::*;
 use super::utils::*;
+use crate::bandwidth::TorrentBandwidth;
 use crate::bencode::BencodeDecodedValue;
 use crate::bencode::*;
 use crate::client::ClientInfo;
@@ -12,6 +13,7 @@ use crate::http::HttpsService;
 use crate::http::IHttpService;
 use crate::peer::peer_message_service_provider;
 use crate::peer::Peer;
+use crate::storage::SharedStorage;
 use log::*;
 use rand::Rng;
 use std::collections::HashMap;
@@ -24,11 +26,44 @@ pub trait ITrackerService: Clone {
 #[derive(Clone)]
 pub struct TrackerService {
     client_info: ClientInfo,
+    storage: Option<SharedStorage>,
+    bandwidth: Option<TorrentBandwidth>,
 }
 
 impl TrackerService {
     pub fn new(client_info: ClientInfo) -> Self {
-        TrackerService { client_info }
+        TrackerService {
+            client_info,
+            storage: None,
+            bandwidth: None,
+        }
+    }
+
+    /// Reports the pieces found in the storage as downloaded.
+    /// Without one, the pieces directory of the legacy storage is looked at
+    pub fn with_storage(mut self, storage: SharedStorage) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    /// Reports the bytes uploaded through the bandwidth, zero without one
+    pub fn with_bandwidth(mut self, bandwidth: TorrentBandwidth) -> Self {
+        self.bandwidth = Some(bandwidth);
+        self
+    }
+
+    fn downloaded_pieces(&self) -> Vec<u32> {
+        if let Some(storage) = &self.storage {
+            return storage.completed_pieces();
+        }
+        let pieces_dir = format!(
+            "{}/{}/pieces",
+            self.client_info.config.download_path, self.client_info.metainfo.info.name
+        );
+        get_existing_pieces(
+            self.client_info.metainfo.get_piece_count(),
+            pieces_dir.as_str(),
+        )
     }
 
     fn parse_response(
@@ -139,6 +174,7 @@ impl TrackerService {
                 port,
                 peer_id,
                 peer_message_service_provider,
+                web_seed: None,
             };
 
             peer_list.push(peer);
@@ -161,6 +197,7 @@ impl TrackerService {
                 port: u16::from_be_bytes([port[0], port[1]]),
                 peer_id: rand::thread_rng().gen::<[u8; 20]>().to_vec(),
                 peer_message_service_provider,
+                web_seed: None,
             };
             peer_list.push(peer);
             i += 6;
@@ -179,18 +216,15 @@ impl TrackerService {
     }
 }
 
-impl ITrackerService for TrackerService {
-    fn announce(&mut self, event: Option<Event>) -> Result<TrackerResponse, TrackerError> {
+impl TrackerService {
+    fn announce_to_swarm(
+        &mut self,
+        info_hash: &[u8],
+        event: Option<Event>,
+    ) -> Result<TrackerResponse, TrackerError> {
         debug!("Sending tracker announce request");
         let mut http_service = HttpsService::from_url(&self.client_info.metainfo.announce)?;
-        let pieces_dir = format!(
-            "{}/{}/pieces",
-            self.client_info.config.download_path, self.client_info.metainfo.info.name
-        );
-        let initial_pieces: Vec<u32> = get_existing_pieces(
-            self.client_info.metainfo.get_piece_count(),
-            pieces_dir.as_str(),
-        );
+        let initial_pieces: Vec<u32> = self.downloaded_pieces();
         let downloaded = if initial_pieces.len() as u32
             * self.client_info.metainfo.info.piece_length as u32
             > self.client_info.metainfo.info.length as u32
@@ -203,10 +237,14 @@ impl ITrackerService for TrackerService {
         let left = self.client_info.metainfo.info.length as u32 - downloaded;
 
         let request_parameters = RequestParameters {
-            info_hash: self.client_info.metainfo.info_hash.to_vec(),
+            info_hash: info_hash.to_vec(),
             peer_id: self.client_info.peer_id.to_vec(),
             port: self.client_info.config.listen_port,
-            uploaded: 0,
+            uploaded: self
+                .bandwidth
+                .as_ref()
+                .map(|bandwidth| bandwidth.uploaded().min(u32::MAX as u64) as u32)
+                .unwrap_or(0),
             downloaded,
             left,
             event: event.unwrap_or(Event::KeepAlive),
@@ -223,6 +261,31 @@ impl ITrackerService for TrackerService {
     }
 }
 
+impl ITrackerService for TrackerService {
+    // hybrid torrents also announce to the swarm of v2 peers, its peers are added if it answers
+    fn announce(&mut self, event: Option<Event>) -> Result<TrackerResponse, TrackerError> {
+        let swarms = self.client_info.metainfo.swarm_info_hashes();
+        let mut response = self.announce_to_swarm(&swarms[0], event)?;
+        for info_hash in &swarms[1..] {
+            match self.announce_to_swarm(info_hash, event) {
+                Ok(swarm) => {
+                    for peer in swarm.peers {
+                        if !response
+                            .peers
+                            .iter()
+                            .any(|known| known.ip == peer.ip && known.port == peer.port)
+                        {
+                            response.peers.push(peer);
+                        }
+                    }
+                }
+                Err(err) => debug!("Couldn't announce to the v2 swarm: {:?}", err),
+            }
+        }
+        Ok(response)
+    }
+}
+
 #[derive(Clone)]
 pub struct MockTrackerService {
     pub responses: Vec<Vec<Peer>>,
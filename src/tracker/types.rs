@@ -1,7 +1,7 @@
 use crate::peer::Peer;
 use std::time::Duration;
 
-#[derive(PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub enum Event {
     Started,
     Completed,
@@ -111,7 +111,12 @@ impl Model {
             false => "not choked",
         };
 
-        let peer_state = peer_interested.to_string() + " and " + peer_choked;
+        let snubbed = match peer_conn_state.snubbed {
+            true => ", snubbing us",
+            false => "",
+        };
+
+        let peer_state = peer_interested.to_string() + " and " + peer_choked + snubbed;
         let imp = self.imp();
         let mut data = imp.0.borrow_mut();
         for item in data.iter_mut() {
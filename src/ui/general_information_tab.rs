@@ -84,6 +84,7 @@ impl GeneralInformationTab {
                 .halign(gtk::Align::Start)
                 .build();
                 Self::add_torrent_data(&summary_box, item, "torrent:", "name");
+                Self::add_torrent_data(&summary_box, item, "state:", "state");
                 Self::add_torrent_data(&summary_box, item, "active peers:", "activeconnections");
                 Self::add_torrent_data(&summary_box, item, "time left:", "timeleft");
                 Self::add_torrent_percentage(&summary_box, item, "Download progress: ", "downloadfraction");
@@ -149,8 +150,15 @@ impl GeneralInformationTab {
             Self::add_torrent_data(&content_area, &item, "Downloaded Pieces: ", "downloadedpieces");
             Self::add_torrent_data(&content_area, &item, "Active Connections: ", "activeconnections");
             Self::add_torrent_data(&content_area, &item, "File Structure: ", "filestructure");
+            Self::add_torrent_data(&content_area, &item, "Comment: ", "comment");
+            Self::add_torrent_data(&content_area, &item, "Created By: ", "createdby");
+            Self::add_torrent_data(&content_area, &item, "Creation Date: ", "creationdate");
+            Self::add_torrent_data(&content_area, &item, "Private: ", "private");
+            Self::add_torrent_data(&content_area, &item, "Trackers: ", "trackers");
+            Self::add_torrent_data(&content_area, &item, "Web Seeds: ", "webseeds");
             Self::add_torrent_percentage(&content_area, &item, "Download progress: ", "downloadfraction");
             Self::add_torrent_data(&content_area, &item, "Time taken: ", "timetaken");
+            Self::add_torrent_data(&content_area, &item, "State: ", "state");
 
 
             dialog.show_all();
@@ -289,14 +297,58 @@ impl GeneralInformationTab {
         files_inside_directory
     }
 
+    // converts seconds since the unix epoch to a UTC date, as YYYY-MM-DD HH:MM:SS
+    fn unix_time_to_date(&self, seconds: i64) -> String {
+        let days = seconds.div_euclid(86400);
+        let time = seconds.rem_euclid(86400) as u32;
+        // days to civil date, from Howard Hinnant's algorithm
+        let days = days + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days.rem_euclid(146097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month_index = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+        let month = if month_index < 10 {
+            month_index + 3
+        } else {
+            month_index - 9
+        };
+        let year = year_of_era + era * 400 + (month <= 2) as i64;
+        format!(
+            "{:04}-{:02}-{:02} {} UTC",
+            year,
+            month,
+            day,
+            self.seconds_to_hh_mm_ss(time)
+        )
+    }
+
     fn add_torrent(&self, metainfo: &Metainfo) -> Result<(), GeneralInformationTabError> {
-        self.model.append(&TorrentInformation::new(
+        let torrent = TorrentInformation::new(
             &metainfo.info.name,
             &self.sha1_of(&metainfo.info_hash),
             self.bytes_to_megabytes(metainfo.info.length),
             metainfo.info.pieces.len() as u32,
             &self.files_to_string(metainfo.info.files.clone(), metainfo.info.name.clone()),
-        ));
+        );
+        let trackers: Vec<String> = metainfo
+            .trackers()
+            .iter()
+            .map(|tier| tier.join(", "))
+            .collect();
+        let creation_date = metainfo
+            .creation_date
+            .map(|seconds| self.unix_time_to_date(seconds));
+        let private = if metainfo.info.private { "Yes" } else { "No" };
+        torrent.set_property("comment", metainfo.comment.as_deref().unwrap_or("-"));
+        torrent.set_property("createdby", metainfo.created_by.as_deref().unwrap_or("-"));
+        torrent.set_property("creationdate", creation_date.as_deref().unwrap_or("-"));
+        torrent.set_property("private", private);
+        torrent.set_property("trackers", trackers.join("\n"));
+        torrent.set_property("webseeds", metainfo.web_seeds().join("\n"));
+        self.model.append(&torrent);
         Ok(())
     }
 
@@ -362,6 +414,17 @@ impl GeneralInformationTab {
         Ok(())
     }
 
+    fn set_torrent_state(
+        &self,
+        torrent: &str,
+        state: &str,
+    ) -> Result<(), GeneralInformationTabError> {
+        self.model.edit(torrent, |item| {
+            item.set_property("state", state);
+        });
+        Ok(())
+    }
+
     pub fn update(&mut self, message: &UIMessage) -> Result<(), GeneralInformationTabError> {
         match message {
             UIMessage::AddTorrent(metainfo) => self.add_torrent(metainfo)?,
@@ -375,6 +438,9 @@ impl GeneralInformationTab {
             UIMessage::TorrentInitialPeers(torrent, amount) => {
                 self.set_initial_torrent_peers(torrent, *amount)?
             }
+            UIMessage::UpdateTorrentState(torrent, state) => {
+                self.set_torrent_state(torrent, state)?
+            }
             _ => {}
         }
         Ok(())
@@ -2,10 +2,12 @@ use crate::metainfo::Metainfo;
 use crate::peer::PeerConnectionState;
 use gtk::{self, glib};
 use log::*;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
 type TorrentName = String;
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct PeerStatistics {
     pub torrentname: String,
     pub peerid: Vec<u8>,
@@ -17,7 +19,7 @@ pub struct PeerStatistics {
 }
 
 pub enum UIMessage {
-    AddTorrent(Metainfo),
+    AddTorrent(Box<Metainfo>),
     TorrentInitialPeers(TorrentName, u32),
     PieceDownloaded(TorrentName, Vec<u8>),
     NewConnection(TorrentName),
@@ -27,12 +29,16 @@ pub enum UIMessage {
     UpdatePeerDownloadRate(f32, Vec<u8>),
     UpdateDownloadedPiece(Vec<u8>),
     UpdatePeerConnectionState(Vec<u8>, PeerConnectionState),
+    UpdateTorrentState(TorrentName, String),
 }
 
+/// Sends what happens to a torrent to the UI, if there is one.
+/// Clones share the latest statistics of every connected peer, so they can be read without a UI
 #[derive(Debug, Clone)]
 pub struct UIMessageSender {
     pub tx: Option<glib::Sender<UIMessage>>,
     torrent_name: String,
+    peers: Arc<Mutex<HashMap<Vec<u8>, PeerStatistics>>>,
 }
 
 impl UIMessageSender {
@@ -40,6 +46,7 @@ impl UIMessageSender {
         UIMessageSender {
             tx: None,
             torrent_name: "".to_string(),
+            peers: Arc::default(),
         }
     }
 
@@ -47,11 +54,12 @@ impl UIMessageSender {
         UIMessageSender {
             tx: Some(tx),
             torrent_name: torrent_name.to_string(),
+            peers: Arc::default(),
         }
     }
 
     pub fn send_metadata(&self, metainfo: Metainfo) {
-        self.send_message_to_ui(UIMessage::AddTorrent(metainfo))
+        self.send_message_to_ui(UIMessage::AddTorrent(Box::new(metainfo)))
     }
 
     pub fn send_initial_peers(&self, num_peers: u32) {
@@ -73,6 +81,9 @@ impl UIMessageSender {
     }
 
     pub fn send_closed_connection(&self, peer_id: Vec<u8>) {
+        if let Ok(mut peers) = self.peers.lock() {
+            peers.remove(&peer_id);
+        }
         self.send_message_to_ui(UIMessage::ClosedConnection(
             self.torrent_name.clone(),
             peer_id,
@@ -80,20 +91,50 @@ impl UIMessageSender {
     }
 
     pub fn send_peer_statistics(&self, peer_statistics: PeerStatistics) {
+        if let Ok(mut peers) = self.peers.lock() {
+            peers.insert(peer_statistics.peerid.clone(), peer_statistics.clone());
+        }
         self.send_message_to_ui(UIMessage::AddPeerStatistics(peer_statistics))
     }
 
     pub fn update_peer_state(&self, peer_id: Vec<u8>, state: PeerConnectionState) {
+        self.edit_peer(&peer_id, |peer| peer.state = state.clone());
         self.send_message_to_ui(UIMessage::UpdatePeerConnectionState(peer_id, state))
     }
 
     pub fn send_upload_rate(&self, rate: f32, peer_id: &[u8]) {
+        self.edit_peer(peer_id, |peer| peer.uploadrate = rate as u32);
         self.send_message_to_ui(UIMessage::UpdatePeerUploadRate(rate, peer_id.to_vec()))
     }
     pub fn send_download_rate(&self, rate: f32, peer_id: &[u8]) {
+        self.edit_peer(peer_id, |peer| peer.downloadrate = rate as u32);
         self.send_message_to_ui(UIMessage::UpdatePeerDownloadRate(rate, peer_id.to_vec()))
     }
 
+    /// Shows what the torrent is doing, like `Seeding` or why it stopped
+    pub fn send_torrent_state(&self, state: &str) {
+        self.send_message_to_ui(UIMessage::UpdateTorrentState(
+            self.torrent_name.clone(),
+            state.to_string(),
+        ))
+    }
+
+    /// The latest statistics of every peer connected to the torrent
+    pub fn peer_statistics(&self) -> Vec<PeerStatistics> {
+        self.peers
+            .lock()
+            .map(|peers| peers.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    fn edit_peer(&self, peer_id: &[u8], edit: impl FnOnce(&mut PeerStatistics)) {
+        if let Ok(mut peers) = self.peers.lock() {
+            if let Some(peer) = peers.get_mut(peer_id) {
+                edit(peer);
+            }
+        }
+    }
+
     pub fn send_message_to_ui(&self, message: UIMessage) {
         if let Some(tx) = &self.tx {
             if tx.send(message).is_err() {
@@ -18,6 +18,13 @@ pub struct TorrentInformation {
     filestructure: RefCell<Option<String>>,
     timeleft: RefCell<Option<String>>,
     timetaken: RefCell<Option<String>>,
+    state: RefCell<Option<String>>,
+    comment: RefCell<Option<String>>,
+    createdby: RefCell<Option<String>>,
+    creationdate: RefCell<Option<String>>,
+    private: RefCell<Option<String>>,
+    trackers: RefCell<Option<String>>,
+    webseeds: RefCell<Option<String>>,
 }
 
 // Basic declaration of our type for the GObject type system
@@ -130,6 +137,55 @@ impl ObjectImpl for TorrentInformation {
                     None, // Default value
                     glib::ParamFlags::READWRITE,
                 ),
+                glib::ParamSpecString::new(
+                    "state",
+                    "State",
+                    "State",
+                    None, // Default value
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "comment",
+                    "Comment",
+                    "Comment",
+                    None, // Default value
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "createdby",
+                    "CreatedBy",
+                    "CreatedBy",
+                    None, // Default value
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "creationdate",
+                    "CreationDate",
+                    "CreationDate",
+                    None, // Default value
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "private",
+                    "Private",
+                    "Private",
+                    None, // Default value
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "trackers",
+                    "Trackers",
+                    "Trackers",
+                    None, // Default value
+                    glib::ParamFlags::READWRITE,
+                ),
+                glib::ParamSpecString::new(
+                    "webseeds",
+                    "WebSeeds",
+                    "WebSeeds",
+                    None, // Default value
+                    glib::ParamFlags::READWRITE,
+                ),
                 glib::ParamSpecString::new(
                     "filestructure",
                     "FileStructure",
@@ -217,6 +273,48 @@ impl ObjectImpl for TorrentInformation {
                     .expect("type conformity checked by `Object::set_property`");
                 self.timetaken.replace(timetaken);
             }
+            "state" => {
+                let state = value
+                    .get()
+                    .expect("type conformity checked by `Object::set_property`");
+                self.state.replace(state);
+            }
+            "comment" => {
+                let comment = value
+                    .get()
+                    .expect("type conformity checked by `Object::set_property`");
+                self.comment.replace(comment);
+            }
+            "createdby" => {
+                let createdby = value
+                    .get()
+                    .expect("type conformity checked by `Object::set_property`");
+                self.createdby.replace(createdby);
+            }
+            "creationdate" => {
+                let creationdate = value
+                    .get()
+                    .expect("type conformity checked by `Object::set_property`");
+                self.creationdate.replace(creationdate);
+            }
+            "private" => {
+                let private = value
+                    .get()
+                    .expect("type conformity checked by `Object::set_property`");
+                self.private.replace(private);
+            }
+            "trackers" => {
+                let trackers = value
+                    .get()
+                    .expect("type conformity checked by `Object::set_property`");
+                self.trackers.replace(trackers);
+            }
+            "webseeds" => {
+                let webseeds = value
+                    .get()
+                    .expect("type conformity checked by `Object::set_property`");
+                self.webseeds.replace(webseeds);
+            }
             "filestructure" => {
                 let filestructure = value
                     .get()
@@ -245,6 +343,13 @@ impl ObjectImpl for TorrentInformation {
             "activeconnections" => self.activeconnections.borrow().to_value(),
             "timeleft" => self.timeleft.borrow().to_value(),
             "timetaken" => self.timetaken.borrow().to_value(),
+            "state" => self.state.borrow().to_value(),
+            "comment" => self.comment.borrow().to_value(),
+            "createdby" => self.createdby.borrow().to_value(),
+            "creationdate" => self.creationdate.borrow().to_value(),
+            "private" => self.private.borrow().to_value(),
+            "trackers" => self.trackers.borrow().to_value(),
+            "webseeds" => self.webseeds.borrow().to_value(),
             "filestructure" => self.filestructure.borrow().to_value(),
             _ => unimplemented!(),
         }
@@ -32,6 +32,7 @@ impl TorrentInformation {
             ("filestructure", &filestructure),
             ("timeleft", &"-"),
             ("timetaken", &"-"),
+            ("state", &"Downloading"),
         ])
         .expect("Failed to create row data")
     }
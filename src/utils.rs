@@ -0,0 +1,29 @@
+/// Writes the bytes as lowercase hexadecimal, the way info_hashes and peer_ids are shown and announced
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Reads bytes written with `to_hex`, None if the string isn't hexadecimal
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(hex.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_goes_both_ways() {
+        let bytes = vec![0, 15, 16, 255];
+        assert_eq!(to_hex(&bytes), "000f10ff");
+        assert_eq!(from_hex("000f10ff"), Some(bytes));
+        assert_eq!(from_hex("0g"), None);
+        assert_eq!(from_hex("abc"), None);
+    }
+}
@@ -0,0 +1,473 @@
+use super::constants::*;
+use super::ledbat::Ledbat;
+use super::packet::*;
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConnectionState {
+    /// waiting for the other side to answer our SYN
+    SynSent,
+    Connected,
+    /// our FIN was sent, waiting for it to be acknowledged
+    FinSent,
+    Closed,
+    /// the other side reset the connection
+    Reset,
+    /// a packet was sent too many times without being acknowledged
+    TimedOut,
+}
+
+// a packet with a sequence number, kept until it is acknowledged to send it again
+struct SentPacket {
+    packet: Packet,
+    sent_at: Instant,
+    transmissions: u8,
+}
+
+/// State of one side of a uTP connection.
+/// It doesn't own a socket: the packets to send pile up until they are taken with `take_outgoing`
+pub struct Connection {
+    pub state: ConnectionState,
+    pub remote: SocketAddr,
+    send_id: u16,
+    // of the next packet sent
+    seq_nr: u16,
+    // of the last packet received in order
+    ack_nr: u16,
+    in_flight: VecDeque<SentPacket>,
+    // written, waiting for room in the window
+    pending: VecDeque<u8>,
+    received: VecDeque<u8>,
+    out_of_order: HashMap<u16, Packet>,
+    outgoing: Vec<Packet>,
+    ledbat: Ledbat,
+    rtt: Option<(Duration, Duration)>,
+    timeout: Duration,
+    peer_window: usize,
+    advertised_window: usize,
+    // delay of the last packet received, sent back so the other side knows its queuing delay
+    reply_micros: u32,
+    // ack_nr of the last packet received, to tell duplicate acks apart
+    last_ack_nr: Option<u16>,
+    duplicate_acks: u8,
+    /// the other side sent its FIN and everything before it arrived
+    pub finished: bool,
+    // our side was shut down, a FIN follows the data pending
+    closing: bool,
+}
+
+impl Connection {
+    fn new(remote: SocketAddr, send_id: u16, seq_nr: u16, ack_nr: u16) -> Self {
+        Self {
+            state: ConnectionState::Connected,
+            remote,
+            send_id,
+            seq_nr,
+            ack_nr,
+            in_flight: VecDeque::new(),
+            pending: VecDeque::new(),
+            received: VecDeque::new(),
+            out_of_order: HashMap::new(),
+            outgoing: Vec::new(),
+            ledbat: Ledbat::default(),
+            rtt: None,
+            timeout: Duration::from_millis(INITIAL_TIMEOUT),
+            peer_window: MAX_PAYLOAD,
+            advertised_window: RECEIVE_BUFFER,
+            reply_micros: 0,
+            last_ack_nr: None,
+            duplicate_acks: 0,
+            finished: false,
+            closing: false,
+        }
+    }
+
+    /// Connection we open, the packets we receive carry `recv_id` and the ones we send the next id
+    pub fn outgoing(remote: SocketAddr, recv_id: u16, now: Instant) -> Self {
+        let mut connection = Self::new(remote, recv_id.wrapping_add(1), 1, 0);
+        connection.state = ConnectionState::SynSent;
+        connection.send_numbered(PacketType::Syn, vec![], now);
+        connection
+    }
+
+    /// Connection the other side opened with `syn`
+    pub fn incoming(remote: SocketAddr, syn: &Packet) -> Self {
+        let mut connection = Self::new(remote, syn.connection_id, rand::random(), syn.seq_nr);
+        connection.reply_micros = now_micros().wrapping_sub(syn.timestamp);
+        connection.send_state();
+        connection
+    }
+
+    /// Packets to send to the other side, in order
+    pub fn take_outgoing(&mut self) -> Vec<Packet> {
+        std::mem::take(&mut self.outgoing)
+    }
+
+    /// Whether the connection is over and can be forgotten once nobody uses it
+    pub fn is_done(&self) -> bool {
+        matches!(
+            self.state,
+            ConnectionState::Closed | ConnectionState::Reset | ConnectionState::TimedOut
+        )
+    }
+
+    /// Whether there's data to read
+    pub fn has_received(&self) -> bool {
+        !self.received.is_empty()
+    }
+
+    pub fn is_writable(&self) -> bool {
+        self.state == ConnectionState::Connected && !self.closing
+    }
+
+    fn receive_window(&self) -> usize {
+        RECEIVE_BUFFER.saturating_sub(self.received.len())
+    }
+
+    fn bytes_in_flight(&self) -> usize {
+        self.in_flight
+            .iter()
+            .map(|sent| sent.packet.payload.len())
+            .sum()
+    }
+
+    fn packet(&mut self, kind: PacketType, payload: Vec<u8>) -> Packet {
+        self.advertised_window = self.receive_window();
+        Packet {
+            kind,
+            // the SYN carries the id the other side sends with
+            connection_id: match kind {
+                PacketType::Syn => self.send_id.wrapping_sub(1),
+                _ => self.send_id,
+            },
+            timestamp: now_micros(),
+            timestamp_difference: self.reply_micros,
+            window: self.advertised_window as u32,
+            seq_nr: self.seq_nr,
+            ack_nr: self.ack_nr,
+            payload,
+        }
+    }
+
+    fn send_numbered(&mut self, kind: PacketType, payload: Vec<u8>, now: Instant) {
+        let packet = self.packet(kind, payload);
+        self.seq_nr = self.seq_nr.wrapping_add(1);
+        self.outgoing.push(packet.clone());
+        self.in_flight.push_back(SentPacket {
+            packet,
+            sent_at: now,
+            transmissions: 1,
+        });
+    }
+
+    // acknowledges what was received, state packets don't take a sequence number
+    fn send_state(&mut self) {
+        let packet = self.packet(PacketType::State, vec![]);
+        self.outgoing.push(packet);
+    }
+
+    fn resend_oldest(&mut self, now: Instant) {
+        let mut header = self.packet(PacketType::State, vec![]);
+        let Some(sent) = self.in_flight.front_mut() else {
+            return;
+        };
+        header.kind = sent.packet.kind;
+        header.connection_id = sent.packet.connection_id;
+        header.seq_nr = sent.packet.seq_nr;
+        header.payload = std::mem::take(&mut sent.packet.payload);
+        sent.packet = header;
+        sent.sent_at = now;
+        sent.transmissions += 1;
+        self.outgoing.push(sent.packet.clone());
+    }
+
+    /// Handles a packet the other side sent
+    pub fn handle(&mut self, packet: Packet, now: Instant) {
+        if packet.kind == PacketType::Reset {
+            self.state = ConnectionState::Reset;
+            return;
+        }
+        self.reply_micros = now_micros().wrapping_sub(packet.timestamp);
+        if self.state == ConnectionState::SynSent && packet.kind == PacketType::State {
+            self.state = ConnectionState::Connected;
+            // the first data packet of the other side carries the same number as its answer
+            self.ack_nr = packet.seq_nr.wrapping_sub(1);
+        }
+        self.acknowledge(&packet, now);
+        self.peer_window = packet.window as usize;
+        match packet.kind {
+            PacketType::Data | PacketType::Fin => {
+                self.receive(packet);
+                self.send_state();
+            }
+            // our answer to it was lost
+            PacketType::Syn => self.send_state(),
+            _ => {}
+        }
+        self.flush(now);
+    }
+
+    fn acknowledge(&mut self, packet: &Packet, now: Instant) {
+        let in_flight = self.bytes_in_flight();
+        let mut acked = None;
+        while let Some(sent) = self.in_flight.front() {
+            if seq_before(packet.ack_nr, sent.packet.seq_nr) {
+                break;
+            }
+            let (transmissions, sent_at) = (sent.transmissions, sent.sent_at);
+            *acked.get_or_insert(0) += sent.packet.payload.len();
+            self.in_flight.pop_front();
+            // packets sent again don't tell which transmission was acknowledged
+            if transmissions == 1 {
+                self.measure_round_trip(now.duration_since(sent_at));
+            }
+        }
+        match acked {
+            Some(acked) => {
+                if acked > 0 {
+                    let delay = packet.timestamp_difference;
+                    self.ledbat.on_ack(acked, delay, in_flight, now);
+                }
+                self.duplicate_acks = 0;
+            }
+            // the other side keeps acknowledging the packet before a lost one. A state packet
+            // telling there's room in its window again acknowledges nothing new either, but isn't a duplicate
+            None if packet.kind == PacketType::State
+                && !self.in_flight.is_empty()
+                && self.last_ack_nr == Some(packet.ack_nr)
+                && packet.window as usize == self.peer_window =>
+            {
+                self.duplicate_acks += 1;
+                if self.duplicate_acks == DUPLICATE_ACKS_BEFORE_RESEND {
+                    self.ledbat.on_loss();
+                    self.resend_oldest(now);
+                    self.duplicate_acks = 0;
+                }
+            }
+            None => {}
+        }
+        self.last_ack_nr = Some(packet.ack_nr);
+        if self.state == ConnectionState::FinSent && self.in_flight.is_empty() {
+            self.state = ConnectionState::Closed;
+        }
+    }
+
+    // round trip time and timeout as in RFC 6298
+    fn measure_round_trip(&mut self, sample: Duration) {
+        let (rtt, variance) = match self.rtt {
+            Some((rtt, variance)) => (
+                (rtt * 7 + sample) / 8,
+                (variance * 3 + rtt.abs_diff(sample)) / 4,
+            ),
+            None => (sample, sample / 2),
+        };
+        self.rtt = Some((rtt, variance));
+        self.timeout = (rtt + variance * 4).clamp(
+            Duration::from_millis(MIN_TIMEOUT),
+            Duration::from_millis(MAX_TIMEOUT),
+        );
+    }
+
+    fn receive(&mut self, packet: Packet) {
+        let ahead = packet.seq_nr.wrapping_sub(self.ack_nr);
+        if self.finished || !seq_before(self.ack_nr, packet.seq_nr) || ahead > MAX_OUT_OF_ORDER {
+            return;
+        }
+        self.out_of_order.insert(packet.seq_nr, packet);
+        while let Some(next) = self.out_of_order.remove(&self.ack_nr.wrapping_add(1)) {
+            self.ack_nr = next.seq_nr;
+            if next.kind == PacketType::Fin {
+                self.finished = true;
+                self.out_of_order.clear();
+                break;
+            }
+            self.received.extend(next.payload);
+        }
+    }
+
+    /// Sends what was written while the window allows it, and the FIN once everything was sent
+    pub fn flush(&mut self, now: Instant) {
+        if self.state != ConnectionState::Connected {
+            return;
+        }
+        while !self.pending.is_empty() {
+            let size = self.pending.len().min(MAX_PAYLOAD);
+            let in_flight = self.bytes_in_flight();
+            let window = self.ledbat.window().min(self.peer_window);
+            // with nothing in flight a packet always goes, so a closed window is probed
+            if in_flight > 0 && in_flight + size > window {
+                break;
+            }
+            let payload = self.pending.drain(..size).collect();
+            self.send_numbered(PacketType::Data, payload, now);
+        }
+        if self.closing && self.pending.is_empty() {
+            self.send_numbered(PacketType::Fin, vec![], now);
+            self.state = ConnectionState::FinSent;
+        }
+    }
+
+    /// Sends the oldest packet again if it wasn't acknowledged in time
+    pub fn tick(&mut self, now: Instant) {
+        let Some(oldest) = self.in_flight.front() else {
+            return;
+        };
+        if now.duration_since(oldest.sent_at) < self.timeout {
+            return;
+        }
+        if oldest.transmissions >= MAX_TRANSMISSIONS {
+            self.state = ConnectionState::TimedOut;
+            self.in_flight.clear();
+            return;
+        }
+        self.timeout = (self.timeout * 2).min(Duration::from_millis(MAX_TIMEOUT));
+        self.ledbat.on_timeout();
+        self.resend_oldest(now);
+    }
+
+    pub fn write(&mut self, data: &[u8], now: Instant) {
+        self.pending.extend(data);
+        self.flush(now);
+    }
+
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let read = self.received.read(buf).unwrap_or(0);
+        // the other side stops sending once our window fills, it is told when there's room again
+        if self.advertised_window < MAX_PAYLOAD && self.receive_window() >= MAX_PAYLOAD {
+            self.send_state();
+        }
+        read
+    }
+
+    /// Closes our side once what was written is sent, the other side may keep sending.
+    /// A connection that wasn't accepted yet is given up on
+    pub fn shutdown(&mut self, now: Instant) {
+        match self.state {
+            ConnectionState::SynSent => {
+                self.state = ConnectionState::Closed;
+                self.in_flight.clear();
+                // nothing is ever coming
+                self.finished = true;
+            }
+            ConnectionState::Connected => {
+                self.closing = true;
+                self.flush(now);
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn deliver(from: &mut Connection, to: &mut Connection, now: Instant) {
+        for packet in from.take_outgoing() {
+            to.handle(packet, now);
+        }
+    }
+
+    fn read_all(connection: &mut Connection) -> Vec<u8> {
+        let mut data = vec![0u8; RECEIVE_BUFFER];
+        let read = connection.read(&mut data);
+        data.truncate(read);
+        data
+    }
+
+    #[test]
+    fn lost_packets_are_sent_again_and_data_arrives_in_order() {
+        let now = Instant::now();
+        let address: SocketAddr = "127.0.0.1:6881".parse().unwrap();
+        let mut client = Connection::outgoing(address, 100, now);
+        let syn = client.take_outgoing().pop().unwrap();
+        assert_eq!((syn.kind, syn.connection_id), (PacketType::Syn, 100));
+        let mut server = Connection::incoming(address, &syn);
+        deliver(&mut server, &mut client, now);
+        assert_eq!(client.state, ConnectionState::Connected);
+
+        let data: Vec<u8> = (0..3 * MAX_PAYLOAD).map(|i| i as u8).collect();
+        client.write(&data, now);
+        let mut sent = client.take_outgoing();
+        assert_eq!(sent.len(), 2);
+        // the other side receives with the id after the one in the SYN
+        assert!(sent.iter().all(|packet| packet.connection_id == 101));
+        // the first packet is lost, the second one waits for it
+        server.handle(sent.pop().unwrap(), now);
+        assert!(!server.has_received());
+        deliver(&mut server, &mut client, now);
+
+        let later = now + Duration::from_millis(INITIAL_TIMEOUT);
+        client.tick(later);
+        deliver(&mut client, &mut server, later);
+        deliver(&mut server, &mut client, later);
+        deliver(&mut client, &mut server, later);
+        assert_eq!(read_all(&mut server), data);
+
+        client.shutdown(later);
+        deliver(&mut client, &mut server, later);
+        assert!(server.finished);
+        deliver(&mut server, &mut client, later);
+        assert_eq!(client.state, ConnectionState::Closed);
+    }
+
+    #[test]
+    fn only_repeated_acks_with_the_same_window_are_duplicates() {
+        let now = Instant::now();
+        let address: SocketAddr = "127.0.0.1:6881".parse().unwrap();
+        let mut client = Connection::outgoing(address, 100, now);
+        let syn = client.take_outgoing().pop().unwrap();
+        let mut server = Connection::incoming(address, &syn);
+        deliver(&mut server, &mut client, now);
+
+        client.write(&vec![0; 2 * MAX_PAYLOAD], now);
+        let mut sent = client.take_outgoing();
+        let lost = sent.remove(0);
+        server.handle(sent.pop().unwrap(), now);
+        let duplicate = server.take_outgoing().pop().unwrap();
+        client.handle(duplicate.clone(), now);
+
+        // the other side read what it had and tells there's room in its window again
+        for room in 1..=DUPLICATE_ACKS_BEFORE_RESEND as u32 {
+            client.handle(
+                Packet {
+                    window: duplicate.window - room,
+                    ..duplicate.clone()
+                },
+                now,
+            );
+        }
+        assert!(client.take_outgoing().is_empty());
+
+        let window = duplicate.window - DUPLICATE_ACKS_BEFORE_RESEND as u32;
+        for _ in 1..DUPLICATE_ACKS_BEFORE_RESEND {
+            client.handle(
+                Packet {
+                    window,
+                    ..duplicate.clone()
+                },
+                now,
+            );
+        }
+        let resent = client.take_outgoing();
+        assert_eq!(resent.len(), 1);
+        assert_eq!(resent[0].seq_nr, lost.seq_nr);
+    }
+
+    #[test]
+    fn unanswered_connections_time_out() {
+        let mut now = Instant::now();
+        let address: SocketAddr = "127.0.0.1:6881".parse().unwrap();
+        let mut client = Connection::outgoing(address, 7, now);
+        for _ in 0..MAX_TRANSMISSIONS {
+            now += Duration::from_millis(MAX_TIMEOUT);
+            client.tick(now);
+        }
+        assert_eq!(client.take_outgoing().len(), MAX_TRANSMISSIONS as usize);
+        assert_eq!(client.state, ConnectionState::TimedOut);
+        assert!(client.is_done());
+    }
+}
@@ -0,0 +1,35 @@
+/// Version of the uTP protocol (BEP 29)
+pub const UTP_VERSION: u8 = 1;
+pub const HEADER_SIZE: usize = 20;
+/// Bytes of data sent in a single packet at most, so packets fit in a typical MTU
+pub const MAX_PAYLOAD: usize = 1380;
+
+/// Queuing delay LEDBAT aims for, in microseconds. Above it the window shrinks
+pub const TARGET_DELAY: u32 = 100_000;
+/// Bytes the window grows at most each round trip, while the delay is below the target
+pub const MAX_CWND_INCREASE_PER_RTT: f64 = 3000.0;
+/// The window never shrinks below a packet
+pub const MIN_WINDOW: usize = MAX_PAYLOAD;
+pub const INITIAL_WINDOW: usize = 2 * MAX_PAYLOAD;
+/// Seconds each base delay measurement covers, the lowest of the last few is the base delay
+pub const BASE_DELAY_INTERVAL: u64 = 60;
+pub const BASE_DELAY_HISTORY: usize = 2;
+
+/// Bytes received and not read yet that are buffered at most, advertised as the window
+pub const RECEIVE_BUFFER: usize = 1024 * 1024;
+/// Packets further ahead than this of the last one received in order are dropped
+pub const MAX_OUT_OF_ORDER: u16 = 1024;
+
+/// Milliseconds to wait for an acknowledgement before the first round trip is measured
+pub const INITIAL_TIMEOUT: u64 = 1000;
+pub const MIN_TIMEOUT: u64 = 500;
+pub const MAX_TIMEOUT: u64 = 30_000;
+/// Times a packet is sent before giving up on the connection
+pub const MAX_TRANSMISSIONS: u8 = 6;
+/// Duplicate acknowledgements after which the packet they are waiting for is sent again
+pub const DUPLICATE_ACKS_BEFORE_RESEND: u8 = 3;
+
+/// Milliseconds between the checks for packets to send again
+pub const TICK_INTERVAL: u64 = 50;
+/// Seconds to wait for the other side to accept a connection, after that TCP is used instead
+pub const UTP_CONNECT_TIMEOUT: u64 = 3;
@@ -0,0 +1,21 @@
+use std::fmt;
+
+#[derive(Debug, PartialEq)]
+/// Errors parsing uTP packets
+pub enum PacketError {
+    /// the datagram is shorter than the header or its extensions
+    TooShort,
+    /// the version isn't 1, or the type isn't one of the five known
+    UnknownPacket(u8),
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PacketError::TooShort => write!(f, "Packet too short"),
+            PacketError::UnknownPacket(first_byte) => {
+                write!(f, "Unknown packet type or version: {:#x}", first_byte)
+            }
+        }
+    }
+}
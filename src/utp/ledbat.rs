@@ -0,0 +1,102 @@
+use super::constants::*;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// LEDBAT congestion control (RFC 6817), as uTP uses it.
+/// The window grows while the queuing delay our packets see is below the target and shrinks
+/// above it, so the connection yields to everything else sharing the uplink
+pub struct Ledbat {
+    window: f64,
+    // lowest delay measured in each of the last intervals, the newest last
+    base_delays: VecDeque<u32>,
+    interval_started: Instant,
+}
+
+impl Default for Ledbat {
+    fn default() -> Self {
+        Self {
+            window: INITIAL_WINDOW as f64,
+            base_delays: VecDeque::from([u32::MAX]),
+            interval_started: Instant::now(),
+        }
+    }
+}
+
+impl Ledbat {
+    /// Bytes that may be in flight
+    pub fn window(&self) -> usize {
+        self.window as usize
+    }
+
+    fn base_delay(&self) -> u32 {
+        self.base_delays.iter().copied().min().unwrap_or(u32::MAX)
+    }
+
+    // the delay includes the difference between both clocks, which the base delay cancels out
+    fn update_base_delay(&mut self, delay: u32, now: Instant) {
+        if now.duration_since(self.interval_started) >= Duration::from_secs(BASE_DELAY_INTERVAL) {
+            self.base_delays.push_back(u32::MAX);
+            if self.base_delays.len() > BASE_DELAY_HISTORY {
+                self.base_delays.pop_front();
+            }
+            self.interval_started = now;
+        }
+        if let Some(lowest) = self.base_delays.back_mut() {
+            *lowest = (*lowest).min(delay);
+        }
+    }
+
+    /// `acked` bytes were acknowledged, the other side measured `delay` for our last packet
+    /// and `in_flight` bytes were waiting for the acknowledgement
+    pub fn on_ack(&mut self, acked: usize, delay: u32, in_flight: usize, now: Instant) {
+        self.update_base_delay(delay, now);
+        let queuing_delay = delay.wrapping_sub(self.base_delay()).min(2 * TARGET_DELAY);
+        let off_target = (TARGET_DELAY as f64 - queuing_delay as f64) / TARGET_DELAY as f64;
+        let window_factor = acked as f64 / self.window.max(in_flight as f64).max(1.0);
+        let gain = MAX_CWND_INCREASE_PER_RTT * off_target * window_factor;
+        self.window = (self.window + gain).max(MIN_WINDOW as f64);
+    }
+
+    /// A packet was lost and sent again
+    pub fn on_loss(&mut self) {
+        self.window = (self.window / 2.0).max(MIN_WINDOW as f64);
+    }
+
+    /// Nothing was acknowledged in time
+    pub fn on_timeout(&mut self) {
+        self.window = MIN_WINDOW as f64;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_window_follows_the_queuing_delay() {
+        let now = Instant::now();
+        let mut ledbat = Ledbat::default();
+        // the first sample is the base delay, so there's no queuing and the window grows
+        ledbat.on_ack(MAX_PAYLOAD, 50_000, INITIAL_WINDOW, now);
+        let grown = ledbat.window();
+        assert!(grown > INITIAL_WINDOW);
+
+        // 150ms above the base delay is past the target
+        for _ in 0..100 {
+            ledbat.on_ack(MAX_PAYLOAD, 200_000, grown, now);
+        }
+        assert!(ledbat.window() < grown);
+        assert_eq!(ledbat.window(), MIN_WINDOW);
+
+        // back at the target the window stays put
+        let window = ledbat.window();
+        ledbat.on_ack(MAX_PAYLOAD, 150_000, window, now);
+        assert_eq!(ledbat.window(), window);
+
+        ledbat.on_ack(100 * MAX_PAYLOAD, 50_000, 0, now);
+        ledbat.on_loss();
+        assert!(ledbat.window() >= MIN_WINDOW);
+        ledbat.on_timeout();
+        assert_eq!(ledbat.window(), MIN_WINDOW);
+    }
+}
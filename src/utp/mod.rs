@@ -0,0 +1,16 @@
+mod connection;
+mod constants;
+mod errors;
+mod ledbat;
+mod packet;
+mod socket;
+mod stream;
+mod types;
+
+pub use constants::*;
+pub use errors::PacketError;
+pub use ledbat::Ledbat;
+pub use packet::{Packet, PacketType};
+pub use socket::UtpSocket;
+pub use stream::UtpStream;
+pub use types::Transport;
@@ -0,0 +1,153 @@
+use super::constants::*;
+use super::errors::PacketError;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Type of a uTP packet, the high nibble of its first byte
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PacketType {
+    Data,
+    Fin,
+    /// acknowledges packets without sending data
+    State,
+    Reset,
+    Syn,
+}
+
+impl PacketType {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(PacketType::Data),
+            1 => Some(PacketType::Fin),
+            2 => Some(PacketType::State),
+            3 => Some(PacketType::Reset),
+            4 => Some(PacketType::Syn),
+            _ => None,
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            PacketType::Data => 0,
+            PacketType::Fin => 1,
+            PacketType::State => 2,
+            PacketType::Reset => 3,
+            PacketType::Syn => 4,
+        }
+    }
+}
+
+/// A uTP packet (BEP 29). Extensions of received packets, like selective acks, are skipped
+#[derive(Debug, Clone, PartialEq)]
+pub struct Packet {
+    pub kind: PacketType,
+    pub connection_id: u16,
+    /// when it was sent, in microseconds of the sender's clock
+    pub timestamp: u32,
+    /// the delay the sender measured for the last packet it received
+    pub timestamp_difference: u32,
+    /// bytes the sender can still receive
+    pub window: u32,
+    pub seq_nr: u16,
+    /// the last packet the sender received in order
+    pub ack_nr: u16,
+    pub payload: Vec<u8>,
+}
+
+impl Packet {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.payload.len());
+        bytes.push(self.kind.to_u8() << 4 | UTP_VERSION);
+        // no extensions
+        bytes.push(0);
+        bytes.extend(self.connection_id.to_be_bytes());
+        bytes.extend(self.timestamp.to_be_bytes());
+        bytes.extend(self.timestamp_difference.to_be_bytes());
+        bytes.extend(self.window.to_be_bytes());
+        bytes.extend(self.seq_nr.to_be_bytes());
+        bytes.extend(self.ack_nr.to_be_bytes());
+        bytes.extend(&self.payload);
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Packet, PacketError> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(PacketError::TooShort);
+        }
+        let kind = PacketType::from_u8(bytes[0] >> 4)
+            .filter(|_| bytes[0] & 0x0f == UTP_VERSION)
+            .ok_or(PacketError::UnknownPacket(bytes[0]))?;
+        let u16_at = |i: usize| u16::from_be_bytes([bytes[i], bytes[i + 1]]);
+        let u32_at =
+            |i: usize| u32::from_be_bytes([bytes[i], bytes[i + 1], bytes[i + 2], bytes[i + 3]]);
+
+        // each extension starts with the type of the next one and its length
+        let mut extension = bytes[1];
+        let mut start = HEADER_SIZE;
+        while extension != 0 {
+            let header = bytes.get(start..start + 2).ok_or(PacketError::TooShort)?;
+            extension = header[0];
+            start += 2 + header[1] as usize;
+        }
+        let payload = bytes.get(start..).ok_or(PacketError::TooShort)?.to_vec();
+
+        Ok(Packet {
+            kind,
+            connection_id: u16_at(2),
+            timestamp: u32_at(4),
+            timestamp_difference: u32_at(8),
+            window: u32_at(12),
+            seq_nr: u16_at(16),
+            ack_nr: u16_at(18),
+            payload,
+        })
+    }
+}
+
+/// Microseconds of the local clock, packets are timestamped with them.
+/// Only differences matter, so it wraps around
+pub fn now_micros() -> u32 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_micros() as u32)
+        .unwrap_or(0)
+}
+
+/// Whether sequence number `a` comes before `b`, they wrap around
+pub fn seq_before(a: u16, b: u16) -> bool {
+    a != b && b.wrapping_sub(a) < 0x8000
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn packets_are_read_back_as_written() {
+        let packet = Packet {
+            kind: PacketType::Data,
+            connection_id: 0xbeef,
+            timestamp: 123_456,
+            timestamp_difference: 789,
+            window: 1 << 20,
+            seq_nr: 65535,
+            ack_nr: 7,
+            payload: b"piece".to_vec(),
+        };
+        let bytes = packet.to_bytes();
+        assert_eq!(bytes[0], 0x01);
+        assert_eq!(Packet::from_bytes(&bytes), Ok(packet));
+
+        // a selective ack extension is skipped
+        let mut with_extension = bytes.clone();
+        with_extension[1] = 1;
+        with_extension.splice(HEADER_SIZE..HEADER_SIZE, [0, 4, 0xff, 0, 0, 0]);
+        assert_eq!(
+            Packet::from_bytes(&with_extension).unwrap().payload,
+            b"piece".to_vec()
+        );
+
+        assert_eq!(Packet::from_bytes(&bytes[..10]), Err(PacketError::TooShort));
+        assert!(Packet::from_bytes(&[0x52; 20]).is_err());
+        assert!(seq_before(65535, 2) && !seq_before(2, 65535) && !seq_before(3, 3));
+    }
+}
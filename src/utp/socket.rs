@@ -0,0 +1,227 @@
+use super::connection::Connection;
+use super::constants::*;
+use super::packet::*;
+use super::stream::UtpStream;
+use log::*;
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// A connection and the streams using it, which wait on `changed` for it to receive something
+pub(super) struct ConnectionCell {
+    pub connection: Mutex<Connection>,
+    pub changed: Condvar,
+    pub handles: AtomicUsize,
+}
+
+impl ConnectionCell {
+    pub fn new(connection: Connection) -> Self {
+        Self {
+            connection: Mutex::new(connection),
+            changed: Condvar::new(),
+            handles: AtomicUsize::new(0),
+        }
+    }
+}
+
+/// UDP socket and the connections multiplexed on it, by the address and id of the other side
+pub(super) struct Shared {
+    pub socket: UdpSocket,
+    pub connections: Mutex<HashMap<(SocketAddr, u16), Arc<ConnectionCell>>>,
+    listening: AtomicBool,
+}
+
+impl Shared {
+    /// Binds the socket and starts the thread that receives its packets
+    pub fn start(
+        address: SocketAddr,
+        incoming: Option<Sender<UtpStream>>,
+        connection: Option<(u16, Connection)>,
+    ) -> io::Result<(Arc<Self>, Option<Arc<ConnectionCell>>)> {
+        let socket = UdpSocket::bind(address)?;
+        socket.set_read_timeout(Some(Duration::from_millis(TICK_INTERVAL)))?;
+        let shared = Arc::new(Self {
+            socket,
+            connections: Mutex::new(HashMap::new()),
+            listening: AtomicBool::new(incoming.is_some()),
+        });
+        // the connection is added before the thread starts, so the thread doesn't stop right away
+        let cell = connection.map(|(recv_id, connection)| {
+            let remote = connection.remote;
+            let cell = Arc::new(ConnectionCell::new(connection));
+            shared.insert((remote, recv_id), cell.clone());
+            cell
+        });
+        if let Some(cell) = &cell {
+            shared.send_outgoing(&mut lock(cell));
+        }
+        let driven = shared.clone();
+        std::thread::Builder::new()
+            .name("utp".to_string())
+            .spawn(move || driven.drive(incoming))?;
+        Ok((shared, cell))
+    }
+
+    fn insert(&self, key: (SocketAddr, u16), cell: Arc<ConnectionCell>) {
+        if let Ok(mut connections) = self.connections.lock() {
+            connections.insert(key, cell);
+        }
+    }
+
+    /// Sends the packets the connection has waiting
+    pub fn send_outgoing(&self, connection: &mut Connection) {
+        for packet in connection.take_outgoing() {
+            if let Err(err) = self.socket.send_to(&packet.to_bytes(), connection.remote) {
+                trace!("Couldn't send uTP packet to {}: {}", connection.remote, err);
+            }
+        }
+    }
+
+    fn drive(self: Arc<Self>, incoming: Option<Sender<UtpStream>>) {
+        let mut buffer = [0u8; 64 * 1024];
+        let mut last_tick = Instant::now();
+        loop {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((read, from)) => self.dispatch(&buffer[..read], from, incoming.as_ref()),
+                Err(ref err)
+                    if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                // errors of packets sent before, like unreachable ports, don't stop the socket
+                Err(err) => trace!("uTP socket receive failed: {}", err),
+            }
+            if last_tick.elapsed() >= Duration::from_millis(TICK_INTERVAL) {
+                self.tick();
+                last_tick = Instant::now();
+            }
+            let idle = self
+                .connections
+                .lock()
+                .map_or(true, |connections| connections.is_empty());
+            if idle && !self.listening.load(Ordering::SeqCst) {
+                break;
+            }
+        }
+        trace!("uTP socket finished");
+    }
+
+    fn dispatch(
+        self: &Arc<Self>,
+        datagram: &[u8],
+        from: SocketAddr,
+        incoming: Option<&Sender<UtpStream>>,
+    ) {
+        let packet = match Packet::from_bytes(datagram) {
+            Ok(packet) => packet,
+            Err(err) => {
+                trace!("Ignoring datagram from {}: {}", from, err);
+                return;
+            }
+        };
+        // a SYN carries the id the other side sends with, we receive with the next one
+        let recv_id = match packet.kind {
+            PacketType::Syn => packet.connection_id.wrapping_add(1),
+            _ => packet.connection_id,
+        };
+        let cell = self
+            .connections
+            .lock()
+            .ok()
+            .and_then(|connections| connections.get(&(from, recv_id)).cloned());
+        match (cell, incoming) {
+            (Some(cell), _) => {
+                let mut connection = lock(&cell);
+                connection.handle(packet, Instant::now());
+                self.send_outgoing(&mut connection);
+                cell.changed.notify_all();
+            }
+            (None, Some(incoming))
+                if packet.kind == PacketType::Syn && self.listening.load(Ordering::SeqCst) =>
+            {
+                let mut connection = Connection::incoming(from, &packet);
+                self.send_outgoing(&mut connection);
+                let cell = Arc::new(ConnectionCell::new(connection));
+                self.insert((from, recv_id), cell.clone());
+                let _ = incoming.send(UtpStream::new(self.clone(), cell));
+            }
+            (None, _) if packet.kind != PacketType::Reset => {
+                let reset = Packet {
+                    kind: PacketType::Reset,
+                    connection_id: packet.connection_id,
+                    timestamp: now_micros(),
+                    timestamp_difference: 0,
+                    window: 0,
+                    seq_nr: rand::random(),
+                    ack_nr: packet.seq_nr,
+                    payload: vec![],
+                };
+                let _ = self.socket.send_to(&reset.to_bytes(), from);
+            }
+            _ => {}
+        }
+    }
+
+    // sends again what wasn't acknowledged in time, and forgets the connections nobody uses
+    fn tick(&self) {
+        let Ok(mut connections) = self.connections.lock() else {
+            return;
+        };
+        let now = Instant::now();
+        connections.retain(|_, cell| {
+            let mut connection = lock(cell);
+            connection.tick(now);
+            self.send_outgoing(&mut connection);
+            cell.changed.notify_all();
+            !connection.is_done() || cell.handles.load(Ordering::SeqCst) > 0
+        });
+    }
+}
+
+pub(super) fn lock(cell: &ConnectionCell) -> std::sync::MutexGuard<'_, Connection> {
+    cell.connection
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Socket accepting uTP connections (BEP 29) on a UDP port.
+/// Its packets are received by a thread of its own, which keeps going while connections are open
+pub struct UtpSocket {
+    shared: Arc<Shared>,
+    incoming: Receiver<UtpStream>,
+}
+
+impl UtpSocket {
+    pub fn bind(address: SocketAddr) -> io::Result<Self> {
+        let (sender, incoming) = mpsc::channel();
+        let (shared, _) = Shared::start(address, Some(sender), None)?;
+        Ok(Self { shared, incoming })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.shared.socket.local_addr()
+    }
+
+    /// Waits for the next connection
+    pub fn accept(&self) -> io::Result<UtpStream> {
+        self.incoming
+            .recv()
+            .map_err(|_| io::Error::other("uTP socket stopped"))
+    }
+
+    /// Waits at most `timeout` for the next connection, None if none arrived
+    pub fn accept_timeout(&self, timeout: Duration) -> io::Result<Option<UtpStream>> {
+        match self.incoming.recv_timeout(timeout) {
+            Ok(stream) => Ok(Some(stream)),
+            Err(RecvTimeoutError::Timeout) => Ok(None),
+            Err(RecvTimeoutError::Disconnected) => Err(io::Error::other("uTP socket stopped")),
+        }
+    }
+}
+
+impl Drop for UtpSocket {
+    fn drop(&mut self) {
+        self.shared.listening.store(false, Ordering::SeqCst);
+    }
+}
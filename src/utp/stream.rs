@@ -0,0 +1,261 @@
+use super::connection::{Connection, ConnectionState};
+use super::socket::{lock, ConnectionCell, Shared};
+use crate::peer::PeerStream;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, MutexGuard};
+use std::time::{Duration, Instant};
+
+/// Connection to another uTP socket, read and written like a `TcpStream`.
+/// Writes don't block, the data is sent as the congestion window lets it.
+/// The connection is shut down once every clone of the stream is dropped
+pub struct UtpStream {
+    shared: Arc<Shared>,
+    cell: Arc<ConnectionCell>,
+    read_timeout: Option<Duration>,
+}
+
+impl UtpStream {
+    pub(super) fn new(shared: Arc<Shared>, cell: Arc<ConnectionCell>) -> Self {
+        cell.handles.fetch_add(1, Ordering::SeqCst);
+        Self {
+            shared,
+            cell,
+            read_timeout: None,
+        }
+    }
+
+    /// Connects from a socket of its own to the uTP socket at `address`,
+    /// waiting at most `timeout` for it to accept
+    pub fn connect(address: SocketAddr, timeout: Duration) -> io::Result<Self> {
+        let local = match address {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        };
+        let local: SocketAddr = local.parse().map_err(io::Error::other)?;
+        let recv_id: u16 = rand::random();
+        let connection = Connection::outgoing(address, recv_id, Instant::now());
+        let (shared, cell) = Shared::start(local, None, Some((recv_id, connection)))?;
+        let cell = cell.ok_or_else(|| io::Error::other("uTP connection wasn't started"))?;
+        let stream = Self::new(shared, cell);
+        let state = stream.wait(Some(timeout), |connection| {
+            connection.state == ConnectionState::SynSent
+        })?;
+        match state {
+            ConnectionState::Connected => Ok(stream),
+            ConnectionState::SynSent => Err(io::Error::new(
+                ErrorKind::TimedOut,
+                format!("uTP connection to {} timed out", address),
+            )),
+            _ => Err(io::Error::new(
+                ErrorKind::ConnectionRefused,
+                format!("uTP connection refused by {}", address),
+            )),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        lock(&self.cell)
+    }
+
+    // waits while `waiting` holds, at most `timeout`, returning the state it ended in
+    fn wait(
+        &self,
+        timeout: Option<Duration>,
+        waiting: impl Fn(&Connection) -> bool,
+    ) -> io::Result<ConnectionState> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut connection = self.lock();
+        while waiting(&connection) {
+            connection = match deadline {
+                Some(deadline) => {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        break;
+                    }
+                    self.cell
+                        .changed
+                        .wait_timeout(connection, left)
+                        .map_err(|_| io::Error::other("uTP connection lock poisoned"))?
+                        .0
+                }
+                None => self
+                    .cell
+                    .changed
+                    .wait(connection)
+                    .map_err(|_| io::Error::other("uTP connection lock poisoned"))?,
+            };
+        }
+        Ok(connection.state)
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        let mut clone = Self::new(self.shared.clone(), self.cell.clone());
+        clone.read_timeout = self.read_timeout;
+        Ok(clone)
+    }
+
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout = timeout;
+    }
+
+    pub fn peer_addr(&self) -> SocketAddr {
+        self.lock().remote
+    }
+
+    /// Closes our side once what was written is sent, what the other side sends can still be read
+    pub fn shutdown(&self) {
+        let mut connection = self.lock();
+        connection.shutdown(Instant::now());
+        self.shared.send_outgoing(&mut connection);
+        self.cell.changed.notify_all();
+    }
+}
+
+// nothing to read yet, and the connection may still bring something
+fn waiting_for_data(connection: &Connection) -> bool {
+    !connection.has_received()
+        && !connection.finished
+        && !matches!(
+            connection.state,
+            ConnectionState::Reset | ConnectionState::TimedOut
+        )
+}
+
+impl Read for UtpStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.wait(self.read_timeout, waiting_for_data)?;
+        let mut connection = self.lock();
+        if connection.has_received() {
+            let read = connection.read(buf);
+            self.shared.send_outgoing(&mut connection);
+            return Ok(read);
+        }
+        match connection.state {
+            ConnectionState::Reset => Err(io::Error::new(
+                ErrorKind::ConnectionReset,
+                "uTP connection reset by peer",
+            )),
+            ConnectionState::TimedOut => Err(io::Error::new(
+                ErrorKind::TimedOut,
+                "uTP connection timed out",
+            )),
+            _ if connection.finished => Ok(0),
+            _ => Err(io::Error::new(
+                ErrorKind::TimedOut,
+                "Timed out waiting for data from peer",
+            )),
+        }
+    }
+}
+
+impl Write for UtpStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut connection = self.lock();
+        if !connection.is_writable() {
+            return Err(io::Error::new(
+                ErrorKind::BrokenPipe,
+                "uTP connection is closed",
+            ));
+        }
+        connection.write(buf, Instant::now());
+        self.shared.send_outgoing(&mut connection);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl PeerStream for UtpStream {
+    fn has_pending_data(&mut self) -> bool {
+        !waiting_for_data(&self.lock())
+    }
+
+    fn wait_for_data(&mut self, timeout: Duration) -> bool {
+        let _ = self.wait(Some(timeout), waiting_for_data);
+        self.has_pending_data()
+    }
+}
+
+impl Drop for UtpStream {
+    fn drop(&mut self) {
+        if self.cell.handles.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.shutdown();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::UtpSocket;
+    use super::*;
+    use crate::peer::{
+        IClientPeerMessageService, IPeerMessageService, IServerPeerMessageService, PeerMessage,
+        PeerMessageId, PeerMessageService,
+    };
+
+    fn listen() -> (UtpSocket, SocketAddr) {
+        let socket = UtpSocket::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+        let address = socket.local_addr().unwrap();
+        (socket, address)
+    }
+
+    #[test]
+    fn data_goes_both_ways_over_loopback() {
+        let (socket, address) = listen();
+        let data: Vec<u8> = (0..300_000).map(|i| (i % 251) as u8).collect();
+        let expected = data.clone();
+        let server = std::thread::spawn(move || {
+            let mut stream = socket.accept().unwrap();
+            let mut received = vec![];
+            stream.read_to_end(&mut received).unwrap();
+            stream.write_all(&received[..100]).unwrap();
+            received
+        });
+
+        let mut stream = UtpStream::connect(address, Duration::from_secs(3)).unwrap();
+        stream.write_all(&data).unwrap();
+        stream.shutdown();
+        assert_eq!(server.join().unwrap(), expected);
+        let mut echoed = vec![];
+        stream.read_to_end(&mut echoed).unwrap();
+        assert_eq!(echoed, expected[..100]);
+    }
+
+    #[test]
+    fn connecting_where_nobody_listens_times_out() {
+        let address = {
+            let (_, address) = listen();
+            address
+        };
+        let timeout = Duration::from_millis(300);
+        assert!(UtpStream::connect(address, timeout).is_err());
+    }
+
+    #[test]
+    fn peer_messages_go_through_utp() {
+        let (socket, address) = listen();
+        let info_hash = vec![3; 20];
+        let server_info_hash = info_hash.clone();
+        let server = std::thread::spawn(move || {
+            let mut service = PeerMessageService::from_stream(socket.accept().unwrap());
+            IServerPeerMessageService::handshake(&mut service, &server_info_hash, &[1; 20])
+                .unwrap();
+            service.send_message(&PeerMessage::unchoke()).unwrap();
+            service.wait_for_message().unwrap()
+        });
+
+        let stream = UtpStream::connect(address, Duration::from_secs(3)).unwrap();
+        let mut service = PeerMessageService::from_stream(stream);
+        IClientPeerMessageService::handshake(&mut service, &info_hash, &[2; 20]).unwrap();
+        assert_eq!(
+            service.wait_for_message().unwrap().id,
+            PeerMessageId::Unchoke
+        );
+        service.send_message(&PeerMessage::interested()).unwrap();
+        assert_eq!(server.join().unwrap().id, PeerMessageId::Interested);
+    }
+}
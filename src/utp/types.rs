@@ -0,0 +1,20 @@
+/// How connections to other peers are opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Transport {
+    /// only TCP
+    #[default]
+    Tcp,
+    /// uTP first, falling back to TCP if the peer doesn't answer.
+    /// The server accepts both
+    Utp,
+}
+
+impl Transport {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim() {
+            "tcp" => Some(Transport::Tcp),
+            "utp" => Some(Transport::Utp),
+            _ => None,
+        }
+    }
+}
@@ -1,21 +1,27 @@
+use bittorrent_rustico::bandwidth::TorrentBandwidth;
 use bittorrent_rustico::client::*;
 use bittorrent_rustico::config::*;
 use bittorrent_rustico::constants::*;
+use bittorrent_rustico::encryption::EncryptionPolicy;
 use bittorrent_rustico::metainfo::*;
 use bittorrent_rustico::peer::*;
+use bittorrent_rustico::piece_manager::DownloadMode;
 use bittorrent_rustico::ui::*;
+use bittorrent_rustico::utp::Transport;
 use sha1::{Digest, Sha1};
 use std::fs::File;
 use std::io::{Read, Write};
 use std::time::Duration;
 mod mock_service_creation;
 use bittorrent_rustico::metainfo::{self, Metainfo};
-use bittorrent_rustico::server::Server;
+use bittorrent_rustico::server::{ServedTorrent, Server};
+use bittorrent_rustico::storage::*;
 use bittorrent_rustico::tracker::MockTrackerService;
 use bittorrent_rustico::tracker::TrackerService;
 use mock_service_creation::*;
 use rand::Rng;
 use std::net::TcpStream;
+use std::sync::Arc;
 
 fn get_mock_tracker_responses() -> Vec<Vec<Peer>> {
     let peer_0 = Peer {
@@ -23,24 +29,28 @@ fn get_mock_tracker_responses() -> Vec<Vec<Peer>> {
         port: 0,
         peer_id: vec![0],
         peer_message_service_provider: mock_peer_message_service_0,
+        web_seed: None,
     };
     let peer_1 = Peer {
         ip: String::from("1.1.1.1"),
         port: 0,
         peer_id: vec![1],
         peer_message_service_provider: mock_peer_message_service_1,
+        web_seed: None,
     };
     let peer_2 = Peer {
         ip: String::from("2.2.2.2"),
         port: 0,
         peer_id: vec![2],
         peer_message_service_provider: mock_peer_message_service_2,
+        web_seed: None,
     };
     let _faulty_peer = Peer {
         ip: String::from("9.9.9.9"),
         port: 0,
         peer_id: vec![99],
         peer_message_service_provider: mock_faulty_peer_message_service,
+        web_seed: None,
     };
 
     vec![
@@ -96,11 +106,13 @@ fn client_integration_test() {
         name: String::from("linux_distribution_test.iso"),
         length: file.len() as u64,
         files: None,
+        ..Default::default()
     };
     let metainfo = Metainfo {
         announce: String::from("mock_url"),
         info_hash: vec![],
         info,
+        ..Default::default()
     };
 
     let tracker_responses = get_mock_tracker_responses();
@@ -109,8 +121,23 @@ fn client_integration_test() {
         peer_id: generate_peer_id(),
         metainfo,
     };
-    let client: TorrentClient =
-        TorrentClient::new(&client_info, UIMessageSender::no_ui(), vec![]).unwrap();
+    let storage = open_storage(&client_info.config, &client_info.metainfo, None).unwrap();
+    let resume = ResumeWriter::new(
+        &resume_path(&client_info.config, &client_info.metainfo),
+        storage.clone(),
+        TorrentBandwidth::unlimited(),
+        None,
+    );
+    let client: TorrentClient = TorrentClient::new(
+        &client_info,
+        UIMessageSender::no_ui(),
+        vec![],
+        storage,
+        resume,
+        Choker::default(),
+        TorrentBandwidth::unlimited(),
+    )
+    .unwrap();
 
     let mut tracker_service = MockTrackerService {
         responses: tracker_responses,
@@ -138,12 +165,14 @@ fn get_metainfo(pieces: Vec<Vec<u8>>, info_hash: Vec<u8>) -> Metainfo {
         name: "target.txt".to_string(),
         length: 24, // 3 pieces of 8 bytes each
         files: None::<Vec<metainfo::File>>,
+        ..Default::default()
     };
 
     Metainfo {
         announce,
         info,
         info_hash,
+        ..Default::default()
     }
 }
 
@@ -237,20 +266,31 @@ fn init_connection(stream: &mut TcpStream, meta: &Metainfo, peer_id: &Vec<u8>) -
     let mut handshake_response = [0u8; 68];
     stream.read_exact(&mut handshake_response).unwrap();
 
-    let unchocke_message: PeerMessage = wait_for_message(stream).unwrap();
-    if unchocke_message.id != PeerMessageId::Unchoke {
+    // our handshake advertises the fast extension, so the seeder says it has every piece with HaveAll
+    let have_all_message: PeerMessage = wait_for_message(stream).unwrap();
+    if have_all_message.id != PeerMessageId::HaveAll {
         return false;
     }
 
-    let bitfield_message: PeerMessage = wait_for_message(stream).unwrap();
-    return bitfield_message.id == PeerMessageId::Bitfield && bitfield_message.payload.len() == 1;
+    let unchocke_message: PeerMessage = wait_for_message(stream).unwrap();
+    return unchocke_message.id == PeerMessageId::Unchoke;
+}
+
+// skips the allowed fast and suggested pieces the seeder tells about after unchoking
+fn wait_for_piece(stream: &mut TcpStream) -> Result<PeerMessage, IPeerMessageServiceError> {
+    loop {
+        let message = wait_for_message(stream)?;
+        if message.id == PeerMessageId::Piece {
+            return Ok(message);
+        }
+    }
 }
 
 fn ask_for_piece(piece_index: u32, stream: &mut TcpStream, meta: Metainfo) -> Vec<u8> {
     let request = PeerMessage::request(piece_index, 0, meta.info.piece_length as u32);
     send_message(stream, &request).unwrap();
 
-    let response: PeerMessage = wait_for_message(stream).unwrap();
+    let response: PeerMessage = wait_for_piece(stream).unwrap();
     response.payload[8..].to_vec()
 }
 
@@ -278,6 +318,24 @@ fn server_integration_test_ask_for_small_pieces() {
         log_path: "./log".to_string(),
         download_path: "./downloads".to_string(),
         persist_pieces: true,
+        storage: StorageKind::Pieces,
+        max_upload_rate: None,
+        max_download_rate: None,
+        torrent_max_upload_rate: None,
+        torrent_max_download_rate: None,
+        peer_max_upload_rate: None,
+        peer_max_download_rate: None,
+        download_mode: DownloadMode::default(),
+        seed_ratio: None,
+        seed_time: None,
+        seed_idle_time: None,
+        max_connections: None,
+        max_connections_per_torrent: None,
+        max_active_downloads: None,
+        rpc_port: None,
+        watch_dir: None,
+        encryption: EncryptionPolicy::Disabled,
+        transport: Transport::Tcp,
     };
 
     let client_info: ClientInfo = ClientInfo {
@@ -288,11 +346,20 @@ fn server_integration_test_ask_for_small_pieces() {
 
     let server: Server = Server::run(
         peer_id,
-        meta.clone(),
         port,
         std::time::Duration::from_secs(2),
-        "./downloads/test_server/pieces",
-        TrackerService::new(client_info),
+        ServedTorrent {
+            metainfo: meta.clone(),
+            storage: Arc::new(PieceFileStorage::new(
+                "./downloads/test_server",
+                meta.get_piece_count(),
+                "",
+                true,
+            )),
+            tracker_service: TrackerService::new(client_info),
+            choker: Choker::default(),
+            bandwidth: TorrentBandwidth::unlimited(),
+        },
     );
     let mut socket: TcpStream;
     loop {
@@ -315,7 +382,7 @@ fn ask_for_block(block_no: u32, stream: &mut TcpStream) -> Vec<u8> {
     let request = PeerMessage::request(0, block_size * block_no, block_size);
     send_message(stream, &request).unwrap();
 
-    let response: PeerMessage = wait_for_message(stream).unwrap();
+    let response: PeerMessage = wait_for_piece(stream).unwrap();
     response.payload[8..].to_vec()
 }
 
@@ -360,11 +427,20 @@ fn server_integration_test_ask_for_blocks_single_piece() {
 
     let server: Server = Server::run(
         peer_id,
-        meta,
         port,
         Duration::from_secs(4),
-        "./tests/test_server/pieces",
-        TrackerService::new(client_info),
+        ServedTorrent {
+            metainfo: meta,
+            storage: Arc::new(PieceFileStorage::new(
+                "./tests/test_server",
+                meta_clone.get_piece_count(),
+                "",
+                true,
+            )),
+            tracker_service: TrackerService::new(client_info),
+            choker: Choker::default(),
+            bandwidth: TorrentBandwidth::unlimited(),
+        },
     );
     let mut socket: TcpStream;
     loop {
@@ -1,5 +1,6 @@
 use bittorrent_rustico::constants::*;
 use bittorrent_rustico::peer::*;
+use bittorrent_rustico::utp::Transport;
 use std::vec::Vec;
 pub const INVALID_IDX: usize = 9;
 pub const INVALID_BYTE: u8 = 9;
@@ -85,6 +86,7 @@ fn create_faulty_file() -> Vec<u8> {
 pub fn mock_peer_message_service_0(
     _ip: String,
     _port: u16,
+    _transport: Transport,
 ) -> Result<Box<dyn IClientPeerMessageService + Send>, PeerConnectionError> {
     Ok(Box::new(PeerMessageServiceMockExtended {
         counter: 0,
@@ -100,6 +102,7 @@ pub fn mock_peer_message_service_0(
 pub fn mock_peer_message_service_1(
     _ip: String,
     _port: u16,
+    _transport: Transport,
 ) -> Result<Box<dyn IClientPeerMessageService + Send>, PeerConnectionError> {
     Ok(Box::new(PeerMessageServiceMockExtended {
         counter: 0,
@@ -115,6 +118,7 @@ pub fn mock_peer_message_service_1(
 pub fn mock_peer_message_service_2(
     _ip: String,
     _port: u16,
+    _transport: Transport,
 ) -> Result<Box<dyn IClientPeerMessageService + Send>, PeerConnectionError> {
     Ok(Box::new(PeerMessageServiceMockExtended {
         counter: 0,
@@ -130,6 +134,7 @@ pub fn mock_peer_message_service_2(
 pub fn mock_faulty_peer_message_service(
     _ip: String,
     _port: u16,
+    _transport: Transport,
 ) -> Result<Box<dyn IClientPeerMessageService + Send>, PeerConnectionError> {
     Ok(Box::new(PeerMessageServiceMockExtended {
         counter: 0,